@@ -0,0 +1,81 @@
+//! Read files in tar archives without extracting them to disk.
+//! Gzip compressed archives are detected by their magic bytes.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use crate::error::ErrorContext as EC;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The path reported for a file in the archive, e.g. `build.tar.gz!src/a.ts`.
+pub fn virtual_path(archive: &Path, entry: &Path) -> PathBuf {
+  PathBuf::from(format!("{}!{}", archive.display(), entry.display()))
+}
+
+/// Call `f` with the path and content of every regular file in the archive.
+/// Entries are streamed one at a time. Files that are not valid UTF-8 are skipped.
+pub fn for_each_file<R: Read>(
+  archive: &Path,
+  reader: R,
+  mut f: impl FnMut(&Path, String) -> Result<()>,
+) -> Result<()> {
+  let read_error = || EC::ReadFile(archive.to_path_buf());
+  let mut reader = BufReader::new(reader);
+  let is_gzip = reader
+    .fill_buf()
+    .with_context(read_error)?
+    .starts_with(&GZIP_MAGIC);
+  let reader: Box<dyn Read> = if is_gzip {
+    Box::new(GzDecoder::new(reader))
+  } else {
+    Box::new(reader)
+  };
+  let mut tar = tar::Archive::new(reader);
+  for entry in tar.entries().with_context(read_error)? {
+    let mut entry = entry.with_context(read_error)?;
+    if !entry.header().entry_type().is_file() {
+      continue;
+    }
+    let path = entry.path().with_context(read_error)?.into_owned();
+    let mut content = String::new();
+    if entry.read_to_string(&mut content).is_ok() {
+      f(&path, content)?;
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_read_plain_tar() {
+    let mut builder = tar::Builder::new(vec![]);
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    header.set_cksum();
+    builder.append_data(&mut header, "src/", &[][..]).unwrap();
+    for (name, content) in [("src/a.ts", &b"let a = 1"[..]), ("bin", &[0xff, 0xfe][..])] {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(content.len() as u64);
+      header.set_cksum();
+      builder.append_data(&mut header, name, content).unwrap();
+    }
+    let tarball = builder.into_inner().unwrap();
+    let mut files = vec![];
+    for_each_file(Path::new("a.tar"), &tarball[..], |path, content| {
+      files.push((path.to_path_buf(), content));
+      Ok(())
+    })
+    .expect("should read");
+    assert_eq!(files, [(PathBuf::from("src/a.ts"), "let a = 1".into())]);
+    let path = virtual_path(Path::new("a.tar"), Path::new("src/a.ts"));
+    assert_eq!(path, Path::new("a.tar!src/a.ts"));
+  }
+}
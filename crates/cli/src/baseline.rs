@@ -0,0 +1,161 @@
+//! Compare findings with those on a git ref for `scan --baseline-diff`.
+//! Files at the ref are read from the object database so the working tree is left untouched.
+//! The tree of the ref is listed once and blobs are read by one `git cat-file --batch` process.
+
+use anyhow::{anyhow, Result};
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+
+use crate::error::ErrorContext as EC;
+
+/// The directory to run git in for the path.
+fn git_dir(path: &Path) -> &Path {
+  if path.is_dir() {
+    return path;
+  }
+  match path.parent() {
+    Some(parent) if !parent.as_os_str().is_empty() => parent,
+    _ => Path::new("."),
+  }
+}
+
+/// Run git in the directory and return its stdout, or `None` if it fails.
+fn git_output(dir: &Path, args: &[&str]) -> Option<Vec<u8>> {
+  let output = Command::new("git")
+    .arg("-C")
+    .arg(dir)
+    .args(args)
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  output.status.success().then_some(output.stdout)
+}
+
+/// A `git cat-file --batch` process reading blobs by object id.
+struct CatFile {
+  child: Child,
+  stdin: ChildStdin,
+  stdout: BufReader<ChildStdout>,
+}
+
+impl CatFile {
+  fn spawn(dir: &Path) -> Option<Self> {
+    let mut child = Command::new("git")
+      .arg("-C")
+      .arg(dir)
+      .args(["cat-file", "--batch"])
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::null())
+      .spawn()
+      .ok()?;
+    let stdin = child.stdin.take()?;
+    let stdout = BufReader::new(child.stdout.take()?);
+    Some(Self {
+      child,
+      stdin,
+      stdout,
+    })
+  }
+
+  /// Content of the blob. The reply is `<id> blob <size>` followed by the content and a newline.
+  fn read(&mut self, id: &str) -> Option<Vec<u8>> {
+    writeln!(self.stdin, "{id}").ok()?;
+    self.stdin.flush().ok()?;
+    let mut header = String::new();
+    self.stdout.read_line(&mut header).ok()?;
+    let size: usize = match header.split_whitespace().collect::<Vec<_>>()[..] {
+      [_, "blob", size] => size.parse().ok()?,
+      _ => return None,
+    };
+    let mut content = vec![0; size + 1];
+    self.stdout.read_exact(&mut content).ok()?;
+    content.pop();
+    Some(content)
+  }
+}
+
+impl Drop for CatFile {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+/// The files of a git ref in the repository of the scanned paths.
+pub struct BaseTree {
+  /// blob ids by the absolute path of the file in the working tree
+  blobs: HashMap<PathBuf, String>,
+  cat_file: Mutex<CatFile>,
+}
+
+impl BaseTree {
+  /// List the tree of the ref, which must name a commit in the repository containing the path.
+  /// Refs starting with `-` are rejected so they cannot be read as options by git.
+  pub fn try_new(git_ref: &str, path: &Path) -> Result<Self> {
+    Self::read_tree(git_ref, git_dir(path)).ok_or_else(|| anyhow!(EC::GitRef(git_ref.to_string())))
+  }
+
+  fn read_tree(git_ref: &str, dir: &Path) -> Option<Self> {
+    if git_ref.starts_with('-') {
+      return None;
+    }
+    let commit = format!("{git_ref}^{{commit}}");
+    let commit = git_output(dir, &["rev-parse", "--verify", "--quiet", &commit])?;
+    let commit = String::from_utf8(commit).ok()?;
+    let root = git_output(dir, &["rev-parse", "--show-toplevel"])?;
+    let root = String::from_utf8(root).ok()?;
+    let root = Path::new(root.trim_end()).canonicalize().ok()?;
+    let tree = git_output(
+      &root,
+      &["ls-tree", "-r", "-z", "--full-tree", commit.trim()],
+    )?;
+    let mut blobs = HashMap::new();
+    // each entry is `<mode> <type> <id>\t<path>` terminated by NUL
+    for entry in tree.split(|&b| b == 0).filter(|e| !e.is_empty()) {
+      let entry = String::from_utf8_lossy(entry);
+      let Some((info, file)) = entry.split_once('\t') else {
+        continue;
+      };
+      if let [_, "blob", id] = info.split(' ').collect::<Vec<_>>()[..] {
+        blobs.insert(root.join(file), id.to_string());
+      }
+    }
+    let cat_file = Mutex::new(CatFile::spawn(&root)?);
+    Some(Self { blobs, cat_file })
+  }
+
+  /// Content of the file at the ref, or `None` if the file does not exist there.
+  pub fn read(&self, path: &Path) -> Option<String> {
+    let id = self.blobs.get(&path.canonicalize().ok()?)?;
+    let mut cat_file = self.cat_file.lock().expect("should not be poisoned");
+    String::from_utf8(cat_file.read(id)?).ok()
+  }
+}
+
+/// Findings on the base ref counted by fingerprint, i.e. the rule id and the matched text.
+/// Positions are left out so that findings in code moved by the change are not new.
+#[derive(Default)]
+pub struct Fingerprints(HashMap<(String, String), usize>);
+
+impl Fingerprints {
+  pub fn add(&mut self, rule_id: &str, text: &str) {
+    *self.0.entry((rule_id.into(), text.into())).or_default() += 1;
+  }
+
+  /// Whether the finding is new. Each base finding offsets one current finding with its fingerprint,
+  /// so a duplicate of existing code is still new.
+  pub fn is_new(&mut self, rule_id: &str, text: &str) -> bool {
+    match self.0.get_mut(&(rule_id.to_string(), text.to_string())) {
+      Some(count) if *count > 0 => {
+        *count -= 1;
+        false
+      }
+      _ => true,
+    }
+  }
+}
@@ -0,0 +1,114 @@
+//! Scan results cached across runs. An entry is reused only if
+//! both the file content and the rule set are unchanged.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::ErrorContext as EC;
+
+/// Hashes are stored on disk, so they must not change between runs or compiler versions
+/// as `DefaultHasher` may.
+pub fn hash_of(bytes: &[u8]) -> u64 {
+  xxh3_64(bytes)
+}
+
+/// A match is recorded by its node so that it can be matched again without scanning the file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedMatch {
+  /// index of the rule among the rules applied to the file
+  pub rule: usize,
+  pub kind_id: u16,
+  pub start: usize,
+  pub end: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheEntry {
+  pub content_hash: u64,
+  pub rules_hash: u64,
+  /// rules with findings, including those whose matches are all omitted
+  pub rules: Vec<usize>,
+  pub matches: Vec<CachedMatch>,
+  /// matches beyond `--max-matches-per-file` by rule index
+  pub omitted: HashMap<usize, usize>,
+}
+
+/// One JSON file per scanned file, named by the hash of the scanned path.
+pub struct ScanCache {
+  dir: PathBuf,
+}
+
+impl ScanCache {
+  pub fn try_new(dir: PathBuf) -> Result<Self> {
+    fs::create_dir_all(&dir).with_context(|| EC::WriteFile(dir.clone()))?;
+    Ok(Self { dir })
+  }
+
+  fn entry_path(&self, path: &Path) -> PathBuf {
+    self.dir.join(format!(
+      "{:016x}.json",
+      hash_of(path.to_string_lossy().as_bytes())
+    ))
+  }
+
+  /// Returns the entry of the path if neither its content nor the rules changed.
+  /// Unreadable entries are treated as missing.
+  pub fn get(&self, path: &Path, content_hash: u64, rules_hash: u64) -> Option<CacheEntry> {
+    let text = fs::read_to_string(self.entry_path(path)).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&text).ok()?;
+    (entry.content_hash == content_hash && entry.rules_hash == rules_hash).then_some(entry)
+  }
+
+  pub fn put(&self, path: &Path, entry: &CacheEntry) -> Result<()> {
+    let entry_path = self.entry_path(path);
+    let text = serde_json::to_string(entry)?;
+    fs::write(&entry_path, text).with_context(|| EC::WriteFile(entry_path))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_cache_invalidation() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let cache = ScanCache::try_new(dir.to_path_buf()).expect("should create");
+    let path = Path::new("src/a.ts");
+    let entry = CacheEntry {
+      content_hash: hash_of(b"console.log(1)"),
+      rules_hash: 42,
+      rules: vec![0],
+      matches: vec![CachedMatch {
+        rule: 0,
+        kind_id: 1,
+        start: 0,
+        end: 14,
+      }],
+      omitted: HashMap::from([(0, 2)]),
+    };
+    cache.put(path, &entry).expect("should write");
+    let hit = cache.get(path, hash_of(b"console.log(1)"), 42);
+    let content_changed = cache.get(path, hash_of(b"console.log(2)"), 42);
+    let rules_changed = cache.get(path, hash_of(b"console.log(1)"), 43);
+    let other_path = cache.get(Path::new("src/b.ts"), hash_of(b"console.log(1)"), 42);
+    assert_eq!(hit, Some(entry));
+    assert_eq!(content_changed, None);
+    assert_eq!(rules_changed, None);
+    assert_eq!(other_path, None);
+  }
+
+  #[test]
+  fn test_stable_hash() {
+    // entries written by other builds of sg must stay readable
+    assert_eq!(hash_of(b""), 0x2d06800538d394c2);
+  }
+}
@@ -1,14 +1,17 @@
 use crate::error::ErrorContext as EC;
 use crate::verify::{SnapshotCollection, TestCase, TestSnapshots};
-use anyhow::{Context, Result};
+use anyhow::{Context, Error, Result};
 use ast_grep_config::{
-  from_str, from_yaml_string, DeserializeEnv, GlobalRules, RuleCollection, RuleConfig,
+  from_str, from_yaml_string_with_severity, from_yaml_string_with_text_rules, DeserializeEnv,
+  ExtendBases, GlobalRules, RuleCollection, RuleConfig, SerializableRule, SerializableRuleCore,
+  Severity, TextRule,
 };
 use ast_grep_language::{config_file_type, SupportLang};
 use clap::ValueEnum;
 use ignore::WalkBuilder;
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
 
@@ -31,25 +34,75 @@ pub struct AstGrepConfig {
   pub util_dirs: Option<Vec<PathBuf>>,
   /// overriding config for rules
   pub rules: Option<Vec<()>>,
+  /// Rules without severity get the one named by their parent directory, e.g. `warning/`.
+  #[serde(default)]
+  pub severity_from_dir: bool,
+  /// File globs for each language, e.g. `ts: ["*.mts"]`. They take precedence over file extensions.
+  /// An earlier glob wins if several match, so the map keeps the order of the config file.
+  pub language_globs: Option<IndexMap<String, Vec<String>>>,
 }
 
 pub fn find_config(config_path: Option<PathBuf>) -> Result<RuleCollection<SupportLang>> {
+  find_config_impl(config_path, None)
+}
+
+/// Like `find_config`, but rule files failing to parse are skipped.
+/// Errors of the skipped rule files are returned along with the valid rules.
+pub fn find_config_skip_invalid(
+  config_path: Option<PathBuf>,
+) -> Result<(RuleCollection<SupportLang>, Vec<Error>)> {
+  let mut skipped = vec![];
+  let configs = find_config_impl(config_path, Some(&mut skipped))?;
+  Ok((configs, skipped))
+}
+
+fn find_config_impl(
+  config_path: Option<PathBuf>,
+  skipped: Option<&mut Vec<Error>>,
+) -> Result<RuleCollection<SupportLang>> {
   let config_path = find_config_path_with_default(config_path).context(EC::ReadConfiguration)?;
-  let config_str = read_to_string(&config_path).context(EC::ReadConfiguration)?;
+  let config_str = read_to_string(&config_path)
+    .with_context(|| format!("Fail to read {}", config_path.display()))
+    .context(EC::ReadConfiguration)?;
   let sg_config: AstGrepConfig = from_str(&config_str).context(EC::ParseConfiguration)?;
+  // rule and util directories are relative to the config file, not the working directory
   let base_dir = config_path
     .parent()
     .expect("config file must have parent directory");
-  let global_rules = find_util_rules(base_dir, sg_config.util_dirs)?;
-  read_directory_yaml(base_dir, sg_config.rule_dirs, global_rules)
+  let (global_rules, global_utils) = find_util_rules(base_dir, sg_config.util_dirs)?;
+  let mut collection = read_directory_yaml(
+    base_dir,
+    sg_config.rule_dirs,
+    global_rules,
+    sg_config.severity_from_dir,
+    skipped,
+  )?;
+  collection.set_global_utils(global_utils);
+  if let Some(globs) = sg_config.language_globs {
+    set_language_globs(&mut collection, globs)?;
+  }
+  Ok(collection)
 }
 
+fn set_language_globs(
+  collection: &mut RuleCollection<SupportLang>,
+  globs: IndexMap<String, Vec<String>>,
+) -> Result<()> {
+  let mut parsed = vec![];
+  for (lang, globs) in globs {
+    let lang: SupportLang = lang.parse().context(EC::ParseConfiguration)?;
+    parsed.push((lang, globs));
+  }
+  collection.set_lang_globs(parsed).context(EC::GlobPattern)
+}
+
+/// The rules of `utilDirs`, parsed and serialized by id with their own utils inlined.
 fn find_util_rules(
   base_dir: &Path,
   util_dirs: Option<Vec<PathBuf>>,
-) -> Result<GlobalRules<SupportLang>> {
+) -> Result<(GlobalRules<SupportLang>, HashMap<String, SerializableRule>)> {
   let Some(util_dirs) = util_dirs else {
-    return Ok(GlobalRules::default())
+    return Ok((GlobalRules::default(), HashMap::new()))
   };
   let mut utils = vec![];
   // TODO: use WalkBuilder::add to avoid loop
@@ -70,20 +123,34 @@ fn find_util_rules(
       }
       let path = config_file.path();
       let file = read_to_string(path)?;
-      let new_configs = from_str(&file)?;
+      let new_configs: SerializableRuleCore<SupportLang> = from_str(&file)?;
       utils.push(new_configs);
     }
   }
+  let no_globals = HashMap::new();
+  let serialized = utils
+    .iter()
+    .map(|util| (util.id.clone(), util.rule_with_utils_inlined(&no_globals)))
+    .collect();
   let ret = DeserializeEnv::parse_global_utils(utils).context("TODO!")?;
-  Ok(ret)
+  Ok((ret, serialized))
 }
 
 fn read_directory_yaml(
   base_dir: &Path,
   rule_dirs: Vec<PathBuf>,
   global_rules: GlobalRules<SupportLang>,
+  severity_from_dir: bool,
+  mut skipped: Option<&mut Vec<Error>>,
 ) -> Result<RuleCollection<SupportLang>> {
-  let mut configs = vec![];
+  let mut skip_or_fail = |e: Error| match skipped.as_mut() {
+    Some(skipped) => {
+      skipped.push(e);
+      Ok(())
+    }
+    None => Err(e),
+  };
+  let mut files = vec![];
   for dir in rule_dirs {
     let dir_path = base_dir.join(dir);
     let walker = WalkBuilder::new(&dir_path)
@@ -100,23 +167,63 @@ fn read_directory_yaml(
         continue;
       }
       let path = config_file.path();
-      let new_configs = read_rule_file(path, Some(&global_rules))?;
-      configs.extend(new_configs);
+      match read_to_string(path) {
+        Ok(yaml) => files.push((path.to_path_buf(), yaml)),
+        Err(e) => skip_or_fail(Error::from(e).context(EC::ReadRule(path.to_path_buf())))?,
+      }
+    }
+  }
+  // rules can extend a rule of any file, so all files are read before any is parsed.
+  // A file with invalid YAML is reported when it is parsed below.
+  let mut bases = ExtendBases::default();
+  for (_, yaml) in &files {
+    let _ = bases.add_yaml(yaml);
+  }
+  let mut configs = vec![];
+  let mut text_rules = vec![];
+  for (path, yaml) in files {
+    let severity = if severity_from_dir {
+      dir_severity(&path)
+    } else {
+      None
+    };
+    match parse_rule_file(&path, &yaml, &global_rules, severity, &bases) {
+      Ok((new_configs, new_text_rules)) => {
+        configs.extend(new_configs);
+        text_rules.extend(new_text_rules);
+      }
+      Err(e) => skip_or_fail(e)?,
     }
   }
-  RuleCollection::try_new(configs).context(EC::GlobPattern)
+  let mut collection = RuleCollection::try_new(configs).context(EC::GlobPattern)?;
+  collection.add_text_rules(text_rules);
+  Ok(collection)
+}
+
+/// The severity named by the parent directory of the rule file, e.g. `warning/no-log.yml`.
+fn dir_severity(path: &Path) -> Option<Severity> {
+  let dir = path.parent()?.file_name()?.to_str()?;
+  from_str(dir).ok()
 }
 
 pub fn read_rule_file(
   path: &Path,
   global_rules: Option<&GlobalRules<SupportLang>>,
-) -> Result<Vec<RuleConfig<SupportLang>>> {
+) -> Result<(Vec<RuleConfig<SupportLang>>, Vec<TextRule>)> {
+  let default = GlobalRules::default();
   let yaml = read_to_string(path).with_context(|| EC::ReadRule(path.to_path_buf()))?;
-  let parsed = if let Some(globals) = global_rules {
-    from_yaml_string(&yaml, globals)
-  } else {
-    from_yaml_string(&yaml, &Default::default())
-  };
+  let global_rules = global_rules.unwrap_or(&default);
+  parse_rule_file(path, &yaml, global_rules, None, &ExtendBases::default())
+}
+
+fn parse_rule_file(
+  path: &Path,
+  yaml: &str,
+  global_rules: &GlobalRules<SupportLang>,
+  severity: Option<Severity>,
+  bases: &ExtendBases,
+) -> Result<(Vec<RuleConfig<SupportLang>>, Vec<TextRule>)> {
+  let parsed = from_yaml_string_with_text_rules(yaml, global_rules, severity, bases);
   parsed.with_context(|| EC::ParseRule(path.to_path_buf()))
 }
 
@@ -201,6 +308,19 @@ pub fn read_test_files(
 const CONFIG_FILE: &str = "sgconfig.yml";
 const SNAPSHOT_DIR: &str = "__snapshots__";
 
+/// The directory of the project config, or the current directory if no config is found.
+pub fn find_project_root(config_path: Option<PathBuf>) -> Result<PathBuf> {
+  let config_path = find_config_path_with_default(config_path)?;
+  let cwd = std::env::current_dir()?;
+  let config_path = cwd.join(config_path);
+  Ok(config_path.parent().map_or(cwd, Path::to_path_buf))
+}
+
+/// Whether an sgconfig.yml is found in the current directory or its ancestors.
+pub fn project_config_exists() -> Result<bool> {
+  Ok(find_config_path_with_default(None)?.exists())
+}
+
 fn find_config_path_with_default(config_path: Option<PathBuf>) -> Result<PathBuf> {
   if let Some(config) = config_path {
     return Ok(config);
@@ -219,6 +339,46 @@ fn find_config_path_with_default(config_path: Option<PathBuf>) -> Result<PathBuf
   }
 }
 
+/// Curated rule sets bundled with ast-grep, for a quick start without writing rules.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Preset {
+  /// Leftovers of debugging, e.g. `debugger` statements.
+  Recommended,
+  /// Risky APIs, e.g. `eval` and assigning `innerHTML`.
+  Security,
+}
+
+impl Preset {
+  fn yaml(&self) -> &'static str {
+    match self {
+      Preset::Recommended => include_str!("../presets/recommended.yml"),
+      Preset::Security => include_str!("../presets/security.yml"),
+    }
+  }
+
+  pub fn rules(&self) -> Vec<RuleConfig<SupportLang>> {
+    let default = GlobalRules::default();
+    from_yaml_string_with_severity(self.yaml(), &default, None)
+      .expect("bundled preset must be valid")
+  }
+}
+
+/// Add the rules of presets to the collection.
+/// A rule in the collection overrides the preset rules with the same id.
+pub fn add_presets(configs: &mut RuleCollection<SupportLang>, presets: &[Preset]) -> Result<()> {
+  let ids: HashSet<_> = configs.iter().map(|rule| rule.id.clone()).collect();
+  let rules = presets
+    .iter()
+    .flat_map(Preset::rules)
+    .filter(|rule| !ids.contains(&rule.id))
+    .collect();
+  configs.extend(rules).context(EC::GlobPattern)
+}
+
+/// Layers of ignore files that can be disabled individually.
+/// When several ignore files match a path, the precedence from high to low is:
+/// `.ignore`, `.gitignore`, `.git/info/exclude` and then the global gitignore.
+/// Ignore files in a child directory take precedence over those in its parents.
 #[derive(Clone, Copy, Deserialize, Serialize, ValueEnum)]
 pub enum IgnoreFile {
   /// Search hidden files and directories. By default, hidden files and directories are skipped.
@@ -283,3 +443,208 @@ impl NoIgnore {
     builder
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ast_grep_core::language::Language;
+  use std::fs::{create_dir_all, write};
+
+  fn walked_files(root: &Path, ignores: Vec<IgnoreFile>) -> Vec<String> {
+    let mut files: Vec<_> = NoIgnore::disregard(&ignores)
+      .walk(&[root.to_path_buf()])
+      .build()
+      .filter_map(|e| e.ok())
+      .filter(|e| e.file_type().map_or(false, |t| t.is_file()))
+      .map(|e| e.file_name().to_string_lossy().to_string())
+      .collect();
+    files.sort();
+    files
+  }
+
+  #[test]
+  fn test_presets_are_valid() {
+    for preset in Preset::value_variants() {
+      assert!(!preset.rules().is_empty());
+    }
+  }
+
+  #[test]
+  fn test_explicit_config_path() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let ci = dir.join("ci");
+    create_dir_all(ci.join("rules")).unwrap();
+    create_dir_all(ci.join("utils")).unwrap();
+    write(
+      ci.join("sgconfig.yml"),
+      "ruleDirs: [rules]\nutilDirs: [utils]",
+    )
+    .unwrap();
+    write(
+      ci.join("utils/log.yml"),
+      "id: is-log\nlanguage: TypeScript\nrule: { pattern: console.log($A) }",
+    )
+    .unwrap();
+    write(
+      ci.join("rules/no-log.yml"),
+      "id: no-log\nmessage: no log\nseverity: error\nlanguage: TypeScript\nrule: { matches: is-log }",
+    )
+    .unwrap();
+    let configs = find_config(Some(ci.join("sgconfig.yml")));
+    let configs = configs.expect("should read config outside working directory");
+    let rule = configs.get_rule("no-log").expect("should have rule");
+    let grep = SupportLang::TypeScript.ast_grep("console.log(1)");
+    assert!(grep.root().find(&rule.matcher).is_some());
+    // global utils are inlined like local ones, e.g. for --print-rules
+    let inlined = rule.with_utils_inlined(configs.global_utils());
+    let yaml = serde_yaml::to_string(&inlined.rule).expect("should serialize");
+    assert_eq!(yaml, "pattern: console.log($A)\n");
+  }
+
+  #[test]
+  fn test_language_globs() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    create_dir_all(dir.join("rules")).unwrap();
+    let sg_config =
+      "ruleDirs: [rules]\nlanguageGlobs:\n  js: ['vite.*']\n  ts: ['*.tsunusual', '*.ts']";
+    write(dir.join("sgconfig.yml"), sg_config).unwrap();
+    write(
+      dir.join("rules/no-log.yml"),
+      "id: no-log\nmessage: no log\nseverity: error\nlanguage: TypeScript\nrule: { pattern: console.log($A) }",
+    )
+    .unwrap();
+    let configs = find_config(Some(dir.join("sgconfig.yml")));
+    write(
+      dir.join("sgconfig.yml"),
+      "ruleDirs: [rules]\nlanguageGlobs: { ts: ['a{'] }",
+    )
+    .unwrap();
+    let invalid = find_config(Some(dir.join("sgconfig.yml")));
+    let configs = configs.expect("should read config");
+    let path = Path::new("src/a.tsunusual");
+    let rules = configs.for_path(path);
+    assert_eq!(rules.len(), 1);
+    let lang = configs.get_lang(path).expect("should map by glob");
+    assert_eq!(lang, SupportLang::TypeScript);
+    // the earlier glob wins, and other collections are not affected
+    assert_eq!(
+      configs.get_lang("vite.config.ts"),
+      Some(SupportLang::JavaScript)
+    );
+    assert_eq!(SupportLang::from_path(path), None);
+    let grep = lang.ast_grep("let a: number = 1; console.log(a)");
+    assert!(grep.root().find(&rules[0].matcher).is_some());
+    let error = invalid.err().expect("should fail");
+    assert!(matches!(error.downcast_ref::<EC>(), Some(EC::GlobPattern)));
+  }
+
+  #[test]
+  fn test_severity_from_dir() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    create_dir_all(dir.join("rules/warning")).unwrap();
+    create_dir_all(dir.join("rules/style")).unwrap();
+    write(
+      dir.join("sgconfig.yml"),
+      "ruleDirs: [rules]\nseverityFromDir: true",
+    )
+    .unwrap();
+    let rule =
+      |id: &str| format!("id: {id}\nmessage: test\nlanguage: TypeScript\nrule: {{ pattern: a }}");
+    write(dir.join("rules/warning/a.yml"), rule("implicit")).unwrap();
+    let explicit = format!("{}\nseverity: error", rule("explicit"));
+    write(dir.join("rules/warning/b.yml"), explicit).unwrap();
+    write(dir.join("rules/style/c.yml"), rule("no-severity")).unwrap();
+    let configs = find_config_skip_invalid(Some(dir.join("sgconfig.yml")));
+    let (configs, skipped) = configs.expect("should read config");
+    let severity = |id| &configs.get_rule(id).expect("should have rule").severity;
+    assert!(matches!(severity("implicit"), Severity::Warning));
+    assert!(matches!(severity("explicit"), Severity::Error));
+    // other directory names do not imply a severity
+    assert_eq!(skipped.len(), 1);
+    assert!(configs.get_rule("no-severity").is_none());
+  }
+
+  #[test]
+  fn test_extends_across_files() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    create_dir_all(dir.join("rules")).unwrap();
+    write(dir.join("sgconfig.yml"), "ruleDirs: [rules]").unwrap();
+    let base = "id: no-log\nmessage: no log\nseverity: warning\nlanguage: TypeScript\nrule: { pattern: console.log($A) }";
+    write(dir.join("rules/a.yml"), base).unwrap();
+    write(
+      dir.join("rules/b.yml"),
+      "id: no-log-info\nextends: no-log\nseverity: info",
+    )
+    .unwrap();
+    write(dir.join("rules/c.yml"), "id: c\nextends: d").unwrap();
+    write(dir.join("rules/d.yml"), "id: d\nextends: c").unwrap();
+    let configs = find_config_skip_invalid(Some(dir.join("sgconfig.yml")));
+    let (configs, skipped) = configs.expect("should read config");
+    let rule = configs
+      .get_rule("no-log-info")
+      .expect("should extend rule of other file");
+    assert_eq!(rule.message, "no log");
+    assert!(matches!(rule.severity, Severity::Info));
+    // the cycle through two files is reported for both of them
+    assert_eq!(skipped.len(), 2);
+    assert!(skipped
+      .iter()
+      .all(|e| format!("{e:#}").contains("extends itself")));
+  }
+
+  #[test]
+  fn test_missing_config_path() {
+    let path = PathBuf::from("not/exist/sgconfig.yml");
+    let error = match find_config(Some(path)) {
+      Ok(_) => panic!("missing config should fail"),
+      Err(e) => e,
+    };
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::ReadConfiguration)
+    ));
+    let causes = format!("{error:#}");
+    assert!(causes.contains("not/exist/sgconfig.yml"), "{causes}");
+  }
+
+  #[test]
+  fn test_no_ignore_layers() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let root = dir.join("project");
+    create_dir_all(root.join(".git/info")).unwrap();
+    write(dir.join(".ignore"), "parent.ts").unwrap();
+    write(root.join(".ignore"), "dot.ts").unwrap();
+    write(root.join(".gitignore"), "vcs.ts").unwrap();
+    write(root.join(".git/info/exclude"), "exclude.ts").unwrap();
+    let files = [
+      "a.ts",
+      ".hidden.ts",
+      "parent.ts",
+      "dot.ts",
+      "vcs.ts",
+      "exclude.ts",
+    ];
+    for file in files {
+      write(root.join(file), "").unwrap();
+    }
+    let walk = |ignores| walked_files(&root, ignores);
+    let default = walk(vec![]);
+    let hidden = walk(vec![IgnoreFile::Hidden]);
+    let parent = walk(vec![IgnoreFile::Parent]);
+    let dot = walk(vec![IgnoreFile::Dot]);
+    let vcs = walk(vec![IgnoreFile::Vcs]);
+    let exclude = walk(vec![IgnoreFile::Exclude]);
+    assert_eq!(default, ["a.ts"]);
+    assert!(hidden.contains(&".hidden.ts".to_string()));
+    assert_eq!(parent, ["a.ts", "parent.ts"]);
+    // parent directories also use .ignore files
+    assert_eq!(dot, ["a.ts", "dot.ts", "parent.ts"]);
+    assert_eq!(vcs, ["a.ts", "exclude.ts", "vcs.ts"]);
+    assert_eq!(exclude, ["a.ts", "exclude.ts"]);
+  }
+}
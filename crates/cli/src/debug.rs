@@ -0,0 +1,129 @@
+use crate::error::ErrorContext as EC;
+use anyhow::{Context, Result};
+use ast_grep_core::language::Language;
+use ast_grep_language::SupportLang;
+use clap::Args;
+
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct DebugAstArg {
+  /// The file to parse.
+  #[clap(value_parser)]
+  path: PathBuf,
+
+  /// The language of the file. It is inferred from the file extension if not specified.
+  #[clap(short, long)]
+  lang: Option<SupportLang>,
+
+  /// Only print the smallest named node spanning the byte range, e.g. `10:25`.
+  #[clap(long, value_name = "START:END", value_parser = parse_byte_range)]
+  range: Option<Range<usize>>,
+
+  /// Print the byte range of every named node.
+  #[clap(long)]
+  show_range: bool,
+}
+
+fn parse_byte_range(s: &str) -> Result<Range<usize>, String> {
+  let (start, end) = s
+    .split_once(':')
+    .ok_or_else(|| format!("`{s}` is not in the form of START:END"))?;
+  let start: usize = start.parse().map_err(|e| format!("invalid start: {e}"))?;
+  let end: usize = end.parse().map_err(|e| format!("invalid end: {e}"))?;
+  if start > end {
+    return Err(format!("start {start} is greater than end {end}"));
+  }
+  Ok(start..end)
+}
+
+/// Print the tree-sitter S-expression of a file to help writing rules.
+pub fn run_debug_ast(arg: DebugAstArg) -> Result<()> {
+  let stdout = std::io::stdout();
+  debug_ast(arg, stdout.lock())
+}
+
+fn debug_ast(arg: DebugAstArg, mut output: impl Write) -> Result<()> {
+  let path = &arg.path;
+  let lang = match arg.lang {
+    Some(lang) => lang,
+    None => SupportLang::from_path(path).with_context(|| EC::InferLanguage(path.clone()))?,
+  };
+  let src = std::fs::read_to_string(path).with_context(|| EC::ReadFile(path.clone()))?;
+  let grep = lang.ast_grep(src);
+  let root = grep.root();
+  let node = match arg.range {
+    Some(range) => root
+      .named_descendant_for_range(range)
+      .unwrap_or_else(|| root.clone()),
+    None => root,
+  };
+  if arg.show_range {
+    writeln!(output, "{}", node.to_sexp_with_range())?;
+  } else {
+    writeln!(output, "{}", node.to_sexp())?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn debug_str(src: &str, args: DebugAstArg) -> String {
+    let tmp = crate::utils::temp_dir();
+    let path = tmp.path().join("a.ts");
+    std::fs::write(&path, src).unwrap();
+    let mut output = vec![];
+    let ret = debug_ast(
+      DebugAstArg {
+        path: path.clone(),
+        ..args
+      },
+      &mut output,
+    );
+    ret.expect("should print");
+    String::from_utf8(output).expect("should be utf8")
+  }
+
+  fn make_arg(range: Option<Range<usize>>, show_range: bool) -> DebugAstArg {
+    DebugAstArg {
+      path: PathBuf::new(),
+      lang: None,
+      range,
+      show_range,
+    }
+  }
+
+  #[test]
+  fn test_debug_ast() {
+    let sexp = debug_str("let a = 1", make_arg(None, false));
+    assert!(sexp.starts_with("(program (lexical_declaration"));
+    assert!(sexp.contains("(variable_declarator name: (identifier) value: (number))"));
+    let sexp = debug_str("let a = 1", make_arg(Some(8..9), true));
+    assert_eq!(sexp, "(number [8, 9])\n");
+  }
+
+  #[test]
+  fn test_infer_language() {
+    let arg = DebugAstArg {
+      path: PathBuf::from("unknown.xyz"),
+      ..make_arg(None, false)
+    };
+    let error = debug_ast(arg, vec![]).expect_err("should fail");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::InferLanguage(_))
+    ));
+  }
+
+  #[test]
+  fn test_parse_byte_range() {
+    assert_eq!(parse_byte_range("10:25"), Ok(10..25));
+    assert!(parse_byte_range("10").is_err());
+    assert!(parse_byte_range("a:b").is_err());
+    assert!(parse_byte_range("5:1").is_err());
+  }
+}
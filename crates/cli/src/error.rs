@@ -1,5 +1,7 @@
 use ansi_term::{Color, Style};
 use anyhow::{Error, Result};
+use clap::ValueEnum;
+use serde_json::{json, Value};
 
 use std::fmt;
 use std::path::PathBuf;
@@ -27,8 +29,17 @@ pub enum ErrorContext {
   GlobPattern,
   // Run
   ParsePattern,
+  Cancelled,
   // Scan
   DiagnosticError(usize),
+  /// Error diagnostics of `scan --quiet`, only reported by the exit code.
+  QuietDiagnosticError(usize),
+  InvalidRulesSkipped(usize),
+  NonIdempotentFix(usize),
+  AtomicFixAborted(PathBuf),
+  RuleNotFound(String),
+  RuleDisabled(String),
+  GitRef(String),
   // LSP
   StartLanguageServer,
   // Edit
@@ -36,17 +47,22 @@ pub enum ErrorContext {
   WriteFile(PathBuf),
   // Test
   TestFail(String),
+  // Debug
+  ReadFile(PathBuf),
+  InferLanguage(PathBuf),
 }
 
 impl ErrorContext {
   fn exit_code(&self) -> i32 {
     use ErrorContext::*;
     match self {
-      ReadConfiguration | ReadRule(_) | WalkRuleDir(_) => 2,
+      ReadConfiguration | ReadRule(_) | WalkRuleDir(_) | ReadFile(_) => 2,
       TestFail(_) => 3,
       ParseTest(_) | ParseRule(_) | ParseConfiguration => 5,
+      InvalidRulesSkipped(_) => 6,
       OpenEditor => 126,
-      DiagnosticError(_) => 1,
+      Cancelled => 130,
+      DiagnosticError(_) | QuietDiagnosticError(_) => 1,
       _ => 1,
     }
   }
@@ -84,7 +100,7 @@ impl ErrorMessage {
     match ctx {
       ReadConfiguration => Self::new(
         "Cannot read configuration.",
-        "Please add an sgconfig.yml configuration file in the project root to run the scan command.",
+        "Please add an sgconfig.yml configuration file in the project root, or pass its path by --config.",
         CONFIG_GUIDE,
       ),
       ParseConfiguration => Self::new(
@@ -109,7 +125,7 @@ impl ErrorMessage {
       ),
       GlobPattern => Self::new(
         "Cannot parse glob pattern in config",
-        "The pattern in files/ignore is not a valid glob. Please refer to doc and fix the error.",
+        "The pattern in files/ignore or languageGlobs is not a valid glob. Please refer to doc and fix the error.",
         CONFIG_GUIDE,
       ),
       ParseTest(file) => Self::new(
@@ -117,16 +133,51 @@ impl ErrorMessage {
         "The file is not a valid ast-grep test case. Please refer to doc and fix the error.",
         TEST_GUIDE,
       ),
-      DiagnosticError(num) => Self::new(
+      DiagnosticError(num) | QuietDiagnosticError(num) => Self::new(
         format!("{num} error(s) found in code."),
         "Scan succeeded and found error level diagnostics in the codebase.",
         None,
       ),
+      InvalidRulesSkipped(num) => Self::new(
+        format!("{num} invalid rule file(s) skipped."),
+        "Scan finished with the valid rules. Fix the invalid rules or remove --strict to allow skipping.",
+        CONFIG_GUIDE,
+      ),
+      AtomicFixAborted(file) => Self::new(
+        format!("Cannot fix {}. No file is modified.", file.display()),
+        "With --atomic-fixes, fixes are written only if every file is fixed successfully. Please resolve the error and run again.",
+        CLI_USAGE,
+      ),
+      NonIdempotentFix(num) => Self::new(
+        format!("{num} fix(es) do not converge."),
+        "Applying the fix either leaves findings of its rule or changes the file again. Please check the fix of the reported rules.",
+        CONFIG_GUIDE,
+      ),
       ParsePattern => Self::new(
         "Cannot parse query as a valid pattern",
         "The pattern either fails to parse or contains error. Please refer to pattern syntax guide.",
         PATTERN_GUIDE,
       ),
+      Cancelled => Self::new(
+        "Operation is cancelled.",
+        "Files after the cancellation were skipped so the output is incomplete.",
+        None,
+      ),
+      RuleNotFound(id) => Self::new(
+        format!("Cannot find rule {id}."),
+        "No rule in the configuration has the id. Use --print-rules to list all rules.",
+        CONFIG_GUIDE,
+      ),
+      RuleDisabled(id) => Self::new(
+        format!("Rule {id} is disabled."),
+        "The rule has `severity: off` so it is not loaded. Please change its severity to use it.",
+        CONFIG_GUIDE,
+      ),
+      GitRef(git_ref) => Self::new(
+        format!("Cannot resolve git ref {git_ref}."),
+        "The base of --baseline-diff must be a commit in the repository of the scanned paths. Please check the ref or fetch it.",
+        CLI_USAGE,
+      ),
       StartLanguageServer => Self::new(
         "Cannot start language server.",
         "Please see language server logging file.",
@@ -147,13 +198,44 @@ impl ErrorMessage {
         "You can use ast-grep playground to debug your rules and test cases.",
         PLAYGROUND,
       ),
+      ReadFile(file) => Self::new(
+        format!("Cannot read file {}", file.display()),
+        "The file either does not exist or cannot be opened.",
+        None,
+      ),
+      InferLanguage(file) => Self::new(
+        format!("Cannot infer language of {}", file.display()),
+        "The file extension is not supported. Please specify the language with --lang.",
+        None,
+      ),
     }
   }
 }
 
-pub fn exit_with_error(error: Error) -> Result<()> {
+/// How errors are reported on stderr.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ErrorOutput {
+  /// Colored message with help text for humans.
+  #[default]
+  Pretty,
+  /// One JSON object for tools to tell error categories apart.
+  Json,
+}
+
+pub fn exit_with_error(error: Error, output: ErrorOutput) -> Result<()> {
   if let Some(e) = error.downcast_ref::<clap::Error>() {
-    e.exit()
+    // help and version are not errors and are printed as usual
+    if matches!(output, ErrorOutput::Pretty) || !e.use_stderr() {
+      e.exit()
+    }
+  }
+  if let Some(e @ ErrorContext::QuietDiagnosticError(_)) = error.downcast_ref() {
+    std::process::exit(e.exit_code())
+  }
+  if let ErrorOutput::Json = output {
+    let json = error_json(&error);
+    eprintln!("{json}");
+    std::process::exit(json["exitCode"].as_i64().unwrap_or(1) as i32)
   }
   if let Some(e) = error.downcast_ref::<ErrorContext>() {
     let error_fmt = ErrorFormat {
@@ -167,11 +249,65 @@ pub fn exit_with_error(error: Error) -> Result<()> {
   Err(error)
 }
 
+/// Any error as one JSON object. Errors without an [`ErrorContext`] have their own message
+/// as the title, e.g. invalid arguments or a plain error from a dependency.
+fn error_json(error: &Error) -> Value {
+  if let Some(context) = error.downcast_ref::<ErrorContext>() {
+    let error_fmt = ErrorFormat {
+      context,
+      inner: error,
+    };
+    return error_fmt.to_json();
+  }
+  if let Some(e) = error.downcast_ref::<clap::Error>() {
+    return json!({
+      "title": e.kind().as_str().unwrap_or("Cannot parse arguments."),
+      "description": e.to_string().trim(),
+      "link": Value::Null,
+      // clap exits with 2 on usage errors
+      "exitCode": 2,
+      "causes": Vec::<String>::new(),
+    });
+  }
+  let causes: Vec<_> = error.chain().skip(1).map(|e| e.to_string()).collect();
+  json!({
+    "title": error.to_string(),
+    "description": "",
+    "link": Value::Null,
+    "exitCode": 1,
+    "causes": causes,
+  })
+}
+
+/// Report an error without exiting, e.g. for a rule file skipped in scanning.
+pub fn print_warning(error: &Error) {
+  let warning = Color::Yellow.paint("Warning:");
+  eprintln!("{warning} {error:#}");
+}
+
 struct ErrorFormat<'a> {
   context: &'a ErrorContext,
   inner: &'a Error,
 }
 
+impl<'a> ErrorFormat<'a> {
+  fn to_json(&self) -> Value {
+    let ErrorMessage {
+      title,
+      description,
+      link,
+    } = ErrorMessage::from_context(self.context);
+    let causes: Vec<_> = self.inner.chain().skip(1).map(|e| e.to_string()).collect();
+    json!({
+      "title": title,
+      "description": description,
+      "link": link.map(|url| format!("{DOC_SITE_HOST}{url}")),
+      "exitCode": self.context.exit_code(),
+      "causes": causes,
+    })
+  }
+}
+
 impl<'a> fmt::Display for ErrorFormat<'a> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     let ErrorMessage {
@@ -231,6 +367,52 @@ mod test {
     assert!(display.contains("test error"));
   }
 
+  #[test]
+  fn test_json_error() {
+    let error = anyhow::anyhow!("test error").context(ErrorContext::ReadConfiguration);
+    let error_fmt = ErrorFormat {
+      context: &ErrorContext::ReadConfiguration,
+      inner: &error,
+    };
+    let json = error_fmt.to_json();
+    assert_eq!(json["title"], "Cannot read configuration.");
+    assert!(json["description"]
+      .as_str()
+      .unwrap()
+      .contains("sgconfig.yml"));
+    assert_eq!(
+      json["link"],
+      "https://ast-grep.github.io/guide/rule-config.html"
+    );
+    assert_eq!(json["exitCode"], 2);
+    assert_eq!(json["causes"], json!(["test error"]));
+    // single line output
+    assert!(!json.to_string().contains('\n'));
+  }
+
+  #[test]
+  fn test_json_plain_error() {
+    let error = anyhow::anyhow!("inner error").context("outer error");
+    let json = error_json(&error);
+    assert_eq!(json["title"], "outer error");
+    assert_eq!(json["link"], Value::Null);
+    assert_eq!(json["exitCode"], 1);
+    assert_eq!(json["causes"], json!(["inner error"]));
+    let error = anyhow::anyhow!("test error").context(ErrorContext::ReadConfiguration);
+    assert_eq!(error_json(&error)["title"], "Cannot read configuration.");
+  }
+
+  #[test]
+  fn test_json_clap_error() {
+    use clap::CommandFactory;
+    let error = crate::App::command()
+      .try_get_matches_from(["sg", "--unknown"])
+      .expect_err("should fail");
+    let json = error_json(&error.into());
+    assert_eq!(json["exitCode"], 2);
+    assert!(json["description"].as_str().unwrap().contains("--unknown"));
+  }
+
   #[test]
   fn test_bare_anyhow() {
     let error = anyhow::anyhow!(ErrorContext::ReadConfiguration);
@@ -246,4 +428,24 @@ mod test {
       "Should not contain error chain"
     );
   }
+
+  #[test]
+  fn test_display_kind_suggestion() {
+    use ast_grep_config::{from_yaml_string, GlobalRules};
+    use ast_grep_language::SupportLang;
+    let yaml =
+      "id: test\nmessage: test\nseverity: hint\nlanguage: TypeScript\nrule:\n  kind: function_decl";
+    let error = from_yaml_string::<SupportLang>(yaml, &GlobalRules::default())
+      .err()
+      .expect("kind should be invalid");
+    let context = ErrorContext::ParseRule(PathBuf::from("test.yml"));
+    let error = anyhow::Error::from(error).context(context.clone());
+    let error_fmt = ErrorFormat {
+      context: &context,
+      inner: &error,
+    };
+    let display = format!("{error_fmt}");
+    assert!(display.contains("Cannot parse rule test.yml"));
+    assert!(display.contains("Did you mean: `function_declaration`"));
+  }
 }
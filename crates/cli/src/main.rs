@@ -1,4 +1,8 @@
+mod archive;
+mod baseline;
+mod cache;
 mod config;
+mod debug;
 mod error;
 mod lsp;
 mod print;
@@ -6,11 +10,13 @@ mod run;
 mod scan;
 mod utils;
 mod verify;
+mod watch;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 
-use error::exit_with_error;
+use debug::{run_debug_ast, DebugAstArg};
+use error::{exit_with_error, ErrorOutput};
 use run::{run_with_pattern, RunArg};
 use scan::{run_with_config, ScanArg};
 use verify::{run_test_rule, TestArg};
@@ -34,6 +40,20 @@ Search and Rewrite code at large scale using AST pattern.
 struct App {
   #[clap(subcommand)]
   command: Commands,
+
+  /// How errors are reported on stderr. `json` prints one JSON object with the title,
+  /// description, link, exit code and causes of the error.
+  #[clap(long, global = true, default_value = "pretty")]
+  error_format: ErrorOutput,
+}
+
+/// Arguments of `run` given without the subcommand, e.g. `sg -p PATTERN`.
+#[derive(Parser)]
+struct DefaultRun {
+  #[clap(flatten)]
+  run: RunArg,
+  #[clap(long, default_value = "pretty")]
+  error_format: ErrorOutput,
 }
 
 #[derive(Subcommand)]
@@ -46,24 +66,47 @@ enum Commands {
   Test(TestArg),
   /// starts language server
   Lsp,
+  /// Print the tree-sitter S-expression of a file to help writing rules.
+  DebugAst(DebugAstArg),
   /// generate rule docs for current configuration
   Docs,
 }
 
 fn main() -> Result<()> {
-  match main_with_args(std::env::args()) {
-    Err(error) => exit_with_error(error),
+  let args: Vec<_> = std::env::args().collect();
+  match main_with_args(args.clone().into_iter()) {
+    Err(error) => exit_with_error(error, error_output(&args)),
     ok => ok,
   }
 }
 
-fn try_default_run(args: &[String]) -> Result<Option<RunArg>> {
+/// The error format of the parsed arguments. If the arguments are invalid,
+/// e.g. for a clap error, a literal `--error-format json` is looked for instead.
+fn error_output(args: &[String]) -> ErrorOutput {
+  if let Ok(Some(arg)) = try_default_run(args) {
+    return arg.error_format;
+  }
+  if let Ok(app) = App::try_parse_from(args) {
+    return app.error_format;
+  }
+  let json = args.iter().any(|a| a == "--error-format=json")
+    || args
+      .windows(2)
+      .any(|w| w[0] == "--error-format" && w[1] == "json");
+  if json {
+    ErrorOutput::Json
+  } else {
+    ErrorOutput::default()
+  }
+}
+
+fn try_default_run(args: &[String]) -> Result<Option<DefaultRun>> {
   // use `run` if there is at lease one pattern arg with no user provided command
   let should_use_default_run_command =
     args.iter().skip(1).any(|p| p == "-p" || p == "--pattern") && args[1].starts_with('-');
   if should_use_default_run_command {
     // handle no subcommand
-    let arg = RunArg::try_parse_from(args)?;
+    let arg = DefaultRun::try_parse_from(args)?;
     Ok(Some(arg))
   } else {
     Ok(None)
@@ -74,7 +117,7 @@ fn try_default_run(args: &[String]) -> Result<Option<RunArg>> {
 fn main_with_args(args: impl Iterator<Item = String>) -> Result<()> {
   let args: Vec<_> = args.collect();
   if let Some(arg) = try_default_run(&args)? {
-    return run_with_pattern(arg);
+    return run_with_pattern(arg.run);
   }
   let app = App::try_parse_from(args)?;
   // TODO: add test for app parse
@@ -83,6 +126,7 @@ fn main_with_args(args: impl Iterator<Item = String>) -> Result<()> {
     Commands::Scan(arg) => run_with_config(arg),
     Commands::Test(arg) => run_test_rule(arg),
     Commands::Lsp => lsp::run_language_server(),
+    Commands::DebugAst(arg) => run_debug_ast(arg),
     Commands::Docs => todo!("todo, generate rule docs based on current config"),
   }
 }
@@ -138,6 +182,24 @@ mod test_cli {
     default_run("-p Some($A) -l rs");
     default_run("-p Some($A)");
     default_run("-p Some($A) -l rs -r $A.unwrap()");
+    default_run("-p Some($A) --error-format json");
+  }
+
+  #[test]
+  fn test_error_output() {
+    let is_json = |args: &str| {
+      let args: Vec<_> = std::iter::once("sg".into())
+        .chain(args.split(' ').map(|s| s.to_string()))
+        .collect();
+      matches!(error_output(&args), ErrorOutput::Json)
+    };
+    assert!(is_json("-p Some($A) --error-format json"));
+    assert!(!is_json("-p Some($A)"));
+    assert!(is_json("scan --error-format json"));
+    // invalid arguments still report in JSON
+    assert!(is_json("-p Some($A) --bad --error-format json"));
+    assert!(is_json("scan --bad --error-format=json"));
+    assert!(!is_json("scan --bad"));
   }
 
   #[test]
@@ -149,11 +211,45 @@ mod test_cli {
     ok("run -p test -l rs --color always");
     ok("run -p test -l rs --heading always");
     ok("run -p test dir1 dir2 dir3"); // multiple paths
+    ok("run -p test -r Test --rewrite-stdout --print-path");
+    ok("run -p test --rewrite-file fix.txt --rewrite-stdout");
+    ok("run -p test -l ts --files-without-match dir");
+    ok("run -p test --sort path dir");
+    ok("run -p test --sort first-match --json");
+    ok("run --pattern-file query.txt --rewrite-file fix.txt dir");
     error("run test");
     error("run --debug-query test"); // missing lang
     error("run -r Test dir");
     error("run -p test -i --json dir"); // conflict
     error("run -p test -l rs -c always"); // no color shortcut
+    error("run -p test --rewrite-stdout"); // missing rewrite
+    error("run -p test -r Test --print-path"); // missing rewrite-stdout
+    error("run -p test -r Test --rewrite-stdout -i"); // conflict
+    error("run -p test -r Test --files-without-match"); // conflict
+    error("run -p test -r Test --rewrite-file fix.txt"); // conflict
+    error("run -p test --rewrite-file fix.txt --files-without-match"); // conflict
+    error("run -p test --sort name"); // unknown key
+    error("run -p test --pattern-file query.txt"); // conflict
+    error("run -p test --sort matches --files-without-match"); // conflict
+    ok("run -p test --json --cbor");
+    error("run -p test --cbor"); // requires json
+    ok("run -p test --format-template {path}:{line}:{col}:{var:A}");
+    error("run -p test --format-template {file}"); // unknown placeholder
+    error("run -p test --format-template {match} --json"); // conflict
+    ok("run -p test --path-style absolute --json");
+    ok("run -p test --path-style relative-to-cwd --files-without-match");
+    error("run -p test --path-style home"); // unknown style
+    error("run -p test --path-style relative -i"); // conflict
+    ok("run -p test -r Test --accept-all --atomic-fixes");
+    error("run -p test -r Test --atomic-fixes"); // requires accept-all
+    ok("run -p test -r Test --preserve-trailing-comma");
+    error("run -p test --preserve-trailing-comma"); // requires rewrite
+    ok("run -p test --after-context-rule function_declaration");
+    error("run -p test --after-context-rule function_declaration --json"); // conflict
+    ok("run --list-files -l ts dir");
+    ok("run -p test --list-files");
+    error("run --list-files -r Test"); // conflict
+    error("run --list-files --files-without-match"); // conflict
   }
 
   #[test]
@@ -165,8 +261,91 @@ mod test_cli {
     ok("scan -c test-rule.yml");
     ok("scan --report-style short"); // conflict
     ok("scan dir1 dir2 dir3"); // multiple paths
+    ok("scan -r test-rule.yml --print-rules");
+    ok("scan --skip-invalid-rules --strict");
+    ok("scan -r test-rule.yml --explain dir");
+    ok("scan --watch dir");
+    ok("scan --watch --json dir");
     error("scan -i --json dir"); // conflict
+    error("scan --explain --json dir"); // conflict
+    error("scan --watch -i dir"); // conflict
     error("scan --report-style rich --json dir"); // conflict
     error("scan -r test.yml -c test.yml --json dir"); // conflict
+    ok("scan --format github dir");
+    error("scan --format github --json dir"); // conflict
+    error("scan --format github --report-style short dir"); // conflict
+    error("scan --format gitlab dir"); // unknown platform
+    ok("scan --max-matches-per-file 10 --json dir");
+    ok("debug-ast test.ts");
+    ok("debug-ast test.rs -l rust --range 10:20 --show-range");
+    error("debug-ast"); // missing path
+    error("debug-ast test.ts --range 10"); // malformed range
+    error("scan --max-matches-per-file many dir"); // not a number
+    error("scan --max-matches-per-file 1 --explain dir"); // conflict
+    ok("scan --fix-rule a --fix-rule b -i");
+    ok("scan -i --fix");
+    error("scan --fix"); // requires interactive
+    error("scan --fix-rule a --explain"); // conflict
+    ok("scan --parse-only -r test-rule.yml dir");
+    error("scan --parse-only --json"); // conflict
+    error("scan --parse-only --explain"); // conflict
+    ok("scan --idempotent-check --fix-rule a dir");
+    error("scan --idempotent-check -i"); // conflict
+    error("scan --idempotent-check --parse-only"); // conflict
+    ok("scan --format-template {path}:{line}:{rule} dir");
+    error("scan --format-template {path dir"); // malformed template
+    error("scan --format-template {rule} --json"); // conflict
+    ok("scan --json --cbor dir");
+    error("scan --cbor dir"); // requires json
+    ok("scan --error-format json dir");
+    ok("--error-format json scan dir");
+    error("scan --error-format xml dir"); // unknown format
+    ok("scan --path-style relative --format github dir");
+    error("scan --path-style absolute --accept-all"); // conflict
+    ok("scan --accept-all --atomic-fixes dir");
+    error("scan -i --atomic-fixes dir"); // requires accept-all
+    error("scan --print-rules -i"); // conflict
+    error("scan --strict"); // requires skip-invalid-rules
+    error("scan -r test-rule.yml --skip-invalid-rules"); // conflict
+    ok("scan --quiet");
+    ok("scan -q --skip-invalid-rules --strict");
+    error("scan -q --json"); // conflict
+    error("scan --quiet -i"); // conflict
+    ok("scan --explain-rule no-log");
+    error("scan --explain-rule no-log --print-rules"); // conflict
+    ok("scan --cache-dir .sg-cache");
+    error("scan --cache-dir .sg-cache --explain"); // conflict
+    ok("scan --archive build.tar.gz");
+    error("scan --archive build.tar.gz --interactive"); // conflict
+    error("scan --archive build.tar.gz --cache-dir .sg-cache"); // conflict
+    ok("scan --list-files -c sgconfig.yml dir");
+    error("scan --list-files --json"); // conflict
+    error("scan --list-files --parse-only"); // conflict
+    ok("scan --preset recommended --preset security dir");
+    ok("scan -r test-rule.yml --preset security");
+    error("scan --preset strictest"); // unknown preset
+    ok("scan --tab-width 4 --format-template {path}:{col}");
+    error("scan --tab-width 0"); // zero width
+    error("scan --tab-width 4 --json"); // conflict
+    ok("run -p test --tab-width 8 --format-template {col}");
+    error("run -p test --tab-width 8"); // requires format-template
+    ok("run -p test --count-by $A");
+    error("run -p test --count-by $A --json"); // conflict
+    ok("scan --diff-context 10 -i");
+    error("scan --diff-context many"); // not a number
+    error("scan --diff-context 1 --json"); // conflict
+    ok("scan --min-severity warning --json");
+    error("scan --min-severity fatal");
+    error("scan --min-severity off");
+    error("scan --min-severity error -q");
+    ok("scan --group-by severity --min-severity warning");
+    error("scan --group-by rule --json"); // conflict
+    error("scan --group-by path");
+    ok("scan --fail-fast --min-severity error --json");
+    error("scan --fail-fast -i"); // conflict
+    ok("scan --report-slow-rules 5 --json");
+    error("scan --report-slow-rules");
+    ok("scan --baseline-diff origin/main --json");
+    error("scan --baseline-diff main --fail-fast"); // conflict
   }
 }
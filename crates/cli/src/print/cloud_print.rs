@@ -0,0 +1,230 @@
+use ast_grep_config::{RuleConfig, Severity, TextMatch, TextRule};
+use ast_grep_core::NodeMatch;
+use ast_grep_language::SupportLang;
+
+use super::{Diff, Printer};
+use anyhow::Result;
+use clap::ValueEnum;
+pub use codespan_reporting::files::SimpleFile;
+
+use std::borrow::Cow;
+use std::io::{Stdout, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// add this macro because neither trait_alias nor type_alias_impl is supported.
+macro_rules! Matches {
+  ($lt: lifetime) => { impl Iterator<Item = NodeMatch<$lt, SupportLang>> };
+}
+macro_rules! Diffs {
+  ($lt: lifetime) => { impl Iterator<Item = Diff<$lt>> };
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Platform {
+  /// Output workflow commands that GitHub Actions renders as annotations.
+  Github,
+}
+
+/// Print rule matches as annotations understood by CI platforms.
+/// Only rule based output is supported since annotations need a severity and a message.
+pub struct CloudPrinter<W: Write> {
+  output: Mutex<W>,
+  platform: Platform,
+}
+
+impl CloudPrinter<Stdout> {
+  pub fn stdout(platform: Platform) -> Self {
+    Self::new(std::io::stdout(), platform)
+  }
+}
+
+impl<W: Write> CloudPrinter<W> {
+  pub fn new(output: W, platform: Platform) -> Self {
+    Self {
+      output: Mutex::new(output),
+      platform,
+    }
+  }
+
+  fn print_annotations<'a>(
+    &self,
+    matches: Matches!('a),
+    path: &str,
+    rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
+  ) -> Result<()> {
+    let annotations = matches.map(|nm| Annotation {
+      start: nm.start_pos(),
+      end: nm.end_pos(),
+      message: rule.get_message(&nm),
+    });
+    self.print_annotation_lines(annotations, path, &rule.id, severity)
+  }
+
+  fn print_annotation_lines(
+    &self,
+    annotations: impl Iterator<Item = Annotation>,
+    path: &str,
+    id: &str,
+    severity: &Severity,
+  ) -> Result<()> {
+    let Platform::Github = self.platform;
+    let level = match severity {
+      Severity::Error => "error",
+      Severity::Warning => "warning",
+      Severity::Info | Severity::Hint => "notice",
+      Severity::Off => unreachable!("turned-off rule should not have match"),
+    };
+    let file = escape_property(path);
+    let title = escape_property(id);
+    let mut lock = self.output.lock().expect("should work");
+    for annotation in annotations {
+      let (start_line, start_col) = annotation.start;
+      let (end_line, end_col) = annotation.end;
+      let message = escape_data(&annotation.message);
+      writeln!(
+        &mut lock,
+        "::{level} file={file},line={},col={},endLine={},endColumn={},title={title}::{message}",
+        start_line + 1,
+        start_col + 1,
+        end_line + 1,
+        end_col + 1,
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Zero-based position and message of one annotation.
+struct Annotation {
+  start: (usize, usize),
+  end: (usize, usize),
+  message: String,
+}
+
+/// Escape the message of a GitHub workflow command.
+fn escape_data(s: &str) -> String {
+  s.replace('%', "%25")
+    .replace('\r', "%0D")
+    .replace('\n', "%0A")
+}
+
+/// Escape a property value of a GitHub workflow command, e.g. file or title.
+fn escape_property(s: &str) -> String {
+  escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+impl<W: Write> Printer for CloudPrinter<W> {
+  fn print_rule<'a>(
+    &self,
+    matches: Matches!('a),
+    file: SimpleFile<Cow<str>, &String>,
+    rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
+  ) -> Result<()> {
+    self.print_annotations(matches, file.name(), rule, severity)
+  }
+
+  fn print_text_rule(
+    &self,
+    matches: Vec<TextMatch>,
+    file: SimpleFile<Cow<str>, &String>,
+    rule: &TextRule,
+  ) -> Result<()> {
+    let annotations = matches.into_iter().map(|m| Annotation {
+      start: m.start_pos(),
+      end: m.end_pos(),
+      message: rule.message.clone(),
+    });
+    self.print_annotation_lines(annotations, file.name(), &rule.id, &rule.severity)
+  }
+
+  fn print_matches<'a>(&self, _matches: Matches!('a), _path: &Path) -> Result<()> {
+    Ok(())
+  }
+
+  fn print_diffs<'a>(&self, _diffs: Diffs!('a), _path: &Path) -> Result<()> {
+    Ok(())
+  }
+
+  fn print_rule_diffs<'a>(
+    &self,
+    diffs: Diffs!('a),
+    path: &Path,
+    rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
+  ) -> Result<()> {
+    let matches = diffs.map(|d| d.node_match);
+    self.print_annotations(matches, &path.to_string_lossy(), rule, severity)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ast_grep_config::{from_yaml_string, GlobalRules};
+  use ast_grep_core::language::Language;
+
+  fn get_text(printer: CloudPrinter<Vec<u8>>) -> String {
+    let buffer = printer.output.into_inner().expect("should work");
+    String::from_utf8(buffer).expect("should be valid utf8")
+  }
+
+  fn make_rule(severity: &str, message: &str) -> RuleConfig<SupportLang> {
+    let yaml = format!(
+      "id: no-log\nmessage: {message}\nseverity: {severity}\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)"
+    );
+    let globals = GlobalRules::default();
+    let mut rules = from_yaml_string(&yaml, &globals).expect("should parse");
+    rules.pop().unwrap()
+  }
+
+  fn print_github(rule: &RuleConfig<SupportLang>, src: &str) -> String {
+    let printer = CloudPrinter::new(vec![], Platform::Github);
+    let src = src.to_string();
+    let grep = SupportLang::TypeScript.ast_grep(&src);
+    let matches = grep.root().find_all(&rule.matcher);
+    let file = SimpleFile::new(Cow::Borrowed("src/test.ts"), &src);
+    printer
+      .print_rule(matches, file, rule, &rule.severity)
+      .unwrap();
+    get_text(printer)
+  }
+
+  #[test]
+  fn test_github_annotation() {
+    let rule = make_rule("error", "No console.log");
+    let text = print_github(&rule, "let a = 1\n  console.log(a)");
+    assert_eq!(
+      text,
+      "::error file=src/test.ts,line=2,col=3,endLine=2,endColumn=17,title=no-log::No console.log\n"
+    );
+  }
+
+  #[test]
+  fn test_github_severity() {
+    let cases = [
+      ("error", "error"),
+      ("warning", "warning"),
+      ("info", "notice"),
+      ("hint", "notice"),
+    ];
+    for (severity, level) in cases {
+      let rule = make_rule(severity, "test");
+      let text = print_github(&rule, "console.log(1)");
+      assert!(text.starts_with(&format!("::{level} file=")), "{text}");
+    }
+  }
+
+  #[test]
+  fn test_github_escape() {
+    let rule = make_rule("warning", "\"100% bad\\nuse $A: logger\"");
+    let text = print_github(&rule, "console.log(a)");
+    assert_eq!(
+      text,
+      "::warning file=src/test.ts,line=1,col=1,endLine=1,endColumn=15,title=no-log::100%25 bad%0Ause a: logger\n"
+    );
+    assert_eq!(escape_property("a:b,c%"), "a%3Ab%2Cc%25");
+  }
+}
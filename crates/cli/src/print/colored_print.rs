@@ -1,5 +1,6 @@
-use super::{Diff, Printer};
-use ast_grep_config::{RuleConfig, Severity};
+use super::{apply_rewrite, display_column, Diff, Printer};
+use ast_grep_config::{RuleConfig, Severity, TextMatch, TextRule};
+use ast_grep_core::meta_var::MetaVariable;
 use ast_grep_core::NodeMatch;
 use ast_grep_language::SupportLang;
 
@@ -7,6 +8,7 @@ use ansi_term::{Color, Style};
 use anyhow::Result;
 use clap::ValueEnum;
 use codespan_reporting::diagnostic::{self, Diagnostic, Label};
+use codespan_reporting::files::{self, Files};
 use codespan_reporting::term::termcolor::{ColorChoice, StandardStream, WriteColor};
 use codespan_reporting::term::{self, DisplayStyle};
 pub use codespan_reporting::{files::SimpleFile, term::ColorArg};
@@ -15,6 +17,8 @@ use similar::{ChangeTag, DiffOp, TextDiff};
 use std::borrow::Cow;
 use std::fmt::Display;
 use std::io::Write;
+use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::path::Path;
 use std::sync::Mutex;
 
@@ -62,6 +66,9 @@ pub struct ColoredPrinter<W: WriteColor> {
   config: term::Config,
   styles: PrintStyles,
   heading: Heading,
+  context_kind: Option<String>,
+  tab_width: Option<NonZeroUsize>,
+  diff_context: usize,
 }
 impl ColoredPrinter<StandardStream> {
   pub fn stdout<C: Into<ColorChoice>>(color: C) -> Self {
@@ -77,6 +84,9 @@ impl<W: WriteColor> ColoredPrinter<W> {
       styles: PrintStyles::from(ColorChoice::Auto),
       config: term::Config::default(),
       heading: Heading::Auto,
+      context_kind: None,
+      tab_width: None,
+      diff_context: DEFAULT_DIFF_CONTEXT,
     }
   }
 
@@ -100,6 +110,73 @@ impl<W: WriteColor> ColoredPrinter<W> {
     self.heading = heading;
     self
   }
+
+  /// Print the first line of the nearest ancestor of the kind before the matches inside it.
+  pub fn context_kind(mut self, kind: Option<String>) -> Self {
+    self.context_kind = kind;
+    self
+  }
+
+  /// Expand tabs to the width in reported columns and rendered source.
+  /// Only the presentation changes, byte offsets are unaffected.
+  pub fn tab_width(mut self, tab_width: Option<NonZeroUsize>) -> Self {
+    if let Some(width) = tab_width {
+      self.config.tab_width = width.get();
+    }
+    self.tab_width = tab_width;
+    self
+  }
+
+  /// Number of unchanged lines around each change in fix diffs, like `diff -U`.
+  pub fn diff_context(mut self, lines: usize) -> Self {
+    self.diff_context = lines;
+    self
+  }
+}
+
+/// A file reporting columns with tabs expanded, as editors display them.
+struct TabExpandedFile<F> {
+  file: F,
+  tab_width: Option<NonZeroUsize>,
+}
+
+impl<'a, F: Files<'a>> Files<'a> for TabExpandedFile<F> {
+  type FileId = F::FileId;
+  type Name = F::Name;
+  type Source = F::Source;
+
+  fn name(&'a self, id: Self::FileId) -> Result<Self::Name, files::Error> {
+    self.file.name(id)
+  }
+
+  fn source(&'a self, id: Self::FileId) -> Result<Self::Source, files::Error> {
+    self.file.source(id)
+  }
+
+  fn line_index(&'a self, id: Self::FileId, byte_index: usize) -> Result<usize, files::Error> {
+    self.file.line_index(id, byte_index)
+  }
+
+  fn line_range(
+    &'a self,
+    id: Self::FileId,
+    line_index: usize,
+  ) -> Result<Range<usize>, files::Error> {
+    self.file.line_range(id, line_index)
+  }
+
+  fn column_number(
+    &'a self,
+    id: Self::FileId,
+    line_index: usize,
+    byte_index: usize,
+  ) -> Result<usize, files::Error> {
+    let Some(tab_width) = self.tab_width else {
+      return self.file.column_number(id, line_index, byte_index);
+    };
+    let source = self.file.source(id)?;
+    Ok(display_column(source.as_ref(), byte_index, tab_width) + 1)
+  }
 }
 
 impl<W: WriteColor> Printer for ColoredPrinter<W> {
@@ -108,15 +185,15 @@ impl<W: WriteColor> Printer for ColoredPrinter<W> {
     matches: Matches!('a),
     file: SimpleFile<Cow<str>, &String>,
     rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
   ) -> Result<()> {
     let config = &self.config;
     let mut writer = self.writer.lock().expect("should not fail");
-    let serverity = match rule.severity {
-      Severity::Error => diagnostic::Severity::Error,
-      Severity::Warning => diagnostic::Severity::Warning,
-      Severity::Info => diagnostic::Severity::Note,
-      Severity::Hint => diagnostic::Severity::Help,
+    let file = TabExpandedFile {
+      file,
+      tab_width: self.tab_width,
     };
+    let serverity = diagnostic_severity(severity);
     for m in matches {
       let range = m.range();
       let mut labels = vec![Label::primary((), range)];
@@ -129,35 +206,68 @@ impl<W: WriteColor> Printer for ColoredPrinter<W> {
       let diagnostic = Diagnostic::new(serverity)
         .with_code(&rule.id)
         .with_message(rule.get_message(&m))
-        .with_notes(rule.note.iter().cloned().collect())
+        .with_notes(rule.get_note(&m).into_iter().collect())
         .with_labels(labels);
       term::emit(&mut *writer, config, &file, &diagnostic)?;
     }
     Ok(())
   }
 
+  fn print_text_rule(
+    &self,
+    matches: Vec<TextMatch>,
+    file: SimpleFile<Cow<str>, &String>,
+    rule: &TextRule,
+  ) -> Result<()> {
+    let config = &self.config;
+    let mut writer = self.writer.lock().expect("should not fail");
+    let file = TabExpandedFile {
+      file,
+      tab_width: self.tab_width,
+    };
+    let serverity = diagnostic_severity(&rule.severity);
+    for m in matches {
+      let diagnostic = Diagnostic::new(serverity)
+        .with_code(&rule.id)
+        .with_message(&rule.message)
+        .with_notes(rule.note.iter().cloned().collect())
+        .with_labels(vec![Label::primary((), m.range())]);
+      term::emit(&mut *writer, config, &file, &diagnostic)?;
+    }
+    Ok(())
+  }
+
   fn print_matches<'a>(&self, matches: Matches!('a), path: &Path) -> Result<()> {
     let writer = &mut *self.writer.lock().expect("should success");
+    let kind = self.context_kind.as_deref();
     if self.heading.should_print() {
-      print_matches_with_heading(matches, path, &self.styles, writer)
+      print_matches_with_heading(matches, path, &self.styles, kind, writer)
     } else {
-      print_matches_with_prefix(matches, path, &self.styles, writer)
+      print_matches_with_prefix(matches, path, &self.styles, kind, writer)
     }
   }
 
   fn print_diffs<'a>(&self, diffs: Diffs!('a), path: &Path) -> Result<()> {
     let writer = &mut *self.writer.lock().expect("should success");
-    print_diffs(diffs, path, &self.styles, writer)
+    print_diffs(diffs, path, self.diff_context, &self.styles, writer)
+  }
+
+  fn print_group(&self, title: &str) -> Result<()> {
+    let writer = &mut *self.writer.lock().expect("should success");
+    let header = format!("== {title} ==");
+    writeln!(writer, "{}", self.styles.rule.message.paint(header))?;
+    Ok(())
   }
   fn print_rule_diffs<'a>(
     &self,
     diffs: Diffs!('a),
     path: &Path,
     rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
   ) -> Result<()> {
     let writer = &mut *self.writer.lock().expect("should success");
-    print_rule_title(rule, &self.styles.rule, writer)?;
-    print_diffs(diffs, path, &self.styles, writer)?;
+    print_rule_title(rule, severity, &self.styles.rule, writer)?;
+    print_diffs(diffs, path, self.diff_context, &self.styles, writer)?;
     if let Some(note) = &rule.note {
       writeln!(writer, "{}", self.styles.rule.note.paint("Note:"))?;
       writeln!(writer, "{note}")?;
@@ -166,16 +276,28 @@ impl<W: WriteColor> Printer for ColoredPrinter<W> {
   }
 }
 
+fn diagnostic_severity(severity: &Severity) -> diagnostic::Severity {
+  match severity {
+    Severity::Error => diagnostic::Severity::Error,
+    Severity::Warning => diagnostic::Severity::Warning,
+    Severity::Info => diagnostic::Severity::Note,
+    Severity::Hint => diagnostic::Severity::Help,
+    Severity::Off => unreachable!("turned-off rule should not have match"),
+  }
+}
+
 fn print_rule_title<'a, W: WriteColor>(
   rule: &RuleConfig<SupportLang>,
+  severity: &Severity,
   style: &RuleStyle,
   writer: &'a mut W,
 ) -> Result<()> {
-  let (level, level_style) = match rule.severity {
+  let (level, level_style) = match severity {
     Severity::Error => ("error", style.error),
     Severity::Warning => ("warning", style.warning),
     Severity::Info => ("note", style.info),
     Severity::Hint => ("help", style.hint),
+    Severity::Off => unreachable!("turned-off rule should not have match"),
   };
   let header = format!("{level}[{}]:", &rule.id);
   let header = level_style.paint(header);
@@ -269,10 +391,32 @@ impl<'a> MatchMerger<'a> {
   }
 }
 
+/// The first line of the nearest ancestor of the kind and its line number, printed as context
+/// before a group of matches. Each ancestor is printed once, and not at all if it starts on
+/// the line of the match.
+fn context_line(
+  nm: &NodeMatch<SupportLang>,
+  kind: Option<&str>,
+  printed: &mut Option<usize>,
+) -> Option<(usize, String)> {
+  let kind = kind?;
+  let ancestor = nm.ancestors().find(|n| n.kind() == kind)?;
+  let id = ancestor.node_id();
+  if *printed == Some(id) || ancestor.start_pos().0 == nm.start_pos().0 {
+    return None;
+  }
+  *printed = Some(id);
+  let display = ancestor.display_context(0);
+  let first_line = display.matched.lines().next().unwrap_or_default();
+  let text = format!("{}{first_line}", display.leading);
+  Some((display.start_line, text))
+}
+
 fn print_matches_with_heading<'a, W: Write>(
   mut matches: Matches!('a),
   path: &Path,
   styles: &PrintStyles,
+  context_kind: Option<&str>,
   writer: &mut W,
 ) -> Result<()> {
   print_prelude(path, styles, writer)?;
@@ -281,10 +425,12 @@ fn print_matches_with_heading<'a, W: Write>(
   };
   let source = first_match.ancestors().last().unwrap().text();
   let display = first_match.display_context(0);
+  let mut printed = None;
+  let mut context = context_line(&first_match, context_kind, &mut printed);
 
   let mut merger = MatchMerger::new(&first_match);
   let mut ret = display.leading.to_string();
-  ret.push_str(&format!("{}", styles.matched.paint(&*display.matched)));
+  ret.push_str(&paint_match(&first_match, styles));
 
   for nm in matches {
     if merger.check_overlapping(&nm) {
@@ -294,25 +440,32 @@ fn print_matches_with_heading<'a, W: Write>(
     // merge adjacent matches
     if let Some(last_end_offset) = merger.merge_adjacent(&nm) {
       ret.push_str(&source[last_end_offset..nm.range().start]);
-      ret.push_str(&format!("{}", styles.matched.paint(&*display.matched)));
+      ret.push_str(&paint_match(&nm, styles));
       continue;
     }
     ret.push_str(merger.last_trailing);
     let lines = ret.lines().count();
     let mut num = merger.last_start_line;
     let width = (lines + num).to_string().chars().count();
+    if let Some((line, text)) = context.take() {
+      writeln!(writer, "{line:>width$}┆{text}")?;
+    }
     write!(writer, "{num:>width$}│")?; // initial line num
     print_highlight(ret.lines(), Style::new(), width, &mut num, writer)?;
     writeln!(writer)?; // end match new line
                        //
     merger.conclude_match(&nm);
+    context = context_line(&nm, context_kind, &mut printed);
     ret = display.leading.to_string();
-    ret.push_str(&format!("{}", styles.matched.paint(&*display.matched)));
+    ret.push_str(&paint_match(&nm, styles));
   }
   ret.push_str(merger.last_trailing);
   let lines = ret.lines().count();
   let mut num = merger.last_start_line;
   let width = (lines + num).to_string().chars().count();
+  if let Some((line, text)) = context {
+    writeln!(writer, "{line:>width$}┆{text}")?;
+  }
   write!(writer, "{num:>width$}│")?; // initial line num
   print_highlight(ret.lines(), Style::new(), width, &mut num, writer)?;
   writeln!(writer)?; // end match new line
@@ -323,6 +476,7 @@ fn print_matches_with_prefix<'a, W: WriteColor>(
   mut matches: Matches!('a),
   path: &Path,
   styles: &PrintStyles,
+  context_kind: Option<&str>,
   writer: &mut W,
 ) -> Result<()> {
   let path = path.display();
@@ -331,10 +485,12 @@ fn print_matches_with_prefix<'a, W: WriteColor>(
   };
   let source = first_match.ancestors().last().unwrap().text();
   let display = first_match.display_context(0);
+  let mut printed = None;
+  let mut context = context_line(&first_match, context_kind, &mut printed);
 
   let mut merger = MatchMerger::new(&first_match);
   let mut ret = display.leading.to_string();
-  ret.push_str(&format!("{}", styles.matched.paint(&*display.matched)));
+  ret.push_str(&paint_match(&first_match, styles));
   for nm in matches {
     if merger.check_overlapping(&nm) {
       continue;
@@ -342,20 +498,27 @@ fn print_matches_with_prefix<'a, W: WriteColor>(
     // merge adjacent matches
     if let Some(last_end_offset) = merger.merge_adjacent(&nm) {
       ret.push_str(&source[last_end_offset..nm.range().start]);
-      ret.push_str(&format!("{}", styles.matched.paint(nm.text())));
+      ret.push_str(&paint_match(&nm, styles));
       continue;
     }
     ret.push_str(merger.last_trailing);
+    if let Some((line, text)) = context.take() {
+      writeln!(writer, "{path}-{line}-{text}")?;
+    }
     for (n, line) in ret.lines().enumerate() {
       let num = merger.last_start_line + n;
       writeln!(writer, "{path}:{num}:{line}")?;
     }
     merger.conclude_match(&nm);
+    context = context_line(&nm, context_kind, &mut printed);
     let display = nm.display_context(0);
     ret = display.leading.to_string();
-    ret.push_str(&format!("{}", styles.matched.paint(&*display.matched)));
+    ret.push_str(&paint_match(&nm, styles));
   }
   ret.push_str(merger.last_trailing);
+  if let Some((line, text)) = context {
+    writeln!(writer, "{path}-{line}-{text}")?;
+  }
   for (n, line) in ret.lines().enumerate() {
     let num = merger.last_start_line + n;
     writeln!(writer, "{path}:{num}:{line}")?;
@@ -363,28 +526,94 @@ fn print_matches_with_prefix<'a, W: WriteColor>(
   Ok(())
 }
 
+/// Paint the matched text with meta variable captures highlighted in distinct styles.
+fn paint_match(nm: &NodeMatch<SupportLang>, styles: &PrintStyles) -> String {
+  let text = nm.text();
+  if styles.meta_vars.is_empty() {
+    return styles.matched.paint(&*text).to_string();
+  }
+  let captures = capture_ranges(nm);
+  let offset = nm.range().start;
+  let mut ret = String::new();
+  for (range, capture) in capture_segments(nm.range(), &captures) {
+    let style = match capture {
+      Some(i) => styles.meta_vars[i % styles.meta_vars.len()],
+      None => styles.matched,
+    };
+    let segment = &text[range.start - offset..range.end - offset];
+    ret.push_str(&style.paint(segment).to_string());
+  }
+  ret
+}
+
+/// Byte ranges of meta variable captures inside the match, ordered by variable name.
+fn capture_ranges(nm: &NodeMatch<SupportLang>) -> Vec<Range<usize>> {
+  let env = nm.get_env();
+  let match_range = nm.range();
+  let mut captures: Vec<_> = env
+    .get_matched_variables()
+    .filter_map(|var| match var {
+      MetaVariable::Named(name, _) => {
+        let range = env.get_match(&name)?.range();
+        Some((name, range))
+      }
+      MetaVariable::NamedEllipsis(name) => {
+        let nodes = env.get_multiple_matches(&name);
+        let range = nodes.first()?.range().start..nodes.last()?.range().end;
+        Some((name, range))
+      }
+      _ => None,
+    })
+    .filter(|(_, range)| {
+      !range.is_empty() && match_range.start <= range.start && range.end <= match_range.end
+    })
+    .collect();
+  captures.sort_by(|a, b| a.0.cmp(&b.0));
+  captures.into_iter().map(|(_, range)| range).collect()
+}
+
+/// Split the match range into consecutive segments, each tagged with the index of
+/// the innermost capture covering it. Nested captures therefore nest visually.
+fn capture_segments(
+  match_range: Range<usize>,
+  captures: &[Range<usize>],
+) -> Vec<(Range<usize>, Option<usize>)> {
+  let mut bounds = vec![match_range.start, match_range.end];
+  for capture in captures {
+    bounds.push(capture.start);
+    bounds.push(capture.end);
+  }
+  bounds.sort_unstable();
+  bounds.dedup();
+  bounds
+    .windows(2)
+    .map(|w| {
+      let innermost = captures
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.start <= w[0] && w[1] <= c.end)
+        .min_by_key(|(_, c)| c.len())
+        .map(|(i, _)| i);
+      (w[0]..w[1], innermost)
+    })
+    .collect()
+}
+
 fn print_diffs<'a, W: WriteColor>(
-  mut diffs: Diffs!('a),
+  diffs: Diffs!('a),
   path: &Path,
+  context: usize,
   styles: &PrintStyles,
   writer: &mut W,
 ) -> Result<()> {
   print_prelude(path, styles, writer)?;
-  let Some(first_diff) = diffs.next() else {
+  let diffs: Vec<_> = diffs.collect();
+  let Some(first_diff) = diffs.first() else {
     return Ok(());
   };
-  let range = first_diff.node_match.range();
   let source = first_diff.node_match.ancestors().last().unwrap().text();
-  let mut start = range.end;
-  let mut new_str = format!("{}{}", &source[..range.start], first_diff.replacement);
-  for diff in diffs {
-    let range = diff.node_match.range();
-    new_str.push_str(&source[start..range.start]);
-    new_str.push_str(&diff.replacement);
-    start = range.end;
-  }
-  new_str.push_str(&source[start..]);
-  print_diff(&source, &new_str, styles, writer)?;
+  let new_str = apply_rewrite(diffs);
+  print_diff(&source, &new_str, context, styles, writer)?;
   Ok(())
 }
 
@@ -416,7 +645,7 @@ fn index_display(index: Option<usize>, style: Style, width: usize) -> impl Displ
   style.paint(index_str)
 }
 
-// TODO: currently diff print context is three lines before/after the match.
+// TODO: currently diff print context is a fixed number of lines before/after the match.
 // This is suboptimal. We should use function/class as the enclosing scope to print relevant lines. See #155
 fn compupte_header(group: &[DiffOp]) -> String {
   let old_start = group[0].old_range().start;
@@ -430,14 +659,19 @@ fn compupte_header(group: &[DiffOp]) -> String {
   )
 }
 
+/// Unchanged lines printed around each change by default, the same as `diff -u`.
+pub const DEFAULT_DIFF_CONTEXT: usize = 3;
+
+/// Print the line diff in hunks with `context` unchanged lines around each change.
 pub fn print_diff(
   old: &str,
   new: &str,
+  context: usize,
   styles: &PrintStyles,
   writer: &mut impl Write,
 ) -> Result<()> {
   let diff = TextDiff::from_lines(old, new);
-  for group in diff.grouped_ops(3) {
+  for group in diff.grouped_ops(context) {
     let op = group.last().unwrap();
     let old_width = op.old_range().end.to_string().chars().count();
     let new_width = op.new_range().end.to_string().chars().count();
@@ -493,6 +727,8 @@ struct RuleStyle {
 pub struct PrintStyles {
   file_path: Style,
   matched: Style,
+  /// palette for meta variable captures in matches, cycled by variable name order
+  meta_vars: Vec<Style>,
   insert: Style,
   insert_emphasis: Style,
   delete: Style,
@@ -509,6 +745,13 @@ impl PrintStyles {
     Self {
       file_path: Color::Cyan.italic(),
       matched: Color::Red.bold(),
+      meta_vars: vec![
+        Color::Blue.bold().underline(),
+        Color::Green.bold().underline(),
+        Color::Purple.bold().underline(),
+        Color::Yellow.bold().underline(),
+        Color::Cyan.bold().underline(),
+      ],
       insert: Style::new().fg(GREEN),
       insert_emphasis: Style::new().fg(GREEN).on(SEA_GREEN).bold(),
       delete: Style::new().fg(RED),
@@ -679,6 +922,54 @@ mod test {
     }
   }
 
+  #[test]
+  fn test_capture_segments() {
+    let grep = SupportLang::Tsx.ast_grep("let a = foo(bar)");
+    let nm = grep.root().find("let $NAME = $VALUE").unwrap();
+    let captures = capture_ranges(&nm);
+    assert_eq!(captures, [4..5, 8..16]);
+    let segments = capture_segments(nm.range(), &captures);
+    let expected = [
+      (0..4, None),
+      (4..5, Some(0)),
+      (5..8, None),
+      (8..16, Some(1)),
+    ];
+    assert_eq!(segments, expected);
+  }
+
+  #[test]
+  fn test_nested_capture_segments() {
+    // $B is nested in $A
+    let segments = capture_segments(0..10, &[2..8, 4..6]);
+    let expected = [
+      (0..2, None),
+      (2..4, Some(0)),
+      (4..6, Some(1)),
+      (6..8, Some(0)),
+      (8..10, None),
+    ];
+    assert_eq!(segments, expected);
+  }
+
+  #[test]
+  fn test_paint_meta_var() {
+    let grep = SupportLang::Tsx.ast_grep("let a = 123");
+    let nm = grep.root().find("let $NAME = $VALUE").unwrap();
+    let styles = PrintStyles::colored();
+    let painted = paint_match(&nm, &styles);
+    let expected = format!(
+      "{}{}{}{}",
+      styles.matched.paint("let "),
+      styles.meta_vars[0].paint("a"),
+      styles.matched.paint(" = "),
+      styles.meta_vars[1].paint("123"),
+    );
+    assert_eq!(painted, expected);
+    let painted = paint_match(&nm, &PrintStyles::no_color());
+    assert_eq!(painted, "let a = 123");
+  }
+
   #[test]
   fn test_print_matches_without_heading() {
     for &(source, pattern, note) in MATCHES_CASES {
@@ -696,6 +987,27 @@ mod test {
     }
   }
 
+  #[test]
+  fn test_print_context_kind() {
+    let source = "function foo(a: number) {\n  let b = 1\n  log(a)\n  log(b)\n}\nlog(c)";
+    let grep = SupportLang::Tsx.ast_grep(source);
+    let kind = Some("function_declaration".to_string());
+    let printer = make_test_printer()
+      .heading(Heading::Always)
+      .context_kind(kind.clone());
+    let matches = grep.root().find_all("log($A)");
+    printer.print_matches(matches, "test.tsx".as_ref()).unwrap();
+    let expected = "test.tsx\n1┆function foo(a: number) {\n3│  log(a)\n4│  log(b)\n6│log(c)\n";
+    assert_eq!(get_text(&printer), expected);
+    let printer = make_test_printer()
+      .heading(Heading::Never)
+      .context_kind(kind);
+    let matches = grep.root().find_all("log($A)");
+    printer.print_matches(matches, "test.tsx".as_ref()).unwrap();
+    let expected = "test.tsx-1-function foo(a: number) {\ntest.tsx:3:  log(a)\ntest.tsx:4:  log(b)\ntest.tsx:6:log(c)\n";
+    assert_eq!(get_text(&printer), expected);
+  }
+
   #[test]
   fn test_printe_rules() {
     let globals = GlobalRules::default();
@@ -722,7 +1034,9 @@ rule:
       .expect("should parse")
       .pop()
       .unwrap();
-      printer.print_rule(matches, file, &rule).expect("test only");
+      printer
+        .print_rule(matches, file, &rule, &rule.severity)
+        .expect("test only");
       let text = get_text(&printer);
       assert!(text.contains("test.tsx"), "{note}");
       assert!(text.contains("note[test-id]"), "{note}");
@@ -730,6 +1044,53 @@ rule:
     }
   }
 
+  #[test]
+  fn test_print_rule_tab_width() {
+    let yaml = "id: no-log\nmessage: test\nseverity: info\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)";
+    let rule = from_yaml_string(yaml, &GlobalRules::default())
+      .expect("should parse")
+      .pop()
+      .unwrap();
+    let source = "if (a) {\n\t\tconsole.log(a)\n}".to_string();
+    let grep = SupportLang::TypeScript.ast_grep(&source);
+    let print = |tab_width| {
+      let printer = make_test_printer()
+        .style(ReportStyle::Short)
+        .tab_width(NonZeroUsize::new(tab_width));
+      let file = SimpleFile::new(Cow::Borrowed("test.ts"), &source);
+      let matches = grep.root().find_all(&rule.matcher);
+      printer
+        .print_rule(matches, file, &rule, &rule.severity)
+        .expect("test only");
+      get_text(&printer)
+    };
+    assert!(print(0).starts_with("test.ts:2:3: "));
+    assert!(print(4).starts_with("test.ts:2:9: "));
+    assert!(print(2).starts_with("test.ts:2:5: "));
+  }
+
+  #[test]
+  fn test_print_diff_context() {
+    let old: String = (1..=12).map(|i| format!("line {i}\n")).collect();
+    let unchanged_lines = |new: &str, context| {
+      let mut output = vec![];
+      print_diff(&old, new, context, &PrintStyles::default(), &mut output).unwrap();
+      let output = String::from_utf8(output).unwrap();
+      output
+        .lines()
+        .filter_map(|l| l.split_once('│')?.1.strip_prefix(" line "))
+        .map(|n| n.parse().unwrap())
+        .collect::<Vec<usize>>()
+    };
+    let new = old.replace("line 6\n", "changed\n");
+    assert!(unchanged_lines(&new, 0).is_empty());
+    assert_eq!(unchanged_lines(&new, 2), [4, 5, 7, 8]);
+    assert_eq!(unchanged_lines(&new, 3), [3, 4, 5, 7, 8, 9]);
+    // context is cut at the start of the file
+    let new = old.replace("line 1\n", "changed\n");
+    assert_eq!(unchanged_lines(&new, 3), [2, 3, 4]);
+  }
+
   #[test]
   #[ignore]
   fn test_printe_diffs() {
@@ -1,9 +1,10 @@
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
 use anyhow::{Context, Result};
-use ast_grep_config::RuleConfig;
+use ast_grep_config::{RuleConfig, Severity, TextMatch, TextRule};
 
-use super::{Diff, Printer};
+use super::{apply_edits_bottom_up, apply_rewrite, Diff, Printer};
 use crate::error::ErrorContext as EC;
 use crate::utils;
 use ast_grep_core::NodeMatch;
@@ -12,6 +13,8 @@ use ast_grep_language::SupportLang;
 pub use codespan_reporting::{files::SimpleFile, term::ColorArg};
 
 use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 // add this macro because neither trait_alias nor type_alias_impl is supported.
@@ -22,14 +25,26 @@ macro_rules! Diffs {
   ($lt: lifetime) => { impl Iterator<Item = Diff<$lt>> };
 }
 
+/// Fixes accepted one by one in a file: the original content and the edits of each finding.
+type AcceptedFixes = HashMap<PathBuf, (String, Vec<Vec<(Range<usize>, String)>>)>;
+
 pub struct InteractivePrinter<P: Printer> {
   accept_all: AtomicBool,
+  /// accepted edits in atomic mode, written in `after_print` only if every file is fixed
+  planned: Option<Mutex<Vec<(PathBuf, String)>>>,
+  /// fixes accepted per finding, applied in `after_print`
+  accepted: Option<Mutex<AcceptedFixes>>,
+  /// answers used instead of reading the terminal, for tests
+  script: Option<Mutex<VecDeque<char>>>,
   inner: P,
 }
 impl<P: Printer> InteractivePrinter<P> {
   pub fn new(inner: P) -> Self {
     Self {
       accept_all: AtomicBool::new(false),
+      planned: None,
+      accepted: None,
+      script: None,
       inner,
     }
   }
@@ -38,6 +53,118 @@ impl<P: Printer> InteractivePrinter<P> {
     self.accept_all.store(accept_all, Ordering::SeqCst);
     self
   }
+
+  /// Hold accepted edits until all files are processed, then write all of them or none.
+  pub fn atomic(self, atomic: bool) -> Self {
+    Self {
+      planned: atomic.then(Default::default),
+      ..self
+    }
+  }
+
+  /// Review fixable findings one at a time instead of all findings of a rule in a file.
+  /// Findings without a fix are skipped.
+  pub fn per_finding(self, per_finding: bool) -> Self {
+    Self {
+      accepted: per_finding.then(Default::default),
+      ..self
+    }
+  }
+
+  #[cfg(test)]
+  fn scripted(self, answers: &str) -> Self {
+    Self {
+      script: Some(Mutex::new(answers.chars().collect())),
+      ..self
+    }
+  }
+
+  fn prompt(&self, text: &str, letters: &str, default: Option<char>) -> Result<char> {
+    match &self.script {
+      Some(script) => Ok(
+        script
+          .lock()
+          .expect("should work")
+          .pop_front()
+          .unwrap_or('q'),
+      ),
+      None => utils::prompt(text, letters, default),
+    }
+  }
+
+  fn in_screen<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    if self.script.is_some() {
+      f()
+    } else {
+      utils::run_in_alternate_screen(f)
+    }
+  }
+
+  /// Prompt for every diff and record the accepted ones. Quitting still applies
+  /// the fixes accepted so far.
+  fn review_each_diff(
+    &self,
+    accepted: &Mutex<AcceptedFixes>,
+    diffs: Vec<Diff<'_>>,
+    path: &Path,
+    rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
+  ) -> Result<()> {
+    for diff in diffs {
+      if !self.accept_all.load(Ordering::SeqCst) {
+        let resp = self.in_screen(|| {
+          self
+            .inner
+            .print_rule_diffs(std::iter::once(diff.clone()), path, rule, severity)?;
+          self.prompt(FINDING_PROMPT, "ynaq", Some('n'))
+        })?;
+        match resp {
+          'y' => (),
+          'a' => self.accept_all.store(true, Ordering::SeqCst),
+          'q' => {
+            self.write_accepted(accepted)?;
+            return Err(anyhow::anyhow!("Exit interactive editing"));
+          }
+          _ => continue,
+        }
+      }
+      let mut accepted = accepted.lock().expect("should work");
+      let (_, edits) = accepted.entry(path.to_path_buf()).or_insert_with(|| {
+        let root = diff.node_match.ancestors().last();
+        let content = root.map_or_else(|| diff.node_match.text(), |r| r.text());
+        (content.to_string(), vec![])
+      });
+      edits.push(diff.edits());
+    }
+    Ok(())
+  }
+
+  fn write_accepted(&self, accepted: &Mutex<AcceptedFixes>) -> Result<()> {
+    let accepted = std::mem::take(&mut *accepted.lock().expect("should work"));
+    for (path, (content, edits)) in accepted {
+      let new_content = apply_edits_bottom_up(content, edits);
+      match &self.planned {
+        Some(planned) => planned
+          .lock()
+          .expect("should work")
+          .push((path, new_content)),
+        None => std::fs::write(&path, new_content).with_context(|| EC::WriteFile(path))?,
+      }
+    }
+    Ok(())
+  }
+
+  fn rewrite(&self, diffs: Vec<Diff<'_>>, path: &PathBuf) -> Result<()> {
+    let Some(planned) = &self.planned else {
+      return rewrite_action(diffs, path);
+    };
+    let new_content = apply_rewrite(diffs);
+    planned
+      .lock()
+      .expect("should work")
+      .push((path.clone(), new_content));
+    Ok(())
+  }
 }
 
 impl<P: Printer> Printer for InteractivePrinter<P> {
@@ -46,9 +173,33 @@ impl<P: Printer> Printer for InteractivePrinter<P> {
     matches: Matches!('a),
     file: SimpleFile<Cow<str>, &String>,
     rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
+  ) -> Result<()> {
+    if self.accepted.is_some() {
+      return Ok(());
+    }
+    utils::run_in_alternate_screen(|| {
+      self.inner.print_rule(matches, file, rule, severity)?;
+      let resp = utils::prompt(VIEW_PROMPT, "q", Some('\n')).expect("cannot fail");
+      if resp == 'q' {
+        Err(anyhow::anyhow!("Exit interactive editing"))
+      } else {
+        Ok(())
+      }
+    })
+  }
+
+  fn print_text_rule(
+    &self,
+    matches: Vec<TextMatch>,
+    file: SimpleFile<Cow<str>, &String>,
+    rule: &TextRule,
   ) -> Result<()> {
+    if self.accepted.is_some() {
+      return Ok(());
+    }
     utils::run_in_alternate_screen(|| {
-      self.inner.print_rule(matches, file, rule)?;
+      self.inner.print_text_rule(matches, file, rule)?;
       let resp = utils::prompt(VIEW_PROMPT, "q", Some('\n')).expect("cannot fail");
       if resp == 'q' {
         Err(anyhow::anyhow!("Exit interactive editing"))
@@ -65,7 +216,7 @@ impl<P: Printer> Printer for InteractivePrinter<P> {
   fn print_diffs<'a>(&self, diffs: Diffs!('a), path: &Path) -> Result<()> {
     let path = path.to_path_buf();
     if self.accept_all.load(Ordering::SeqCst) {
-      return rewrite_action(diffs.collect(), &path);
+      return self.rewrite(diffs.collect(), &path);
     }
     utils::run_in_alternate_screen(|| {
       let all = print_diffs_and_prompt_action(&self.inner, &path, diffs, None)?;
@@ -80,43 +231,105 @@ impl<P: Printer> Printer for InteractivePrinter<P> {
     diffs: Diffs!('a),
     path: &Path,
     rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
   ) -> Result<()> {
+    if let Some(accepted) = &self.accepted {
+      return self.review_each_diff(accepted, diffs.collect(), path, rule, severity);
+    }
     let path = path.to_path_buf();
     if self.accept_all.load(Ordering::SeqCst) {
-      return rewrite_action(diffs.collect(), &path);
+      return self.rewrite(diffs.collect(), &path);
     }
     utils::run_in_alternate_screen(|| {
-      let all = print_diffs_and_prompt_action(&self.inner, &path, diffs, Some(rule))?;
+      let all = print_diffs_and_prompt_action(&self.inner, &path, diffs, Some((rule, severity)))?;
       if all {
         self.accept_all.store(true, Ordering::SeqCst);
       }
       Ok(())
     })
   }
+
+  fn after_print(&self) -> Result<()> {
+    if let Some(accepted) = &self.accepted {
+      self.write_accepted(accepted)?;
+    }
+    match &self.planned {
+      Some(planned) => {
+        write_all_or_none(std::mem::take(&mut *planned.lock().expect("should work")))
+      }
+      None => Ok(()),
+    }
+  }
 }
 
 const EDIT_PROMPT: &str = "Accept change? (Yes[y], No[n], Accept All[a], Quit[q], Edit[e])";
 const VIEW_PROMPT: &str = "Next[enter], Quit[q]";
+const FINDING_PROMPT: &str = "Accept fix? (Yes[y], No[n], Accept All[a], Quit[q])";
 
 fn rewrite_action(diffs: Vec<Diff<'_>>, path: &PathBuf) -> Result<()> {
   let new_content = apply_rewrite(diffs);
   std::fs::write(path, new_content).with_context(|| EC::WriteFile(path.clone()))
 }
 
+/// Write new contents to temporary files next to the originals and then rename them.
+/// If any temporary file cannot be written, all of them are removed and no file is modified.
+/// If a rename fails, the remaining temporary files are removed and the files already renamed
+/// get their original contents back. Only a crash between two renames leaves some files fixed.
+fn write_all_or_none(edits: Vec<(PathBuf, String)>) -> Result<()> {
+  let mut staged = Vec::with_capacity(edits.len());
+  for (path, new_content) in edits {
+    let tmp = staging_path(&path);
+    if let Err(e) = std::fs::write(&tmp, new_content) {
+      for (_, tmp, _) in staged {
+        let _ = std::fs::remove_file(tmp);
+      }
+      return Err(anyhow::Error::from(e).context(EC::AtomicFixAborted(path)));
+    }
+    // keep permissions like the executable bit of the original file
+    if let Ok(meta) = std::fs::metadata(&path) {
+      let _ = std::fs::set_permissions(&tmp, meta.permissions());
+    }
+    let original = std::fs::read(&path).ok();
+    staged.push((path, tmp, original));
+  }
+  for i in 0..staged.len() {
+    let (path, tmp, _) = &staged[i];
+    if let Err(e) = std::fs::rename(tmp, path) {
+      for (_, tmp, _) in &staged[i..] {
+        let _ = std::fs::remove_file(tmp);
+      }
+      for (path, _, original) in &staged[..i] {
+        let _ = match original {
+          Some(original) => std::fs::write(path, original),
+          None => std::fs::remove_file(path),
+        };
+      }
+      return Err(anyhow::Error::from(e).context(EC::AtomicFixAborted(path.clone())));
+    }
+  }
+  Ok(())
+}
+
+fn staging_path(path: &Path) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_os_string();
+  name.push(".sg-tmp");
+  path.with_file_name(name)
+}
+
 /// returns if accept_all is chosen
 fn print_diffs_and_prompt_action<'a>(
   printer: &impl Printer,
   path: &PathBuf,
   diffs: Diffs!('a),
-  rule: Option<&RuleConfig<SupportLang>>,
+  rule: Option<(&RuleConfig<SupportLang>, &Severity)>,
 ) -> Result<bool> {
   let diffs: Vec<_> = diffs.collect();
   let first_match = match diffs.first() {
     Some(n) => n.node_match.start_pos().0,
     None => return Ok(false),
   };
-  if let Some(rule) = rule {
-    printer.print_rule_diffs(diffs.clone().into_iter(), path, rule)?;
+  if let Some((rule, severity)) = rule {
+    printer.print_rule_diffs(diffs.clone().into_iter(), path, rule, severity)?;
   } else {
     printer.print_diffs(diffs.clone().into_iter(), path)?;
   }
@@ -155,30 +368,14 @@ fn print_matches_and_confirm_next<'a>(
   }
 }
 
-fn apply_rewrite(diffs: Vec<Diff>) -> String {
-  let mut new_content = String::new();
-  let Some(first) = diffs.first() else {
-    return new_content
-  };
-  let old_content = first.node_match.ancestors().last().unwrap().text();
-  let mut start = 0;
-  for diff in diffs {
-    let range = diff.node_match.range();
-    new_content.push_str(&old_content[start..range.start]);
-    new_content.push_str(&diff.replacement);
-    start = range.end;
-  }
-  // add trailing statements
-  new_content.push_str(&old_content[start..]);
-  new_content
-}
-
 #[cfg(test)]
 mod test {
   use super::*;
+  use crate::print::ColoredPrinter;
   use ast_grep_config::{from_yaml_string, GlobalRules};
   use ast_grep_core::traversal::Visitor;
   use ast_grep_core::{AstGrep, Matcher, Pattern};
+  use codespan_reporting::term::termcolor::Buffer;
 
   fn make_rule(rule: &str) -> RuleConfig<SupportLang> {
     let globals = GlobalRules::default();
@@ -241,4 +438,115 @@ fix: ($B, lifecycle.update(['$A']))",
     let ret = apply_rewrite(diffs);
     assert_eq!("Some(1)", ret);
   }
+
+  fn fix_file(printer: &InteractivePrinter<impl Printer>, path: &Path, src: &str) -> Result<()> {
+    let grep = AstGrep::new(src, SupportLang::TypeScript);
+    let fixer = Pattern::new("bar($A)", SupportLang::TypeScript);
+    let diffs = make_diffs(&grep, "foo($A)", &fixer);
+    printer.print_diffs(diffs.into_iter(), path)
+  }
+
+  #[test]
+  fn test_atomic_fixes() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let (a, b) = (dir.join("a.ts"), dir.join("b.ts"));
+    std::fs::write(&a, "foo(1)").unwrap();
+    std::fs::write(&b, "foo(2)").unwrap();
+    let printer = InteractivePrinter::new(ColoredPrinter::new(Buffer::no_color()))
+      .accept_all(true)
+      .atomic(true);
+    fix_file(&printer, &a, "foo(1)").unwrap();
+    fix_file(&printer, &b, "foo(2)").unwrap();
+    // nothing is written before all files are fixed
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "foo(1)");
+    printer.after_print().expect("should write");
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "bar(1)");
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "bar(2)");
+
+    // the directory of the second file is removed before writing
+    let missing = dir.join("missing").join("c.ts");
+    let printer = InteractivePrinter::new(ColoredPrinter::new(Buffer::no_color()))
+      .accept_all(true)
+      .atomic(true);
+    fix_file(&printer, &a, "bar(1); foo(3)").unwrap();
+    fix_file(&printer, &missing, "foo(4)").unwrap();
+    let error = printer.after_print().expect_err("should abort");
+    let a_content = std::fs::read_to_string(&a).unwrap();
+    let written: Vec<_> = std::fs::read_dir(dir)
+      .unwrap()
+      .map(|e| e.unwrap().file_name())
+      .collect();
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::AtomicFixAborted(p)) if p == &missing
+    ));
+    assert_eq!(a_content, "bar(1)", "no file should be modified");
+    assert_eq!(written.len(), 2, "temporary files should be removed");
+  }
+
+  #[test]
+  fn test_atomic_fixes_rename_failure() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let (a, b) = (dir.join("a.ts"), dir.join("b.ts"));
+    // a file cannot be renamed over a directory that is not empty
+    std::fs::create_dir_all(b.join("c")).unwrap();
+    std::fs::write(&a, "foo(1)").unwrap();
+    let printer = InteractivePrinter::new(ColoredPrinter::new(Buffer::no_color()))
+      .accept_all(true)
+      .atomic(true);
+    fix_file(&printer, &a, "foo(1)").unwrap();
+    fix_file(&printer, &b, "foo(2)").unwrap();
+    let error = printer.after_print().expect_err("should abort");
+    let a_content = std::fs::read_to_string(&a).unwrap();
+    let mut written: Vec<_> = std::fs::read_dir(dir)
+      .unwrap()
+      .map(|e| e.unwrap().file_name())
+      .collect();
+    written.sort();
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::AtomicFixAborted(p)) if p == &b
+    ));
+    assert_eq!(a_content, "foo(1)", "renamed files should be rolled back");
+    assert_eq!(
+      written,
+      ["a.ts", "b.ts"],
+      "temporary files should be removed"
+    );
+  }
+
+  fn review(answers: &str, src: &str) -> (String, Result<()>) {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let path = dir.join("a.ts");
+    std::fs::write(&path, src).unwrap();
+    let rule = make_rule("rule: { pattern: foo($A) }\nfix: bar($A)");
+    let grep = AstGrep::new(src, SupportLang::TypeScript);
+    let diffs = make_diffs(&grep, &rule.matcher, rule.fixer.as_ref().unwrap());
+    let printer = InteractivePrinter::new(ColoredPrinter::new(Buffer::no_color()))
+      .per_finding(true)
+      .scripted(answers);
+    let ret = printer
+      .print_rule_diffs(diffs.into_iter(), &path, &rule, &rule.severity)
+      .and_then(|_| printer.after_print());
+    let fixed = std::fs::read_to_string(&path).unwrap();
+    (fixed, ret)
+  }
+
+  #[test]
+  fn test_review_each_finding() {
+    let src = "foo(1)\nfoo(2)\nfoo(3)\nfoo(4)";
+    let (fixed, ret) = review("ynyn", src);
+    assert!(ret.is_ok());
+    assert_eq!(fixed, "bar(1)\nfoo(2)\nbar(3)\nfoo(4)");
+    // accept the rest
+    let (fixed, _) = review("na", src);
+    assert_eq!(fixed, "foo(1)\nbar(2)\nbar(3)\nbar(4)");
+    // quitting keeps fixes accepted so far
+    let (fixed, ret) = review("yq", src);
+    assert!(ret.is_err());
+    assert_eq!(fixed, "bar(1)\nfoo(2)\nfoo(3)\nfoo(4)");
+  }
 }
@@ -1,4 +1,4 @@
-use ast_grep_config::{RuleConfig, Severity};
+use ast_grep_config::{RuleConfig, Severity, TextMatch, TextRule};
 use ast_grep_core::{meta_var::MetaVariable, Node, NodeMatch};
 use ast_grep_language::SupportLang;
 use std::collections::HashMap;
@@ -7,6 +7,7 @@ use super::{Diff, Printer};
 use anyhow::Result;
 pub use codespan_reporting::{files::SimpleFile, term::ColorArg};
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value as YamlValue;
 
 use std::borrow::Cow;
 use std::io::{Stdout, Write};
@@ -14,6 +15,10 @@ use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
+/// Start of a CBOR array whose length is not known ahead, ended by [`CBOR_BREAK`].
+const CBOR_INDEFINITE_ARRAY: u8 = 0x9f;
+const CBOR_BREAK: u8 = 0xff;
+
 // add this macro because neither trait_alias nor type_alias_impl is supported.
 macro_rules! Matches {
   ($lt: lifetime) => { impl Iterator<Item = NodeMatch<$lt, SupportLang>> };
@@ -22,6 +27,7 @@ macro_rules! Diffs {
   ($lt: lifetime) => { impl Iterator<Item = Diff<$lt>> };
 }
 
+/// `line` is 1-based. `column` is the 0-based byte offset in the line, as reported by tree-sitter.
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Position {
@@ -32,31 +38,41 @@ struct Position {
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Range {
-  /// inclusive start, exclusive end
-  byte_offset: std::ops::Range<usize>,
   start: Position,
   end: Position,
 }
 
+/// Location of a node in both byte offsets and line/column, flattened into the node's JSON.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+  /// inclusive start, exclusive end
+  byte_range: (usize, usize),
+  range: Range,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct LabelJSON<'a> {
   text: &'a str,
-  range: Range,
+  #[serde(flatten)]
+  location: Location,
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct MatchNode<'a> {
   text: Cow<'a, str>,
-  range: Range,
+  #[serde(flatten)]
+  location: Location,
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct MatchJSON<'a> {
   text: Cow<'a, str>,
-  range: Range,
+  #[serde(flatten)]
+  location: Location,
   file: Cow<'a, str>,
   #[serde(skip_serializing_if = "Option::is_none")]
   replacement: Option<Cow<'a, str>>,
@@ -86,7 +102,7 @@ fn from_env<'a>(nm: &NodeMatch<'a, SupportLang>) -> Option<MetaVariables<'a>> {
           n,
           MatchNode {
             text: node.text(),
-            range: get_range(node),
+            location: get_location(node),
           },
         );
       }
@@ -98,7 +114,7 @@ fn from_env<'a>(nm: &NodeMatch<'a, SupportLang>) -> Option<MetaVariables<'a>> {
             .into_iter()
             .map(|node| MatchNode {
               text: node.text(),
-              range: get_range(&node),
+              location: get_location(&node),
             })
             .collect(),
         );
@@ -109,19 +125,28 @@ fn from_env<'a>(nm: &NodeMatch<'a, SupportLang>) -> Option<MetaVariables<'a>> {
   Some(MetaVariables { single, multi })
 }
 
-fn get_range(n: &Node<'_, SupportLang>) -> Range {
-  let start_pos = n.start_pos();
-  let end_pos = n.end_pos();
-  Range {
-    byte_offset: n.range(),
+fn get_location(n: &Node<'_, SupportLang>) -> Location {
+  to_location(n.range(), n.start_pos(), n.end_pos())
+}
+
+fn to_location(
+  byte_range: std::ops::Range<usize>,
+  start_pos: (usize, usize),
+  end_pos: (usize, usize),
+) -> Location {
+  let range = Range {
     start: Position {
-      line: start_pos.0,
+      line: start_pos.0 + 1,
       column: start_pos.1,
     },
     end: Position {
-      line: end_pos.0,
+      line: end_pos.0 + 1,
       column: end_pos.1,
     },
+  };
+  Location {
+    byte_range: (byte_range.start, byte_range.end),
+    range,
   }
 }
 
@@ -132,7 +157,7 @@ impl<'a> MatchJSON<'a> {
       text: nm.text(),
       language: *nm.lang(),
       replacement: None,
-      range: get_range(&nm),
+      location: get_location(&nm),
       meta_variables: from_env(&nm),
     }
   }
@@ -145,7 +170,7 @@ fn get_labels<'a>(nm: &NodeMatch<'a, SupportLang>) -> Option<Vec<MatchNode<'a>>>
       .iter()
       .map(|l| MatchNode {
         text: l.text(),
-        range: get_range(l),
+        location: get_location(l),
       })
       .collect(),
   )
@@ -161,18 +186,51 @@ struct RuleMatchJSON<'a> {
   message: String,
   #[serde(skip_serializing_if = "Option::is_none")]
   labels: Option<Vec<MatchNode<'a>>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  metadata: Option<HashMap<String, YamlValue>>,
 }
 impl<'a> RuleMatchJSON<'a> {
-  fn new(nm: NodeMatch<'a, SupportLang>, path: &'a str, rule: &'a RuleConfig<SupportLang>) -> Self {
+  fn new(
+    nm: NodeMatch<'a, SupportLang>,
+    path: &'a str,
+    rule: &'a RuleConfig<SupportLang>,
+    severity: &Severity,
+  ) -> Self {
     let message = rule.get_message(&nm);
     let labels = get_labels(&nm);
     let matched = MatchJSON::new(nm, path);
     Self {
       matched,
       rule_id: &rule.id,
-      severity: rule.severity.clone(),
+      severity: severity.clone(),
       message,
       labels,
+      metadata: rule.metadata.clone(),
+    }
+  }
+}
+
+/// A match of a rule with `language: all`, which has no language or meta variables.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextRuleMatchJSON<'a> {
+  text: &'a str,
+  #[serde(flatten)]
+  location: Location,
+  file: &'a str,
+  rule_id: &'a str,
+  severity: Severity,
+  message: &'a str,
+}
+impl<'a> TextRuleMatchJSON<'a> {
+  fn new(m: TextMatch<'a>, path: &'a str, rule: &'a TextRule) -> Self {
+    Self {
+      text: m.text(),
+      location: to_location(m.range(), m.start_pos(), m.end_pos()),
+      file: path,
+      rule_id: &rule.id,
+      severity: rule.severity.clone(),
+      message: &rule.message,
     }
   }
 }
@@ -181,6 +239,8 @@ pub struct JSONPrinter<W: Write> {
   output: Mutex<W>,
   // indicate if any matches happened
   matched: AtomicBool,
+  // encode the same documents in CBOR instead of JSON text
+  cbor: bool,
 }
 impl JSONPrinter<Stdout> {
   pub fn stdout() -> Self {
@@ -194,15 +254,27 @@ impl<W: Write> JSONPrinter<W> {
     Self {
       output: Mutex::new(output),
       matched: AtomicBool::new(false),
+      cbor: false,
     }
   }
 
+  /// Output a CBOR array of the JSON documents, which is faster to decode than JSON text.
+  pub fn cbor(self, cbor: bool) -> Self {
+    Self { cbor, ..self }
+  }
+
   fn print_docs<S: Serialize>(&self, docs: impl Iterator<Item = S>) -> Result<()> {
     let mut docs = docs.peekable();
     if docs.peek().is_none() {
       return Ok(());
     }
     let mut lock = self.output.lock().expect("should work");
+    if self.cbor {
+      for doc in docs {
+        serde_cbor::to_writer(&mut *lock, &doc)?;
+      }
+      return Ok(());
+    }
     let matched = self.matched.swap(true, Ordering::AcqRel);
     if !matched {
       writeln!(&mut lock)?;
@@ -223,9 +295,23 @@ impl<W: Write> Printer for JSONPrinter<W> {
     matches: Matches!('a),
     file: SimpleFile<Cow<str>, &String>,
     rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
   ) -> Result<()> {
     let path = file.name();
-    let jsons = matches.map(|nm| RuleMatchJSON::new(nm, path, rule));
+    let jsons = matches.map(|nm| RuleMatchJSON::new(nm, path, rule, severity));
+    self.print_docs(jsons)
+  }
+
+  fn print_text_rule(
+    &self,
+    matches: Vec<TextMatch>,
+    file: SimpleFile<Cow<str>, &String>,
+    rule: &TextRule,
+  ) -> Result<()> {
+    let path = file.name();
+    let jsons = matches
+      .into_iter()
+      .map(|m| TextRuleMatchJSON::new(m, path, rule));
     self.print_docs(jsons)
   }
 
@@ -249,10 +335,11 @@ impl<W: Write> Printer for JSONPrinter<W> {
     diffs: Diffs!('a),
     path: &Path,
     rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
   ) -> Result<()> {
     let path = path.to_string_lossy();
     let jsons = diffs.map(|diff| {
-      let mut v = RuleMatchJSON::new(diff.node_match, &path, rule);
+      let mut v = RuleMatchJSON::new(diff.node_match, &path, rule, severity);
       v.matched.replacement = Some(diff.replacement);
       v
     });
@@ -261,12 +348,20 @@ impl<W: Write> Printer for JSONPrinter<W> {
 
   fn before_print(&self) -> Result<()> {
     let mut lock = self.output.lock().expect("should work");
+    if self.cbor {
+      lock.write_all(&[CBOR_INDEFINITE_ARRAY])?;
+      return Ok(());
+    }
     write!(&mut lock, "[")?;
     Ok(())
   }
 
   fn after_print(&self) -> Result<()> {
     let mut lock = self.output.lock().expect("should work");
+    if self.cbor {
+      lock.write_all(&[CBOR_BREAK])?;
+      return Ok(());
+    }
     let matched = self.matched.load(Ordering::Acquire);
     if matched {
       writeln!(&mut lock)?;
@@ -278,7 +373,183 @@ impl<W: Write> Printer for JSONPrinter<W> {
 
 #[cfg(test)]
 mod test {
+  use super::*;
+  use ast_grep_config::{from_yaml_string, GlobalRules};
+  use ast_grep_core::language::Language;
+
   #[test]
   #[ignore]
   fn test_invariant() {}
+
+  fn get_text(printer: JSONPrinter<Vec<u8>>) -> String {
+    let buffer = printer.output.into_inner().expect("should work");
+    String::from_utf8(buffer).expect("should be valid utf8")
+  }
+
+  fn make_rule(metadata: &str) -> RuleConfig<SupportLang> {
+    let yaml = format!(
+      "id: test\nmessage: test\nseverity: info\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)\n{metadata}"
+    );
+    let globals = GlobalRules::default();
+    let mut rules = from_yaml_string(&yaml, &globals).expect("should parse");
+    rules.pop().unwrap()
+  }
+
+  fn print_rule_json(rule: &RuleConfig<SupportLang>) -> serde_json::Value {
+    let printer = JSONPrinter::new(vec![]);
+    let src = "console.log(123)".to_string();
+    let grep = SupportLang::TypeScript.ast_grep(&src);
+    let matches = grep.root().find_all(&rule.matcher);
+    let file = SimpleFile::new(Cow::Borrowed("test.ts"), &src);
+    printer.before_print().unwrap();
+    printer
+      .print_rule(matches, file, rule, &rule.severity)
+      .unwrap();
+    printer.after_print().unwrap();
+    serde_json::from_str(&get_text(printer)).expect("should be valid json")
+  }
+
+  #[test]
+  fn test_reporter() {
+    let rule = make_rule("");
+    let rules = ast_grep_config::RuleCollection::try_new(vec![rule]).expect("should build");
+    let files = [
+      ("a.ts", "console.log(1); console.log(2)".to_string()),
+      ("b.py", "console.log(3)".to_string()),
+      ("c.ts", "let a = 1".to_string()),
+    ];
+    let mut printer = JSONPrinter::new(vec![]);
+    rules.report(files, &mut printer).expect("should report");
+    let json: serde_json::Value = serde_json::from_str(&get_text(printer)).unwrap();
+    let found: Vec<_> = json
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|m| (m["file"].as_str().unwrap(), m["text"].as_str().unwrap()))
+      .collect();
+    assert_eq!(
+      found,
+      [("a.ts", "console.log(1)"), ("a.ts", "console.log(2)")]
+    );
+    assert_eq!(json[0]["ruleId"], "test");
+  }
+
+  #[test]
+  fn test_rule_metadata() {
+    let rule = make_rule("metadata:\n  owner: team-a\n  tickets: [A-1]\n  level: 2");
+    let json = print_rule_json(&rule);
+    let metadata = &json[0]["metadata"];
+    assert_eq!(metadata["owner"], "team-a");
+    assert_eq!(metadata["tickets"][0], "A-1");
+    assert_eq!(metadata["level"], 2);
+  }
+
+  #[test]
+  fn test_rule_without_metadata() {
+    let rule = make_rule("");
+    let json = print_rule_json(&rule);
+    assert_eq!(json[0]["ruleId"], "test");
+    assert!(json[0].get("metadata").is_none());
+  }
+
+  #[test]
+  fn test_text_rule() {
+    let yaml =
+      "{ id: fixme, message: no fixme, severity: warning, language: all, rule: { regex: FIXME } }";
+    let globals = GlobalRules::<SupportLang>::default();
+    let (_, mut rules) =
+      ast_grep_config::from_yaml_string_with_text_rules(yaml, &globals, None, &Default::default())
+        .expect("should parse");
+    let rule = rules.pop().unwrap();
+    let src = "# title\nFIXME: more".to_string();
+    let printer = JSONPrinter::new(vec![]);
+    let file = SimpleFile::new(Cow::Borrowed("README.md"), &src);
+    printer.before_print().unwrap();
+    printer
+      .print_text_rule(rule.find_all(&src).collect(), file, &rule)
+      .unwrap();
+    printer.after_print().unwrap();
+    let json: serde_json::Value = serde_json::from_str(&get_text(printer)).unwrap();
+    let matched = &json[0];
+    assert_eq!(matched["text"], "FIXME");
+    assert_eq!(matched["file"], "README.md");
+    assert_eq!(matched["ruleId"], "fixme");
+    assert_eq!(matched["severity"], "warning");
+    assert_eq!(matched["message"], "no fixme");
+    assert_eq!(matched["byteRange"], serde_json::json!([8, 13]));
+    assert_eq!(
+      matched["range"]["start"],
+      serde_json::json!({"line": 2, "column": 0})
+    );
+    assert!(matched.get("language").is_none());
+  }
+
+  #[test]
+  fn test_match_location() {
+    let printer = JSONPrinter::new(vec![]);
+    let grep = SupportLang::TypeScript.ast_grep("let a = 1\nfoo(\n  bar)");
+    let matches = grep.root().find_all("foo($A)");
+    printer.before_print().unwrap();
+    printer.print_matches(matches, "test.ts".as_ref()).unwrap();
+    printer.after_print().unwrap();
+    let json: serde_json::Value = serde_json::from_str(&get_text(printer)).unwrap();
+    let matched = &json[0];
+    assert_eq!(matched["byteRange"], serde_json::json!([10, 21]));
+    let range = serde_json::json!({
+      "start": { "line": 2, "column": 0 },
+      "end": { "line": 3, "column": 6 },
+    });
+    assert_eq!(matched["range"], range);
+    let single = &matched["metaVariables"]["single"]["A"];
+    assert_eq!(single["byteRange"], serde_json::json!([17, 20]));
+    assert_eq!(
+      single["range"]["start"],
+      serde_json::json!({"line": 3, "column": 2})
+    );
+  }
+
+  #[test]
+  fn test_rule_match_location() {
+    let rule = make_rule("");
+    let json = print_rule_json(&rule);
+    assert_eq!(json[0]["byteRange"], serde_json::json!([0, 16]));
+    assert_eq!(json[0]["range"]["start"]["line"], 1);
+    assert_eq!(json[0]["range"]["end"]["column"], 16);
+  }
+
+  #[test]
+  fn test_cbor_round_trip() {
+    let rule = make_rule("metadata:\n  owner: team-a");
+    let src = "console.log(123)\nconsole.log(a, 'µ')".to_string();
+    let grep = SupportLang::TypeScript.ast_grep(&src);
+    let print = |printer: &JSONPrinter<Vec<u8>>| {
+      let matches = grep.root().find_all(&rule.matcher);
+      let file = SimpleFile::new(Cow::Borrowed("test.ts"), &src);
+      printer.before_print().unwrap();
+      printer
+        .print_rule(matches, file, &rule, &rule.severity)
+        .unwrap();
+      let matches = grep.root().find_all("console.log($$$ARGS)");
+      printer.print_matches(matches, "test.ts".as_ref()).unwrap();
+      printer.after_print().unwrap();
+    };
+    let json_printer = JSONPrinter::new(vec![]);
+    print(&json_printer);
+    let json: serde_json::Value = serde_json::from_str(&get_text(json_printer)).unwrap();
+    let cbor_printer = JSONPrinter::new(vec![]).cbor(true);
+    print(&cbor_printer);
+    let buffer = cbor_printer.output.into_inner().unwrap();
+    let decoded: serde_json::Value = serde_cbor::from_slice(&buffer).unwrap();
+    assert_eq!(json.as_array().map(Vec::len), Some(3));
+    assert_eq!(decoded, json);
+  }
+
+  #[test]
+  fn test_cbor_no_match() {
+    let printer = JSONPrinter::new(vec![]).cbor(true);
+    printer.before_print().unwrap();
+    printer.after_print().unwrap();
+    let buffer = printer.output.into_inner().unwrap();
+    assert_eq!(buffer, [CBOR_INDEFINITE_ARRAY, CBOR_BREAK]);
+  }
 }
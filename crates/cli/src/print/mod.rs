@@ -1,22 +1,33 @@
+mod cloud_print;
 mod colored_print;
 mod interactive_print;
 mod json_print;
+mod rewrite_print;
+mod template_print;
 
-use ast_grep_config::RuleConfig;
-use ast_grep_core::{Matcher, NodeMatch, Pattern};
+use ast_grep_config::{Reporter, RuleConfig, Severity, TextMatch, TextRule};
+use ast_grep_core::language::Language;
+use ast_grep_core::{Matcher, Node, NodeMatch, Pattern};
 use ast_grep_language::SupportLang;
 
 use anyhow::Result;
 use clap::ValueEnum;
 
 use std::borrow::Cow;
+use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::path::Path;
 
+pub use cloud_print::{CloudPrinter, Platform};
 pub use codespan_reporting::files::SimpleFile;
 pub use codespan_reporting::term::termcolor::ColorChoice;
-pub use colored_print::{print_diff, ColoredPrinter, Heading, PrintStyles, ReportStyle};
+pub use colored_print::{
+  print_diff, ColoredPrinter, Heading, PrintStyles, ReportStyle, DEFAULT_DIFF_CONTEXT,
+};
 pub use interactive_print::InteractivePrinter;
 pub use json_print::JSONPrinter;
+pub use rewrite_print::RewritePrinter;
+pub use template_print::{Template, TemplatePrinter};
 
 // add this macro because neither trait_alias nor type_alias_impl is supported.
 macro_rules! Matches {
@@ -27,11 +38,21 @@ macro_rules! Diffs {
 }
 
 pub trait Printer {
+  /// Print the matches of a rule. The severity is the one reported for the matches,
+  /// which differs from the rule's if it is escalated by `escalate`.
   fn print_rule<'a>(
     &self,
     matches: Matches!('a),
     file: SimpleFile<Cow<str>, &String>,
     rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
+  ) -> Result<()>;
+  /// Print the matches of a rule with `language: all` in the raw text of a file.
+  fn print_text_rule(
+    &self,
+    matches: Vec<TextMatch>,
+    file: SimpleFile<Cow<str>, &String>,
+    rule: &TextRule,
   ) -> Result<()>;
   fn print_matches<'a>(&self, matches: Matches!('a), path: &Path) -> Result<()>;
   fn print_diffs<'a>(&self, diffs: Diffs!('a), path: &Path) -> Result<()>;
@@ -40,6 +61,7 @@ pub trait Printer {
     diffs: Diffs!('a),
     path: &Path,
     rule: &RuleConfig<SupportLang>,
+    severity: &Severity,
   ) -> Result<()>;
   #[inline]
   fn before_print(&self) -> Result<()> {
@@ -49,6 +71,92 @@ pub trait Printer {
   fn after_print(&self) -> Result<()> {
     Ok(())
   }
+  /// Print the header of a group of findings, e.g. a rule id under `scan --group-by rule`.
+  #[inline]
+  fn print_group(&self, _title: &str) -> Result<()> {
+    Ok(())
+  }
+}
+
+/// Print nothing. Used when only the exit code matters.
+pub struct SilentPrinter;
+
+/// Print one match reported by `RuleCollection::report` as a finding of the rule.
+fn report_match(
+  printer: &impl Printer,
+  file: &Path,
+  rule: &RuleConfig<SupportLang>,
+  matched: &NodeMatch<SupportLang>,
+) -> Result<()> {
+  let root = matched.ancestors().last();
+  let source = root
+    .map_or_else(|| matched.text(), |r| r.text())
+    .to_string();
+  let file = SimpleFile::new(file.to_string_lossy(), &source);
+  printer.print_rule(std::iter::once(matched.clone()), file, rule, &rule.severity)
+}
+
+// let library users plug the human and JSON formatters into RuleCollection::report
+macro_rules! impl_reporter {
+  ($printer: ident, $bound: path) => {
+    impl<W: $bound> Reporter<SupportLang> for $printer<W> {
+      type Error = anyhow::Error;
+      fn on_start(&mut self) -> Result<()> {
+        self.before_print()
+      }
+      fn on_match(
+        &mut self,
+        file: &Path,
+        rule: &RuleConfig<SupportLang>,
+        matched: &NodeMatch<SupportLang>,
+      ) -> Result<()> {
+        report_match(self, file, rule, matched)
+      }
+      fn on_finish(&mut self) -> Result<()> {
+        self.after_print()
+      }
+    }
+  };
+}
+impl_reporter!(
+  ColoredPrinter,
+  codespan_reporting::term::termcolor::WriteColor
+);
+impl_reporter!(JSONPrinter, std::io::Write);
+
+impl Printer for SilentPrinter {
+  fn print_rule<'a>(
+    &self,
+    _matches: Matches!('a),
+    _file: SimpleFile<Cow<str>, &String>,
+    _rule: &RuleConfig<SupportLang>,
+    _severity: &Severity,
+  ) -> Result<()> {
+    Ok(())
+  }
+  fn print_text_rule(
+    &self,
+    _matches: Vec<TextMatch>,
+    _file: SimpleFile<Cow<str>, &String>,
+    _rule: &TextRule,
+  ) -> Result<()> {
+    Ok(())
+  }
+  fn print_matches<'a>(&self, _matches: Matches!('a), _path: &Path) -> Result<()> {
+    Ok(())
+  }
+  fn print_diffs<'a>(&self, _diffs: Diffs!('a), _path: &Path) -> Result<()> {
+    Ok(())
+  }
+  fn print_rule_diffs<'a>(
+    &self,
+    _diffs: Diffs!('a),
+    _path: &Path,
+    _rule: &RuleConfig<SupportLang>,
+    _severity: &Severity,
+  ) -> Result<()> {
+    Ok(())
+  }
 }
 
 #[derive(Clone)]
@@ -57,6 +165,8 @@ pub struct Diff<'n> {
   pub node_match: NodeMatch<'n, SupportLang>,
   /// string content for the replacement
   pub replacement: Cow<'n, str>,
+  /// edits of the same fix outside the matched node, e.g. adding an import
+  pub extra_edits: Vec<(Range<usize>, String)>,
 }
 
 impl<'n> Diff<'n> {
@@ -75,10 +185,114 @@ impl<'n> Diff<'n> {
     Self {
       node_match,
       replacement,
+      extra_edits: vec![],
+    }
+  }
+
+  /// The diff of the rule's fix for the match. Edits of a fix list inside the matched node
+  /// make up the replacement. The matched code is kept if the fix list cannot be applied.
+  pub fn from_rule(node_match: NodeMatch<'n, SupportLang>, rule: &RuleConfig<SupportLang>) -> Self {
+    if let Some(fixer) = &rule.fixer {
+      return Self::generate(node_match, &rule.matcher, fixer);
+    }
+    let range = node_match.range();
+    let mut replacement = node_match.text().to_string();
+    let mut extra_edits = vec![];
+    let edits = rule.fix_edits(&node_match).unwrap_or_default();
+    // edits are sorted, so apply them from the end to keep earlier offsets valid
+    for edit in edits.into_iter().rev() {
+      let edit_range = edit.position..edit.position + edit.deleted_length;
+      if range.start <= edit_range.start && edit_range.end <= range.end {
+        let inner = edit_range.start - range.start..edit_range.end - range.start;
+        replacement.replace_range(inner, &edit.inserted_text);
+      } else {
+        extra_edits.push((edit_range, edit.inserted_text));
+      }
+    }
+    Self {
+      node_match,
+      replacement: Cow::Owned(replacement),
+      extra_edits,
+    }
+  }
+
+  /// All edits of the diff: the replacement of the matched node and the extra edits.
+  pub fn edits(&self) -> Vec<(Range<usize>, String)> {
+    let mut edits = self.extra_edits.clone();
+    edits.push((self.node_match.range(), self.replacement.to_string()));
+    edits
+  }
+
+  /// Make multi-line lists in the replacement follow the trailing comma style of the matched code.
+  /// The replacement is kept as is if the matched code has no multi-line list.
+  pub fn preserve_trailing_comma(self) -> Self {
+    let Some(trailing) = trailing_comma_style(&self.node_match) else {
+      return self;
+    };
+    let grep = self.node_match.lang().ast_grep(&self.replacement);
+    let mut edits = vec![];
+    for last in grep.root().dfs().filter_map(|n| multiline_list_last(&n)) {
+      let range = last.range();
+      if last.kind() == "," && !trailing {
+        edits.push((range, ""));
+      } else if last.is_named() && !last.kind().contains("comment") && !is_rest(&last) && trailing {
+        edits.push((range.end..range.end, ","));
+      }
+    }
+    if edits.is_empty() {
+      return self;
+    }
+    let mut replacement = self.replacement.into_owned();
+    for (range, text) in edits.into_iter().rev() {
+      replacement.replace_range(range, text);
+    }
+    Self {
+      node_match: self.node_match,
+      replacement: Cow::Owned(replacement),
+      extra_edits: self.extra_edits,
     }
   }
 }
 
+/// Whether multi-line lists in the node end with a comma, or None if there is no such list.
+fn trailing_comma_style<L: Language>(node: &Node<L>) -> Option<bool> {
+  let mut lasts = node
+    .dfs()
+    .filter_map(|n| multiline_list_last(&n))
+    .peekable();
+  lasts.peek()?;
+  Some(lasts.any(|n| n.kind() == ","))
+}
+
+/// The last child before the closing bracket of a comma separated list,
+/// if the closing bracket is on its own line.
+fn multiline_list_last<'r, L: Language>(node: &Node<'r, L>) -> Option<Node<'r, L>> {
+  if node.is_named() || !matches!(&*node.kind(), ")" | "]" | "}") {
+    return None;
+  }
+  let last = node.prev()?;
+  if last.end_pos().0 == node.start_pos().0 {
+    return None;
+  }
+  let parent = node.parent()?;
+  let has_comma = parent.children().any(|c| c.kind() == ",");
+  has_comma.then_some(last)
+}
+
+/// Rest elements like `...rest` in JavaScript or `..` in Rust patterns must come last
+/// without a comma after them.
+fn is_rest<L: Language>(node: &Node<L>) -> bool {
+  const REST_KINDS: [&str; 3] = [
+    "rest_pattern",
+    "remaining_field_pattern",
+    "base_field_initializer",
+  ];
+  let first = node.children().find(|n| n.is_named());
+  std::iter::once(node.clone())
+    .chain(first)
+    .any(|n| REST_KINDS.contains(&&*n.kind()))
+}
+
 #[derive(ValueEnum, Clone, Copy)]
 pub enum ColorArg {
   /// Try to use colors, but don't force the issue. If the output is piped to another program,
@@ -112,3 +326,134 @@ impl From<ColorArg> for ColorChoice {
     }
   }
 }
+
+/// The 0-based column of the byte offset as editors display it:
+/// a tab moves to the next multiple of `tab_width` and other characters take one column.
+pub fn display_column(source: &str, offset: usize, tab_width: NonZeroUsize) -> usize {
+  let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+  let tab_width = tab_width.get();
+  source[line_start..offset].chars().fold(0, |col, c| {
+    if c == '\t' {
+      col + tab_width - col % tab_width
+    } else {
+      col + 1
+    }
+  })
+}
+
+/// Apply all diffs to the source of the matched file and return the new content.
+fn apply_rewrite(diffs: Vec<Diff>) -> String {
+  let Some(first) = diffs.first() else {
+    return String::new();
+  };
+  let old_content = first.node_match.ancestors().last().unwrap().text();
+  let edits = diffs.iter().map(Diff::edits).collect();
+  apply_edits_bottom_up(old_content.to_string(), edits)
+}
+
+/// Apply the edits of each finding from the end of the file so earlier ranges stay valid.
+/// The edits of a finding are applied all or none: if any of them overlaps an edit of a later
+/// finding, e.g. of a nested match, the whole finding is dropped. Identical edits, e.g. the same
+/// import added by fixes of different matches, are applied once.
+pub fn apply_edits_bottom_up(
+  mut content: String,
+  findings: Vec<Vec<(Range<usize>, String)>>,
+) -> String {
+  let mut edits: Vec<(Range<usize>, String)> = vec![];
+  for finding in findings.into_iter().rev() {
+    let accepted = edits.len();
+    let applicable = finding.into_iter().all(|edit| {
+      if edits.contains(&edit) {
+        return true;
+      }
+      let conflict = edits.iter().any(|(range, _)| overlaps(range, &edit.0));
+      edits.push(edit);
+      !conflict
+    });
+    if !applicable {
+      edits.truncate(accepted);
+    }
+  }
+  // an insertion is applied after a replacement starting at the same position
+  edits.sort_by(|(a, a_text), (b, b_text)| {
+    (b.start, b.end)
+      .cmp(&(a.start, a.end))
+      .then_with(|| a_text.cmp(b_text))
+  });
+  for (range, replacement) in edits {
+    content.replace_range(range, &replacement);
+  }
+  content
+}
+
+/// Two replacements overlap if they share a character.
+/// An insertion overlaps a replacement only if it is strictly inside of it.
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+  a.start < b.end && b.start < a.end
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ast_grep_config::{from_yaml_string, GlobalRules};
+
+  #[test]
+  fn test_display_column() {
+    let width = NonZeroUsize::new(4).unwrap();
+    let src = "a\n\tb\n  \tc\n\t\té";
+    assert_eq!(display_column(src, 0, width), 0);
+    assert_eq!(display_column(src, 3, width), 4);
+    assert_eq!(display_column(src, 8, width), 4);
+    assert_eq!(display_column(src, 12, width), 8);
+    // multi-byte characters take one column
+    assert_eq!(display_column(src, src.len(), width), 9);
+  }
+
+  #[test]
+  fn test_apply_edits_bottom_up() {
+    let edits = vec![
+      vec![(0..3, "x".into())],
+      vec![(4..7, "yy".into())],
+      vec![(5..6, "z".into())],
+    ];
+    // the overlapping outer edit is dropped
+    assert_eq!(apply_edits_bottom_up("abc def".into(), edits), "x dzf");
+    // identical insertions are applied once
+    let edits = vec![
+      vec![(0..0, "a ".into()), (0..3, "x".into())],
+      vec![(0..0, "a ".into())],
+    ];
+    assert_eq!(apply_edits_bottom_up("abc def".into(), edits), "a x def");
+    // all edits of a finding are dropped if one of them overlaps
+    let edits = vec![
+      vec![(0..0, "a ".into()), (4..7, "yy".into())],
+      vec![(5..6, "z".into())],
+    ];
+    assert_eq!(apply_edits_bottom_up("abc def".into(), edits), "abc dzf");
+  }
+
+  #[test]
+  fn test_fix_list() {
+    let yaml = "
+id: use-logger
+message: test
+severity: hint
+language: TypeScript
+rule: { pattern: console.log($A) }
+fix:
+  - { target: fileStart, template: \"import { logger } from './logger'\" }
+  - { template: logger.info($A) }";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let src = "let a = 1\nconsole.log(a)\nconsole.log(2)";
+    let grep = SupportLang::TypeScript.ast_grep(src);
+    let diffs: Vec<_> = grep
+      .root()
+      .find_all(&rules[0].matcher)
+      .map(|nm| Diff::from_rule(nm, &rules[0]))
+      .collect();
+    assert_eq!(diffs[0].replacement, "logger.info(a)");
+    assert_eq!(diffs[0].extra_edits.len(), 1);
+    let expected = "import { logger } from './logger'\nlet a = 1\nlogger.info(a)\nlogger.info(2)";
+    assert_eq!(apply_rewrite(diffs), expected);
+  }
+}
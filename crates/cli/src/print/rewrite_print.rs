@@ -0,0 +1,147 @@
+use ast_grep_config::{RuleConfig, Severity, TextMatch, TextRule};
+use ast_grep_core::NodeMatch;
+use ast_grep_language::SupportLang;
+
+use super::{apply_rewrite, Diff, Printer};
+use anyhow::Result;
+pub use codespan_reporting::files::SimpleFile;
+
+use std::borrow::Cow;
+use std::io::{Stdout, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// add this macro because neither trait_alias nor type_alias_impl is supported.
+macro_rules! Matches {
+  ($lt: lifetime) => { impl Iterator<Item = NodeMatch<$lt, SupportLang>> };
+}
+macro_rules! Diffs {
+  ($lt: lifetime) => { impl Iterator<Item = Diff<$lt>> };
+}
+
+/// Print rewritten file content instead of diffs, without modifying files.
+/// Matches without rewrite are not printed.
+pub struct RewritePrinter<W: Write> {
+  output: Mutex<W>,
+  print_path: bool,
+}
+
+impl RewritePrinter<Stdout> {
+  pub fn stdout() -> Self {
+    Self::new(std::io::stdout())
+  }
+}
+
+impl<W: Write> RewritePrinter<W> {
+  pub fn new(output: W) -> Self {
+    Self {
+      output: Mutex::new(output),
+      print_path: false,
+    }
+  }
+
+  /// Print the file path as a header before the rewritten content of each file.
+  pub fn print_path(mut self, print_path: bool) -> Self {
+    self.print_path = print_path;
+    self
+  }
+
+  fn print_rewrite(&self, diffs: Vec<Diff>, path: &Path) -> Result<()> {
+    if diffs.is_empty() {
+      return Ok(());
+    }
+    let new_content = apply_rewrite(diffs);
+    let mut lock = self.output.lock().expect("should work");
+    if self.print_path {
+      writeln!(&mut lock, "{}", path.display())?;
+    }
+    write!(&mut lock, "{new_content}")?;
+    Ok(())
+  }
+}
+
+impl<W: Write> Printer for RewritePrinter<W> {
+  fn print_rule<'a>(
+    &self,
+    _matches: Matches!('a),
+    _file: SimpleFile<Cow<str>, &String>,
+    _rule: &RuleConfig<SupportLang>,
+    _severity: &Severity,
+  ) -> Result<()> {
+    Ok(())
+  }
+
+  fn print_text_rule(
+    &self,
+    _matches: Vec<TextMatch>,
+    _file: SimpleFile<Cow<str>, &String>,
+    _rule: &TextRule,
+  ) -> Result<()> {
+    Ok(())
+  }
+
+  fn print_matches<'a>(&self, _matches: Matches!('a), _path: &Path) -> Result<()> {
+    Ok(())
+  }
+
+  fn print_diffs<'a>(&self, diffs: Diffs!('a), path: &Path) -> Result<()> {
+    self.print_rewrite(diffs.collect(), path)
+  }
+
+  fn print_rule_diffs<'a>(
+    &self,
+    diffs: Diffs!('a),
+    path: &Path,
+    _rule: &RuleConfig<SupportLang>,
+    _severity: &Severity,
+  ) -> Result<()> {
+    self.print_rewrite(diffs.collect(), path)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ast_grep_core::traversal::Visitor;
+  use ast_grep_core::{AstGrep, Pattern};
+
+  fn get_text(printer: RewritePrinter<Vec<u8>>) -> String {
+    let buffer = printer.output.into_inner().expect("should work");
+    String::from_utf8(buffer).expect("should be valid utf8")
+  }
+
+  fn print_rewrite(printer: &RewritePrinter<Vec<u8>>, src: &str, path: &str) -> String {
+    let grep = AstGrep::new(src, SupportLang::TypeScript);
+    let pattern = Pattern::new("console.log($A)", SupportLang::TypeScript);
+    let rewrite = Pattern::new("logger.info($A)", SupportLang::TypeScript);
+    let diffs = || {
+      Visitor::new(&pattern)
+        .reentrant(false)
+        .visit(grep.root())
+        .map(|nm| Diff::generate(nm, &pattern, &rewrite))
+    };
+    printer.print_diffs(diffs(), path.as_ref()).unwrap();
+    // content to write when applying the rewrite in place
+    apply_rewrite(diffs().collect())
+  }
+
+  #[test]
+  fn test_print_rewrite() {
+    let printer = RewritePrinter::new(vec![]);
+    let src = "console.log(1);\nfoo();\nconsole.log(2);\n";
+    let applied = print_rewrite(&printer, src, "test.ts");
+    let text = get_text(printer);
+    assert_eq!(text, "logger.info(1);\nfoo();\nlogger.info(2);\n");
+    assert_eq!(text, applied);
+  }
+
+  #[test]
+  fn test_print_rewrite_with_path() {
+    let printer = RewritePrinter::new(vec![]).print_path(true);
+    print_rewrite(&printer, "console.log(1);\n", "a.ts");
+    print_rewrite(&printer, "no_match();\n", "b.ts");
+    print_rewrite(&printer, "console.log(3);\n", "c.ts");
+    let text = get_text(printer);
+    assert_eq!(text, "a.ts\nlogger.info(1);\nc.ts\nlogger.info(3);\n");
+  }
+}
@@ -0,0 +1,365 @@
+use ast_grep_config::{RuleConfig, Severity, TextMatch, TextRule};
+use ast_grep_core::NodeMatch;
+use ast_grep_language::SupportLang;
+
+use super::{display_column, Diff, Printer};
+use anyhow::Result;
+pub use codespan_reporting::files::SimpleFile;
+
+use std::borrow::Cow;
+use std::io::{Stdout, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::Mutex;
+
+// add this macro because neither trait_alias nor type_alias_impl is supported.
+macro_rules! Matches {
+  ($lt: lifetime) => { impl Iterator<Item = NodeMatch<$lt, SupportLang>> };
+}
+macro_rules! Diffs {
+  ($lt: lifetime) => { impl Iterator<Item = Diff<$lt>> };
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+  Literal(String),
+  Path,
+  /// 1-based start line
+  Line,
+  /// 1-based start column
+  Col,
+  EndLine,
+  EndCol,
+  /// text of the matched node
+  Match,
+  Rule,
+  Message,
+  /// text of a captured meta variable
+  Var(String),
+}
+
+/// A line format like `{path}:{line}:{col}: {match}`, printed once per match.
+/// `{{` and `}}` print literal braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template(Vec<Segment>);
+
+impl Template {
+  pub fn try_new(src: &str) -> Result<Self, String> {
+    let mut segments = vec![];
+    let mut literal = String::new();
+    let mut rest = src;
+    while let Some(i) = rest.find(['{', '}']) {
+      literal.push_str(&rest[..i]);
+      let brace = &rest[i..i + 1];
+      rest = &rest[i + 1..];
+      if let Some(r) = rest.strip_prefix(brace) {
+        literal.push_str(brace);
+        rest = r;
+        continue;
+      }
+      if brace == "}" {
+        return Err("unmatched `}` in template. Use `}}` to print a brace".into());
+      }
+      let (name, r) = rest
+        .split_once('}')
+        .ok_or_else(|| "unclosed `{` in template. Use `{{` to print a brace".to_string())?;
+      rest = r;
+      if !literal.is_empty() {
+        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+      }
+      segments.push(parse_placeholder(name)?);
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+      segments.push(Segment::Literal(literal));
+    }
+    Ok(Self(segments))
+  }
+
+  fn render(
+    &self,
+    nm: &NodeMatch<SupportLang>,
+    path: &str,
+    rule: Option<&RuleConfig<SupportLang>>,
+    tab_width: Option<NonZeroUsize>,
+  ) -> String {
+    let mut fields = Fields {
+      path,
+      start: nm.start_pos(),
+      end: nm.end_pos(),
+      text: &nm.text(),
+      rule: rule.map_or("", |r| &r.id),
+      message: &rule.map(|r| r.get_message(nm)).unwrap_or_default(),
+    };
+    if let Some(tab_width) = tab_width {
+      let src = root_text(nm);
+      fields.start.1 = display_column(&src, nm.range().start, tab_width);
+      fields.end.1 = display_column(&src, nm.range().end, tab_width);
+    }
+    self.render_fields(&fields, |name| capture_text(nm, name))
+  }
+
+  /// Render a match of a rule with `language: all`. It has no meta variables so `{var:..}` is empty.
+  fn render_text(
+    &self,
+    m: &TextMatch,
+    path: &str,
+    rule: &TextRule,
+    tab_width: Option<NonZeroUsize>,
+  ) -> String {
+    let mut fields = Fields {
+      path,
+      start: m.start_pos(),
+      end: m.end_pos(),
+      text: m.text(),
+      rule: &rule.id,
+      message: &rule.message,
+    };
+    if let Some(tab_width) = tab_width {
+      let src = m.source();
+      fields.start.1 = display_column(src, m.range().start, tab_width);
+      fields.end.1 = display_column(src, m.range().end, tab_width);
+    }
+    self.render_fields(&fields, |_| Cow::Borrowed(""))
+  }
+
+  fn render_fields<'a>(&self, fields: &Fields, var: impl Fn(&str) -> Cow<'a, str>) -> String {
+    let (line, col) = fields.start;
+    let (end_line, end_col) = fields.end;
+    let mut ret = String::new();
+    for segment in &self.0 {
+      match segment {
+        Segment::Literal(s) => ret.push_str(s),
+        Segment::Path => ret.push_str(fields.path),
+        Segment::Line => ret.push_str(&(line + 1).to_string()),
+        Segment::Col => ret.push_str(&(col + 1).to_string()),
+        Segment::EndLine => ret.push_str(&(end_line + 1).to_string()),
+        Segment::EndCol => ret.push_str(&(end_col + 1).to_string()),
+        Segment::Match => ret.push_str(fields.text),
+        Segment::Rule => ret.push_str(fields.rule),
+        Segment::Message => ret.push_str(fields.message),
+        Segment::Var(name) => ret.push_str(&var(name)),
+      }
+    }
+    ret
+  }
+}
+
+/// Values of the placeholders for one match. Positions are zero-based.
+struct Fields<'a> {
+  path: &'a str,
+  start: (usize, usize),
+  end: (usize, usize),
+  text: &'a str,
+  rule: &'a str,
+  message: &'a str,
+}
+
+fn parse_placeholder(name: &str) -> Result<Segment, String> {
+  let segment = match name {
+    "path" => Segment::Path,
+    "line" => Segment::Line,
+    "col" => Segment::Col,
+    "end_line" => Segment::EndLine,
+    "end_col" => Segment::EndCol,
+    "match" => Segment::Match,
+    "rule" => Segment::Rule,
+    "message" => Segment::Message,
+    _ => match name.strip_prefix("var:") {
+      Some(var) if !var.is_empty() => Segment::Var(var.to_string()),
+      _ => return Err(format!("unknown placeholder `{{{name}}}` in template")),
+    },
+  };
+  Ok(segment)
+}
+
+/// Text of a single capture, or the source spanned by a multiple capture. Empty if not captured.
+fn capture_text<'a>(nm: &NodeMatch<'a, SupportLang>, name: &str) -> Cow<'a, str> {
+  let env = nm.get_env();
+  if let Some(node) = env.get_match(name) {
+    return node.text();
+  }
+  let nodes = env.get_multiple_matches(name);
+  let (Some(first), Some(last)) = (nodes.first(), nodes.last()) else {
+    return Cow::Borrowed("");
+  };
+  let src = root_text(nm);
+  Cow::Owned(src[first.range().start..last.range().end].to_string())
+}
+
+fn root_text<'a>(nm: &NodeMatch<'a, SupportLang>) -> Cow<'a, str> {
+  let root = nm
+    .ancestors()
+    .last()
+    .unwrap_or_else(|| nm.get_node().clone());
+  root.text()
+}
+
+/// Print every match as one line rendered from a user provided [`Template`].
+pub struct TemplatePrinter<W: Write> {
+  output: Mutex<W>,
+  template: Template,
+  tab_width: Option<NonZeroUsize>,
+}
+
+impl TemplatePrinter<Stdout> {
+  pub fn stdout(template: Template) -> Self {
+    Self::new(std::io::stdout(), template)
+  }
+}
+
+impl<W: Write> TemplatePrinter<W> {
+  pub fn new(output: W, template: Template) -> Self {
+    Self {
+      output: Mutex::new(output),
+      template,
+      tab_width: None,
+    }
+  }
+
+  /// Report `{col}` and `{end_col}` as display columns with tabs expanded to the width.
+  pub fn tab_width(mut self, tab_width: Option<NonZeroUsize>) -> Self {
+    self.tab_width = tab_width;
+    self
+  }
+
+  fn print_lines<'a>(
+    &self,
+    matches: Matches!('a),
+    path: &str,
+    rule: Option<&RuleConfig<SupportLang>>,
+  ) -> Result<()> {
+    let mut lock = self.output.lock().expect("should work");
+    for nm in matches {
+      let line = self.template.render(&nm, path, rule, self.tab_width);
+      writeln!(&mut lock, "{line}")?;
+    }
+    Ok(())
+  }
+}
+
+impl<W: Write> Printer for TemplatePrinter<W> {
+  fn print_rule<'a>(
+    &self,
+    matches: Matches!('a),
+    file: SimpleFile<Cow<str>, &String>,
+    rule: &RuleConfig<SupportLang>,
+    _severity: &Severity,
+  ) -> Result<()> {
+    self.print_lines(matches, file.name(), Some(rule))
+  }
+
+  fn print_text_rule(
+    &self,
+    matches: Vec<TextMatch>,
+    file: SimpleFile<Cow<str>, &String>,
+    rule: &TextRule,
+  ) -> Result<()> {
+    let mut lock = self.output.lock().expect("should work");
+    for m in matches {
+      let line = self
+        .template
+        .render_text(&m, file.name(), rule, self.tab_width);
+      writeln!(&mut lock, "{line}")?;
+    }
+    Ok(())
+  }
+
+  fn print_matches<'a>(&self, matches: Matches!('a), path: &Path) -> Result<()> {
+    self.print_lines(matches, &path.to_string_lossy(), None)
+  }
+
+  fn print_diffs<'a>(&self, diffs: Diffs!('a), path: &Path) -> Result<()> {
+    let matches = diffs.map(|d| d.node_match);
+    self.print_lines(matches, &path.to_string_lossy(), None)
+  }
+
+  fn print_rule_diffs<'a>(
+    &self,
+    diffs: Diffs!('a),
+    path: &Path,
+    rule: &RuleConfig<SupportLang>,
+    _severity: &Severity,
+  ) -> Result<()> {
+    let matches = diffs.map(|d| d.node_match);
+    self.print_lines(matches, &path.to_string_lossy(), Some(rule))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ast_grep_config::{from_yaml_string, GlobalRules};
+  use ast_grep_core::language::Language;
+
+  fn get_text(printer: TemplatePrinter<Vec<u8>>) -> String {
+    let buffer = printer.output.into_inner().expect("should work");
+    String::from_utf8(buffer).expect("should be valid utf8")
+  }
+
+  fn print_matches(template: &str, pattern: &str, src: &str) -> String {
+    let template = Template::try_new(template).expect("should parse");
+    let printer = TemplatePrinter::new(vec![], template);
+    let grep = SupportLang::TypeScript.ast_grep(src);
+    let matches = grep.root().find_all(pattern);
+    printer.print_matches(matches, "src/a.ts".as_ref()).unwrap();
+    get_text(printer)
+  }
+
+  #[test]
+  fn test_capture_placeholder() {
+    let template = "{path}:{line}:{col}: {var:A}";
+    let text = print_matches(
+      template,
+      "console.log($A)",
+      "let a = 1\n  console.log(a + 1)",
+    );
+    assert_eq!(text, "src/a.ts:2:3: a + 1\n");
+    let template = "{match} [{end_line}:{end_col}] {{{var:ARGS}}}";
+    let text = print_matches(template, "foo($$$ARGS)", "foo(a,  b)\nfoo()");
+    assert_eq!(text, "foo(a,  b) [1:11] {a,  b}\nfoo() [2:6] {}\n");
+  }
+
+  #[test]
+  fn test_tab_width() {
+    let src = "function a() {\n\tif (b) {\n\t\tconsole.log(b)\n\t}\n}";
+    let template = Template::try_new("{line}:{col}-{end_col}").expect("should parse");
+    let grep = SupportLang::TypeScript.ast_grep(src);
+    let printer = TemplatePrinter::new(vec![], template.clone());
+    let matches = grep.root().find_all("console.log($A)");
+    printer.print_matches(matches, "a.ts".as_ref()).unwrap();
+    // tabs count as one byte by default
+    assert_eq!(get_text(printer), "3:3-17\n");
+    let printer = TemplatePrinter::new(vec![], template).tab_width(NonZeroUsize::new(4));
+    let matches = grep.root().find_all("console.log($A)");
+    printer.print_matches(matches, "a.ts".as_ref()).unwrap();
+    assert_eq!(get_text(printer), "3:9-23\n");
+  }
+
+  #[test]
+  fn test_rule_placeholder() {
+    let yaml = "id: no-log\nmessage: Remove $A\nseverity: info\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)";
+    let mut rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let rule = rules.pop().unwrap();
+    let template = Template::try_new("{rule}: {message}").unwrap();
+    let printer = TemplatePrinter::new(vec![], template);
+    let src = "console.log(b)".to_string();
+    let grep = SupportLang::TypeScript.ast_grep(&src);
+    let matches = grep.root().find_all(&rule.matcher);
+    let file = SimpleFile::new(Cow::Borrowed("a.ts"), &src);
+    printer
+      .print_rule(matches, file, &rule, &rule.severity)
+      .unwrap();
+    assert_eq!(get_text(printer), "no-log: Remove b\n");
+  }
+
+  #[test]
+  fn test_invalid_template() {
+    assert!(Template::try_new("{path}:{lines}").is_err());
+    assert!(Template::try_new("{var:}").is_err());
+    assert!(Template::try_new("{path").is_err());
+    assert!(Template::try_new("path}").is_err());
+    let template = Template::try_new("{{path}}").expect("should parse");
+    assert_eq!(template, Template(vec![Segment::Literal("{path}".into())]));
+  }
+}
@@ -1,9 +1,17 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::Write;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use anyhow::{Context, Result};
 use ast_grep_core::language::Language;
+use ast_grep_core::matcher::RegexMatcher;
+use ast_grep_core::meta_var::MetaVarEnv;
 use ast_grep_core::traversal::Visitor;
-use ast_grep_core::{Matcher, Pattern};
+use ast_grep_core::{Matcher, Node, Pattern};
 use clap::Parser;
 use ignore::WalkParallel;
 
@@ -11,21 +19,47 @@ use crate::config::{IgnoreFile, NoIgnore};
 use crate::error::ErrorContext as EC;
 use crate::print::{
   ColorArg, ColoredPrinter, Diff, Heading, InteractivePrinter, JSONPrinter, Printer,
+  RewritePrinter, Template, TemplatePrinter,
+};
+use crate::utils::{
+  filter_file_interactive, sort_items, MatchUnit, PathRenderer, PathStyle, SortBy,
 };
-use crate::utils::{filter_file_interactive, MatchUnit};
 use crate::utils::{run_worker, Items, Worker};
 use ast_grep_language::{file_types, SupportLang};
+use bit_set::BitSet;
 
 #[derive(Parser)]
 pub struct RunArg {
   /// AST pattern to match.
-  #[clap(short, long)]
+  #[clap(
+    short,
+    long,
+    required_unless_present_any = ["list_files", "pattern_file"],
+    default_value = "",
+    hide_default_value = true
+  )]
   pattern: String,
 
+  /// Read the AST pattern to match from a file. It is useful for long or multi-line patterns
+  /// and patterns with characters special to the shell. One trailing newline is ignored.
+  #[clap(long, value_name = "FILE", conflicts_with = "pattern")]
+  pattern_file: Option<PathBuf>,
+
   /// String to replace the matched AST node.
-  #[clap(short, long)]
+  #[clap(short, long, group = "rewriter")]
   rewrite: Option<String>,
 
+  /// Read the string to replace the matched AST node from a file.
+  /// It is useful for large multi-line templates. One trailing newline is ignored.
+  #[clap(long, value_name = "FILE", group = "rewriter")]
+  rewrite_file: Option<PathBuf>,
+
+  /// Keep the trailing comma style of multi-line lists in the matched code, e.g. arguments,
+  /// by adding or removing trailing commas in multi-line lists of the rewrite.
+  /// The rewrite is parsed on its own to find the lists.
+  #[clap(long, requires = "rewriter")]
+  preserve_trailing_comma: bool,
+
   /// Print query pattern's tree-sitter AST. Requires lang be set explicitly.
   #[clap(long, requires = "lang")]
   debug_query: bool,
@@ -46,11 +80,21 @@ pub struct RunArg {
   #[clap(long)]
   accept_all: bool,
 
+  /// Write fixes only after every file is fixed successfully. Requires accept-all.
+  /// If any file fails, no file is modified and the failing file is reported.
+  #[clap(long, requires = "accept_all")]
+  atomic_fixes: bool,
+
   /// Output matches in structured JSON text useful for tools like jq.
   /// Conflicts with interactive.
   #[clap(long, conflicts_with = "interactive")]
   json: bool,
 
+  /// Encode the JSON output in CBOR, a compact binary format that is faster to decode.
+  /// The output is one CBOR array with the same documents as the JSON output.
+  #[clap(long, requires = "json")]
+  cbor: bool,
+
   /// Print the file name as heading before all matches of that file.
   /// File path will be printed before each match as prefix if heading is disabled.
   /// This is the default mode when printing to a terminal.
@@ -61,22 +105,204 @@ pub struct RunArg {
   #[clap(long, default_value = "auto")]
   color: ColorArg,
 
+  /// Print the first line of the nearest ancestor of the kind as context for the matches
+  /// inside it, e.g. `function_declaration` prints the enclosing function signature.
+  #[clap(
+    long,
+    value_name = "KIND",
+    conflicts_with = "rewriter",
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "rewrite_stdout",
+    conflicts_with = "format_template"
+  )]
+  after_context_rule: Option<String>,
+
   /// Do not respect hidden file system or ignore files (.gitignore, .ignore, etc.).
   /// You can suppress multiple ignore files by passing `no-ignore` multiple times.
   #[clap(long, action = clap::ArgAction::Append)]
   no_ignore: Vec<IgnoreFile>,
+
+  /// Print the rewritten content of matched files to stdout without modifying them.
+  #[clap(
+    long,
+    requires = "rewriter",
+    conflicts_with = "interactive",
+    conflicts_with = "json"
+  )]
+  rewrite_stdout: bool,
+
+  /// Print the file path before the rewritten content of each file.
+  #[clap(long, requires = "rewrite_stdout")]
+  print_path: bool,
+
+  /// Print only the paths of files where the pattern matches nothing, like `grep -L`.
+  #[clap(
+    long,
+    conflicts_with = "rewriter",
+    conflicts_with = "interactive",
+    conflicts_with = "json"
+  )]
+  files_without_match: bool,
+
+  /// Sort files in the output instead of printing them as soon as they are scanned.
+  /// Ties are broken by file path.
+  #[clap(long, value_name = "KEY", conflicts_with = "files_without_match")]
+  sort: Option<SortBy>,
+
+  /// Print each match as one line formatted by the template, e.g. `{path}:{line}:{col}: {match}`.
+  /// Placeholders are `{path}`, `{line}`, `{col}`, `{end_line}`, `{end_col}`, `{match}`,
+  /// `{rule}`, `{message}` and captures like `{var:NAME}`. Lines and columns are 1-based.
+  /// Rule placeholders are empty for pattern matches. Use `{{` and `}}` for literal braces.
+  #[clap(
+    long,
+    value_name = "TEMPLATE",
+    value_parser = Template::try_new,
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "rewrite_stdout",
+    conflicts_with = "files_without_match"
+  )]
+  format_template: Option<Template>,
+
+  /// Count a tab as advancing to the next multiple of N columns in `{col}` and `{end_col}`,
+  /// matching what editors show. Only the presentation changes, byte offsets are unaffected.
+  #[clap(long, value_name = "N", requires = "format_template")]
+  tab_width: Option<NonZeroUsize>,
+
+  /// How file paths are printed. `relative` is relative to the project root,
+  /// where sgconfig.yml is found. Paths are printed as walked by default.
+  /// Conflicts with interactive, which edits files by the walked paths.
+  #[clap(
+    long,
+    value_name = "STYLE",
+    conflicts_with = "interactive",
+    conflicts_with = "accept_all"
+  )]
+  path_style: Option<PathStyle>,
+
+  /// Print the sorted paths of files that would be searched, then exit without searching.
+  /// Files are filtered by the ignore files and `--lang`, or by a known file extension
+  /// if no language is given. The pattern is not required.
+  #[clap(
+    long,
+    conflicts_with = "rewriter",
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "debug_query",
+    conflicts_with = "files_without_match",
+    conflicts_with = "sort",
+    conflicts_with = "format_template"
+  )]
+  list_files: bool,
+
+  /// Count the matches by the text of the capture, e.g. `$MOD`, instead of printing them.
+  /// The counts are printed as a table sorted by frequency. Matches without the capture are skipped.
+  #[clap(
+    long,
+    value_name = "VAR",
+    conflicts_with = "rewriter",
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "debug_query",
+    conflicts_with = "files_without_match",
+    conflicts_with = "sort",
+    conflicts_with = "format_template",
+    conflicts_with = "list_files"
+  )]
+  count_by: Option<String>,
+
+  /// Match the pattern as a regular expression of whole words, like `grep -w`, in the text
+  /// of leaf nodes such as identifiers, string contents and comments, instead of as an AST pattern.
+  /// `foo` matches `foo` and `"foo bar"` but not `foobar`.
+  #[clap(long, conflicts_with = "debug_query")]
+  word: bool,
+}
+
+/// Sort items if requested, otherwise stream them in the order they are scanned.
+fn maybe_sort<'a, M, T: 'a>(
+  items: Items<'a, T>,
+  sort: Option<SortBy>,
+  unit: impl Fn(&T) -> &MatchUnit<M>,
+) -> Box<dyn Iterator<Item = T> + 'a>
+where
+  M: Matcher<SupportLang>,
+{
+  match sort {
+    Some(sort) => Box::new(sort_items(items, sort, unit).into_iter()),
+    None => Box::new(items),
+  }
+}
+
+impl RunArg {
+  /// Move the pattern in `--pattern-file` to `pattern`.
+  fn read_pattern_file(mut self) -> Result<Self> {
+    if let Some(path) = self.pattern_file.take() {
+      self.pattern = read_template(&path)?;
+    }
+    Ok(self)
+  }
+
+  /// Move the template in `--rewrite-file` to `rewrite`.
+  fn read_rewrite_file(mut self) -> Result<Self> {
+    if let Some(path) = self.rewrite_file.take() {
+      self.rewrite = Some(read_template(&path)?);
+    }
+    Ok(self)
+  }
+
+  /// Walk the files of the language, or all files if none is specified.
+  fn build_walk(&self) -> WalkParallel {
+    let threads = num_cpus::get().min(12);
+    let mut walk = NoIgnore::disregard(&self.no_ignore).walk(&self.paths);
+    if let Some(lang) = &self.lang {
+      walk.types(file_types(lang));
+    }
+    walk.threads(threads).build_parallel()
+  }
+}
+
+/// Read a pattern or template from the file without its trailing newline.
+fn read_template(path: &Path) -> Result<String> {
+  let template = read_to_string(path).with_context(|| EC::ReadFile(path.to_path_buf()))?;
+  let template = template.strip_suffix('\n').unwrap_or(&template);
+  let template = template.strip_suffix('\r').unwrap_or(template);
+  Ok(template.to_string())
 }
 
 // Every run will include Search or Replace
 // Search or Replace by arguments `pattern` and `rewrite` passed from CLI
 pub fn run_with_pattern(arg: RunArg) -> Result<()> {
+  let arg = arg.read_pattern_file()?.read_rewrite_file()?;
+  if arg.list_files {
+    return run_worker(ListFiles::new(arg, std::io::stdout())?);
+  }
+  if arg.files_without_match {
+    return run_worker(FilesWithoutMatch::new(arg, std::io::stdout())?);
+  }
+  if arg.count_by.is_some() {
+    return run_worker(CountByCapture::new(arg, std::io::stdout())?);
+  }
   if arg.json {
-    return run_pattern_with_printer(arg, JSONPrinter::stdout());
+    let printer = JSONPrinter::stdout().cbor(arg.cbor);
+    return run_pattern_with_printer(arg, printer);
+  }
+  if arg.rewrite_stdout {
+    let printer = RewritePrinter::stdout().print_path(arg.print_path);
+    return run_pattern_with_printer(arg, printer);
   }
-  let printer = ColoredPrinter::stdout(arg.color).heading(arg.heading);
+  if let Some(template) = arg.format_template.clone() {
+    let printer = TemplatePrinter::stdout(template).tab_width(arg.tab_width);
+    return run_pattern_with_printer(arg, printer);
+  }
+  let printer = ColoredPrinter::stdout(arg.color)
+    .heading(arg.heading)
+    .context_kind(arg.after_context_rule.clone());
   let interactive = arg.interactive || arg.accept_all;
   if interactive {
-    let printer = InteractivePrinter::new(printer).accept_all(arg.accept_all);
+    let printer = InteractivePrinter::new(printer)
+      .accept_all(arg.accept_all)
+      .atomic(arg.atomic_fixes);
     run_pattern_with_printer(arg, printer)
   } else {
     run_pattern_with_printer(arg, printer)
@@ -87,30 +313,33 @@ fn run_pattern_with_printer(arg: RunArg, printer: impl Printer + Sync) -> Result
   if arg.lang.is_some() {
     run_worker(RunWithSpecificLang::new(arg, printer)?)
   } else {
-    run_worker(RunWithInferredLang { arg, printer })
+    let pattern = FilePattern::new(&arg)?;
+    let paths = PathRenderer::try_new(arg.path_style, None)?;
+    run_worker(RunWithInferredLang {
+      arg,
+      printer,
+      pattern,
+      paths,
+    })
   }
 }
 
 struct RunWithInferredLang<Printer> {
   arg: RunArg,
   printer: Printer,
+  pattern: FilePattern,
+  paths: PathRenderer,
 }
 
 impl<P: Printer + Sync> Worker for RunWithInferredLang<P> {
-  type Item = (MatchUnit<Pattern<SupportLang>>, SupportLang);
+  type Item = (MatchUnit<RunMatcher>, SupportLang);
   fn build_walk(&self) -> WalkParallel {
-    let arg = &self.arg;
-    let threads = num_cpus::get().min(12);
-    NoIgnore::disregard(&arg.no_ignore)
-      .walk(&arg.paths)
-      .threads(threads)
-      .build_parallel()
+    self.arg.build_walk()
   }
 
   fn produce_item(&self, path: &Path) -> Option<Self::Item> {
-    let lang = SupportLang::from_path(path)?;
-    let matcher = Pattern::try_new(&self.arg.pattern, lang).ok()?;
-    let match_unit = filter_file_interactive(path, lang, matcher)?;
+    let (lang, matcher) = self.pattern.for_file(path)?;
+    let match_unit = filter_file_interactive(path, lang, matcher.into_owned())?;
     Some((match_unit, lang))
   }
 
@@ -118,15 +347,20 @@ impl<P: Printer + Sync> Worker for RunWithInferredLang<P> {
     let rewrite = &self.arg.rewrite;
     let printer = &self.printer;
     printer.before_print()?;
+    let items = maybe_sort(items, self.arg.sort, |(unit, _)| unit);
     for (match_unit, lang) in items {
       let rewrite = rewrite
         .as_ref()
         .map(|s| Pattern::try_new(s, lang))
         .transpose();
       match rewrite {
-        Ok(r) => match_one_file(printer, &match_unit, &r)?,
+        Ok(r) => match_one_file(printer, &match_unit, &r, &self.arg, &self.paths)?,
+        Err(e) if self.arg.atomic_fixes => {
+          let path = match_unit.path.clone();
+          return Err(anyhow::Error::from(e).context(EC::AtomicFixAborted(path)));
+        }
         Err(e) => {
-          match_one_file(printer, &match_unit, &None)?;
+          match_one_file(printer, &match_unit, &None, &self.arg, &self.paths)?;
           eprintln!("⚠️  Rewriting was skipped because pattern fails to parse. Error detail:");
           eprintln!("╰▻ {e}");
         }
@@ -140,33 +374,117 @@ impl<P: Printer + Sync> Worker for RunWithInferredLang<P> {
 struct RunWithSpecificLang<Printer> {
   arg: RunArg,
   printer: Printer,
-  pattern: Pattern<SupportLang>,
+  pattern: RunMatcher,
+  paths: PathRenderer,
+}
+
+/// Parse the pattern, reporting every issue found if it cannot be built.
+fn parse_pattern(src: &str, lang: SupportLang) -> Result<Pattern<SupportLang>> {
+  let (pattern, errors) = Pattern::new_with_diagnostics(src, lang);
+  pattern.ok_or_else(|| {
+    let issues: Vec<_> = errors.iter().map(ToString::to_string).collect();
+    anyhow::anyhow!(issues.join("\n")).context(EC::ParsePattern)
+  })
+}
+
+/// Matcher of `run`, an AST pattern or a regex of whole words with `--word`.
+#[derive(Clone)]
+enum RunMatcher {
+  Pattern(Pattern<SupportLang>),
+  Word(RegexMatcher<SupportLang>),
+}
+
+fn parse_matcher(src: &str, word: bool, lang: SupportLang) -> Result<RunMatcher> {
+  if word {
+    let regex = RegexMatcher::try_new_word(src).context(EC::ParsePattern)?;
+    Ok(RunMatcher::Word(regex))
+  } else {
+    Ok(RunMatcher::Pattern(parse_pattern(src, lang)?))
+  }
+}
+
+impl Matcher<SupportLang> for RunMatcher {
+  fn match_node_with_env<'tree>(
+    &self,
+    node: Node<'tree, SupportLang>,
+    env: &mut MetaVarEnv<'tree, SupportLang>,
+  ) -> Option<Node<'tree, SupportLang>> {
+    match self {
+      RunMatcher::Pattern(pattern) => pattern.match_node_with_env(node, env),
+      // only leaves, otherwise every ancestor of a word would match too
+      RunMatcher::Word(regex) if node.is_leaf() => regex.match_node_with_env(node, env),
+      RunMatcher::Word(_) => None,
+    }
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    match self {
+      RunMatcher::Pattern(pattern) => pattern.potential_kinds(),
+      RunMatcher::Word(regex) => regex.potential_kinds(),
+    }
+  }
+
+  fn get_match_len(&self, node: Node<SupportLang>) -> Option<usize> {
+    match self {
+      RunMatcher::Pattern(pattern) => pattern.get_match_len(node),
+      RunMatcher::Word(regex) => regex.get_match_len(node),
+    }
+  }
+}
+
+/// The pattern parsed ahead when the language is specified,
+/// or for the language inferred from each file path otherwise.
+struct FilePattern {
+  src: String,
+  word: bool,
+  lang: Option<SupportLang>,
+  pattern: Option<RunMatcher>,
+}
+
+impl FilePattern {
+  fn new(arg: &RunArg) -> Result<Self> {
+    let pattern = arg
+      .lang
+      .map(|lang| parse_matcher(&arg.pattern, arg.word, lang))
+      .transpose()?;
+    Ok(Self {
+      src: arg.pattern.clone(),
+      word: arg.word,
+      lang: arg.lang,
+      pattern,
+    })
+  }
+
+  /// The language and pattern of the file, or None if the pattern cannot apply to it.
+  fn for_file(&self, path: &Path) -> Option<(SupportLang, Cow<'_, RunMatcher>)> {
+    let lang = self.lang.or_else(|| SupportLang::from_path(path))?;
+    let pattern = match &self.pattern {
+      Some(p) => Cow::Borrowed(p),
+      None => Cow::Owned(parse_matcher(&self.src, self.word, lang).ok()?),
+    };
+    Some((lang, pattern))
+  }
 }
 
 impl<Printer> RunWithSpecificLang<Printer> {
   fn new(arg: RunArg, printer: Printer) -> Result<Self> {
     let pattern = &arg.pattern;
     let lang = arg.lang.expect("must present");
-    let pattern = Pattern::try_new(pattern, lang).context(EC::ParsePattern)?;
+    let pattern = parse_matcher(pattern, arg.word, lang)?;
+    let paths = PathRenderer::try_new(arg.path_style, None)?;
     Ok(Self {
       arg,
       printer,
       pattern,
+      paths,
     })
   }
 }
 
 impl<P: Printer + Sync> Worker for RunWithSpecificLang<P> {
-  type Item = MatchUnit<Pattern<SupportLang>>;
+  type Item = MatchUnit<RunMatcher>;
   fn build_walk(&self) -> WalkParallel {
-    let arg = &self.arg;
-    let threads = num_cpus::get().min(12);
-    let lang = arg.lang.expect("must present");
-    NoIgnore::disregard(&arg.no_ignore)
-      .walk(&arg.paths)
-      .threads(threads)
-      .types(file_types(&lang))
-      .build_parallel()
+    self.arg.build_walk()
   }
   fn produce_item(&self, path: &Path) -> Option<Self::Item> {
     let arg = &self.arg;
@@ -179,38 +497,437 @@ impl<P: Printer + Sync> Worker for RunWithSpecificLang<P> {
     printer.before_print()?;
     let arg = &self.arg;
     let lang = arg.lang.expect("must present");
-    if arg.debug_query {
-      println!("Pattern TreeSitter {:?}", self.pattern);
+    if let (true, RunMatcher::Pattern(pattern)) = (arg.debug_query, &self.pattern) {
+      println!("Pattern TreeSitter {:?}", pattern);
     }
     let rewrite = if let Some(s) = &arg.rewrite {
-      Some(Pattern::try_new(s, lang).context(EC::ParsePattern)?)
+      Some(parse_pattern(s, lang)?)
     } else {
       None
     };
-    for match_unit in items {
-      match_one_file(printer, &match_unit, &rewrite)?;
+    for match_unit in maybe_sort(items, arg.sort, |unit| unit) {
+      match_one_file(printer, &match_unit, &rewrite, arg, &self.paths)?;
     }
     printer.after_print()?;
     Ok(())
   }
 }
 
+/// List files of the pattern's language where the pattern matches nothing.
+/// If no language is specified, it is inferred from each file path.
+struct FilesWithoutMatch<W: Write> {
+  arg: RunArg,
+  pattern: FilePattern,
+  output: Mutex<W>,
+  paths: PathRenderer,
+}
+
+impl<W: Write> FilesWithoutMatch<W> {
+  fn new(arg: RunArg, output: W) -> Result<Self> {
+    let pattern = FilePattern::new(&arg)?;
+    let paths = PathRenderer::try_new(arg.path_style, None)?;
+    Ok(Self {
+      arg,
+      pattern,
+      output: Mutex::new(output),
+      paths,
+    })
+  }
+}
+
+impl<W: Write + Send> Worker for FilesWithoutMatch<W> {
+  type Item = PathBuf;
+  fn build_walk(&self) -> WalkParallel {
+    self.arg.build_walk()
+  }
+  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+    let (lang, pattern) = self.pattern.for_file(path)?;
+    let content = read_to_string(path).ok()?;
+    let grep = lang.ast_grep(content);
+    let has_match = grep.root().find(&*pattern).is_some();
+    (!has_match).then(|| path.to_path_buf())
+  }
+  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+    let mut output = self.output.lock().expect("should work");
+    for path in items {
+      writeln!(output, "{}", self.paths.render(&path).display())?;
+    }
+    Ok(())
+  }
+}
+
+/// Tally the matches of the pattern by the text of one capture.
+/// If no language is specified, it is inferred from each file path.
+struct CountByCapture<W: Write> {
+  arg: RunArg,
+  pattern: FilePattern,
+  var: String,
+  output: Mutex<W>,
+}
+
+impl<W: Write> CountByCapture<W> {
+  fn new(arg: RunArg, output: W) -> Result<Self> {
+    let pattern = FilePattern::new(&arg)?;
+    let var = arg.count_by.as_deref().expect("must present");
+    let var = var.strip_prefix('$').unwrap_or(var).to_string();
+    Ok(Self {
+      arg,
+      pattern,
+      var,
+      output: Mutex::new(output),
+    })
+  }
+}
+
+impl<W: Write + Send> Worker for CountByCapture<W> {
+  type Item = Vec<String>;
+  fn build_walk(&self) -> WalkParallel {
+    self.arg.build_walk()
+  }
+  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+    let (lang, pattern) = self.pattern.for_file(path)?;
+    let content = read_to_string(path).ok()?;
+    let grep = lang.ast_grep(content);
+    let captured: Vec<_> = Visitor::new(&*pattern)
+      .reentrant(false)
+      .visit(grep.root())
+      .filter_map(|nm| Some(nm.get_env().get_match(&self.var)?.text().to_string()))
+      .collect();
+    (!captured.is_empty()).then_some(captured)
+  }
+  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for text in items.flatten() {
+      *counts.entry(text).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    // most frequent first, ties broken by text
+    counts.sort_by(|(a, m), (b, n)| n.cmp(m).then_with(|| a.cmp(b)));
+    let width = counts.first().map_or(0, |(_, n)| n.to_string().len());
+    let mut output = self.output.lock().expect("should work");
+    for (text, count) in counts {
+      writeln!(output, "{count:>width$} {text}")?;
+    }
+    Ok(())
+  }
+}
+
+/// List files of the language, or of any supported language if none is specified.
+struct ListFiles<W: Write> {
+  arg: RunArg,
+  output: Mutex<W>,
+  paths: PathRenderer,
+}
+
+impl<W: Write> ListFiles<W> {
+  fn new(arg: RunArg, output: W) -> Result<Self> {
+    let paths = PathRenderer::try_new(arg.path_style, None)?;
+    Ok(Self {
+      arg,
+      output: Mutex::new(output),
+      paths,
+    })
+  }
+}
+
+impl<W: Write + Send> Worker for ListFiles<W> {
+  type Item = PathBuf;
+  fn build_walk(&self) -> WalkParallel {
+    self.arg.build_walk()
+  }
+  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+    if self.arg.lang.is_none() {
+      SupportLang::from_path(path)?;
+    }
+    Some(path.to_path_buf())
+  }
+  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+    let mut files: Vec<_> = items.collect();
+    files.sort();
+    let mut output = self.output.lock().expect("should work");
+    for path in files {
+      writeln!(output, "{}", self.paths.render(&path).display())?;
+    }
+    Ok(())
+  }
+}
+
 fn match_one_file(
   printer: &impl Printer,
   match_unit: &MatchUnit<impl Matcher<SupportLang>>,
   rewrite: &Option<Pattern<SupportLang>>,
+  arg: &RunArg,
+  paths: &PathRenderer,
 ) -> Result<()> {
   let MatchUnit {
     path,
     grep,
     matcher,
   } = match_unit;
+  let path = &paths.render(path);
 
   let matches = Visitor::new(matcher).reentrant(false).visit(grep.root());
   if let Some(rewrite) = rewrite {
-    let diffs = matches.map(|m| Diff::generate(m, matcher, rewrite));
+    let diffs = matches.map(|m| {
+      let diff = Diff::generate(m, matcher, rewrite);
+      if arg.preserve_trailing_comma {
+        diff.preserve_trailing_comma()
+      } else {
+        diff
+      }
+    });
     printer.print_diffs(diffs, path)
   } else {
     printer.print_matches(matches, path)
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::utils::{collect_files, run_worker_on};
+
+  fn rewrite_with_comma(src: &str, pattern: &str, rewrite: &str) -> String {
+    let lang = SupportLang::TypeScript;
+    let grep = lang.ast_grep(src);
+    let pattern = Pattern::new(pattern, lang);
+    let rewrite = Pattern::new(rewrite, lang);
+    let found = grep.root().find(&pattern).expect("should match");
+    let diff = Diff::generate(found, &pattern, &rewrite).preserve_trailing_comma();
+    diff.replacement.to_string()
+  }
+
+  #[test]
+  fn test_preserve_trailing_comma() {
+    let with_comma = "foo(\n  a,\n  b,\n)";
+    let without_comma = "foo(\n  a,\n  b\n)";
+    for rewrite in ["bar(\n  $B,\n  $A\n)", "bar(\n  $B,\n  $A,\n)"] {
+      let ret = rewrite_with_comma(with_comma, "foo($A, $B,)", rewrite);
+      assert_eq!(ret, "bar(\n  b,\n  a,\n)");
+      let ret = rewrite_with_comma(without_comma, "foo($A, $B)", rewrite);
+      assert_eq!(ret, "bar(\n  b,\n  a\n)");
+    }
+    // single line lists are left alone
+    let ret = rewrite_with_comma(with_comma, "foo($A, $B,)", "bar($B, $A)");
+    assert_eq!(ret, "bar(b, a)");
+    // a rest parameter cannot have a comma after it
+    let rewrite = "function bar(\n  $A,\n  ...$B\n) {}";
+    let ret = rewrite_with_comma(with_comma, "foo($A, $B,)", rewrite);
+    assert_eq!(ret, "function bar(\n  a,\n  ...b\n) {}");
+    let rewrite = "const {\n  $A,\n  ...$B\n} = obj";
+    let ret = rewrite_with_comma(with_comma, "foo($A, $B,)", rewrite);
+    assert_eq!(ret, "const {\n  a,\n  ...b\n} = obj");
+  }
+
+  fn list_files_without_match(args: &[&str]) -> Vec<String> {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::write(dir.join("a.ts"), "console.log(1)").unwrap();
+    std::fs::write(dir.join("b.ts"), "let b = 1").unwrap();
+    std::fs::write(dir.join("c.ts"), "let c = 1; console.log(c)").unwrap();
+    std::fs::write(dir.join("d.ts"), "console.error(1)").unwrap();
+    std::fs::write(dir.join("e.py"), "e = 1").unwrap();
+    let dir_arg = dir.to_string_lossy();
+    let mut command = vec!["run", "--files-without-match", &dir_arg];
+    command.extend(args);
+    let arg = RunArg::try_parse_from(command).expect("should parse");
+    let worker = FilesWithoutMatch::new(arg, vec![]).expect("should create");
+    let files = collect_files(worker.build_walk());
+    let ret = run_worker_on(&worker, &files);
+    ret.expect("should run");
+    let output = worker.output.into_inner().unwrap();
+    let output = String::from_utf8(output).expect("should be utf8");
+    let mut files: Vec<_> = output
+      .lines()
+      .map(|l| {
+        Path::new(l)
+          .file_name()
+          .unwrap()
+          .to_string_lossy()
+          .to_string()
+      })
+      .collect();
+    files.sort();
+    files
+  }
+
+  #[test]
+  fn test_files_without_match() {
+    let files = list_files_without_match(&["-p", "console.log($A)", "-l", "ts"]);
+    assert_eq!(files, ["b.ts", "d.ts"]);
+  }
+
+  #[test]
+  fn test_files_without_match_infer_lang() {
+    let files = list_files_without_match(&["-p", "console.log($A)"]);
+    assert_eq!(files, ["b.ts", "d.ts", "e.py"]);
+  }
+
+  #[test]
+  fn test_files_without_match_word() {
+    let files = list_files_without_match(&["-p", "error|log", "--word", "-l", "ts"]);
+    assert_eq!(files, ["b.ts"]);
+    // a part of a word is not a match
+    let files = list_files_without_match(&["-p", "err", "--word", "-l", "ts"]);
+    assert_eq!(files, ["a.ts", "b.ts", "c.ts", "d.ts"]);
+  }
+
+  fn list_files(args: &[&str]) -> Vec<String> {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::create_dir_all(dir.join(".git")).expect("should create dir");
+    std::fs::write(dir.join(".gitignore"), "dist.ts").unwrap();
+    for file in ["a.ts", "b.py", "dist.ts", "notes.txt", ".hidden.ts"] {
+      std::fs::write(dir.join(file), "").unwrap();
+    }
+    let dir_arg = dir.to_string_lossy();
+    let mut command = vec!["run", "--list-files", &dir_arg];
+    command.extend(args);
+    let arg = RunArg::try_parse_from(command).expect("should parse");
+    let worker = ListFiles::new(arg, vec![]).expect("should create");
+    let files = collect_files(worker.build_walk());
+    let ret = run_worker_on(&worker, &files);
+    ret.expect("should run");
+    let output = String::from_utf8(worker.output.into_inner().unwrap()).unwrap();
+    let prefix = format!("{}/", dir.display());
+    output
+      .lines()
+      .map(|l| {
+        l.strip_prefix(&prefix)
+          .expect("should be in dir")
+          .to_string()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_count_by_capture() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let a = "require('react')\nrequire('lodash')\nrequire('react')";
+    std::fs::write(dir.join("a.ts"), a).unwrap();
+    std::fs::write(dir.join("b.ts"), "require('vue')\nrequire('react')").unwrap();
+    std::fs::write(dir.join("c.ts"), "require('lodash')").unwrap();
+    let dir_arg = dir.to_string_lossy();
+    let command = ["run", "-p", "require($MOD)", "--count-by", "$MOD", &dir_arg];
+    let arg = RunArg::try_parse_from(command).expect("should parse");
+    let worker = CountByCapture::new(arg, vec![]).expect("should create");
+    let files = collect_files(worker.build_walk());
+    let ret = run_worker_on(&worker, &files);
+    ret.expect("should run");
+    let output = String::from_utf8(worker.output.into_inner().unwrap()).unwrap();
+    assert_eq!(output, "3 'react'\n2 'lodash'\n1 'vue'\n");
+  }
+
+  #[test]
+  fn test_list_files() {
+    assert_eq!(list_files(&[]), ["a.ts", "b.py"]);
+    // a file type whitelisted by the language takes precedence over hidden files
+    let lang = list_files(&["-l", "ts"]);
+    assert_eq!(lang, [".hidden.ts", "a.ts"]);
+    let hidden = list_files(&["--no-ignore", "hidden"]);
+    assert_eq!(hidden, [".hidden.ts", "a.ts", "b.py"]);
+  }
+
+  #[test]
+  fn test_rewrite_file() {
+    let tmp = crate::utils::temp_dir();
+    let path = tmp.path().join("rewrite.txt");
+    std::fs::write(&path, "if ($A) {\n  logger.info($A);\n}\n").unwrap();
+    let path_arg = path.to_string_lossy();
+    let command = ["run", "-p", "console.log($A)", "--rewrite-file", &path_arg];
+    let arg = RunArg::try_parse_from(command).expect("should parse");
+    let arg = arg.read_rewrite_file();
+    let rewrite = arg
+      .expect("should read")
+      .rewrite
+      .expect("should have rewrite");
+    assert_eq!(rewrite, "if ($A) {\n  logger.info($A);\n}");
+    let lang = SupportLang::TypeScript;
+    let mut grep = lang.ast_grep("console.log(a)");
+    let pattern = Pattern::new("console.log($A)", lang);
+    assert!(grep.replace(pattern, Pattern::new(&rewrite, lang)).unwrap());
+    assert_eq!(grep.generate(), "if (a) {\n  logger.info(a);\n}");
+  }
+
+  #[test]
+  fn test_pattern_file() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let pattern_path = dir.join("query.txt");
+    let rewrite_path = dir.join("rewrite.txt");
+    std::fs::write(&pattern_path, "if ($COND) {\n  return $A;\n}\n").unwrap();
+    std::fs::write(&rewrite_path, "return $COND ? $A : undefined;\n").unwrap();
+    let (pattern_arg, rewrite_arg) = (
+      pattern_path.to_string_lossy(),
+      rewrite_path.to_string_lossy(),
+    );
+    let command = [
+      "run",
+      "--pattern-file",
+      &pattern_arg,
+      "--rewrite-file",
+      &rewrite_arg,
+    ];
+    let arg = RunArg::try_parse_from(command).expect("should parse");
+    let arg = arg.read_pattern_file().and_then(RunArg::read_rewrite_file);
+    let arg = arg.expect("should read");
+    assert_eq!(arg.pattern, "if ($COND) {\n  return $A;\n}");
+    let lang = SupportLang::TypeScript;
+    let src = "function f(a) {\n  if (a) {\n    return 1;\n  }\n}";
+    let mut grep = lang.ast_grep(src);
+    let pattern = Pattern::new(&arg.pattern, lang);
+    let rewrite = arg.rewrite.expect("should have rewrite");
+    assert!(grep.replace(pattern, Pattern::new(&rewrite, lang)).unwrap());
+    assert_eq!(
+      grep.generate(),
+      "function f(a) {\n  return a ? 1 : undefined;\n}"
+    );
+    let command = ["run", "-p", "a", "--pattern-file", "query.txt"];
+    assert!(RunArg::try_parse_from(command).is_err());
+  }
+
+  #[test]
+  fn test_missing_rewrite_file() {
+    let command = ["run", "-p", "a", "--rewrite-file", "not-exist-template.txt"];
+    let arg = RunArg::try_parse_from(command).expect("should parse");
+    let error = arg.read_rewrite_file().err().expect("should fail");
+    assert!(matches!(error.downcast_ref::<EC>(), Some(EC::ReadFile(_))));
+  }
+
+  #[test]
+  fn test_parse_pattern_issues() {
+    let error = parse_pattern("foo(a b); bar()", SupportLang::TypeScript).expect_err("should fail");
+    assert!(matches!(error.downcast_ref::<EC>(), Some(EC::ParsePattern)));
+    let issues = error.chain().nth(1).expect("should have cause").to_string();
+    assert_eq!(issues.lines().count(), 2, "{issues}");
+    assert!(parse_pattern("foo(a b)", SupportLang::TypeScript).is_ok());
+  }
+
+  #[test]
+  fn test_identifier_pattern_is_whole_word() {
+    let pattern = parse_pattern("foo", SupportLang::TypeScript).expect("should parse");
+    let grep = SupportLang::TypeScript.ast_grep("foobar(foo); barfoo + foo");
+    let found: Vec<_> = grep
+      .root()
+      .find_all(pattern)
+      .map(|n| n.text().to_string())
+      .collect();
+    assert_eq!(found, ["foo", "foo"]);
+  }
+
+  #[test]
+  fn test_word_matcher() {
+    let lang = SupportLang::TypeScript;
+    let grep = lang.ast_grep("foobar(foo); let s = 'foo bar' // barfoo");
+    let texts = |matcher: RunMatcher| -> Vec<String> {
+      let found = grep.root().find_all(matcher);
+      found.map(|n| n.text().to_string()).collect()
+    };
+    let word = parse_matcher("foo", true, lang).expect("should parse");
+    assert_eq!(texts(word), ["foo", "foo bar"]);
+    let pattern = parse_matcher("foo", false, lang).expect("should parse");
+    assert_eq!(texts(pattern), ["foo"]);
+    assert!(parse_matcher("foo(", true, lang).is_err());
+  }
+}
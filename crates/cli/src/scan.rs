@@ -1,19 +1,42 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use ast_grep_config::{RuleCollection, RuleConfig, Severity};
+use ast_grep_config::{
+  to_string as to_yaml, RuleCollection, RuleConfig, SerializableFix, Severity, SymbolIndex,
+  TextMatch,
+};
+use ast_grep_core::language::Language;
+use ast_grep_core::matcher::MatchTrace;
+use ast_grep_core::traversal::Visitor;
 use ast_grep_core::{AstGrep, Matcher, NodeMatch};
-use clap::Args;
+use clap::{Args, ValueEnum};
 use ignore::WalkParallel;
 
-use crate::config::{find_config, read_rule_file, IgnoreFile, NoIgnore};
-use crate::error::ErrorContext as EC;
+use crate::archive::{for_each_file, virtual_path};
+use crate::baseline::{BaseTree, Fingerprints};
+use crate::cache::{hash_of, CacheEntry, CachedMatch, ScanCache};
+use crate::config::{
+  add_presets, find_config, find_config_skip_invalid, find_tests, project_config_exists,
+  read_rule_file, IgnoreFile, NoIgnore, Preset,
+};
+use crate::error::{print_warning, ErrorContext as EC};
 use crate::print::{
-  ColorArg, ColoredPrinter, Diff, InteractivePrinter, JSONPrinter, Printer, ReportStyle, SimpleFile,
+  apply_edits_bottom_up, CloudPrinter, ColorArg, ColoredPrinter, Diff, InteractivePrinter,
+  JSONPrinter, Platform, Printer, ReportStyle, SilentPrinter, SimpleFile, Template,
+  TemplatePrinter, DEFAULT_DIFF_CONTEXT,
 };
-use crate::utils::filter_file_interactive;
+use crate::utils::{filter_file_interactive, read_file, read_text, PathRenderer, PathStyle};
 use crate::utils::{run_worker, Items, Worker};
+use crate::verify::TestCase;
+use crate::watch::{watch, WatchWorker};
 use ast_grep_language::SupportLang;
 
 #[derive(Args)]
@@ -42,10 +65,36 @@ pub struct ScanArg {
   #[clap(long, conflicts_with = "color", conflicts_with = "report_style")]
   json: bool,
 
+  /// Encode the JSON output in CBOR, a compact binary format that is faster to decode.
+  /// The output is one CBOR array with the same documents as the JSON output.
+  #[clap(long, requires = "json")]
+  cbor: bool,
+
+  /// Output matches as annotations for a CI platform, e.g. `github` for GitHub Actions.
+  /// Conflicts with json, color and report-style.
+  #[clap(
+    long,
+    conflicts_with = "json",
+    conflicts_with = "interactive",
+    conflicts_with = "color",
+    conflicts_with = "report_style"
+  )]
+  format: Option<Platform>,
+
   /// Apply all rewrite without confirmation if true.
   #[clap(long)]
   accept_all: bool,
 
+  /// Write fixes only after every file is fixed successfully. Requires accept-all.
+  /// If any file fails, no file is modified and the failing file is reported.
+  #[clap(long, requires = "accept_all")]
+  atomic_fixes: bool,
+
+  /// Review fixable findings one by one with the rule message and diff. Requires interactive.
+  /// Accepted fixes of a file are applied together, bottom-up, after the review.
+  #[clap(long, requires = "interactive")]
+  fix: bool,
+
   /// The paths to search. You can provide multiple paths separated by spaces.
   #[clap(value_parser, default_value = ".")]
   paths: Vec<PathBuf>,
@@ -53,21 +102,388 @@ pub struct ScanArg {
   /// Do not respect ignore files. You can suppress multiple ignore files by passing `no-ignore` multiple times.
   #[clap(long, action = clap::ArgAction::Append)]
   no_ignore: Vec<IgnoreFile>,
+
+  /// Print the resolved rules as YAML and exit without scanning.
+  /// Utility rules are inlined in the printed rules.
+  #[clap(long, conflicts_with = "interactive", conflicts_with = "json")]
+  print_rules: bool,
+
+  /// Print the message, note, link and fix of the rule with the id and exit without scanning.
+  /// An example of what the rule matches is printed from its test cases if present.
+  #[clap(
+    long,
+    value_name = "ID",
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "print_rules"
+  )]
+  explain_rule: Option<String>,
+
+  /// Scan with a curated rule set bundled with ast-grep, along with the project rules.
+  /// The project config is optional if a preset is used. A project rule overrides
+  /// the preset rule with the same id. You can use multiple presets by passing `preset` multiple times.
+  #[clap(long, value_name = "NAME", action = clap::ArgAction::Append)]
+  preset: Vec<Preset>,
+
+  /// Skip rule files that fail to parse with a warning, instead of aborting the scan.
+  #[clap(long, conflicts_with = "rule")]
+  skip_invalid_rules: bool,
+
+  /// Exit with a distinct non-zero code if any invalid rule is skipped.
+  #[clap(long, requires = "skip_invalid_rules")]
+  strict: bool,
+
+  /// Print a trace of which sub-rules succeed or fail instead of reporting matches.
+  /// Every rule is explained against its first matched node in each file,
+  /// or the first node of a possible kind if nothing matches.
+  #[clap(
+    long,
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "print_rules"
+  )]
+  explain: bool,
+
+  /// Keep running and re-scan files when they change. Press Ctrl-C to exit.
+  #[clap(
+    long,
+    conflicts_with = "interactive",
+    conflicts_with = "print_rules",
+    conflicts_with = "explain"
+  )]
+  watch: bool,
+
+  /// Stop reporting after NUM matches in one file and print how many more are omitted.
+  /// The exit code and `escalate` thresholds still count the omitted matches.
+  #[clap(long, value_name = "NUM", conflicts_with = "explain")]
+  max_matches_per_file: Option<usize>,
+
+  /// Only show and apply fixes of the rule with this id. Other rules are reported without fixes.
+  /// You can select multiple rules by passing `fix-rule` multiple times.
+  #[clap(
+    long,
+    value_name = "RULE_ID",
+    action = clap::ArgAction::Append,
+    conflicts_with = "explain"
+  )]
+  fix_rule: Vec<String>,
+
+  /// Parse every file that has applicable rules without running them, and report the elapsed time.
+  /// This helps to tell whether a slow scan is caused by parsing or by matching.
+  #[clap(
+    long,
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "format",
+    conflicts_with = "print_rules",
+    conflicts_with = "explain",
+    conflicts_with = "watch"
+  )]
+  parse_only: bool,
+
+  /// Check that fixes converge without changing files. Every fix is applied in memory,
+  /// then the scan fails if its rule still matches the fixed code or if applying it again
+  /// changes the code. Use it to catch buggy fixes before running auto-fix in CI.
+  #[clap(
+    long,
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "format",
+    conflicts_with = "print_rules",
+    conflicts_with = "explain",
+    conflicts_with = "watch",
+    conflicts_with = "parse_only"
+  )]
+  idempotent_check: bool,
+
+  /// Print each match as one line formatted by the template, e.g. `{path}:{line}: {rule}`.
+  /// See `run --format-template` for placeholders.
+  #[clap(
+    long,
+    value_name = "TEMPLATE",
+    value_parser = Template::try_new,
+    conflicts_with = "interactive",
+    conflicts_with = "json",
+    conflicts_with = "format",
+    conflicts_with = "report_style",
+    conflicts_with = "explain"
+  )]
+  format_template: Option<Template>,
+
+  /// Number of unchanged lines around each change in fix diffs, like `diff -U`.
+  #[clap(
+    long,
+    value_name = "N",
+    default_value_t = DEFAULT_DIFF_CONTEXT,
+    conflicts_with = "json",
+    conflicts_with = "format",
+    conflicts_with = "format_template"
+  )]
+  diff_context: usize,
+
+  /// Count a tab as advancing to the next multiple of N columns in reported columns,
+  /// matching what editors show. Only the presentation changes, byte offsets are unaffected.
+  #[clap(
+    long,
+    value_name = "N",
+    conflicts_with = "json",
+    conflicts_with = "format",
+    conflicts_with = "quiet"
+  )]
+  tab_width: Option<NonZeroUsize>,
+
+  /// How file paths are printed. `relative` is relative to the project root,
+  /// where sgconfig.yml is found. Paths are printed as walked by default.
+  /// Conflicts with interactive, which edits files by the walked paths.
+  #[clap(
+    long,
+    value_name = "STYLE",
+    conflicts_with = "interactive",
+    conflicts_with = "accept_all"
+  )]
+  path_style: Option<PathStyle>,
+
+  /// Print no diagnostics, only exit with a non-zero code if any error is found.
+  /// Fatal errors and warnings are still printed to stderr.
+  #[clap(
+    short,
+    long,
+    conflicts_with = "interactive",
+    conflicts_with = "accept_all",
+    conflicts_with = "json",
+    conflicts_with = "format",
+    conflicts_with = "format_template",
+    conflicts_with = "print_rules",
+    conflicts_with = "explain",
+    conflicts_with = "parse_only",
+    conflicts_with = "idempotent_check"
+  )]
+  quiet: bool,
+
+  /// Only print diagnostics of rules at this severity or above, e.g. `warning` hides hint and info.
+  /// The level applies to the severity after `escalate`, and hidden diagnostics never fail the scan.
+  /// Use `severity: off` to disable a rule instead.
+  #[clap(
+    long,
+    value_name = "LEVEL",
+    conflicts_with = "quiet",
+    conflicts_with = "explain",
+    conflicts_with = "parse_only"
+  )]
+  min_severity: Option<Severity>,
+
+  /// Stop the scan at the first file with a finding shown by `--min-severity`, report it and
+  /// exit with the error code regardless of its severity. Useful to check quickly for any finding.
+  #[clap(
+    long,
+    conflicts_with = "interactive",
+    conflicts_with = "accept_all",
+    conflicts_with = "print_rules",
+    conflicts_with = "explain",
+    conflicts_with = "watch",
+    conflicts_with = "parse_only",
+    conflicts_with = "idempotent_check",
+    conflicts_with = "archive",
+    conflicts_with = "list_files"
+  )]
+  fail_fast: bool,
+
+  /// Print the N rules that spent the most time matching, with their total match time, after the scan.
+  /// The report is printed to stderr. Use `timeout` in a rule to skip it on files where it is too slow.
+  #[clap(
+    long,
+    value_name = "N",
+    conflicts_with = "print_rules",
+    conflicts_with = "explain",
+    conflicts_with = "watch",
+    conflicts_with = "parse_only",
+    conflicts_with = "idempotent_check",
+    conflicts_with = "list_files"
+  )]
+  report_slow_rules: Option<usize>,
+
+  /// Only report findings that are new compared to the git ref, e.g. `origin/main`.
+  /// Each file is also scanned as it is at the ref. A finding is new if the file at the ref
+  /// has fewer findings of the same rule with the same text, wherever they are.
+  #[clap(
+    long,
+    value_name = "REF",
+    conflicts_with = "explain",
+    conflicts_with = "parse_only",
+    conflicts_with = "idempotent_check",
+    conflicts_with = "list_files",
+    conflicts_with = "archive",
+    conflicts_with = "fail_fast",
+    conflicts_with = "max_matches_per_file"
+  )]
+  baseline_diff: Option<String>,
+
+  /// Print the findings in sections under a header of each rule or severity, instead of file by file.
+  /// Files are sorted by path in each section. Findings are printed after the whole scan.
+  #[clap(
+    long,
+    value_name = "KEY",
+    default_value = "file",
+    conflicts_with = "interactive",
+    conflicts_with = "accept_all",
+    conflicts_with = "json",
+    conflicts_with = "format",
+    conflicts_with = "format_template",
+    conflicts_with = "quiet",
+    conflicts_with = "watch",
+    conflicts_with = "archive"
+  )]
+  group_by: GroupBy,
+
+  /// Cache results of each file in the directory. Files whose content and rules
+  /// are unchanged since the last scan reuse the cached findings.
+  #[clap(
+    long,
+    value_name = "DIR",
+    conflicts_with = "explain",
+    conflicts_with = "parse_only",
+    conflicts_with = "idempotent_check"
+  )]
+  cache_dir: Option<PathBuf>,
+
+  /// Scan files inside the tar archive, optionally gzip compressed, instead of the paths.
+  /// Files are read without extracting them and reported as `ARCHIVE!path/in/archive`.
+  /// Fixes are never applied to archived files.
+  #[clap(
+    long,
+    value_name = "FILE",
+    conflicts_with = "interactive",
+    conflicts_with = "accept_all",
+    conflicts_with = "explain",
+    conflicts_with = "watch",
+    conflicts_with = "parse_only",
+    conflicts_with = "idempotent_check",
+    conflicts_with = "cache_dir"
+  )]
+  archive: Option<PathBuf>,
+
+  /// Print the sorted paths of files that would be scanned, then exit without scanning.
+  /// A file is listed if it passes the ignore files and at least one rule applies to it.
+  #[clap(
+    long,
+    conflicts_with = "interactive",
+    conflicts_with = "accept_all",
+    conflicts_with = "json",
+    conflicts_with = "format",
+    conflicts_with = "format_template",
+    conflicts_with = "print_rules",
+    conflicts_with = "explain",
+    conflicts_with = "watch",
+    conflicts_with = "parse_only",
+    conflicts_with = "idempotent_check",
+    conflicts_with = "quiet",
+    conflicts_with = "cache_dir",
+    conflicts_with = "archive"
+  )]
+  list_files: bool,
 }
 
 pub fn run_with_config(arg: ScanArg) -> Result<()> {
+  let (configs, skipped) = load_rules(&arg)?;
+  if arg.print_rules {
+    return print_rules(&configs, std::io::stdout());
+  }
+  if let Some(id) = &arg.explain_rule {
+    let Some(rule) = configs.iter().find(|rule| &rule.id == id) else {
+      let context = if configs.is_disabled(id) {
+        EC::RuleDisabled(id.clone())
+      } else {
+        EC::RuleNotFound(id.clone())
+      };
+      return Err(anyhow::anyhow!(context));
+    };
+    // a single rule file has no project config to find tests
+    let cases = if arg.rule.is_some() {
+      vec![]
+    } else {
+      find_tests(arg.config.clone())?.test_cases
+    };
+    return explain_rule(rule, &cases, std::io::stdout());
+  }
+  for id in &arg.fix_rule {
+    if !configs.iter().any(|rule| &rule.id == id) {
+      print_warning(&anyhow::anyhow!(
+        "--fix-rule `{id}` does not match any rule."
+      ));
+    }
+  }
+  let strict = arg.strict;
+  run_scan(arg, configs)?;
+  if strict && skipped > 0 {
+    Err(anyhow::anyhow!(EC::InvalidRulesSkipped(skipped)))
+  } else {
+    Ok(())
+  }
+}
+
+fn run_scan(arg: ScanArg, configs: RuleCollection<SupportLang>) -> Result<()> {
+  if arg.explain {
+    return run_worker(ExplainWithConfig {
+      arg,
+      configs,
+      index: Mutex::default(),
+    });
+  }
+  if arg.list_files {
+    return run_worker(ListFiles::new(arg, configs, std::io::stdout())?);
+  }
+  if arg.parse_only {
+    return run_worker(ParseOnly::new(arg, configs, std::io::stdout()));
+  }
+  if arg.idempotent_check {
+    return run_worker(IdempotentCheck::new(arg, configs, std::io::stdout()));
+  }
+  if arg.quiet {
+    let worker = ScanWithConfig::new(arg, SilentPrinter, configs)?;
+    return run_or_watch(worker).map_err(|error| match error.downcast_ref::<EC>() {
+      Some(EC::DiagnosticError(num)) => anyhow::anyhow!(EC::QuietDiagnosticError(*num)),
+      _ => error,
+    });
+  }
   if arg.json {
-    let worker = ScanWithConfig::try_new(arg, JSONPrinter::stdout())?;
-    return run_worker(worker);
+    let printer = JSONPrinter::stdout().cbor(arg.cbor);
+    let worker = ScanWithConfig::new(arg, printer, configs)?;
+    return run_or_watch(worker);
+  }
+  if let Some(template) = arg.format_template.clone() {
+    let printer = TemplatePrinter::stdout(template).tab_width(arg.tab_width);
+    let worker = ScanWithConfig::new(arg, printer, configs)?;
+    return run_or_watch(worker);
   }
-  let printer = ColoredPrinter::stdout(arg.color).style(arg.report_style);
+  if let Some(platform) = arg.format {
+    let worker = ScanWithConfig::new(arg, CloudPrinter::stdout(platform), configs)?;
+    return run_or_watch(worker);
+  }
+  let printer = ColoredPrinter::stdout(arg.color)
+    .style(arg.report_style)
+    .tab_width(arg.tab_width)
+    .diff_context(arg.diff_context);
   let interactive = arg.interactive || arg.accept_all;
   if interactive {
-    let printer = InteractivePrinter::new(printer).accept_all(arg.accept_all);
-    let worker = ScanWithConfig::try_new(arg, printer)?;
+    let printer = InteractivePrinter::new(printer)
+      .accept_all(arg.accept_all)
+      .atomic(arg.atomic_fixes)
+      .per_finding(arg.fix);
+    let worker = ScanWithConfig::new(arg, printer, configs)?;
     run_worker(worker)
   } else {
-    let worker = ScanWithConfig::try_new(arg, printer)?;
+    let worker = ScanWithConfig::new(arg, printer, configs)?;
+    run_or_watch(worker)
+  }
+}
+
+fn run_or_watch<P: Printer + Sync>(worker: ScanWithConfig<P>) -> Result<()> {
+  if let Some(archive) = &worker.arg.archive {
+    let file = File::open(archive).with_context(|| EC::ReadFile(archive.clone()))?;
+    worker.scan_archive(archive, file)
+  } else if worker.arg.watch {
+    watch(worker)
+  } else {
     run_worker(worker)
   }
 }
@@ -76,150 +492,2430 @@ struct ScanWithConfig<Printer> {
   arg: ScanArg,
   printer: Printer,
   configs: RuleCollection<SupportLang>,
+  paths: PathRenderer,
+  cache: Option<ScanCache>,
+  /// hash of everything besides file content that affects scan results
+  rules_hash: u64,
+  /// files at the ref of `--baseline-diff`
+  base: Option<BaseTree>,
+  /// imports of the scanned files for cross-file rules, complete once all files are produced
+  index: Mutex<SymbolIndex>,
+  /// set by `--fail-fast` once a file with a shown finding is produced
+  found: AtomicBool,
+  /// findings of rules with `language: all`, printed after the other findings
+  text_findings: Mutex<Vec<TextFindings>>,
+  /// match time of rules for `--report-slow-rules`
+  times: Option<RuleTimes>,
 }
 impl<P: Printer> ScanWithConfig<P> {
-  fn try_new(mut arg: ScanArg, printer: P) -> Result<Self> {
-    let configs = if let Some(path) = &arg.rule {
-      let rules = read_rule_file(path, None)?;
-      RuleCollection::try_new(rules).context(EC::GlobPattern)?
+  fn new(arg: ScanArg, printer: P, configs: RuleCollection<SupportLang>) -> Result<Self> {
+    let paths = PathRenderer::try_new(arg.path_style, arg.config.clone())?;
+    let base = match (&arg.baseline_diff, arg.paths.first()) {
+      (Some(git_ref), Some(path)) => Some(BaseTree::try_new(git_ref, path)?),
+      _ => None,
+    };
+    let cache = arg.cache_dir.clone().map(ScanCache::try_new).transpose()?;
+    let rules_hash = if cache.is_some() {
+      hash_rules(&configs, arg.max_matches_per_file)?
     } else {
-      find_config(arg.config.take())?
+      0
     };
     Ok(Self {
-      arg,
       printer,
       configs,
+      paths,
+      cache,
+      rules_hash,
+      base,
+      index: Mutex::default(),
+      found: AtomicBool::new(false),
+      text_findings: Mutex::default(),
+      times: arg.report_slow_rules.map(|_| RuleTimes::default()),
+      arg,
     })
   }
-}
 
-impl<P: Printer + Sync> Worker for ScanWithConfig<P> {
-  type Item = (PathBuf, AstGrep<SupportLang>);
-  fn build_walk(&self) -> WalkParallel {
-    let arg = &self.arg;
-    let threads = num_cpus::get().min(12);
-    NoIgnore::disregard(&arg.no_ignore)
-      .walk(&arg.paths)
-      .threads(threads)
-      .build_parallel()
+  /// Match the rules with `language: all` against the raw text of a file and keep the findings.
+  /// Findings on the base ref of `--baseline-diff` are dropped like those of other rules.
+  fn scan_text(&self, path: &Path, content: &str) {
+    let mut base = Fingerprints::default();
+    let base_content = self.base.as_ref().and_then(|base| base.read(path));
+    let mut matches = vec![];
+    for (idx, rule) in self.configs.text_rules().iter().enumerate() {
+      if let Some(base_content) = &base_content {
+        for m in rule.find_all(base_content) {
+          base.add(&rule.id, m.text());
+        }
+      }
+      let ranges: Vec<_> = rule
+        .find_all(content)
+        .filter(|m| base.is_new(&rule.id, m.text()))
+        .map(|m| m.range())
+        .collect();
+      if !ranges.is_empty() {
+        matches.push((idx, ranges));
+      }
+    }
+    if matches.is_empty() {
+      return;
+    }
+    let rules = self.configs.text_rules();
+    let shown = matches
+      .iter()
+      .any(|(idx, _)| shown(self.arg.min_severity.as_ref(), &rules[*idx].severity));
+    if self.arg.fail_fast && shown {
+      self.found.store(true, Ordering::Relaxed);
+    }
+    let findings = TextFindings {
+      path: path.to_path_buf(),
+      content: content.to_string(),
+      matches,
+    };
+    let mut text_findings = self.text_findings.lock().expect("should not be poisoned");
+    text_findings.push(findings);
   }
-  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
-    let rules = self.configs.for_path(path);
-    if rules.is_empty() {
-      return None;
+
+  /// Print the findings of rules with `language: all` in path order.
+  /// Returns the number of diagnostics with error severity.
+  /// Under `--fail-fast` only the first file with a shown finding is printed.
+  fn report_text(&self) -> Result<usize> {
+    let mut files =
+      std::mem::take(&mut *self.text_findings.lock().expect("should not be poisoned"));
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let rules = self.configs.text_rules();
+    let mut has_error = 0;
+    for file in files {
+      let path = self.paths.render(&file.path);
+      let mut printed = false;
+      for (idx, ranges) in file.matches {
+        let rule = &rules[idx];
+        if !shown(self.arg.min_severity.as_ref(), &rule.severity) {
+          continue;
+        }
+        if matches!(rule.severity, Severity::Error) {
+          has_error += ranges.len();
+        }
+        printed = true;
+        let matches = ranges
+          .into_iter()
+          .map(|range| TextMatch::new(&file.content, range))
+          .collect();
+        let simple_file = SimpleFile::new(path.to_string_lossy(), &file.content);
+        self.printer.print_text_rule(matches, simple_file, rule)?;
+      }
+      if printed && self.arg.fail_fast {
+        return Ok(has_error.max(1));
+      }
     }
-    let lang = rules[0].language;
-    let combined = CombinedScan::new(rules);
-    let unit = filter_file_interactive(path, lang, ast_grep_core::matcher::MatchAll)?;
-    if combined.find(&unit.grep) {
-      return Some((unit.path, unit.grep));
+    Ok(has_error)
+  }
+
+  /// Drop findings that are also on the base ref of `--baseline-diff`.
+  /// Rules left without findings are removed so that they do not count toward the exit code.
+  fn retain_new(
+    &self,
+    path: &Path,
+    combined: &CombinedScan,
+    index: Option<&SymbolIndex>,
+    matched: &mut HashMap<usize, Vec<NodeMatch<SupportLang>>>,
+  ) {
+    let Some(base_tree) = &self.base else {
+      return;
+    };
+    let mut base = Fingerprints::default();
+    if let (Some(content), Some(rule)) = (base_tree.read(path), combined.rules.first()) {
+      let grep = rule.language.ast_grep(content);
+      let mut base_scan = CombinedScan::new(combined.rules.clone());
+      if let Some(index) = index {
+        base_scan = base_scan.with_index(path, index);
+      }
+      for (idx, matches) in base_scan.scan(&grep, None).0 {
+        for m in matches {
+          base.add(&combined.rules[idx].id, &m.text());
+        }
+      }
     }
-    None
+    for (idx, matches) in matched.iter_mut() {
+      let id = &combined.rules[*idx].id;
+      matches.retain(|m| base.is_new(id, &m.text()));
+    }
+    matched.retain(|_, matches| !matches.is_empty());
   }
-  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+
+  fn print_slow_rules(&self) {
+    if let (Some(n), Some(times)) = (self.arg.report_slow_rules, &self.times) {
+      eprint!("{}", times.report(n));
+    }
+  }
+
+  /// The matches of the file if it has a finding shown by `--min-severity`.
+  /// They are passed on as an entry to rematch so the file is not scanned again when consumed.
+  fn shown_entry(
+    &self,
+    combined: &CombinedScan,
+    grep: &AstGrep<SupportLang>,
+  ) -> Option<CacheEntry> {
+    let (matched, omitted) = combined.scan(grep, self.arg.max_matches_per_file);
+    // the file is reported before other files are counted, so only its own matches escalate
+    let has_shown = matched.iter().any(|(&idx, matches)| {
+      let count = matches.len() + omitted.get(&idx).copied().unwrap_or(0);
+      let severity = combined.rules[idx].severity_for_count(count);
+      shown(self.arg.min_severity.as_ref(), severity)
+    });
+    has_shown.then(|| CacheEntry {
+      rules: matched.keys().copied().collect(),
+      matches: cached_matches(&matched),
+      omitted,
+      ..Default::default()
+    })
+  }
+
+  /// Print the matches in one file with the severity of each rule after escalation by `counts`.
+  /// Returns the number of diagnostics with error severity, including the matches
+  /// of the rules omitted by `--max-matches-per-file`.
+  #[allow(clippy::too_many_arguments)]
+  fn report_file(
+    &self,
+    path: &Path,
+    combined: &CombinedScan,
+    matched: HashMap<usize, Vec<NodeMatch<SupportLang>>>,
+    omitted: &HashMap<usize, usize>,
+    file_content: &String,
+    allow_fix: bool,
+    counts: &EscalationCounts,
+  ) -> Result<usize> {
+    let mut has_error = 0;
+    let mut omitted_shown = 0;
+    let path = &self.paths.render(path);
+    // report rules in order so that interactive review is deterministic
+    let mut matched: Vec<_> = matched.into_iter().collect();
+    matched.sort_by_key(|(idx, _)| *idx);
+    for (idx, matches) in matched {
+      let rule = &combined.rules[idx];
+      let severity = counts.severity_of(rule);
+      if !shown(self.arg.min_severity.as_ref(), severity) {
+        continue;
+      }
+      let omitted = omitted.get(&idx).copied().unwrap_or(0);
+      omitted_shown += omitted;
+      if matches!(severity, Severity::Error) {
+        has_error += matches.len() + omitted;
+      }
+      if !matches.is_empty() {
+        let fix = allow_fix && fix_selected(&self.arg.fix_rule, rule);
+        match_rule_on_file(
+          path,
+          matches,
+          rule,
+          severity,
+          fix,
+          file_content,
+          &self.printer,
+        )?;
+      }
+    }
+    if omitted_shown > 0 && !self.arg.quiet {
+      // stderr keeps structured output like JSON valid
+      eprintln!("{}", omitted_note(path, omitted_shown));
+    }
+    Ok(has_error)
+  }
+
+  /// Scan and report a produced file. Returns true if the scan stops by `--fail-fast`.
+  fn consume_item(
+    &self,
+    (path, grep, cached): (PathBuf, AstGrep<SupportLang>, Cached),
+    index: Option<&SymbolIndex>,
+    totals: &mut ScanTotals,
+  ) -> Result<bool> {
+    let file_content = grep.root().text().to_string();
+    let rules = self.configs.for_path(&path);
+    let mut combined = CombinedScan::new(rules).with_timing(&path, self.times.as_ref());
+    if let Some(index) = index {
+      combined = combined.with_index(&path, index);
+    }
+    let (mut matched, omitted) = match cached {
+      Cached::Hit(entry) => (combined.rematch(&grep, &entry), entry.omitted),
+      Cached::Disabled => combined.scan(&grep, self.arg.max_matches_per_file),
+      Cached::Miss(content_hash) => {
+        let (matched, omitted) = combined.scan(&grep, self.arg.max_matches_per_file);
+        let entry = CacheEntry {
+          content_hash,
+          rules_hash: self.rules_hash,
+          rules: matched.keys().copied().collect(),
+          matches: cached_matches(&matched),
+          omitted,
+        };
+        if let Some(cache) = &self.cache {
+          if let Err(error) = cache.put(&path, &entry) {
+            print_warning(&error);
+          }
+        }
+        (matched, entry.omitted)
+      }
+    };
+    self.retain_new(&path, &combined, index, &mut matched);
+    totals.counts.add(&combined, &matched, &omitted);
+    if self.arg.fail_fast {
+      // only the first file is reported, other files produced concurrently are dropped
+      let counts = &totals.counts;
+      self.report_file(
+        &path,
+        &combined,
+        matched,
+        &omitted,
+        &file_content,
+        true,
+        counts,
+      )?;
+      return Ok(true);
+    }
+    // the severity of a rule that escalates is only known once all files are scanned
+    let escalates = matched
+      .keys()
+      .any(|&idx| combined.rules[idx].escalate.is_some());
+    if matches!(self.arg.group_by, GroupBy::File) && !escalates {
+      let counts = &totals.counts;
+      totals.has_error += self.report_file(
+        &path,
+        &combined,
+        matched,
+        &omitted,
+        &file_content,
+        true,
+        counts,
+      )?;
+      return Ok(false);
+    }
+    let entry = CacheEntry {
+      rules: matched.keys().copied().collect(),
+      matches: cached_matches(&matched),
+      omitted,
+      ..Default::default()
+    };
+    totals.grouped.push((path, entry));
+    Ok(false)
+  }
+
+  /// Print the findings of the files kept until the scan ends, in sections by `--group-by`.
+  /// Matches of each file are recorded like the cache entry, and the file is read
+  /// and parsed again to recover them for each section it has findings in.
+  /// Rules are grouped by their severity after escalation, known once all files are scanned.
+  /// Returns the number of diagnostics with error severity.
+  fn report_grouped(
+    &self,
+    mut files: Vec<(PathBuf, CacheEntry)>,
+    counts: &EscalationCounts,
+  ) -> Result<usize> {
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    let combined: Vec<_> = files
+      .iter()
+      .map(|(path, _)| CombinedScan::new(self.configs.for_path(path)))
+      .collect();
+    let group_of = |rule: &RuleConfig<SupportLang>| {
+      let severity = counts.severity_of(rule);
+      self.arg.group_by.group_of(rule, severity)
+    };
+    let mut groups = BTreeSet::new();
+    for ((_, entry), combined) in files.iter().zip(&combined) {
+      // rules whose matches are all omitted still have findings in their group
+      for &idx in &entry.rules {
+        let rule = combined.rules[idx];
+        if shown(self.arg.min_severity.as_ref(), counts.severity_of(rule)) {
+          groups.insert(group_of(rule));
+        }
+      }
+    }
+    let mut has_error = 0;
+    for group in groups {
+      if !matches!(self.arg.group_by, GroupBy::File) {
+        self.printer.print_group(&group.1)?;
+      }
+      for ((path, entry), combined) in files.iter().zip(&combined) {
+        let in_group = |idx: usize| group_of(combined.rules[idx]) == group;
+        if !entry.rules.iter().any(|&idx| in_group(idx)) {
+          continue;
+        }
+        let Some(file_content) = read_file(path) else {
+          continue;
+        };
+        let grep = combined.rules[0].language.ast_grep(&file_content);
+        let mut matched = combined.rematch(&grep, entry);
+        matched.retain(|&idx, _| in_group(idx));
+        let omitted = &entry.omitted;
+        has_error += self.report_file(
+          path,
+          combined,
+          matched,
+          omitted,
+          &file_content,
+          true,
+          counts,
+        )?;
+      }
+    }
+    Ok(has_error)
+  }
+
+  /// Scan every file in the archive as it is read.
+  /// Files with cross-file rules are scanned after the whole archive is read and indexed,
+  /// and files matched by rules that escalate are reported once all files are scanned.
+  fn scan_archive(&self, archive: &Path, reader: impl Read) -> Result<()> {
     self.printer.before_print()?;
     let mut has_error = 0;
-    for (path, grep) in items {
-      let file_content = grep.root().text().to_string();
-      let path = &path;
+    let mut counts = EscalationCounts::default();
+    let mut deferred = vec![];
+    let mut escalating = vec![];
+    let mut scan_entry = |path: &Path, grep: AstGrep<SupportLang>, index| -> Result<()> {
       let rules = self.configs.for_path(path);
-      let combined = CombinedScan::new(rules);
-      let matched = combined.scan(&grep);
-      for (idx, matches) in matched {
-        let rule = &combined.rules[idx];
-        if matches!(rule.severity, Severity::Error) {
-          has_error += 1;
+      let mut combined = CombinedScan::new(rules).with_timing(path, self.times.as_ref());
+      if let Some(index) = index {
+        combined = combined.with_index(path, index);
+      }
+      let (matched, omitted) = combined.scan(&grep, self.arg.max_matches_per_file);
+      counts.add(&combined, &matched, &omitted);
+      if matched
+        .keys()
+        .any(|&idx| combined.rules[idx].escalate.is_some())
+      {
+        let entry = CacheEntry {
+          rules: matched.keys().copied().collect(),
+          matches: cached_matches(&matched),
+          omitted,
+          ..Default::default()
+        };
+        drop(matched);
+        escalating.push((path.to_path_buf(), grep, entry));
+        return Ok(());
+      }
+      let content = grep.root().text().to_string();
+      has_error +=
+        self.report_file(path, &combined, matched, &omitted, &content, false, &counts)?;
+      Ok(())
+    };
+    for_each_file(archive, reader, |entry, content| {
+      let path = virtual_path(archive, entry);
+      if !self.configs.text_rules().is_empty() {
+        self.scan_text(&path, &content);
+      }
+      let rules = self.configs.for_path(entry);
+      let Some(lang) = rules.first().map(|r| r.language) else {
+        return Ok(());
+      };
+      let grep = lang.ast_grep(&content);
+      let cross_file = cross_file_rules(&rules);
+      if cross_file.is_empty() {
+        scan_entry(&path, grep, None)
+      } else {
+        if index_file(&self.index, &cross_file, &path, &grep) {
+          deferred.push((path, grep));
         }
-        match_rule_on_file(path, matches, rule, &file_content, &self.printer)?;
+        Ok(())
       }
+    })?;
+    let index = self.index.lock().expect("index should not be poisoned");
+    for (path, grep) in deferred {
+      scan_entry(&path, grep, Some(&*index))?;
+    }
+    for (path, grep, entry) in escalating {
+      let combined = CombinedScan::new(self.configs.for_path(&path));
+      let matched = combined.rematch(&grep, &entry);
+      let content = grep.root().text().to_string();
+      has_error += self.report_file(
+        &path,
+        &combined,
+        matched,
+        &entry.omitted,
+        &content,
+        false,
+        &counts,
+      )?;
     }
+    has_error += self.report_text()?;
     self.printer.after_print()?;
-    if has_error > 0 {
-      Err(anyhow::anyhow!(EC::DiagnosticError(has_error)))
-    } else {
-      Ok(())
+    self.print_slow_rules();
+    diagnostic_result(has_error)
+  }
+}
+
+/// Matches of rules with `language: all` in one file, as byte ranges grouped by rule index.
+struct TextFindings {
+  path: PathBuf,
+  content: String,
+  matches: Vec<(usize, Vec<Range<usize>>)>,
+}
+
+/// Results accumulated over the files of a scan.
+#[derive(Default)]
+struct ScanTotals {
+  /// number of diagnostics with error severity
+  has_error: usize,
+  counts: EscalationCounts,
+  /// matches of files to report after the scan, by `--group-by` sections
+  /// or because a rule matching them escalates
+  grouped: Vec<(PathBuf, CacheEntry)>,
+}
+
+/// Match counts of rules that can escalate, accumulated over the whole scan by rule id.
+#[derive(Default)]
+struct EscalationCounts(HashMap<String, usize>);
+
+impl EscalationCounts {
+  fn add(
+    &mut self,
+    combined: &CombinedScan,
+    matched: &HashMap<usize, Vec<NodeMatch<SupportLang>>>,
+    omitted: &HashMap<usize, usize>,
+  ) {
+    for (idx, matches) in matched {
+      let rule = &combined.rules[*idx];
+      if rule.escalate.is_some() {
+        let count = matches.len() + omitted.get(idx).copied().unwrap_or(0);
+        *self.0.entry(rule.id.clone()).or_default() += count;
+      }
     }
   }
+
+  /// The severity of the rule after escalation by its matches so far.
+  fn severity_of<'r>(&self, rule: &'r RuleConfig<SupportLang>) -> &'r Severity {
+    let count = self.0.get(&rule.id).copied().unwrap_or(0);
+    rule.severity_for_count(count)
+  }
 }
 
-fn match_rule_on_file(
-  path: &Path,
-  matches: Vec<NodeMatch<SupportLang>>,
-  rule: &RuleConfig<SupportLang>,
-  file_content: &String,
-  reporter: &impl Printer,
-) -> Result<()> {
-  let matches = matches.into_iter();
-  let file = SimpleFile::new(path.to_string_lossy(), file_content);
-  if let Some(fixer) = &rule.fixer {
-    let diffs = matches.map(|m| Diff::generate(m, &rule.matcher, fixer));
-    reporter.print_rule_diffs(diffs, path, rule)?;
+fn diagnostic_result(has_error: usize) -> Result<()> {
+  if has_error > 0 {
+    Err(anyhow::anyhow!(EC::DiagnosticError(has_error)))
   } else {
-    reporter.print_rule(matches, file, rule)?;
+    Ok(())
   }
-  Ok(())
 }
 
-struct CombinedScan<'r> {
-  rules: Vec<&'r RuleConfig<SupportLang>>,
-  kind_rule_mapping: Vec<Vec<usize>>,
+fn hash_rules(configs: &RuleCollection<SupportLang>, max_matches: Option<usize>) -> Result<u64> {
+  let mut yaml = String::new();
+  for rule in configs.iter() {
+    yaml.push_str(&to_yaml(&rule.with_utils_inlined(configs.global_utils()))?);
+  }
+  let version = env!("CARGO_PKG_VERSION");
+  let key = format!("{version}\n{max_matches:?}\n{yaml}");
+  Ok(hash_of(key.as_bytes()))
 }
 
-impl<'r> CombinedScan<'r> {
-  fn new(rules: Vec<&'r RuleConfig<SupportLang>>) -> Self {
-    let mut mapping = Vec::new();
-    for (idx, rule) in rules.iter().enumerate() {
-      for kind in &rule
-        .matcher
-        .potential_kinds()
-        .expect("rule must have kinds")
-      {
-        let k = kind as usize;
-        while mapping.len() <= k {
-          mapping.push(vec![]);
-        }
-        mapping[k].push(idx);
+/// Cross-file rules among the rules of a file.
+fn cross_file_rules<'r>(rules: &[&'r RuleConfig<SupportLang>]) -> Vec<&'r RuleConfig<SupportLang>> {
+  rules
+    .iter()
+    .copied()
+    .filter(|rule| rule.is_cross_file())
+    .collect()
+}
+
+/// Index the imports of the file for its cross-file rules, in the same pass that scans it.
+/// Returns whether any of the rules matches, so that the file is reported once all files are indexed.
+fn index_file(
+  index: &Mutex<SymbolIndex>,
+  rules: &[&RuleConfig<SupportLang>],
+  path: &Path,
+  grep: &AstGrep<SupportLang>,
+) -> bool {
+  if rules.is_empty() {
+    return false;
+  }
+  // match imports outside of the lock shared by the threads
+  let mut imports = SymbolIndex::default();
+  imports.index_file(rules, path, grep);
+  index
+    .lock()
+    .expect("index should not be poisoned")
+    .merge(imports);
+  rules
+    .iter()
+    .any(|rule| grep.root().find(&rule.matcher).is_some())
+}
+
+/// Whether the results of a file come from the cache.
+enum Cached {
+  /// No cache is used.
+  Disabled,
+  /// Results are computed and saved for the content hash.
+  Miss(u64),
+  Hit(CacheEntry),
+}
+
+/// Returns the rules to scan with and the number of skipped invalid rule files.
+fn load_rules(arg: &ScanArg) -> Result<(RuleCollection<SupportLang>, usize)> {
+  let (mut configs, skipped) = load_project_rules(arg)?;
+  add_presets(&mut configs, &arg.preset)?;
+  Ok((configs, skipped))
+}
+
+fn load_project_rules(arg: &ScanArg) -> Result<(RuleCollection<SupportLang>, usize)> {
+  let only_presets = arg.rule.is_none() && arg.config.is_none() && !arg.preset.is_empty();
+  if only_presets && !project_config_exists()? {
+    let configs = RuleCollection::try_new(vec![]).context(EC::GlobPattern)?;
+    return Ok((configs, 0));
+  }
+  if let Some(path) = &arg.rule {
+    let (rules, text_rules) = read_rule_file(path, None)?;
+    let mut configs = RuleCollection::try_new(rules).context(EC::GlobPattern)?;
+    configs.add_text_rules(text_rules);
+    return Ok((configs, 0));
+  }
+  if !arg.skip_invalid_rules {
+    return Ok((find_config(arg.config.clone())?, 0));
+  }
+  let (configs, skipped) = find_config_skip_invalid(arg.config.clone())?;
+  for error in &skipped {
+    print_warning(error);
+  }
+  Ok((configs, skipped.len()))
+}
+
+fn print_rules(configs: &RuleCollection<SupportLang>, mut output: impl Write) -> Result<()> {
+  for rule in configs.iter() {
+    let resolved = rule.with_utils_inlined(configs.global_utils());
+    let yaml = to_yaml(&resolved)?;
+    writeln!(output, "---")?;
+    write!(output, "{yaml}")?;
+  }
+  for rule in configs.text_rules() {
+    let yaml = to_yaml(&**rule)?;
+    writeln!(output, "---")?;
+    write!(output, "{yaml}")?;
+  }
+  Ok(())
+}
+
+fn explain_rule(
+  rule: &RuleConfig<SupportLang>,
+  cases: &[TestCase],
+  mut output: impl Write,
+) -> Result<()> {
+  let severity = match rule.severity {
+    Severity::Hint => "hint",
+    Severity::Info => "info",
+    Severity::Warning => "warning",
+    Severity::Error => "error",
+    Severity::Off => "off",
+  };
+  writeln!(output, "Rule: {}", rule.id)?;
+  writeln!(output, "Severity: {severity}")?;
+  writeln!(output, "Message: {}", rule.message)?;
+  if let Some(note) = &rule.note {
+    writeln!(output, "Note: {}", note.trim_end())?;
+  }
+  if let Some(url) = &rule.url {
+    writeln!(output, "Link: {url}")?;
+  }
+  match &rule.fix {
+    Some(SerializableFix::Str(fix)) => writeln!(output, "Fix: {}", fix.trim_end())?,
+    Some(SerializableFix::List(edits)) => {
+      writeln!(output, "Fix:")?;
+      for edit in edits {
+        let target = edit.target.as_deref().unwrap_or("match");
+        writeln!(output, "  {target}: {}", edit.template.trim_end())?;
       }
     }
-    Self {
-      rules,
-      kind_rule_mapping: mapping,
+    None => writeln!(output, "Fix: none")?,
+  }
+  let invalid = cases
+    .iter()
+    .filter(|case| case.id == rule.id)
+    .flat_map(|case| &case.invalid);
+  let example = invalid
+    .map(|src| (src, rule.language.ast_grep(src)))
+    .find(|(_, grep)| grep.root().find(&rule.matcher).is_some());
+  let Some((src, grep)) = example else {
+    return Ok(());
+  };
+  let ranges: Vec<_> = grep
+    .root()
+    .find_all(&rule.matcher)
+    .map(|m| m.range())
+    .collect();
+  writeln!(output, "Example:")?;
+  write!(output, "{}", underline_ranges(src, &ranges))?;
+  if rule.has_fix() {
+    writeln!(output, "Fixed:")?;
+    for line in apply_fix(src, rule).lines() {
+      writeln!(output, "  {line}")?;
+    }
+  }
+  Ok(())
+}
+
+/// Indent every line of the source and mark the characters in the ranges with `^` below.
+fn underline_ranges(src: &str, ranges: &[std::ops::Range<usize>]) -> String {
+  let mut ret = String::new();
+  let mut offset = 0;
+  for raw in src.split_inclusive('\n') {
+    let line = raw.trim_end_matches(['\n', '\r']);
+    let marks: String = line
+      .char_indices()
+      .map(
+        |(i, c)| match (ranges.iter().any(|r| r.contains(&(offset + i))), c) {
+          (true, _) => '^',
+          (false, '\t') => '\t',
+          _ => ' ',
+        },
+      )
+      .collect();
+    ret.push_str(&format!("  {line}\n"));
+    if marks.contains('^') {
+      ret.push_str(&format!("  {}\n", marks.trim_end()));
     }
+    offset += raw.len();
   }
+  ret
+}
 
-  fn find(&self, root: &AstGrep<SupportLang>) -> bool {
-    for node in root.root().dfs() {
-      let kind = node.kind_id() as usize;
-      let Some(rule_idx) = self.kind_rule_mapping.get(kind) else {
-        continue;
+impl<P: Printer + Sync> Worker for ScanWithConfig<P> {
+  type Item = (PathBuf, AstGrep<SupportLang>, Cached);
+  fn build_walk(&self) -> WalkParallel {
+    let arg = &self.arg;
+    let threads = num_cpus::get().min(12);
+    NoIgnore::disregard(&arg.no_ignore)
+      .walk(&arg.paths)
+      .threads(threads)
+      .build_parallel()
+  }
+  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+    if self.walk_done() {
+      return None;
+    }
+    let rules = self.configs.for_path(path);
+    // rules with `language: all` also check files without a grammar
+    let content = if self.configs.text_rules().is_empty() {
+      None
+    } else {
+      let content = if rules.is_empty() {
+        read_text(path)?
+      } else {
+        read_file(path)?
       };
-      for &idx in rule_idx {
-        let rule = &self.rules[idx];
-        if rule.matcher.match_node(node.clone()).is_some() {
-          return true;
-        }
+      self.scan_text(path, &content);
+      Some(content)
+    };
+    if rules.is_empty() {
+      return None;
+    }
+    let content = match content {
+      Some(content) => content,
+      None => read_file(path)?,
+    };
+    let lang = rules[0].language;
+    let cross_file = cross_file_rules(&rules);
+    let combined = CombinedScan::new(rules).with_times(self.times.as_ref());
+    if self.arg.fail_fast {
+      // findings of cross-file rules are only known at the end, so they do not stop the scan
+      let grep = lang.ast_grep(content);
+      let entry = self.shown_entry(&combined, &grep)?;
+      self.found.store(true, Ordering::Relaxed);
+      return Some((path.to_path_buf(), grep, Cached::Hit(entry)));
+    }
+    // matches of cross-file rules depend on other files and are not cached
+    let Some(cache) = self.cache.as_ref().filter(|_| cross_file.is_empty()) else {
+      let grep = lang.ast_grep(content);
+      let found = index_file(&self.index, &cross_file, path, &grep);
+      return (found || combined.find(&grep)).then(|| (path.to_path_buf(), grep, Cached::Disabled));
+    };
+    let content_hash = hash_of(content.as_bytes());
+    if let Some(entry) = cache.get(path, content_hash, self.rules_hash) {
+      if entry.rules.is_empty() {
+        return None;
       }
+      return Some((
+        path.to_path_buf(),
+        lang.ast_grep(content),
+        Cached::Hit(entry),
+      ));
+    }
+    let grep = lang.ast_grep(content);
+    if combined.find(&grep) {
+      return Some((path.to_path_buf(), grep, Cached::Miss(content_hash)));
     }
-    false
+    let entry = CacheEntry {
+      content_hash,
+      rules_hash: self.rules_hash,
+      ..Default::default()
+    };
+    if let Err(error) = cache.put(path, &entry) {
+      print_warning(&error);
+    }
+    None
   }
-  fn scan<'a>(
-    &self,
-    root: &'a AstGrep<SupportLang>,
-  ) -> HashMap<usize, Vec<NodeMatch<'a, SupportLang>>> {
-    let mut results = HashMap::new();
-    for node in root.root().dfs() {
-      let kind = node.kind_id() as usize;
-      let Some(rule_idx) = self.kind_rule_mapping.get(kind) else {
-        continue;
-      };
-      for &idx in rule_idx {
-        let rule = &self.rules[idx];
-        if let Some(ret) = rule.matcher.match_node(node.clone()) {
-          let matches = results.entry(idx).or_insert_with(Vec::new);
-          matches.push(ret);
-        }
+  /// Files with cross-file rules are scanned after all files are produced, when the index is complete.
+  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+    self.printer.before_print()?;
+    let mut totals = ScanTotals::default();
+    let mut deferred = vec![];
+    for item in items {
+      if !self.arg.fail_fast
+        && self
+          .configs
+          .for_path(&item.0)
+          .iter()
+          .any(|r| r.is_cross_file())
+      {
+        deferred.push(item);
+      } else if self.consume_item(item, None, &mut totals)? {
+        self.printer.after_print()?;
+        return diagnostic_result(1);
       }
     }
+    let index = self.index.lock().expect("index should not be poisoned");
+    for item in deferred {
+      self.consume_item(item, Some(&*index), &mut totals)?;
+    }
+    totals.has_error += self.report_grouped(totals.grouped, &totals.counts)?;
+    totals.has_error += self.report_text()?;
+    self.printer.after_print()?;
+    self.print_slow_rules();
+    diagnostic_result(totals.has_error)
+  }
+  fn walk_done(&self) -> bool {
+    self.found.load(Ordering::Relaxed)
+  }
+}
+
+/// Findings of cross-file rules in a file depend on the files importing from it.
+impl<P: Printer + Sync> WatchWorker for ScanWithConfig<P> {
+  fn roots(&self) -> Vec<PathBuf> {
+    self.arg.paths.clone()
+  }
+  fn remove_files(&self, paths: &[PathBuf]) {
+    let mut index = self.index.lock().expect("index should not be poisoned");
+    for path in paths {
+      index.remove_file(path);
+    }
+  }
+  fn dependencies(&self, paths: &[PathBuf], files: &[PathBuf]) -> Vec<PathBuf> {
+    let index = self.index.lock().expect("index should not be poisoned");
+    files
+      .iter()
+      .filter(|file| paths.iter().any(|path| index.imports_from(path, file)))
+      .cloned()
+      .collect()
+  }
+}
+
+struct ExplainWithConfig {
+  arg: ScanArg,
+  configs: RuleCollection<SupportLang>,
+  /// imports of the scanned files for cross-file rules, complete once all files are produced
+  index: Mutex<SymbolIndex>,
+}
+
+impl Worker for ExplainWithConfig {
+  type Item = (PathBuf, AstGrep<SupportLang>);
+  fn build_walk(&self) -> WalkParallel {
+    let arg = &self.arg;
+    let threads = num_cpus::get().min(12);
+    NoIgnore::disregard(&arg.no_ignore)
+      .walk(&arg.paths)
+      .threads(threads)
+      .build_parallel()
+  }
+  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+    let rules = self.configs.for_path(path);
+    let lang = rules.first()?.language;
+    let unit = filter_file_interactive(path, lang, ast_grep_core::matcher::MatchAll)?;
+    index_file(
+      &self.index,
+      &cross_file_rules(&rules),
+      &unit.path,
+      &unit.grep,
+    );
+    Some((unit.path, unit.grep))
+  }
+  /// Files with cross-file rules are explained after all files are produced, when the index is complete.
+  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    let mut explain = |path: &Path, grep: &AstGrep<SupportLang>, index| -> Result<()> {
+      let rules = self.configs.for_path(path);
+      let mut combined = CombinedScan::new(rules);
+      if let Some(index) = index {
+        combined = combined.with_index(path, index);
+      }
+      for (idx, trace) in combined.explain(grep) {
+        let rule = &combined.rules[idx];
+        writeln!(stdout, "{}: {}", path.display(), rule.id)?;
+        write!(stdout, "{trace}")?;
+      }
+      Ok(())
+    };
+    let mut deferred = vec![];
+    for (path, grep) in items {
+      if self
+        .configs
+        .for_path(&path)
+        .iter()
+        .any(|r| r.is_cross_file())
+      {
+        deferred.push((path, grep));
+      } else {
+        explain(&path, &grep, None)?;
+      }
+    }
+    let index = self.index.lock().expect("index should not be poisoned");
+    for (path, grep) in deferred {
+      explain(&path, &grep, Some(&*index))?;
+    }
+    Ok(())
+  }
+}
+
+/// List files that have applicable rules without reading them.
+struct ListFiles<W: Write> {
+  arg: ScanArg,
+  configs: RuleCollection<SupportLang>,
+  output: Mutex<W>,
+  paths: PathRenderer,
+}
+
+impl<W: Write> ListFiles<W> {
+  fn new(arg: ScanArg, configs: RuleCollection<SupportLang>, output: W) -> Result<Self> {
+    let paths = PathRenderer::try_new(arg.path_style, arg.config.clone())?;
+    Ok(Self {
+      arg,
+      configs,
+      output: Mutex::new(output),
+      paths,
+    })
+  }
+}
+
+impl<W: Write + Send> Worker for ListFiles<W> {
+  type Item = PathBuf;
+  fn build_walk(&self) -> WalkParallel {
+    let arg = &self.arg;
+    let threads = num_cpus::get().min(12);
+    NoIgnore::disregard(&arg.no_ignore)
+      .walk(&arg.paths)
+      .threads(threads)
+      .build_parallel()
+  }
+  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+    let rules = self.configs.for_path(path);
+    (!rules.is_empty()).then(|| path.to_path_buf())
+  }
+  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+    let mut files: Vec<_> = items.collect();
+    files.sort();
+    let mut output = self.output.lock().expect("should work");
+    for path in files {
+      writeln!(output, "{}", self.paths.render(&path).display())?;
+    }
+    Ok(())
+  }
+}
+
+/// Parse files without matching rules. Items are the parsed files.
+struct ParseOnly<W: Write> {
+  arg: ScanArg,
+  configs: RuleCollection<SupportLang>,
+  output: Mutex<W>,
+  /// when the worker is created, right before the walk starts
+  start: Instant,
+}
+
+impl<W: Write> ParseOnly<W> {
+  fn new(arg: ScanArg, configs: RuleCollection<SupportLang>, output: W) -> Self {
+    Self {
+      arg,
+      configs,
+      output: Mutex::new(output),
+      start: Instant::now(),
+    }
+  }
+}
+
+impl<W: Write + Send> Worker for ParseOnly<W> {
+  type Item = ();
+  fn build_walk(&self) -> WalkParallel {
+    let arg = &self.arg;
+    let threads = num_cpus::get().min(12);
+    NoIgnore::disregard(&arg.no_ignore)
+      .walk(&arg.paths)
+      .threads(threads)
+      .build_parallel()
+  }
+  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+    let rules = self.configs.for_path(path);
+    let lang = rules.first()?.language;
+    let src = std::fs::read_to_string(path)
+      .with_context(|| EC::ReadFile(path.to_path_buf()))
+      .map_err(|err| eprintln!("{err:?}"))
+      .ok()?;
+    drop(lang.ast_grep(src));
+    Some(())
+  }
+  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+    let files = items.count();
+    let total = self.start.elapsed();
+    let mut output = self.output.lock().expect("should work");
+    writeln!(output, "{}", parse_summary(files, total))?;
+    Ok(())
+  }
+}
+
+/// The total is wall time, so the rate counts files parsed by all threads together.
+fn parse_summary(files: usize, total: Duration) -> String {
+  let plural = if files == 1 { "file" } else { "files" };
+  let secs = total.as_secs_f64();
+  let rate = if secs > 0.0 { files as f64 / secs } else { 0.0 };
+  format!("Parsed {files} {plural} in {secs:.3}s ({rate:.1} files/sec)")
+}
+
+/// Apply fixes in memory and report fixes that do not converge.
+struct IdempotentCheck<W: Write> {
+  arg: ScanArg,
+  configs: RuleCollection<SupportLang>,
+  output: Mutex<W>,
+}
+
+impl<W: Write> IdempotentCheck<W> {
+  fn new(arg: ScanArg, configs: RuleCollection<SupportLang>, output: W) -> Self {
+    Self {
+      arg,
+      configs,
+      output: Mutex::new(output),
+    }
+  }
+}
+
+impl<W: Write + Send> Worker for IdempotentCheck<W> {
+  type Item = (PathBuf, AstGrep<SupportLang>);
+  fn build_walk(&self) -> WalkParallel {
+    let arg = &self.arg;
+    let threads = num_cpus::get().min(12);
+    NoIgnore::disregard(&arg.no_ignore)
+      .walk(&arg.paths)
+      .threads(threads)
+      .build_parallel()
+  }
+  fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+    let rules = self.configs.for_path(path);
+    let lang = rules.first()?.language;
+    let combined = CombinedScan::new(rules);
+    let unit = filter_file_interactive(path, lang, ast_grep_core::matcher::MatchAll)?;
+    combined.find(&unit.grep).then_some((unit.path, unit.grep))
+  }
+  fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+    let mut output = self.output.lock().expect("should work");
+    let mut failed = 0;
+    for (path, grep) in items {
+      let src = grep.root().text();
+      for rule in self.configs.for_path(&path) {
+        if !fix_selected(&self.arg.fix_rule, rule) {
+          continue;
+        }
+        if let Some(issue) = check_fix(&src, rule) {
+          failed += 1;
+          writeln!(output, "{}: fix of `{}` {issue}", path.display(), rule.id)?;
+        }
+      }
+    }
+    if failed > 0 {
+      Err(anyhow::anyhow!(EC::NonIdempotentFix(failed)))
+    } else {
+      Ok(())
+    }
+  }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FixIssue {
+  /// the rule still matches the fixed code, but reapplying the fix changes nothing
+  Unresolved,
+  /// reapplying the fix changes the fixed code again
+  Unstable,
+}
+
+impl std::fmt::Display for FixIssue {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Unresolved => write!(f, "leaves matches of its rule"),
+      Self::Unstable => write!(f, "changes the code again when reapplied"),
+    }
+  }
+}
+
+/// Apply the fix to every match of the rule in the source and return the fixed code.
+/// When matches nest, only the outermost one is fixed.
+fn apply_fix(src: &str, rule: &RuleConfig<SupportLang>) -> String {
+  let grep = rule.language.ast_grep(src);
+  let matches = Visitor::new(&rule.matcher)
+    .reentrant(false)
+    .visit(grep.root());
+  let edits = matches
+    .map(|nm| {
+      let edits = rule.fix_edits(&nm).unwrap_or_default().into_iter();
+      edits
+        .map(|e| (e.position..e.position + e.deleted_length, e.inserted_text))
+        .collect()
+    })
+    .collect();
+  apply_edits_bottom_up(src.to_string(), edits)
+}
+
+fn check_fix(src: &str, rule: &RuleConfig<SupportLang>) -> Option<FixIssue> {
+  let fixed = apply_fix(src, rule);
+  let grep = rule.language.ast_grep(&fixed);
+  grep.root().find(&rule.matcher)?;
+  if apply_fix(&fixed, rule) == fixed {
+    Some(FixIssue::Unresolved)
+  } else {
+    Some(FixIssue::Unstable)
+  }
+}
+
+fn cached_matches(matched: &HashMap<usize, Vec<NodeMatch<SupportLang>>>) -> Vec<CachedMatch> {
+  let mut cached = vec![];
+  for (&rule, matches) in matched {
+    for m in matches {
+      let range = m.range();
+      cached.push(CachedMatch {
+        rule,
+        kind_id: m.kind_id(),
+        start: range.start,
+        end: range.end,
+      });
+    }
+  }
+  cached
+}
+
+/// Total match time of each rule over the scan by rule id, for `--report-slow-rules`.
+#[derive(Default)]
+struct RuleTimes(Mutex<HashMap<String, Duration>>);
+
+impl RuleTimes {
+  fn add(&self, rules: &[&RuleConfig<SupportLang>], elapsed: &[Duration]) {
+    let mut times = self.0.lock().expect("lock should not be poisoned");
+    for (rule, elapsed) in rules.iter().zip(elapsed) {
+      *times.entry(rule.id.clone()).or_default() += *elapsed;
+    }
+  }
+
+  /// The `n` slowest rules, slowest first. Rules with the same time are sorted by id.
+  fn report(&self, n: usize) -> String {
+    let times = self.0.lock().expect("lock should not be poisoned");
+    let mut sorted: Vec<_> = times.iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let mut ret = String::from("Slowest rules:\n");
+    for (id, elapsed) in sorted.into_iter().take(n) {
+      let ms = elapsed.as_secs_f64() * 1000.0;
+      ret.push_str(&format!("{ms:>10.2}ms  {id}\n"));
+    }
+    ret
+  }
+}
+
+fn omitted_note(path: &Path, omitted: usize) -> String {
+  let plural = if omitted == 1 { "match" } else { "matches" };
+  format!("{}: ... and {omitted} more {plural}", path.display())
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GroupBy {
+  /// Print findings file by file as they are scanned.
+  /// Files matched by a rule that escalates are printed after the scan.
+  File,
+  /// Print a section for each rule, sorted by rule id.
+  Rule,
+  /// Print a section for each severity, from error to hint.
+  /// Findings of a rule escalated by its match count are in the escalated section.
+  Severity,
+}
+
+impl GroupBy {
+  /// The sort key and the header of the section the findings of the rule belong to.
+  /// The severity is the one reported for the rule, which may be escalated.
+  fn group_of(&self, rule: &RuleConfig<SupportLang>, severity: &Severity) -> (usize, String) {
+    match self {
+      Self::File => (0, String::new()),
+      Self::Rule => (0, rule.id.clone()),
+      Self::Severity => match severity {
+        Severity::Error => (0, "error".into()),
+        Severity::Warning => (1, "warning".into()),
+        Severity::Info => (2, "info".into()),
+        Severity::Hint => (3, "hint".into()),
+        Severity::Off => (4, "off".into()),
+      },
+    }
+  }
+}
+
+/// Whether diagnostics of the severity are printed under `--min-severity`.
+/// The severity of a rule that escalates is the one after escalation.
+fn shown(min_severity: Option<&Severity>, severity: &Severity) -> bool {
+  min_severity.map_or(true, |min| severity >= min)
+}
+
+/// Whether the rule has a fix that is not limited to other rules by `--fix-rule`.
+fn fix_selected(fix_rule: &[String], rule: &RuleConfig<SupportLang>) -> bool {
+  rule.has_fix() && (fix_rule.is_empty() || fix_rule.contains(&rule.id))
+}
+
+fn match_rule_on_file(
+  path: &Path,
+  matches: Vec<NodeMatch<SupportLang>>,
+  rule: &RuleConfig<SupportLang>,
+  severity: &Severity,
+  fix: bool,
+  file_content: &String,
+  reporter: &impl Printer,
+) -> Result<()> {
+  let matches = matches.into_iter();
+  let file = SimpleFile::new(path.to_string_lossy(), file_content);
+  if fix {
+    let diffs = matches.map(|m| Diff::from_rule(m, rule));
+    reporter.print_rule_diffs(diffs, path, rule, severity)?;
+  } else {
+    reporter.print_rule(matches, file, rule, severity)?;
+  }
+  Ok(())
+}
+
+/// Matches of one file by rule index.
+type FileMatches<'a> = HashMap<usize, Vec<NodeMatch<'a, SupportLang>>>;
+
+/// The shared scheduler with CLI reporting on top: match caps, cached matches and explanations.
+struct CombinedScan<'r> {
+  inner: ast_grep_config::CombinedScan<'r, SupportLang>,
+  /// path of the scanned file to warn about rules exceeding their timeout
+  path: Option<&'r Path>,
+  times: Option<&'r RuleTimes>,
+}
+
+impl<'r> Deref for CombinedScan<'r> {
+  type Target = ast_grep_config::CombinedScan<'r, SupportLang>;
+  fn deref(&self) -> &Self::Target {
+    &self.inner
+  }
+}
+
+impl<'r> CombinedScan<'r> {
+  fn new(rules: Vec<&'r RuleConfig<SupportLang>>) -> Self {
+    Self {
+      inner: ast_grep_config::CombinedScan::new(rules),
+      path: None,
+      times: None,
+    }
+  }
+
+  /// Report matches of cross-file rules in the file unless the index has them imported.
+  fn with_index(self, path: &'r Path, index: &'r SymbolIndex) -> Self {
+    Self {
+      inner: self.inner.with_index(path, index),
+      ..self
+    }
+  }
+
+  /// Warn about rules exceeding their timeout in the file and add the match time to `times`.
+  fn with_timing(self, path: &'r Path, times: Option<&'r RuleTimes>) -> Self {
+    Self {
+      path: Some(path),
+      ..self.with_times(times)
+    }
+  }
+
+  /// Add the match time to `times` without warning about timeouts, e.g. when a file is produced.
+  fn with_times(self, times: Option<&'r RuleTimes>) -> Self {
+    Self { times, ..self }
+  }
+
+  /// Whether any rule matches. Timed like `scan_all` if the match time is recorded.
+  fn find(&self, root: &AstGrep<SupportLang>) -> bool {
+    if self.times.is_none() {
+      return self.inner.find(root);
+    }
+    !self.scan_all(root).is_empty()
+  }
+
+  /// Matches of all rules, timed if the match time is recorded or any rule has a timeout.
+  fn scan_all<'a>(
+    &self,
+    root: &'a AstGrep<SupportLang>,
+  ) -> Vec<(usize, NodeMatch<'a, SupportLang>)> {
+    if self.times.is_none() && self.rules.iter().all(|rule| rule.timeout.is_none()) {
+      return self.inner.scan(root).collect();
+    }
+    let (found, elapsed) = self.inner.scan_timed(root);
+    if let Some(times) = self.times {
+      times.add(&self.rules, &elapsed);
+    }
+    let Some(path) = self.path else {
+      return found;
+    };
+    for (rule, elapsed) in self.rules.iter().zip(elapsed) {
+      if rule.timed_out(elapsed) {
+        print_warning(&anyhow::anyhow!(
+          "Rule `{}` exceeds its timeout of {}ms in {} and is skipped for the file.",
+          rule.id,
+          rule.timeout.unwrap_or_default(),
+          path.display()
+        ));
+      }
+    }
+    found
+  }
+
+  /// Collect matches of every rule in document order, keeping at most `max_matches` in the file.
+  /// Returns the number of matches beyond the cap by rule. A rule whose matches are all omitted
+  /// still has an empty entry so that its findings are reflected in the exit code.
+  fn scan<'a>(
+    &self,
+    root: &'a AstGrep<SupportLang>,
+    max_matches: Option<usize>,
+  ) -> (FileMatches<'a>, HashMap<usize, usize>) {
+    let max_matches = max_matches.unwrap_or(usize::MAX);
+    let mut results = HashMap::new();
+    let mut kept = 0;
+    let mut omitted = HashMap::new();
+    // (rule index, scope id) pairs already reported for firstPerFile/firstPerScope
+    let mut scopes = HashSet::new();
+    for (idx, ret) in self.scan_all(root) {
+      let rule = &self.rules[idx];
+      if let Some(scope) = rule.first_match_scope(&ret) {
+        if !scopes.insert((idx, scope)) {
+          continue;
+        }
+      }
+      let matches = results.entry(idx).or_insert_with(Vec::new);
+      if kept < max_matches {
+        kept += 1;
+        matches.push(ret);
+      } else {
+        *omitted.entry(idx).or_default() += 1;
+      }
+    }
+    (results, omitted)
+  }
+
+  /// Restore matches recorded in the cache. Only the recorded nodes are matched again
+  /// to recover meta variables, instead of scanning the whole file.
+  fn rematch<'a>(
+    &self,
+    root: &'a AstGrep<SupportLang>,
+    entry: &CacheEntry,
+  ) -> HashMap<usize, Vec<NodeMatch<'a, SupportLang>>> {
+    let mut recorded: HashMap<_, Vec<usize>> = HashMap::new();
+    for m in &entry.matches {
+      recorded
+        .entry((m.kind_id, m.start, m.end))
+        .or_default()
+        .push(m.rule);
+    }
+    let mut results: HashMap<_, Vec<_>> = entry.rules.iter().map(|&i| (i, vec![])).collect();
+    let mut elapsed = vec![Duration::ZERO; self.rules.len()];
+    for node in root.root().dfs() {
+      let range = node.range();
+      let Some(rule_idx) = recorded.get(&(node.kind_id(), range.start, range.end)) else {
+        continue;
+      };
+      for &idx in rule_idx {
+        let Some(rule) = self.rules.get(idx) else {
+          continue;
+        };
+        let start = Instant::now();
+        let matched = rule.matcher.match_node(node.clone());
+        elapsed[idx] += start.elapsed();
+        if let Some(ret) = matched {
+          results.entry(idx).or_default().push(ret);
+        }
+      }
+    }
+    if let Some(times) = self.times {
+      times.add(&self.rules, &elapsed);
+    }
     results
   }
+
+  /// Explain every rule against its first matched node, or its first candidate if none matches.
+  /// Rules without any candidate node in the file are not explained.
+  fn explain(&self, root: &AstGrep<SupportLang>) -> Vec<(usize, MatchTrace)> {
+    let mut candidates = vec![None; self.rules.len()];
+    let mut matched = vec![false; self.rules.len()];
+    for node in root.root().dfs() {
+      for &idx in self.candidates(node.kind_id()) {
+        if matched[idx] {
+          continue;
+        }
+        let found = self.rules[idx].matcher.match_node(node.clone());
+        if found.map_or(false, |nm| self.is_reported(idx, &nm)) {
+          matched[idx] = true;
+          candidates[idx] = Some(node.clone());
+        } else if candidates[idx].is_none() {
+          candidates[idx] = Some(node.clone());
+        }
+      }
+    }
+    candidates
+      .into_iter()
+      .enumerate()
+      .filter_map(|(idx, node)| Some((idx, self.rules[idx].matcher.explain(node?))))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ast_grep_config::{from_yaml_string, GlobalRules};
+  use clap::Parser;
+  use std::sync::atomic::AtomicUsize;
+
+  /// Parse the arguments after `sg scan`.
+  fn scan_arg(args: &[&str]) -> ScanArg {
+    let command = ["sg", "scan"].iter().chain(args);
+    let arg = crate::App::try_parse_from(command).expect("should parse");
+    let crate::Commands::Scan(arg) = arg.command else {
+      panic!("should be scan");
+    };
+    arg
+  }
+
+  #[test]
+  fn test_print_rules() {
+    let yaml = r"
+id: test-rule
+message: test
+severity: hint
+language: TypeScript
+rule:
+  kind: call_expression
+  has:
+    matches: callee
+utils:
+  callee:
+    pattern: console.log
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let mut output = vec![];
+    print_rules(&configs, &mut output).expect("should print");
+    let printed = String::from_utf8(output).expect("should be utf8");
+    assert!(printed.starts_with("---\n"));
+    assert!(printed.contains("id: test-rule"));
+    assert!(printed.contains("pattern: console.log"));
+    assert!(!printed.contains("matches"));
+    assert!(!printed.contains("utils"));
+  }
+
+  #[test]
+  fn test_explain_rule() {
+    let yaml = r"
+id: no-log
+message: Remove console.log
+note: Use the logger instead.
+url: https://example.com/no-log
+severity: warning
+language: TypeScript
+rule:
+  pattern: console.log($A)
+fix: logger.info($A)
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let cases = [TestCase {
+      id: "no-log".into(),
+      valid: vec!["logger.info(1)".into()],
+      invalid: vec!["let a = 1\nconsole.log(a)".into()],
+      expect: vec![],
+    }];
+    let mut output = vec![];
+    explain_rule(&rules[0], &cases, &mut output).expect("should print");
+    let printed = String::from_utf8(output).expect("should be utf8");
+    let expected = "\
+Rule: no-log
+Severity: warning
+Message: Remove console.log
+Note: Use the logger instead.
+Link: https://example.com/no-log
+Fix: logger.info($A)
+Example:
+  let a = 1
+  console.log(a)
+  ^^^^^^^^^^^^^^
+Fixed:
+  let a = 1
+  logger.info(a)
+";
+    assert_eq!(printed, expected);
+    // no example without test cases
+    let mut output = vec![];
+    explain_rule(&rules[0], &[], &mut output).expect("should print");
+    let printed = String::from_utf8(output).expect("should be utf8");
+    assert!(printed.ends_with("Fix: logger.info($A)\n"));
+  }
+
+  #[test]
+  fn test_explain_disabled_rule() {
+    let tmp = crate::utils::temp_dir();
+    let rule = tmp.path().join("rule.yml");
+    let yaml = "id: no-log\nmessage: test\nseverity: off\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)";
+    std::fs::write(&rule, yaml).unwrap();
+    let rule = rule.to_string_lossy().to_string();
+    let explain = |id: &str| {
+      let arg = scan_arg(&["-r", &rule, "--explain-rule", id]);
+      run_with_config(arg).expect_err("should fail")
+    };
+    let error = explain("no-log");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::RuleDisabled(_))
+    ));
+    let error = explain("no-such-rule");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::RuleNotFound(_))
+    ));
+  }
+
+  #[test]
+  fn test_skip_invalid_rules() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let rule_dir = dir.join("rules");
+    std::fs::create_dir_all(&rule_dir).expect("should create dir");
+    std::fs::write(dir.join("sgconfig.yml"), "ruleDirs: [rules]").unwrap();
+    let valid = |id: &str, pattern: &str| {
+      format!("id: {id}\nmessage: test\nseverity: hint\nlanguage: TypeScript\nrule:\n  pattern: {pattern}")
+    };
+    std::fs::write(rule_dir.join("a.yml"), valid("a", "foo($A)")).unwrap();
+    std::fs::write(rule_dir.join("b.yml"), valid("b", "bar($A)")).unwrap();
+    std::fs::write(rule_dir.join("broken.yml"), "id: broken\nrule: 123").unwrap();
+
+    let ret = find_config_skip_invalid(Some(dir.join("sgconfig.yml")));
+    assert!(find_config(Some(dir.join("sgconfig.yml"))).is_err());
+    let (configs, skipped) = ret.expect("should load valid rules");
+    assert_eq!(skipped.len(), 1);
+    let rules = configs.for_path("test.ts");
+    assert_eq!(rules.len(), 2);
+    let grep = SupportLang::TypeScript.ast_grep("foo(1); bar(2)");
+    let (matched, _) = CombinedScan::new(rules).scan(&grep, None);
+    assert_eq!(matched.len(), 2);
+  }
+
+  #[test]
+  fn test_rule_language() {
+    let yaml = r"
+id: ts-rule
+message: test
+severity: hint
+language: TypeScript
+rule:
+  pattern: print($A)
+---
+id: py-rule
+message: test
+severity: hint
+language: Python
+rule:
+  pattern: print($A)
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let ts_rules = configs.for_path("test.ts");
+    assert_eq!(ts_rules.len(), 1);
+    assert_eq!(ts_rules[0].id, "ts-rule");
+    let py_rules = configs.for_path("test.py");
+    assert_eq!(py_rules.len(), 1);
+    assert_eq!(py_rules[0].id, "py-rule");
+    let grep = SupportLang::Python.ast_grep("print(123)");
+    assert_eq!(CombinedScan::new(py_rules).scan(&grep, None).0.len(), 1);
+    assert!(configs.for_path("test.go").is_empty());
+  }
+
+  #[test]
+  fn test_rule_without_language() {
+    let yaml = "id: test\nmessage: test\nseverity: hint\nrule:\n  pattern: a";
+    let ret = from_yaml_string::<SupportLang>(yaml, &GlobalRules::default());
+    assert!(ret.is_err());
+  }
+
+  #[test]
+  fn test_explain() {
+    let yaml = r"
+id: log-in-function
+message: test
+severity: hint
+language: TypeScript
+rule:
+  pattern: console.log($A)
+  inside:
+    kind: function_declaration
+    stopBy: end
+---
+id: no-candidate
+message: test
+severity: hint
+language: TypeScript
+rule:
+  kind: class_declaration
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let combined = CombinedScan::new(configs.for_path("test.ts"));
+    let grep = SupportLang::TypeScript.ast_grep("console.log(1)");
+    let traces = combined.explain(&grep);
+    assert_eq!(traces.len(), 1);
+    let (idx, trace) = &traces[0];
+    assert_eq!(combined.rules[*idx].id, "log-in-function");
+    assert!(!trace.matched);
+    assert!(trace.children[0].matched);
+    assert_eq!(trace.children[1].label, "inside");
+    assert!(!trace.children[1].matched);
+
+    let grep = SupportLang::TypeScript.ast_grep("console.log(1); function a() { console.log(2) }");
+    let (_, trace) = &combined.explain(&grep)[0];
+    assert!(trace.matched);
+    assert!(trace.env.contains(&("A".to_string(), "2".to_string())));
+  }
+
+  #[test]
+  fn test_max_matches_per_file() {
+    let yaml = r"
+id: no-log
+message: test
+severity: error
+language: TypeScript
+rule:
+  pattern: console.log($A)
+---
+id: no-debugger
+message: test
+severity: warning
+language: TypeScript
+rule:
+  kind: debugger_statement
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let combined = CombinedScan::new(configs.for_path("test.ts"));
+    let src = "console.log(1); console.log(2); console.log(3); debugger; console.log(4)";
+    let grep = SupportLang::TypeScript.ast_grep(src);
+    let (matched, omitted) = combined.scan(&grep, Some(2));
+    assert_eq!(omitted, HashMap::from([(0, 2), (1, 1)]));
+    assert_eq!(matched.values().map(Vec::len).sum::<usize>(), 2);
+    let texts: Vec<_> = matched.values().flatten().map(|m| m.text()).collect();
+    assert_eq!(texts, ["console.log(1)", "console.log(2)"]);
+    // rules with only omitted matches are kept for the exit code
+    assert_eq!(matched.len(), 2);
+    let note = omitted_note(Path::new("gen.ts"), omitted.values().sum());
+    assert_eq!(note, "gen.ts: ... and 3 more matches");
+    let (matched, omitted) = combined.scan(&grep, None);
+    assert!(omitted.is_empty());
+    assert_eq!(matched.values().map(Vec::len).sum::<usize>(), 5);
+  }
+
+  #[test]
+  fn test_first_per_scope() {
+    let yaml = r"
+id: no-log
+message: test
+severity: error
+language: TypeScript
+firstPerScope: function_declaration
+rule:
+  pattern: console.log($A)
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let combined = CombinedScan::new(configs.for_path("test.ts"));
+    let src = "function a() { console.log(1); console.log(2) } console.log(3); console.log(4)";
+    let grep = SupportLang::TypeScript.ast_grep(src);
+    let (matched, omitted) = combined.scan(&grep, None);
+    assert!(omitted.is_empty());
+    let texts: Vec<_> = matched.values().flatten().map(|m| m.text()).collect();
+    assert_eq!(texts, ["console.log(1)", "console.log(3)"]);
+  }
+
+  #[test]
+  fn test_preset() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let rule = dir.join("rule.yml");
+    let yaml = "id: no-log\nmessage: test\nseverity: error\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)\n---\nid: no-eval\nmessage: user\nseverity: hint\nlanguage: TypeScript\nrule:\n  pattern: eval(1)";
+    std::fs::write(&rule, yaml).unwrap();
+    let rule = rule.to_string_lossy().to_string();
+    let presets = ["--preset", "recommended", "--preset", "security"];
+    let arg = scan_arg(&[&["-r", &rule], &presets[..]].concat());
+    let ret = load_rules(&arg);
+    let (configs, _) = ret.expect("should load");
+    let combined = CombinedScan::new(configs.for_path("test.ts"));
+    let src = "console.log(1); debugger; eval(1); eval(2); a.innerHTML = b";
+    let grep = SupportLang::TypeScript.ast_grep(src);
+    let (matched, _) = combined.scan(&grep, None);
+    let mut found: Vec<_> = matched
+      .iter()
+      .flat_map(|(idx, nms)| nms.iter().map(|nm| (combined.rules[*idx], nm.text())))
+      .map(|(rule, text)| format!("{}: {text}", rule.id))
+      .collect();
+    found.sort();
+    // the user rule `no-eval` overrides the preset rule
+    let expected = [
+      "no-debugger: debugger;",
+      "no-eval: eval(1)",
+      "no-inner-html: a.innerHTML = b",
+      "no-log: console.log(1)",
+    ];
+    assert_eq!(found, expected);
+    let mut no_eval = configs.iter().filter(|r| r.id == "no-eval");
+    assert!(no_eval.all(|r| r.message == "user"));
+  }
+
+  #[test]
+  fn test_fix_rule() {
+    let yaml = r"
+id: log-to-info
+message: test
+severity: hint
+language: TypeScript
+rule:
+  pattern: console.log($A)
+fix: logger.info($A)
+---
+id: var-to-let
+message: test
+severity: hint
+language: TypeScript
+rule:
+  pattern: var $A = $B
+fix: let $A = $B
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let fix_rule = vec!["log-to-info".to_string()];
+    let src = "var a = 1\nconsole.log(a)";
+    let grep = SupportLang::TypeScript.ast_grep(src);
+    let combined = CombinedScan::new(configs.for_path("test.ts"));
+    let (matched, _) = combined.scan(&grep, None);
+    // both rules are still reported
+    assert_eq!(matched.len(), 2);
+    let mut fixed = SupportLang::TypeScript.ast_grep(src);
+    for idx in matched.keys() {
+      let rule = combined.rules[*idx];
+      let expected = rule.id == "log-to-info";
+      assert_eq!(fix_selected(&fix_rule, rule), expected);
+      if fix_selected(&fix_rule, rule) {
+        let fixer = rule.fixer.as_ref().expect("should have fixer");
+        fixed.replace(&rule.matcher, fixer).expect("should apply");
+      }
+    }
+    assert_eq!(fixed.generate(), "var a = 1\nlogger.info(a)");
+    // all fixes are applied by default
+    for rule in configs.for_path("test.ts") {
+      assert!(fix_selected(&[], rule));
+    }
+  }
+
+  #[test]
+  fn test_parse_only() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::write(dir.join("a.ts"), "console.log(1)").unwrap();
+    std::fs::write(dir.join("b.ts"), "console.log(2)").unwrap();
+    std::fs::write(dir.join("c.py"), "print(3)").unwrap();
+    let yaml = "id: no-log\nmessage: test\nseverity: error\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&["--parse-only"]);
+    let worker = ParseOnly::new(arg, configs, vec![]);
+    let paths: Vec<_> = ["a.ts", "b.ts", "c.py"].map(|p| dir.join(p)).into();
+    // error diagnostics are not reported since no rule runs
+    let ret = crate::utils::run_worker_on(&worker, &paths);
+    ret.expect("should not report diagnostics");
+    let output = String::from_utf8(worker.output.into_inner().unwrap()).unwrap();
+    assert!(output.starts_with("Parsed 2 files in "), "{output}");
+    assert!(output.ends_with(" files/sec)\n"), "{output}");
+    assert!(!output.contains("no-log"));
+  }
+
+  #[test]
+  fn test_list_files() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::create_dir_all(dir.join(".git")).expect("should create dir");
+    std::fs::create_dir_all(dir.join("gen")).expect("should create dir");
+    std::fs::write(dir.join(".gitignore"), "dist.ts").unwrap();
+    for file in ["a.ts", "b.py", "dist.ts", "gen/c.ts", ".hidden.ts"] {
+      std::fs::write(dir.join(file), "").unwrap();
+    }
+    let yaml = "id: no-log\nmessage: test\nseverity: error\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)\nignores: ['**/gen/**']";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let dir_arg = dir.to_string_lossy();
+    let arg = scan_arg(&["--list-files", &dir_arg]);
+    let worker = ListFiles::new(arg, configs, vec![]).expect("should create");
+    let files = crate::utils::collect_files(worker.build_walk());
+    let ret = crate::utils::run_worker_on(&worker, &files);
+    ret.expect("should list");
+    let output = String::from_utf8(worker.output.into_inner().unwrap()).unwrap();
+    assert_eq!(output, format!("{}\n", dir.join("a.ts").display()));
+  }
+
+  #[test]
+  fn test_all_languages_rule() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let rule_file = dir.join("fixme.yml");
+    let yaml =
+      "id: fixme\nmessage: leftover FIXME\nseverity: warning\nlanguage: all\nrule:\n  regex: FIXME";
+    std::fs::write(&rule_file, yaml).unwrap();
+    let (rules, text_rules) =
+      crate::config::read_rule_file(&rule_file, None).expect("should parse");
+    std::fs::remove_file(&rule_file).unwrap();
+    assert!(rules.is_empty());
+    let mut configs = RuleCollection::try_new(rules).expect("should build");
+    configs.add_text_rules(text_rules);
+    let files = [
+      ("a.ts", "let a = 1\n  // FIXME: remove"),
+      ("b.py", "a = 1\n  # FIXME: remove"),
+      ("c.rs", "fn a() {}\n  /* FIXME */ // FIXME"),
+      ("d.go", "package a\n  // FIXME"),
+      ("e.md", "# FIXME\n"),
+      ("f.py", "a = 1 # fixme"),
+    ];
+    for (path, src) in files {
+      std::fs::write(dir.join(path), src).unwrap();
+    }
+    std::fs::write(dir.join("g.bin"), [0xff, 0xfe, b'F']).unwrap();
+    let arg = scan_arg(&[]);
+    let mut output = vec![];
+    let template = Template::try_new("{line}:{col} {rule} {match}").unwrap();
+    let printer = TemplatePrinter::new(&mut output, template);
+    let worker = ScanWithConfig::new(arg, printer, configs).expect("should create");
+    let mut paths: Vec<_> = files.iter().map(|(path, _)| dir.join(path)).collect();
+    paths.push(dir.join("g.bin"));
+    let ret = crate::utils::run_worker_on(&worker, &paths);
+    assert!(ret.is_ok());
+    drop(worker);
+    let printed = String::from_utf8(output).expect("should be utf8");
+    let expected = [
+      "2:6 fixme FIXME",
+      "2:5 fixme FIXME",
+      "2:6 fixme FIXME",
+      "2:18 fixme FIXME",
+      "2:6 fixme FIXME",
+      "1:3 fixme FIXME",
+    ];
+    assert_eq!(printed.lines().collect::<Vec<_>>(), expected);
+  }
+
+  #[test]
+  fn test_quiet() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::write(dir.join("a.ts"), "console.log(1)").unwrap();
+    std::fs::write(dir.join("b.ts"), "let a = 1").unwrap();
+    let yaml = "id: no-log\nmessage: test\nseverity: error\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&["--quiet"]);
+    assert!(arg.quiet);
+    let worker = ScanWithConfig::new(arg, SilentPrinter, configs).expect("should create");
+    let ret = crate::utils::run_worker_on(&worker, &[dir.join("b.ts")]);
+    assert!(ret.is_ok());
+    let ret = crate::utils::run_worker_on(&worker, &[dir.join("a.ts")]);
+    let error = ret.expect_err("should fail");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::DiagnosticError(1))
+    ));
+  }
+
+  #[test]
+  fn test_escalate_severity() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::write(dir.join("a.ts"), "console.log(1)\n".repeat(5)).unwrap();
+    std::fs::write(dir.join("b.ts"), "console.log(2)\n".repeat(4)).unwrap();
+    std::fs::write(dir.join("c.ts"), "console.log(3)\n".repeat(2)).unwrap();
+    let yaml = "id: no-log\nmessage: test\nseverity: warning\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)\nescalate: { threshold: 10, to: error }";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&[]);
+    let mut output = vec![];
+    let worker =
+      ScanWithConfig::new(arg, JSONPrinter::new(&mut output), configs).expect("should create");
+    // 9 matches stay warning
+    let ret = crate::utils::run_worker_on(&worker, &[dir.join("a.ts"), dir.join("b.ts")]);
+    assert!(ret.is_ok());
+    // 11 matches across files escalate to error, each an error diagnostic
+    let files = ["a.ts", "b.ts", "c.ts"].map(|f| dir.join(f));
+    let ret = crate::utils::run_worker_on(&worker, &files);
+    let error = ret.expect_err("should fail");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::DiagnosticError(11))
+    ));
+    drop(worker);
+    let output = String::from_utf8(output).expect("should be utf8");
+    assert_eq!(output.matches(r#""severity": "warning""#).count(), 9);
+    assert_eq!(output.matches(r#""severity": "error""#).count(), 11);
+  }
+
+  #[test]
+  fn test_escalate_with_max_matches() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::write(dir.join("a.ts"), "console.log(1)\n".repeat(11)).unwrap();
+    let yaml = "id: no-log\nmessage: test\nseverity: warning\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)\nescalate: { threshold: 10, to: error }";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&["--max-matches-per-file", "2"]);
+    let mut output = vec![];
+    let worker =
+      ScanWithConfig::new(arg, JSONPrinter::new(&mut output), configs).expect("should create");
+    // omitted matches count toward the threshold and the errors
+    let ret = crate::utils::run_worker_on(&worker, &[dir.join("a.ts")]);
+    let error = ret.expect_err("should fail");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::DiagnosticError(11))
+    ));
+    drop(worker);
+    let output = String::from_utf8(output).expect("should be utf8");
+    assert_eq!(output.matches(r#""severity": "error""#).count(), 2);
+  }
+
+  #[test]
+  fn test_min_severity() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::write(dir.join("a.ts"), "console.log(1)\ndebugger").unwrap();
+    let yaml = r"
+id: no-log
+message: no log
+severity: info
+language: TypeScript
+rule:
+  pattern: console.log($A)
+---
+id: no-debugger
+message: no debugger
+severity: warning
+language: TypeScript
+rule:
+  kind: debugger_statement
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&["--min-severity", "warning"]);
+    let mut output = vec![];
+    let template = Template::try_new("{line}: {message}").unwrap();
+    let printer = TemplatePrinter::new(&mut output, template);
+    let worker = ScanWithConfig::new(arg, printer, configs).expect("should create");
+    let ret = crate::utils::run_worker_on(&worker, &[dir.join("a.ts")]);
+    assert!(ret.is_ok());
+    drop(worker);
+    let printed = String::from_utf8(output).expect("should be utf8");
+    assert_eq!(printed, "2: no debugger\n");
+    // the level applies to the escalated severity, in the output and the exit code alike
+    let escalate = |threshold: usize| {
+      let escalated = format!("{yaml}escalate: {{ threshold: {threshold}, to: error }}");
+      let rules = from_yaml_string(&escalated, &GlobalRules::default()).expect("should parse");
+      let configs = RuleCollection::try_new(rules).expect("should build");
+      let arg = scan_arg(&["--min-severity", "error"]);
+      let mut output = vec![];
+      let template = Template::try_new("{line}: {message}").unwrap();
+      let printer = TemplatePrinter::new(&mut output, template);
+      let worker = ScanWithConfig::new(arg, printer, configs).expect("should create");
+      let ret = crate::utils::run_worker_on(&worker, &[dir.join("a.ts")]);
+      drop(worker);
+      (ret, String::from_utf8(output).expect("should be utf8"))
+    };
+    let (ret, printed) = escalate(1);
+    assert!(ret.is_ok());
+    assert_eq!(printed, "");
+    let (ret, printed) = escalate(0);
+    let error = ret.expect_err("should fail");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::DiagnosticError(1))
+    ));
+    assert_eq!(printed, "2: no debugger\n");
+    // a disabled rule is below any level
+    assert!(!shown(Some(&Severity::Hint), &Severity::Off));
+    assert!(shown(Some(&Severity::Hint), &Severity::Error));
+  }
+
+  #[test]
+  fn test_report_slow_rules() {
+    let yaml = r"
+id: a-fast
+message: test
+severity: hint
+language: TypeScript
+rule: { kind: debugger_statement }
+---
+id: b-slow
+message: test
+severity: hint
+language: TypeScript
+rule: { kind: identifier, inside: { kind: program, stopBy: end } }
+---
+id: c-slow
+message: test
+severity: hint
+language: TypeScript
+rule: { kind: identifier }
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let grep = SupportLang::TypeScript.ast_grep("let a = b + c");
+    let times = RuleTimes::default();
+    let combined =
+      CombinedScan::new(rules.iter().collect()).with_timing(Path::new("a.ts"), Some(&times));
+    let (matched, _) = combined.scan(&grep, None);
+    assert_eq!(matched.len(), 2);
+    assert_eq!(times.0.lock().unwrap().len(), 3);
+    let rules: Vec<_> = rules.iter().collect();
+    let ms = Duration::from_millis;
+    times.add(&rules, &[ms(1), ms(30), ms(30)]);
+    times.add(&rules, &[ms(5), ms(0), ms(10)]);
+    let report = times.report(2);
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "Slowest rules:");
+    assert!(lines[1].ends_with("ms  c-slow"), "{report}");
+    assert!(lines[2].ends_with("ms  b-slow"), "{report}");
+    assert!(lines[1].trim_start().starts_with("40."), "{report}");
+  }
+
+  #[test]
+  fn test_report_slow_rules_on_every_path() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let file = dir.join("a.ts");
+    std::fs::write(&file, "console.log(1)").unwrap();
+    let yaml = "id: no-log\nmessage: test\nseverity: hint\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)";
+    let cache_dir = dir.join("cache").to_string_lossy().to_string();
+    let run = |args: &[&str], file: &PathBuf| {
+      let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+      let configs = RuleCollection::try_new(rules).expect("should build");
+      let arg = scan_arg(&[&["--report-slow-rules", "1"], args].concat());
+      let worker = ScanWithConfig::new(arg, SilentPrinter, configs).expect("should create");
+      let _ = crate::utils::run_worker_on(&worker, std::slice::from_ref(file));
+      let times = worker.times.expect("should record times");
+      let recorded = times.0.lock().unwrap().contains_key("no-log");
+      recorded
+    };
+    // the first run fills the cache and the second one only rematches cached nodes
+    assert!(run(&["--cache-dir", &cache_dir], &file));
+    assert!(run(&["--cache-dir", &cache_dir], &file));
+    // a file without findings is only matched when produced under --fail-fast
+    let clean = dir.join("b.ts");
+    std::fs::write(&clean, "let a = 1").unwrap();
+    assert!(run(&["--fail-fast"], &clean));
+  }
+
+  #[test]
+  fn test_baseline_diff() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let git = |args: &[&str]| {
+      let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        // settings like commit.gpgsign of the user must not apply
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .stdout(std::process::Stdio::null())
+        .status()
+        .expect("git should run");
+      assert!(status.success(), "git {args:?}");
+    };
+    git(&["init", "-q"]);
+    std::fs::write(dir.join("a.ts"), "console.log(1)\n").unwrap();
+    git(&["add", "a.ts"]);
+    git(&["commit", "-q", "-m", "base"]);
+    // the existing finding moves down and a new one is added
+    std::fs::write(
+      dir.join("a.ts"),
+      "let a = 0\nconsole.log(1)\nconsole.log(2)\n",
+    )
+    .unwrap();
+    let yaml = r"
+id: no-log
+message: no log
+severity: warning
+language: TypeScript
+rule:
+  pattern: console.log($A)
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let path = dir.to_string_lossy().to_string();
+    let arg = scan_arg(&["--baseline-diff", "HEAD", &path]);
+    let mut output = vec![];
+    let template = Template::try_new("{line}: {match}").unwrap();
+    let printer = TemplatePrinter::new(&mut output, template);
+    let worker = ScanWithConfig::new(arg, printer, configs).expect("should create");
+    let ret = crate::utils::run_worker_on(&worker, &[dir.join("a.ts")]);
+    drop(worker);
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&["--baseline-diff", "no-such-ref", &path]);
+    let invalid = ScanWithConfig::new(arg, SilentPrinter, configs);
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&["--baseline-diff=--output=out.txt", &path]);
+    let option = ScanWithConfig::new(arg, SilentPrinter, configs);
+    assert!(ret.is_ok());
+    let printed = String::from_utf8(output).expect("should be utf8");
+    assert_eq!(printed, "3: console.log(2)\n");
+    for error in [invalid.err(), option.err()] {
+      let error = error.expect("should fail");
+      assert!(matches!(error.downcast_ref::<EC>(), Some(EC::GitRef(_))));
+    }
+    assert!(!dir.join("out.txt").exists());
+  }
+
+  #[test]
+  fn test_fail_fast() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    for i in 0..200 {
+      std::fs::write(dir.join(format!("{i}.ts")), "let a = 1\ndebugger").unwrap();
+    }
+    let yaml = r"
+id: no-debugger
+message: no debugger
+severity: hint
+language: TypeScript
+rule:
+  kind: debugger_statement
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let path = dir.to_string_lossy().to_string();
+    let arg = scan_arg(&["--fail-fast", &path]);
+    let mut output = vec![];
+    let template = Template::try_new("{line}: {message}").unwrap();
+    let printer = TemplatePrinter::new(&mut output, template);
+    let produced = AtomicUsize::new(0);
+    let worker = CountProduced {
+      inner: ScanWithConfig::new(arg, printer, configs).expect("should create"),
+      produced: &produced,
+    };
+    let ret = run_worker(worker);
+    let error = ret.expect_err("a hint fails the scan too");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::DiagnosticError(1))
+    ));
+    let printed = String::from_utf8(output).expect("should be utf8");
+    assert_eq!(printed, "2: no debugger\n");
+    // the walk stops after the first match
+    assert!(produced.into_inner() < 200);
+  }
+
+  /// Counts the files the inner worker produces.
+  struct CountProduced<'a, W> {
+    inner: W,
+    produced: &'a AtomicUsize,
+  }
+
+  impl<W: Worker> Worker for CountProduced<'_, W> {
+    type Item = W::Item;
+    fn build_walk(&self) -> WalkParallel {
+      self.inner.build_walk()
+    }
+    fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+      let item = self.inner.produce_item(path)?;
+      self.produced.fetch_add(1, Ordering::Relaxed);
+      Some(item)
+    }
+    fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+      self.inner.consume_items(items)
+    }
+    fn walk_done(&self) -> bool {
+      self.inner.walk_done()
+    }
+  }
+
+  #[test]
+  fn test_group_by() {
+    use codespan_reporting::term::termcolor::Buffer;
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::write(dir.join("a.ts"), "console.log(1)\ndebugger").unwrap();
+    std::fs::write(dir.join("b.ts"), "debugger\nconsole.log(2)").unwrap();
+    let yaml = r"
+id: no-log
+message: no log
+severity: error
+language: TypeScript
+rule:
+  pattern: console.log($A)
+---
+id: no-debugger
+message: no debugger
+severity: warning
+language: TypeScript
+rule:
+  kind: debugger_statement
+";
+    let group = |yaml: &str, key: &str| {
+      let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+      let configs = RuleCollection::try_new(rules).expect("should build");
+      let arg = scan_arg(&["--group-by", key]);
+      let mut buffer = Buffer::no_color();
+      let printer = ColoredPrinter::new(&mut buffer)
+        .color(ColorArg::Never)
+        .style(ReportStyle::Short);
+      let worker = ScanWithConfig::new(arg, printer, configs).expect("should create");
+      let files = [dir.join("b.ts"), dir.join("a.ts")];
+      let ret = crate::utils::run_worker_on(&worker, &files);
+      assert!(ret.is_err(), "errors are still reported");
+      drop(worker);
+      let output = String::from_utf8(buffer.into_inner()).expect("should be utf8");
+      let prefix = format!("{}/", dir.display());
+      output.replace(&prefix, "")
+    };
+    let by_rule = group(yaml, "rule");
+    let by_severity = group(yaml, "severity");
+    let expected = "\
+== no-debugger ==
+a.ts:2:1: warning[no-debugger]: no debugger
+b.ts:1:1: warning[no-debugger]: no debugger
+== no-log ==
+a.ts:1:1: error[no-log]: no log
+b.ts:2:1: error[no-log]: no log
+";
+    assert_eq!(by_rule, expected);
+    let expected = "\
+== error ==
+a.ts:1:1: error[no-log]: no log
+b.ts:2:1: error[no-log]: no log
+== warning ==
+a.ts:2:1: warning[no-debugger]: no debugger
+b.ts:1:1: warning[no-debugger]: no debugger
+";
+    assert_eq!(by_severity, expected);
+    // two matches escalate no-debugger to error
+    let escalated = format!("{yaml}escalate: {{ threshold: 1, to: error }}\n");
+    let expected = "\
+== error ==
+a.ts:1:1: error[no-log]: no log
+a.ts:2:1: error[no-debugger]: no debugger
+b.ts:2:1: error[no-log]: no log
+b.ts:1:1: error[no-debugger]: no debugger
+";
+    assert_eq!(group(&escalated, "severity"), expected);
+  }
+
+  #[test]
+  fn test_cross_file() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let lib = "export function used() {}\nexport function unused() {}";
+    std::fs::write(dir.join("lib.ts"), lib).unwrap();
+    std::fs::write(dir.join("main.ts"), "import { used } from './lib'\nused()").unwrap();
+    // the same name imported from another module does not count
+    std::fs::create_dir_all(dir.join("other")).unwrap();
+    let other = "import { unused } from './lib'\nunused()";
+    std::fs::write(dir.join("other/main.ts"), other).unwrap();
+    let yaml = r"
+id: unused-export
+message: $NAME is never imported
+severity: warning
+language: TypeScript
+rule:
+  pattern: export function $NAME($$$ARGS) { $$$BODY }
+crossFile:
+  imports:
+    kind: import_specifier
+    has: { kind: identifier, pattern: $NAME }
+    inside:
+      kind: import_statement
+      has: { field: source, pattern: $SOURCE }
+      stopBy: end
+  unimported: $NAME
+  module: $SOURCE
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let dir_arg = dir.to_string_lossy().to_string();
+    let arg = scan_arg(&[&dir_arg]);
+    let mut output = vec![];
+    let template = Template::try_new("{line}: {message}").unwrap();
+    let printer = TemplatePrinter::new(&mut output, template);
+    let worker = ScanWithConfig::new(arg, printer, configs).expect("should create");
+    // the file with findings is produced before the files importing from it
+    let files = [
+      dir.join("lib.ts"),
+      dir.join("main.ts"),
+      dir.join("other/main.ts"),
+    ];
+    let ret = crate::utils::run_worker_on(&worker, &files);
+    assert!(ret.is_ok());
+    // in watch mode the findings of lib.ts are checked again when a file importing it changes
+    let deps = worker.dependencies(&files[1..2], &files);
+    assert_eq!(deps, &files[..1]);
+    worker.remove_files(&files[1..]);
+    assert!(worker.dependencies(&files[1..2], &files).is_empty());
+    drop(worker);
+    let printed = String::from_utf8(output).expect("should be utf8");
+    assert_eq!(printed, "2: unused is never imported\n");
+  }
+
+  #[test]
+  fn test_cache_dir() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let file = dir.join("a.ts");
+    std::fs::write(&file, "console.log(1)").unwrap();
+    let yaml = "id: no-log\nmessage: test\nseverity: error\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let cache_dir = dir.join("cache").to_string_lossy().to_string();
+    let arg = scan_arg(&["--cache-dir", &cache_dir]);
+    let worker = ScanWithConfig::new(arg, SilentPrinter, configs).expect("should create");
+    let cache = worker.cache.as_ref().expect("should have cache");
+    let content_hash = hash_of(b"console.log(1)");
+    let is_error = |ret: Result<()>| matches!(ret, Err(e) if e.downcast_ref::<EC>().is_some());
+    // the first run saves the findings
+    assert!(is_error(crate::utils::run_worker_on(
+      &worker,
+      std::slice::from_ref(&file)
+    )));
+    let entry = cache.get(&file, content_hash, worker.rules_hash);
+    let entry = entry.expect("should save entry");
+    assert_eq!(entry.rules, [0]);
+    assert_eq!(entry.matches.len(), 1);
+    let Some((_, grep, Cached::Hit(entry))) = worker.produce_item(&file) else {
+      panic!("should hit cache");
+    };
+    let matched = CombinedScan::new(worker.configs.for_path(&file)).rematch(&grep, &entry);
+    assert_eq!(matched[&0][0].get_env().get_match("A").unwrap().text(), "1");
+    // a hit skips matching, so a tampered entry reports nothing
+    let empty = CacheEntry {
+      content_hash,
+      rules_hash: worker.rules_hash,
+      ..Default::default()
+    };
+    cache.put(&file, &empty).unwrap();
+    assert!(worker.produce_item(&file).is_none());
+    assert!(crate::utils::run_worker_on(&worker, std::slice::from_ref(&file)).is_ok());
+    // changed content invalidates the entry
+    std::fs::write(&file, "console.log(2)").unwrap();
+    let ret = crate::utils::run_worker_on(&worker, std::slice::from_ref(&file));
+    assert!(is_error(ret));
+  }
+
+  #[test]
+  fn test_scan_archive() {
+    use flate2::{write::GzEncoder, Compression};
+    let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+    let files = [
+      ("src/a.ts", "let a = 1\nconsole.log(a)"),
+      ("src/b.py", "print(1)"),
+      ("c.ts", "console.log(2)"),
+    ];
+    for (name, content) in files {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(content.len() as u64);
+      header.set_cksum();
+      builder
+        .append_data(&mut header, name, content.as_bytes())
+        .unwrap();
+    }
+    let tarball = builder.into_inner().unwrap().finish().unwrap();
+    let yaml = "id: no-log\nmessage: test\nseverity: error\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)\nfix: logger.log($A)";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&["--archive", "build.tar.gz"]);
+    let mut output = vec![];
+    let template = Template::try_new("{path}:{line}: {match}").unwrap();
+    let printer = TemplatePrinter::new(&mut output, template);
+    let worker = ScanWithConfig::new(arg, printer, configs).expect("should create");
+    let ret = worker.scan_archive(Path::new("build.tar.gz"), &tarball[..]);
+    assert!(matches!(
+      ret.expect_err("should report error").downcast_ref::<EC>(),
+      Some(EC::DiagnosticError(2))
+    ));
+    drop(worker);
+    let printed = String::from_utf8(output).expect("should be utf8");
+    assert_eq!(
+      printed,
+      "build.tar.gz!src/a.ts:2: console.log(a)\nbuild.tar.gz!c.ts:1: console.log(2)\n"
+    );
+  }
+
+  #[test]
+  fn test_parse_summary() {
+    let summary = parse_summary(1, Duration::from_millis(500));
+    assert_eq!(summary, "Parsed 1 file in 0.500s (2.0 files/sec)");
+    assert_eq!(
+      parse_summary(0, Duration::ZERO),
+      "Parsed 0 files in 0.000s (0.0 files/sec)"
+    );
+  }
+
+  const FIX_RULES: &str = r"
+id: var-to-let
+message: test
+severity: hint
+language: TypeScript
+rule:
+  pattern: var $A = $B
+fix: let $A = $B
+---
+id: wrap-log
+message: test
+severity: hint
+language: TypeScript
+rule:
+  pattern: console.log($A)
+fix: console.log(($A))
+---
+id: same-call
+message: test
+severity: hint
+language: TypeScript
+rule:
+  pattern: foo($A)
+fix: foo($A)
+";
+
+  #[test]
+  fn test_check_fix() {
+    let rules = from_yaml_string(FIX_RULES, &GlobalRules::default()).expect("should parse");
+    let check = |idx: usize, src: &str| {
+      let rule: &RuleConfig<SupportLang> = &rules[idx];
+      check_fix(src, rule)
+    };
+    assert_eq!(check(0, "var a = 1; var b = 2"), None);
+    assert_eq!(check(0, "let a = 1"), None);
+    assert_eq!(check(1, "console.log(a)"), Some(FixIssue::Unstable));
+    assert_eq!(check(2, "foo(a)"), Some(FixIssue::Unresolved));
+  }
+
+  #[test]
+  fn test_idempotent_check() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    std::fs::write(dir.join("a.ts"), "var a = 1").unwrap();
+    std::fs::write(dir.join("b.ts"), "var b = 1; console.log(b)").unwrap();
+    let rules = from_yaml_string(FIX_RULES, &GlobalRules::default()).expect("should parse");
+    let configs = RuleCollection::try_new(rules).expect("should build");
+    let arg = scan_arg(&["--idempotent-check"]);
+    let worker = IdempotentCheck::new(arg, configs, vec![]);
+    let ret = crate::utils::run_worker_on(&worker, &[dir.join("a.ts")]);
+    assert!(ret.is_ok());
+    let ret = crate::utils::run_worker_on(&worker, &[dir.join("b.ts")]);
+    let error = ret.expect_err("should fail");
+    assert!(matches!(
+      error.downcast_ref::<EC>(),
+      Some(EC::NonIdempotentFix(1))
+    ));
+    let output = String::from_utf8(worker.output.into_inner().unwrap()).unwrap();
+    assert!(output.ends_with("b.ts: fix of `wrap-log` changes the code again when reapplied\n"));
+    assert_eq!(output.lines().count(), 1);
+  }
 }
@@ -1,5 +1,7 @@
+use crate::config::find_project_root;
 use crate::error::ErrorContext as EC;
 use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
 use crossterm::{
   event::{self, Event, KeyCode},
   execute,
@@ -10,10 +12,12 @@ use ignore::{DirEntry, WalkParallel, WalkState};
 use ast_grep_core::{AstGrep, Matcher};
 use ast_grep_language::{Language, SupportLang};
 
+use std::borrow::Cow;
 use std::fs::read_to_string;
 use std::io::stdout;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 
 fn read_char() -> Result<char> {
@@ -73,21 +77,38 @@ pub trait Worker: Sync {
   type Item: Send;
   fn build_walk(&self) -> WalkParallel;
   fn produce_item(&self, path: &Path) -> Option<Self::Item>;
-  fn consume_items(&self, items: Items<Self::Item>) -> Result<()>;
+  fn consume_items(&self, items: Items<'_, Self::Item>) -> Result<()>;
+  /// Whether the walk can stop because no more items are needed, e.g. a finding under `--fail-fast`.
+  /// Unlike cancellation, the items produced so far are still consumed.
+  fn walk_done(&self) -> bool {
+    false
+  }
 }
 
-pub struct Items<T>(mpsc::Receiver<T>);
-impl<T> Iterator for Items<T> {
+/// Items produced by the worker. The iteration ends early once the run is cancelled.
+pub struct Items<'c, T> {
+  rx: mpsc::Receiver<T>,
+  cancel: Option<&'c AtomicBool>,
+}
+impl<'c, T> Items<'c, T> {
+  fn new(rx: mpsc::Receiver<T>, cancel: Option<&'c AtomicBool>) -> Self {
+    Self { rx, cancel }
+  }
+}
+impl<'c, T> Iterator for Items<'c, T> {
   type Item = T;
   fn next(&mut self) -> Option<Self::Item> {
-    if let Ok(match_result) = self.0.recv() {
-      Some(match_result)
-    } else {
-      None
+    if is_cancelled(self.cancel) {
+      return None;
     }
+    self.rx.recv().ok()
   }
 }
 
+fn is_cancelled(cancel: Option<&AtomicBool>) -> bool {
+  cancel.map_or(false, |c| c.load(Ordering::Relaxed))
+}
+
 fn filter_result(result: Result<DirEntry, ignore::Error>) -> Option<PathBuf> {
   let entry = match result {
     Ok(entry) => entry,
@@ -100,12 +121,23 @@ fn filter_result(result: Result<DirEntry, ignore::Error>) -> Option<PathBuf> {
 }
 
 pub fn run_worker<MW: Worker>(worker: MW) -> Result<()> {
+  run_worker_cancellable(worker, None)
+}
+
+/// Run the worker until every file is consumed or `cancel` is set, e.g. when a client disconnects.
+/// Both the directory walk and the consumption of matched files check the flag before each file.
+/// Returns [`EC::Cancelled`] if the run is cancelled since its output is incomplete.
+pub fn run_worker_cancellable<MW: Worker>(worker: MW, cancel: Option<&AtomicBool>) -> Result<()> {
   let producer = |path: PathBuf| worker.produce_item(&path);
+  let done = || worker.walk_done();
   let (tx, rx) = mpsc::channel();
   let walker = worker.build_walk();
   walker.run(|| {
     let tx = tx.clone();
     Box::new(move |result| {
+      if is_cancelled(cancel) || done() {
+        return WalkState::Quit;
+      }
       let maybe_result = filter_result(result).and_then(producer);
       let result = match maybe_result {
         Some(ret) => ret,
@@ -119,7 +151,40 @@ pub fn run_worker<MW: Worker>(worker: MW) -> Result<()> {
   });
   // drop the last sender to stop rx awaiting message
   drop(tx);
-  worker.consume_items(Items(rx))
+  let ret = worker.consume_items(Items::new(rx, cancel));
+  if is_cancelled(cancel) {
+    return Err(anyhow!(EC::Cancelled));
+  }
+  ret
+}
+
+/// Run the worker on the given files instead of walking the file system.
+pub fn run_worker_on<MW: Worker>(worker: &MW, paths: &[PathBuf]) -> Result<()> {
+  let (tx, rx) = mpsc::channel();
+  for item in paths.iter().filter_map(|p| worker.produce_item(p)) {
+    tx.send(item).expect("receiver should be alive");
+  }
+  drop(tx);
+  worker.consume_items(Items::new(rx, None))
+}
+
+/// Collect all file paths the walker visits.
+pub fn collect_files(walker: WalkParallel) -> Vec<PathBuf> {
+  let (tx, rx) = mpsc::channel();
+  walker.run(|| {
+    let tx = tx.clone();
+    Box::new(move |result| {
+      let Some(path) = filter_result(result) else {
+        return WalkState::Continue;
+      };
+      match tx.send(path) {
+        Ok(_) => WalkState::Continue,
+        Err(_) => WalkState::Quit,
+      }
+    })
+  });
+  drop(tx);
+  rx.into_iter().collect()
 }
 
 pub fn open_in_editor(path: &PathBuf, start_line: usize) -> Result<()> {
@@ -143,6 +208,18 @@ pub fn filter_file_interactive<M: Matcher<SupportLang>>(
   lang: SupportLang,
   matcher: M,
 ) -> Option<MatchUnit<M>> {
+  let file_content = read_file(path)?;
+  let grep = lang.ast_grep(file_content);
+  let has_match = grep.root().find(&matcher).is_some();
+  has_match.then(|| MatchUnit {
+    grep,
+    path: path.to_path_buf(),
+    matcher,
+  })
+}
+
+/// Read a file to scan. Unreadable and large files are skipped.
+pub fn read_file(path: &Path) -> Option<String> {
   let file_content = read_to_string(path)
     .with_context(|| format!("Cannot read file {}", path.to_string_lossy()))
     .map_err(|err| eprintln!("{err}"))
@@ -152,13 +229,13 @@ pub fn filter_file_interactive<M: Matcher<SupportLang>>(
     // TODO add output
     return None;
   }
-  let grep = lang.ast_grep(file_content);
-  let has_match = grep.root().find(&matcher).is_some();
-  has_match.then(|| MatchUnit {
-    grep,
-    path: path.to_path_buf(),
-    matcher,
-  })
+  Some(file_content)
+}
+
+/// Read a file for rules matching raw text. Files that are not UTF-8 text, e.g. images, are skipped silently.
+pub fn read_text(path: &Path) -> Option<String> {
+  let file_content = read_to_string(path).ok()?;
+  (!file_too_large(&file_content)).then_some(file_content)
 }
 
 const MAX_FILE_SIZE: usize = 3_000_000;
@@ -177,9 +254,158 @@ pub struct MatchUnit<M: Matcher<SupportLang>> {
   pub matcher: M,
 }
 
+/// Order of files in the output. Parallel walking yields files in arbitrary order.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SortBy {
+  /// Sort by file path.
+  Path,
+  /// Files with more matches come first.
+  Matches,
+  /// Sort by the line of the first match in each file.
+  FirstMatch,
+}
+
+struct SortKey {
+  count: usize,
+  first_line: usize,
+}
+
+impl SortBy {
+  fn key<M: Matcher<SupportLang>>(&self, unit: &MatchUnit<M>) -> SortKey {
+    let root = unit.grep.root();
+    let mut matches = root.find_all(&unit.matcher);
+    match self {
+      SortBy::Path => SortKey {
+        count: 0,
+        first_line: 0,
+      },
+      SortBy::Matches => SortKey {
+        count: matches.count(),
+        first_line: 0,
+      },
+      SortBy::FirstMatch => SortKey {
+        count: 0,
+        first_line: matches.next().map_or(0, |m| m.start_pos().0),
+      },
+    }
+  }
+}
+
+/// Collect all items and sort them. Ties are broken by path so the order is deterministic.
+/// Paths are compared byte-wise to be independent of locale.
+pub fn sort_items<M, T>(
+  items: impl Iterator<Item = T>,
+  sort: SortBy,
+  unit: impl Fn(&T) -> &MatchUnit<M>,
+) -> Vec<T>
+where
+  M: Matcher<SupportLang>,
+{
+  let mut keyed: Vec<_> = items.map(|t| (sort.key(unit(&t)), t)).collect();
+  keyed.sort_by(|(ka, a), (kb, b)| {
+    kb.count
+      .cmp(&ka.count)
+      .then(ka.first_line.cmp(&kb.first_line))
+      .then_with(|| {
+        let a = unit(a).path.to_string_lossy();
+        let b = unit(b).path.to_string_lossy();
+        a.as_bytes().cmp(b.as_bytes())
+      })
+  });
+  keyed.into_iter().map(|(_, t)| t).collect()
+}
+
+/// How file paths are rendered in the output.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PathStyle {
+  /// Relative to the project root, i.e. the directory of sgconfig.yml.
+  /// The current directory is used if no config is found.
+  Relative,
+  /// Absolute paths.
+  Absolute,
+  /// Relative to the current directory.
+  RelativeToCwd,
+}
+
+/// Render paths in a [`PathStyle`]. Paths are printed as walked if no style is specified.
+pub struct PathRenderer {
+  style: Option<PathStyle>,
+  root: PathBuf,
+  cwd: PathBuf,
+}
+
+impl PathRenderer {
+  pub fn try_new(style: Option<PathStyle>, config_path: Option<PathBuf>) -> Result<Self> {
+    let (root, cwd) = match style {
+      None => (PathBuf::new(), PathBuf::new()),
+      Some(PathStyle::Relative) => {
+        let cwd = std::env::current_dir()?;
+        (normalize(&find_project_root(config_path)?), cwd)
+      }
+      Some(_) => {
+        let cwd = std::env::current_dir()?;
+        (cwd.clone(), cwd)
+      }
+    };
+    Ok(Self { style, root, cwd })
+  }
+
+  pub fn render<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+    let Some(style) = self.style else {
+      return Cow::Borrowed(path);
+    };
+    let absolute = normalize(&self.cwd.join(path));
+    Cow::Owned(match style {
+      PathStyle::Absolute => absolute,
+      PathStyle::Relative => relative_to(&absolute, &self.root),
+      PathStyle::RelativeToCwd => relative_to(&absolute, &self.cwd),
+    })
+  }
+}
+
+/// Remove `.` and resolve `..` lexically, without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+  let mut ret = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => (),
+      Component::ParentDir
+        if matches!(ret.components().next_back(), Some(Component::Normal(_))) =>
+      {
+        ret.pop();
+      }
+      c => ret.push(c),
+    }
+  }
+  ret
+}
+
+/// Both paths must be absolute and normalized.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+  let mut path = path.components().peekable();
+  let mut base = base.components().peekable();
+  while let (Some(p), Some(b)) = (path.peek(), base.peek()) {
+    if p != b {
+      break;
+    }
+    path.next();
+    base.next();
+  }
+  base.map(|_| Component::ParentDir).chain(path).collect()
+}
+
+/// A directory for the files of a test. It is removed on drop, even if the test panics.
+#[cfg(test)]
+pub fn temp_dir() -> tempfile::TempDir {
+  tempfile::tempdir().expect("should create temp dir")
+}
+
 #[cfg(test)]
 mod test {
   use super::*;
+  use ast_grep_core::Pattern;
+  use ignore::WalkBuilder;
+  use std::sync::Mutex;
 
   #[test]
   fn test_open_editor() {
@@ -202,4 +428,119 @@ mod test {
     let error = error.downcast_ref::<EC>().expect("should be error context");
     assert!(matches!(error, EC::OpenEditor));
   }
+
+  fn make_unit(path: &str, src: &str) -> MatchUnit<Pattern<SupportLang>> {
+    let lang = SupportLang::TypeScript;
+    MatchUnit {
+      path: PathBuf::from(path),
+      grep: lang.ast_grep(src),
+      matcher: Pattern::new("foo($A)", lang),
+    }
+  }
+
+  fn sorted_paths(sort: SortBy) -> Vec<String> {
+    let units = vec![
+      make_unit("b.ts", "\nfoo(1)"),
+      make_unit("a/c.ts", "foo(1); foo(2)"),
+      make_unit("a.ts", "\n\nfoo(1); foo(2); foo(3)"),
+      make_unit("B.ts", "\nfoo(1)"),
+      make_unit("a-b.ts", "\nfoo(1)"),
+    ];
+    sort_items(units.into_iter(), sort, |u| u)
+      .into_iter()
+      .map(|u| u.path.to_string_lossy().into_owned())
+      .collect()
+  }
+
+  #[test]
+  fn test_sort_by_path() {
+    let paths = sorted_paths(SortBy::Path);
+    assert_eq!(paths, ["B.ts", "a-b.ts", "a.ts", "a/c.ts", "b.ts"]);
+  }
+
+  #[test]
+  fn test_sort_by_matches() {
+    let paths = sorted_paths(SortBy::Matches);
+    assert_eq!(paths, ["a.ts", "a/c.ts", "B.ts", "a-b.ts", "b.ts"]);
+  }
+
+  #[test]
+  fn test_sort_by_first_match() {
+    let paths = sorted_paths(SortBy::FirstMatch);
+    assert_eq!(paths, ["a/c.ts", "B.ts", "a-b.ts", "b.ts", "a.ts"]);
+  }
+
+  struct CancelWorker<'a> {
+    root: PathBuf,
+    cancel: &'a AtomicBool,
+    produced: &'a Mutex<usize>,
+    consumed: &'a Mutex<usize>,
+  }
+
+  impl<'a> Worker for CancelWorker<'a> {
+    type Item = PathBuf;
+    fn build_walk(&self) -> WalkParallel {
+      WalkBuilder::new(&self.root).threads(1).build_parallel()
+    }
+    fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+      *self.produced.lock().unwrap() += 1;
+      // simulate a client disconnecting after the first file
+      self.cancel.store(true, Ordering::Relaxed);
+      Some(path.to_path_buf())
+    }
+    fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+      *self.consumed.lock().unwrap() += items.count();
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_cancel_worker() {
+    let tmp = temp_dir();
+    let dir = tmp.path();
+    for i in 0..20 {
+      std::fs::write(dir.join(format!("{i}.ts")), "let a = 1").unwrap();
+    }
+    let cancel = AtomicBool::new(false);
+    let produced = Mutex::new(0);
+    let consumed = Mutex::new(0);
+    let worker = CancelWorker {
+      root: dir.to_path_buf(),
+      cancel: &cancel,
+      produced: &produced,
+      consumed: &consumed,
+    };
+    let ret = run_worker_cancellable(worker, Some(&cancel));
+    let error = ret.expect_err("should be cancelled");
+    assert!(matches!(error.downcast_ref::<EC>(), Some(EC::Cancelled)));
+    assert_eq!(*produced.lock().unwrap(), 1);
+    assert_eq!(*consumed.lock().unwrap(), 0);
+  }
+
+  fn render_path(style: PathStyle, path: &str) -> String {
+    let renderer = PathRenderer {
+      style: Some(style),
+      root: PathBuf::from("/repo"),
+      cwd: PathBuf::from("/repo/src"),
+    };
+    renderer
+      .render(Path::new(path))
+      .to_string_lossy()
+      .into_owned()
+  }
+
+  #[test]
+  fn test_path_style() {
+    use PathStyle::*;
+    assert_eq!(render_path(Relative, "./lib/a.ts"), "src/lib/a.ts");
+    assert_eq!(render_path(Absolute, "./lib/a.ts"), "/repo/src/lib/a.ts");
+    assert_eq!(render_path(RelativeToCwd, "./lib/a.ts"), "lib/a.ts");
+    assert_eq!(render_path(Relative, "../test/b.ts"), "test/b.ts");
+    assert_eq!(render_path(Absolute, "../test/b.ts"), "/repo/test/b.ts");
+    assert_eq!(render_path(RelativeToCwd, "../test/b.ts"), "../test/b.ts");
+    assert_eq!(render_path(Relative, "/other/c.ts"), "../other/c.ts");
+    assert_eq!(render_path(RelativeToCwd, "/repo/src/d.ts"), "d.ts");
+    let renderer = PathRenderer::try_new(None, None).expect("should create");
+    assert_eq!(renderer.render(Path::new("./a.ts")), Path::new("./a.ts"));
+  }
 }
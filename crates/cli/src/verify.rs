@@ -1,18 +1,18 @@
 use crate::config::{find_config, find_tests, read_test_files, TestHarness};
 use crate::error::ErrorContext;
-use crate::print::{print_diff, ColorChoice, PrintStyles};
+use crate::print::{print_diff, ColorChoice, PrintStyles, DEFAULT_DIFF_CONTEXT};
 use crate::utils::{prompt, run_in_alternate_screen};
 use ansi_term::{Color, Style};
 use anyhow::{anyhow, Result};
-use ast_grep_config::{RuleCollection, RuleConfig};
-use ast_grep_core::{Node, NodeMatch};
+use ast_grep_config::{RuleCollection, RuleConfig, SymbolIndex};
+use ast_grep_core::{AstGrep, Node, NodeMatch};
 use ast_grep_language::{Language, SupportLang};
 use clap::Args;
 use serde::{Deserialize, Serialize, Serializer};
 use serde_yaml::to_string;
 use std::collections::BTreeMap;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
@@ -32,6 +32,24 @@ pub struct TestCase {
   pub valid: Vec<String>,
   #[serde(default)]
   pub invalid: Vec<String>,
+  /// Code samples with the exact ranges the rule should match.
+  #[serde(default)]
+  pub expect: Vec<ExpectCase>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpectCase {
+  pub code: String,
+  #[serde(default)]
+  pub matches: Vec<ExpectedMatch>,
+}
+
+/// A match by its byte range `[start, end]` in the code.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpectedMatch {
+  pub range: [usize; 2],
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
@@ -285,28 +303,45 @@ enum SnapshotAction {
   Selectively(SnapshotCollection),
 }
 
+/// Matches of the rule in a test snippet. The snippet is scanned as the only file of a project,
+/// so a cross-file rule reports the symbols that the snippet does not import from itself.
+fn snippet_matches<'a>(
+  rule_config: &RuleConfig<SupportLang>,
+  sg: &'a AstGrep<SupportLang>,
+) -> Vec<NodeMatch<'a, SupportLang>> {
+  let path = Path::new("");
+  let mut index = SymbolIndex::default();
+  index.index_file(&[rule_config], path, sg);
+  sg.root()
+    .find_all(&rule_config.matcher)
+    .filter(|nm| rule_config.passes_index(nm, path, Some(&index)))
+    .collect()
+}
+
 fn verify_invalid_case<'a>(
   rule_config: &RuleConfig<SupportLang>,
   case: &'a str,
   snapshot: Option<&TestSnapshots>,
 ) -> CaseStatus<'a> {
   let sg = rule_config.language.ast_grep(case);
-  let rule = &rule_config.matcher;
-  let Some(matched) = sg.root().find(rule) else {
+  let Some(matched) = snippet_matches(rule_config, &sg).into_iter().next() else {
     return CaseStatus::Missing(case);
   };
-  let labels = Label::from_matched(matched);
-  let fixer = &rule_config.fixer;
-  let mut sg = sg;
-  let fixed = if let Some(fix) = fixer {
-    match sg.replace(rule, fix) {
-      Ok(changed) => debug_assert!(changed),
-      Err(_) => return CaseStatus::Error,
+  let fixed = if rule_config.has_fix() {
+    // a fix list whose edits cannot be applied together is an error of the rule
+    let Some(edits) = rule_config.fix_edits(&matched) else {
+      return CaseStatus::Error;
     };
-    Some(sg.source().to_string())
+    let mut fixed = case.to_string();
+    for edit in edits.into_iter().rev() {
+      let range = edit.position..edit.position + edit.deleted_length;
+      fixed.replace_range(range, &edit.inserted_text);
+    }
+    Some(fixed)
   } else {
     None
   };
+  let labels = Label::from_matched(matched);
   let actual = TestSnapshot { fixed, labels };
   let Some(expected) = snapshot.and_then(|s| s.snapshots.get(case)) else {
     return CaseStatus::Wrong {
@@ -326,6 +361,34 @@ fn verify_invalid_case<'a>(
   }
 }
 
+fn verify_expect_case<'a>(
+  rule_config: &RuleConfig<SupportLang>,
+  case: &'a ExpectCase,
+) -> CaseStatus<'a> {
+  let sg = rule_config.language.ast_grep(&case.code);
+  let mut actual: Vec<_> = snippet_matches(rule_config, &sg)
+    .into_iter()
+    .map(|m| {
+      let range = m.range();
+      ExpectedMatch {
+        range: [range.start, range.end],
+      }
+    })
+    .collect();
+  let mut expected = case.matches.clone();
+  actual.sort_by_key(|m| m.range);
+  expected.sort_by_key(|m| m.range);
+  if actual == expected {
+    CaseStatus::Reported
+  } else {
+    CaseStatus::Mismatched {
+      source: &case.code,
+      actual,
+      expected,
+    }
+  }
+}
+
 fn verify_test_case_simple<'a>(
   rules: &RuleCollection<SupportLang>,
   test_case: &'a TestCase,
@@ -333,17 +396,16 @@ fn verify_test_case_simple<'a>(
 ) -> Option<CaseResult<'a>> {
   let rule_config = rules.get_rule(&test_case.id)?;
   let lang = rule_config.language;
-  let rule = &rule_config.matcher;
   let valid_cases = test_case.valid.iter().map(|valid| {
     let sg = lang.ast_grep(valid);
-    if sg.root().find(rule).is_some() {
+    if !snippet_matches(rule_config, &sg).is_empty() {
       CaseStatus::Noisy(valid)
     } else {
       CaseStatus::Validated
     }
   });
   let invalid_cases = test_case.invalid.iter();
-  let cases = if let Some(snapshots) = snapshots {
+  let mut cases: Vec<_> = if let Some(snapshots) = snapshots {
     let snapshot = snapshots.get(&test_case.id);
     let invalid_cases =
       invalid_cases.map(|invalid| verify_invalid_case(rule_config, invalid, snapshot));
@@ -351,8 +413,7 @@ fn verify_test_case_simple<'a>(
   } else {
     let invalid_cases = invalid_cases.map(|invalid| {
       let sg = rule_config.language.ast_grep(invalid);
-      let rule = &rule_config.matcher;
-      if sg.root().find(rule).is_some() {
+      if !snippet_matches(rule_config, &sg).is_empty() {
         CaseStatus::Reported
       } else {
         CaseStatus::Missing(invalid)
@@ -360,6 +421,8 @@ fn verify_test_case_simple<'a>(
     });
     valid_cases.chain(invalid_cases).collect()
   };
+  let expect_cases = test_case.expect.iter();
+  cases.extend(expect_cases.map(|case| verify_expect_case(rule_config, case)));
   Some(CaseResult {
     id: &test_case.id,
     cases,
@@ -407,6 +470,12 @@ enum CaseStatus<'a> {
     actual: TestSnapshot,
     expected: Option<TestSnapshot>,
   },
+  /// Reported issues at other ranges than expected.
+  Mismatched {
+    source: &'a str,
+    actual: Vec<ExpectedMatch>,
+    expected: Vec<ExpectedMatch>,
+  },
   /// Reported no issue for invalid code
   Missing(&'a str),
   /// Reported some issue for valid code
@@ -481,6 +550,7 @@ trait Reporter {
       .map(|s| match s {
         CaseStatus::Validated | CaseStatus::Reported => '.',
         CaseStatus::Wrong { .. } => 'W',
+        CaseStatus::Mismatched { .. } => 'X',
         CaseStatus::Missing(_) => 'M',
         CaseStatus::Noisy(_) => 'N',
         CaseStatus::Error => 'E',
@@ -504,6 +574,18 @@ fn indented_write<W: Write>(output: &mut W, code: &str) -> Result<()> {
   Ok(())
 }
 
+/// One line per match with its range and text, for diffing expected and actual matches.
+fn matches_to_lines(source: &str, matches: &[ExpectedMatch]) -> String {
+  matches
+    .iter()
+    .map(|m| {
+      let [start, end] = m.range;
+      let text = source.get(start..end).unwrap_or("<out of bounds>");
+      format!("[{start}, {end}] {text}\n")
+    })
+    .collect()
+}
+
 fn report_case_detail_impl<W: Write>(
   output: &mut W,
   case_id: &str,
@@ -513,6 +595,7 @@ fn report_case_detail_impl<W: Write>(
   let noisy = Style::new().underline().paint("Noisy");
   let missing = Style::new().underline().paint("Missing");
   let wrong = Style::new().underline().paint("Wrong");
+  let mismatch = Style::new().underline().paint("Mismatch");
   let error = Style::new().underline().paint("Error");
   let styles = PrintStyles::from(ColorChoice::Auto);
   match result {
@@ -530,7 +613,13 @@ fn report_case_detail_impl<W: Write>(
         let actual_str = to_string(&actual)?;
         let expected_str = to_string(&expected)?;
         writeln!(output, "{}", Style::new().italic().paint("Diff:"))?;
-        print_diff(&expected_str, &actual_str, &styles, output)?;
+        print_diff(
+          &expected_str,
+          &actual_str,
+          DEFAULT_DIFF_CONTEXT,
+          &styles,
+          output,
+        )?;
       } else {
         writeln!(output, "[{wrong}] No {case_id} basline found.")?;
         // TODO: add to print_styles
@@ -546,6 +635,29 @@ fn report_case_detail_impl<W: Write>(
       indented_write(output, source)?;
       writeln!(output)?;
     }
+    CaseStatus::Mismatched {
+      source,
+      actual,
+      expected,
+    } => {
+      writeln!(
+        output,
+        "[{mismatch}] {case_id} matched different ranges than expected."
+      )?;
+      writeln!(output, "{}", Style::new().italic().paint("Diff:"))?;
+      let expected_str = matches_to_lines(source, expected);
+      let actual_str = matches_to_lines(source, actual);
+      print_diff(
+        &expected_str,
+        &actual_str,
+        DEFAULT_DIFF_CONTEXT,
+        &styles,
+        output,
+      )?;
+      writeln!(output, "{}", Style::new().italic().paint("For Code:"))?;
+      indented_write(output, source)?;
+      writeln!(output)?;
+    }
     CaseStatus::Missing(s) => {
       writeln!(
         output,
@@ -714,6 +826,7 @@ rule:
       id: TEST_RULE.into(),
       valid: vec!["123".into()],
       invalid: vec![],
+      expect: vec![],
     }
   }
 
@@ -722,6 +835,7 @@ rule:
       id: TEST_RULE.into(),
       valid: vec![],
       invalid: vec!["123".into()],
+      expect: vec![],
     }
   }
 
@@ -768,6 +882,7 @@ rule:
       id: "no-such-rule".into(),
       valid: vec![],
       invalid: vec![],
+      expect: vec![],
     };
     let rule = never_report_rule();
     let ret = verify_test_case_simple(&rule, &case, None);
@@ -795,4 +910,110 @@ rule:
     let ret = verify_invalid_case(&rule, "function () { let a = 1 }", Some(&test_snapshots));
     assert!(matches!(ret, CaseStatus::Reported));
   }
+
+  fn expect_case(matches: &[[usize; 2]]) -> ExpectCase {
+    ExpectCase {
+      code: "console.log(1); foo(); console.log(2)".into(),
+      matches: matches
+        .iter()
+        .map(|&range| ExpectedMatch { range })
+        .collect(),
+    }
+  }
+
+  #[test]
+  fn test_expect_ranges() {
+    let serialize = from_str("pattern: console.log($A)").expect("should parse");
+    let rule = get_rule_config(serialize);
+    let case = expect_case(&[[23, 37], [0, 14]]);
+    assert_eq!(verify_expect_case(&rule, &case), CaseStatus::Reported);
+    let case = expect_case(&[[0, 14]]);
+    let ret = verify_expect_case(&rule, &case);
+    let CaseStatus::Mismatched {
+      actual, expected, ..
+    } = &ret
+    else {
+      panic!("should mismatch");
+    };
+    assert_eq!(actual.len(), 2);
+    assert_eq!(expected.len(), 1);
+    let case = expect_case(&[[0, 14], [16, 21], [23, 37]]);
+    assert!(matches!(
+      verify_expect_case(&rule, &case),
+      CaseStatus::Mismatched { .. }
+    ));
+  }
+
+  #[test]
+  fn test_report_mismatch() {
+    let serialize = from_str("pattern: console.log($A)").expect("should parse");
+    let rule = get_rule_config(serialize);
+    let case = expect_case(&[[0, 14], [16, 21]]);
+    let ret = verify_expect_case(&rule, &case);
+    let mut output = vec![];
+    report_case_detail_impl(&mut output, TEST_RULE, &ret).expect("should report");
+    let output = String::from_utf8(output).expect("should be utf8");
+    assert!(output.contains("matched different ranges than expected"));
+    assert!(output.contains("[16, 21] foo()"), "{output}");
+    assert!(output.contains("[23, 37] console.log(2)"), "{output}");
+    assert!(output.contains("For Code:"));
+  }
+
+  #[test]
+  fn test_expect_in_test_case() {
+    let case: TestCase = from_str(
+      "
+id: test-rule
+expect:
+- code: console.log(1); foo(); console.log(2)
+  matches:
+  - range: [0, 14]
+",
+    )
+    .expect("should parse");
+    let serialize = from_str("pattern: console.log($A)").expect("should parse");
+    let rules = RuleCollection::try_new(vec![get_rule_config(serialize)]).expect("should build");
+    let ret = verify_test_case_simple(&rules, &case, None).expect("should find rule");
+    assert!(!ret.passed());
+    assert!(matches!(ret.cases[0], CaseStatus::Mismatched { .. }));
+  }
+
+  #[test]
+  fn test_cross_file_case() {
+    let case: TestCase = from_str(
+      "
+id: test-rule
+valid:
+- function unused() {}
+invalid:
+- export function unused() {}
+",
+    )
+    .expect("should parse");
+    let inner: SerializableRuleConfig<SupportLang> = from_str(&format!(
+      "
+id: {TEST_RULE}
+message: test
+severity: hint
+language: TypeScript
+rule:
+  pattern: export function $NAME() {{}}
+crossFile:
+  imports:
+    kind: import_specifier
+    has: {{ kind: identifier, pattern: $NAME }}
+    inside:
+      kind: import_statement
+      has: {{ field: source, pattern: $SOURCE }}
+      stopBy: end
+  unimported: $NAME
+  module: $SOURCE
+"
+    ))
+    .expect("should parse");
+    let rule = RuleConfig::try_from(inner, &GlobalRules::default()).expect("should build");
+    let rules = RuleCollection::try_new(vec![rule]).expect("should build");
+    let ret = verify_test_case_simple(&rules, &case, None).expect("should find rule");
+    assert_eq!(ret.cases, [CaseStatus::Validated, CaseStatus::Reported]);
+  }
 }
@@ -0,0 +1,219 @@
+use crate::error::print_warning;
+use crate::utils::{collect_files, run_worker_on, Worker};
+use anyhow::Result;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A worker whose results for a file can depend on other files,
+/// e.g. on the imports indexed for cross-file rules.
+pub trait WatchWorker: Worker {
+  /// Paths to watch for changes, the same ones the walk starts from.
+  fn roots(&self) -> Vec<PathBuf>;
+  /// Forget what is recorded about deleted files.
+  fn remove_files(&self, paths: &[PathBuf]);
+  /// Files among `files` whose results depend on any of `paths`.
+  fn dependencies(&self, paths: &[PathBuf], files: &[PathBuf]) -> Vec<PathBuf>;
+}
+
+/// Files visited by the walk of the worker, by the absolute path that change events report.
+/// The walk respects the same ignore files as scanning.
+#[derive(Default)]
+struct WalkedFiles(HashMap<PathBuf, PathBuf>);
+
+impl WalkedFiles {
+  fn new(files: Vec<PathBuf>) -> Self {
+    let files = files
+      .into_iter()
+      .map(|file| (file.canonicalize().unwrap_or_else(|_| file.clone()), file))
+      .collect();
+    Self(files)
+  }
+
+  fn paths(&self) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = self.0.values().cloned().collect();
+    paths.sort();
+    paths
+  }
+}
+
+/// Scan all files, then keep re-scanning changed files until the process is interrupted.
+/// Rules are compiled once in the worker and reused across iterations.
+/// Errors in one iteration are reported as warnings without stopping the watch.
+pub fn watch<W: WatchWorker>(worker: W) -> Result<()> {
+  let (tx, rx) = channel();
+  let mut watcher = notify::recommended_watcher(tx)?;
+  for root in worker.roots() {
+    let root = root.canonicalize().unwrap_or(root);
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+  }
+  let mut files = WalkedFiles::new(collect_files(worker.build_walk()));
+  if let Err(error) = run_worker_on(&worker, &files.paths()) {
+    print_warning(&error);
+  }
+  loop {
+    let changed = wait_for_changes(&rx)?;
+    if let Err(error) = rescan(&worker, &changed, &mut files) {
+      print_warning(&error);
+    }
+  }
+}
+
+/// Block until some paths change. Changes are debounced so that a burst of
+/// saves, e.g. from a formatter, triggers only one re-scan.
+fn wait_for_changes(rx: &Receiver<notify::Result<Event>>) -> Result<Vec<PathBuf>> {
+  let mut changed = vec![];
+  let mut add = |event: notify::Result<Event>| match event {
+    Ok(event) if matches!(event.kind, EventKind::Access(_)) => (),
+    Ok(event) => changed.extend(event.paths),
+    Err(error) => print_warning(&error.into()),
+  };
+  add(rx.recv()?);
+  while let Ok(event) = rx.recv_timeout(DEBOUNCE_INTERVAL) {
+    add(event);
+  }
+  changed.sort();
+  changed.dedup();
+  Ok(changed)
+}
+
+/// Scan the changed files again with the files depending on them, both through what they
+/// depended on before the change and what they depend on after it.
+fn rescan<W: WatchWorker>(worker: &W, changed: &[PathBuf], files: &mut WalkedFiles) -> Result<()> {
+  let old = std::mem::replace(files, WalkedFiles::new(collect_files(worker.build_walk())));
+  let existing: Vec<_> = changed
+    .iter()
+    .filter_map(|p| files.0.get(p))
+    .cloned()
+    .collect();
+  let removed: Vec<_> = changed
+    .iter()
+    .filter(|p| !files.0.contains_key(*p))
+    .filter_map(|p| old.0.get(p))
+    .cloned()
+    .collect();
+  let all = files.paths();
+  let touched = [existing.clone(), removed.clone()].concat();
+  let mut scanned = worker.dependencies(&touched, &all);
+  scanned.extend(existing.iter().cloned());
+  scanned.sort();
+  scanned.dedup();
+  worker.remove_files(&removed);
+  if !scanned.is_empty() {
+    run_worker_on(worker, &scanned)?;
+  }
+  let added: Vec<_> = worker
+    .dependencies(&existing, &all)
+    .into_iter()
+    .filter(|p| !scanned.contains(p))
+    .collect();
+  if !added.is_empty() {
+    run_worker_on(worker, &added)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::utils::Items;
+  use ignore::{WalkBuilder, WalkParallel};
+  use std::path::Path;
+  use std::sync::Mutex;
+
+  /// Records produced files. A file depends on the files named in its content.
+  struct RecordWorker {
+    root: PathBuf,
+    produced: Mutex<Vec<PathBuf>>,
+    removed: Mutex<Vec<PathBuf>>,
+  }
+
+  impl Worker for RecordWorker {
+    type Item = PathBuf;
+    fn build_walk(&self) -> WalkParallel {
+      WalkBuilder::new(&self.root).build_parallel()
+    }
+    fn produce_item(&self, path: &Path) -> Option<Self::Item> {
+      self.produced.lock().unwrap().push(path.to_path_buf());
+      Some(path.to_path_buf())
+    }
+    fn consume_items(&self, items: Items<Self::Item>) -> Result<()> {
+      assert!(items.count() > 0);
+      Ok(())
+    }
+  }
+
+  impl WatchWorker for RecordWorker {
+    fn roots(&self) -> Vec<PathBuf> {
+      vec![self.root.clone()]
+    }
+    fn remove_files(&self, paths: &[PathBuf]) {
+      self.removed.lock().unwrap().extend(paths.iter().cloned());
+    }
+    fn dependencies(&self, paths: &[PathBuf], files: &[PathBuf]) -> Vec<PathBuf> {
+      let content: String = paths
+        .iter()
+        .filter_map(|p| std::fs::read_to_string(p).ok())
+        .collect();
+      files
+        .iter()
+        .filter(|f| content.contains(&*f.file_name().unwrap().to_string_lossy()))
+        .cloned()
+        .collect()
+    }
+  }
+
+  fn take(list: &Mutex<Vec<PathBuf>>) -> Vec<PathBuf> {
+    let mut list = std::mem::take(&mut *list.lock().unwrap());
+    list.sort();
+    list
+  }
+
+  #[test]
+  fn test_rescan_changed_file() {
+    let tmp = crate::utils::temp_dir();
+    let dir = tmp.path();
+    let a = dir.join("a.ts");
+    let b = dir.join("b.ts");
+    std::fs::write(&a, "let a = 1").unwrap();
+    std::fs::write(&b, "let b = 1").unwrap();
+    let worker = RecordWorker {
+      root: dir.to_path_buf(),
+      produced: Mutex::new(vec![]),
+      removed: Mutex::new(vec![]),
+    };
+    let mut files = WalkedFiles::new(collect_files(worker.build_walk()));
+    assert_eq!(files.paths(), [a.clone(), b.clone()]);
+    let event = |path: &Path| path.canonicalize().unwrap();
+    // an edit of b scans b only
+    std::fs::write(&b, "let b = 12345").unwrap();
+    rescan(&worker, &[event(&b)], &mut files).expect("should scan");
+    assert_eq!(take(&worker.produced), std::slice::from_ref(&b));
+    // a file that b starts to depend on is scanned again
+    std::fs::write(&b, "import a.ts").unwrap();
+    rescan(&worker, &[event(&b)], &mut files).expect("should scan");
+    assert_eq!(take(&worker.produced), [a.clone(), b.clone()]);
+    // a deleted file is forgotten
+    let removed = event(&b);
+    std::fs::remove_file(&b).unwrap();
+    rescan(&worker, &[removed], &mut files).expect("should scan");
+    assert_eq!(take(&worker.removed), [b]);
+    assert!(take(&worker.produced).is_empty());
+  }
+
+  #[test]
+  fn test_wait_for_changes() {
+    let (tx, rx) = channel();
+    let event = |path: &str| Ok(Event::new(EventKind::Any).add_path(path.into()));
+    tx.send(event("b.ts")).unwrap();
+    tx.send(event("a.ts")).unwrap();
+    tx.send(event("b.ts")).unwrap();
+    let changed = wait_for_changes(&rx).expect("should receive");
+    assert_eq!(changed, [PathBuf::from("a.ts"), PathBuf::from("b.ts")]);
+  }
+}
@@ -0,0 +1,29 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const NO_LOG: &str = "id: no-log\nmessage: test\nseverity: error\nlanguage: TypeScript\nrule:\n  pattern: console.log($A)";
+
+fn sg(dir: &Path, args: &[&str]) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_sg"))
+    .args(args)
+    .current_dir(dir)
+    .output()
+    .expect("should run sg")
+}
+
+#[test]
+fn test_scan_quiet() {
+  let tmp = tempfile::tempdir().expect("should create temp dir");
+  let dir = tmp.path();
+  std::fs::write(dir.join("rule.yml"), NO_LOG).unwrap();
+  std::fs::write(dir.join("a.ts"), "console.log(1)").unwrap();
+  let output = sg(dir, &["scan", "--quiet", "-r", "rule.yml", "a.ts"]);
+  assert_eq!(output.status.code(), Some(1));
+  assert!(output.stdout.is_empty());
+  assert!(output.stderr.is_empty());
+  // the error report is printed without --quiet
+  let output = sg(dir, &["scan", "-r", "rule.yml", "a.ts"]);
+  assert_eq!(output.status.code(), Some(1));
+  let stderr = String::from_utf8(output.stderr).expect("should be utf8");
+  assert!(stderr.contains("1 error(s) found in code."));
+}
@@ -0,0 +1,258 @@
+use crate::{RuleConfig, SymbolIndex};
+
+use ast_grep_core::language::Language;
+use ast_grep_core::{AstGrep, Matcher, Node, NodeMatch};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Run many rules in a single traversal of the tree instead of one traversal per rule.
+/// Each node is only matched against rules whose potential kinds include the node's kind.
+/// Rules without potential kinds, e.g. a sole `regex`, are tried on every node.
+/// Matches of cross-file rules are only reported against a [`SymbolIndex`], see `with_index`.
+pub struct CombinedScan<'r, L: Language> {
+  pub rules: Vec<&'r RuleConfig<L>>,
+  /// rule indices in ascending order, indexed by node kind id
+  kind_rule_mapping: Vec<Vec<usize>>,
+  /// path of the scanned file and the imports of all scanned files
+  index: Option<(&'r Path, &'r SymbolIndex)>,
+}
+
+impl<'r, L: Language> CombinedScan<'r, L> {
+  pub fn new(rules: Vec<&'r RuleConfig<L>>) -> Self {
+    let mut mapping: Vec<Vec<usize>> = Vec::new();
+    for (idx, rule) in rules.iter().enumerate() {
+      let kinds: Vec<_> = match rule.matcher.potential_kinds() {
+        Some(kinds) => kinds.iter().collect(),
+        None => (0..rule.language.get_ts_language().node_kind_count())
+          .map(usize::from)
+          .collect(),
+      };
+      for k in kinds {
+        while mapping.len() <= k {
+          mapping.push(vec![]);
+        }
+        mapping[k].push(idx);
+      }
+    }
+    Self {
+      rules,
+      kind_rule_mapping: mapping,
+      index: None,
+    }
+  }
+
+  /// Report matches of cross-file rules in the file at `path` unless the index has them imported.
+  pub fn with_index(self, path: &'r Path, index: &'r SymbolIndex) -> Self {
+    Self {
+      index: Some((path, index)),
+      ..self
+    }
+  }
+
+  /// Whether the match of the rule at `idx` is reported. See [`RuleConfig::passes_index`].
+  pub fn is_reported(&self, idx: usize, nm: &NodeMatch<L>) -> bool {
+    let rule = self.rules[idx];
+    match self.index {
+      Some((path, index)) => rule.passes_index(nm, path, Some(index)),
+      None => rule.passes_index(nm, Path::new(""), None),
+    }
+  }
+
+  /// Indices of the rules that may match a node of the kind.
+  pub fn candidates(&self, kind: u16) -> &[usize] {
+    self
+      .kind_rule_mapping
+      .get(kind as usize)
+      .map_or(&[], |idx| idx)
+  }
+
+  /// Whether any rule matches a node in the tree. Rules with a `timeout` are timed as in `scan_timed`.
+  pub fn find(&self, root: &AstGrep<L>) -> bool {
+    if self.rules.iter().any(|rule| rule.timeout.is_some()) {
+      return !self.scan_timed(root).0.is_empty();
+    }
+    self.scan(root).next().is_some()
+  }
+
+  /// Like `scan`, but also returns the time each rule spends matching, indexed like `rules`.
+  /// A rule exceeding its `timeout` is skipped for the rest of the tree and its matches are dropped.
+  /// The time is checked before each node, so a match already running is never preempted.
+  pub fn scan_timed<'t>(
+    &self,
+    root: &'t AstGrep<L>,
+  ) -> (Vec<(usize, NodeMatch<'t, L>)>, Vec<Duration>) {
+    let mut elapsed = vec![Duration::ZERO; self.rules.len()];
+    let mut matches = vec![];
+    for node in root.root().dfs() {
+      for &idx in self.candidates(node.kind_id()) {
+        let rule = self.rules[idx];
+        if rule.timed_out(elapsed[idx]) {
+          continue;
+        }
+        let start = Instant::now();
+        let matched = rule.matcher.match_node(node.clone());
+        elapsed[idx] += start.elapsed();
+        if let Some(matched) = matched.filter(|nm| self.is_reported(idx, nm)) {
+          matches.push((idx, matched));
+        }
+      }
+    }
+    matches.retain(|(idx, _)| !self.rules[*idx].timed_out(elapsed[*idx]));
+    (matches, elapsed)
+  }
+
+  /// Matches of all rules in the tree paired with the rule index. See `scan_nodes`.
+  pub fn scan<'s, 't: 's>(
+    &'s self,
+    root: &'t AstGrep<L>,
+  ) -> impl Iterator<Item = (usize, NodeMatch<'t, L>)> + 's
+  where
+    L: 't,
+  {
+    self.scan_nodes(root.root().dfs())
+  }
+
+  /// Match the nodes in order. Matches on the same node are ordered by rule index,
+  /// and every match has its own meta variable environment.
+  pub fn scan_nodes<'s, 't: 's>(
+    &'s self,
+    nodes: impl Iterator<Item = Node<'t, L>> + 's,
+  ) -> impl Iterator<Item = (usize, NodeMatch<'t, L>)> + 's
+  where
+    L: 't,
+  {
+    nodes.flat_map(move |node| {
+      let candidates = self.candidates(node.kind_id());
+      candidates.iter().filter_map(move |&idx| {
+        let matched = self.rules[idx].matcher.match_node(node.clone())?;
+        self.is_reported(idx, &matched).then_some((idx, matched))
+      })
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test::TypeScript;
+  use crate::{from_yaml_string, GlobalRules};
+
+  const RULES: &str = "
+id: log
+message: test
+severity: hint
+language: Tsx
+rule: { pattern: console.log($A) }
+---
+id: call
+message: test
+severity: hint
+language: Tsx
+rule: { pattern: $A($$$) }
+---
+id: foo-regex
+message: test
+severity: hint
+language: Tsx
+rule: { regex: ^foo$ }
+";
+
+  // (rule index, matched text, text of $A)
+  type Found = (usize, String, Option<String>);
+
+  fn found(idx: usize, nm: NodeMatch<TypeScript>) -> Found {
+    let var = nm.get_env().get_match("A").map(|n| n.text().to_string());
+    (idx, nm.text().to_string(), var)
+  }
+
+  #[test]
+  fn test_combined_scan() {
+    let rules = from_yaml_string(RULES, &GlobalRules::default()).expect("should parse");
+    let combined = CombinedScan::new(rules.iter().collect());
+    let grep = TypeScript::Tsx.ast_grep("console.log(foo(1)); bar(foo)");
+    let mut naive: Vec<Found> = vec![];
+    for (idx, rule) in rules.iter().enumerate() {
+      naive.extend(grep.root().find_all(&rule.matcher).map(|nm| found(idx, nm)));
+    }
+    let mut visited = 0;
+    let nodes = grep.root().dfs().inspect(|_| visited += 1);
+    let mut combined: Vec<_> = combined
+      .scan_nodes(nodes)
+      .map(|(idx, nm)| found(idx, nm))
+      .collect();
+    // a single traversal visits every node once
+    assert_eq!(visited, grep.root().dfs().count());
+    combined.sort_by_key(|(idx, ..)| *idx);
+    assert_eq!(combined, naive);
+    assert!(combined.contains(&(1, "console.log(foo(1))".into(), Some("console.log".into()))));
+    assert!(combined.contains(&(0, "console.log(foo(1))".into(), Some("foo(1)".into()))));
+    assert_eq!(combined.iter().filter(|f| f.0 == 2).count(), 2);
+  }
+
+  #[test]
+  fn test_find() {
+    let rules = from_yaml_string(RULES, &GlobalRules::default()).expect("should parse");
+    let combined = CombinedScan::new(rules.iter().skip(2).collect());
+    assert!(combined.find(&TypeScript::Tsx.ast_grep("let a = foo")));
+    assert!(!combined.find(&TypeScript::Tsx.ast_grep("let a = food")));
+  }
+
+  #[test]
+  fn test_scan_timed() {
+    let yaml = RULES.replace(
+      "rule: { pattern: $A($$$) }",
+      "rule: { pattern: $A($$$) }\ntimeout: 0",
+    );
+    let rules = from_yaml_string(&yaml, &GlobalRules::default()).expect("should parse");
+    let combined = CombinedScan::new(rules.iter().collect());
+    let grep = TypeScript::Tsx.ast_grep("console.log(foo(1)); bar(foo)");
+    let (matches, elapsed) = combined.scan_timed(&grep);
+    assert_eq!(elapsed.len(), 3);
+    // the call rule exceeds its zero timeout on its first candidate
+    assert!(rules[1].timed_out(elapsed[1]));
+    assert!(!matches.iter().any(|(idx, _)| *idx == 1));
+    let found: Vec<_> = matches
+      .into_iter()
+      .map(|(idx, nm)| found(idx, nm))
+      .collect();
+    assert_eq!(found.len(), 3);
+    assert!(found.contains(&(0, "console.log(foo(1))".into(), Some("foo(1)".into()))));
+  }
+
+  #[test]
+  fn test_with_index() {
+    let yaml = "
+id: unused-export
+message: test
+severity: hint
+language: Tsx
+rule: { pattern: export const $NAME = 1 }
+crossFile:
+  imports:
+    kind: import_specifier
+    has: { kind: identifier, pattern: $NAME }
+    inside: { kind: import_statement, has: { field: source, pattern: $SRC }, stopBy: end }
+  unimported: $NAME
+  module: $SRC
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let mut index = SymbolIndex::default();
+    let main = TypeScript::Tsx.ast_grep("import { a } from './lib'");
+    index.index_file(
+      &rules.iter().collect::<Vec<_>>(),
+      Path::new("main.ts"),
+      &main,
+    );
+    let grep = TypeScript::Tsx.ast_grep("export const a = 1; export const b = 1");
+    let combined = CombinedScan::new(rules.iter().collect());
+    // unknown without an index
+    assert!(!combined.find(&grep));
+    let combined = combined.with_index(Path::new("lib.ts"), &index);
+    let found: Vec<_> = combined
+      .scan(&grep)
+      .map(|(_, nm)| nm.text().to_string())
+      .collect();
+    assert_eq!(found, ["export const b = 1"]);
+  }
+}
@@ -1,10 +1,18 @@
 use serde::{Deserialize, Serialize};
 
+use crate::deserialize_env::DeserializeEnv;
 use crate::referent_rule::RuleRegistration;
-use crate::rule::Rule;
+use crate::rule::{deserialize_rule, Rule, RuleSerializeError, SerializableRule};
+use crate::transform::Transformation;
+use crate::wrap::{Wrap, WRAPPED_VAR};
 use ast_grep_core::language::Language;
-use ast_grep_core::matcher::{KindMatcher, KindMatcherError, RegexMatcher, RegexMatcherError};
-use ast_grep_core::meta_var::{MetaVarEnv, MetaVarMatcher, MetaVarMatchers};
+use ast_grep_core::matcher::{
+  KindMatcher, KindMatcherError, MatchTrace, NumberMatcher, NumberRange, RegexMatcher,
+  RegexMatcherError,
+};
+use ast_grep_core::meta_var::{
+  MetaVarEnv, MetaVarMatcher, MetaVarMatchers, MetaVarRelation, SpanUnit,
+};
 use ast_grep_core::{Matcher, Node, Pattern, PatternError};
 
 use bit_set::BitSet;
@@ -22,6 +30,127 @@ pub enum SerializableMetaVarMatcher {
   Pattern(String),
   /// A kind_id to filter matched metavar based on its ts-node kind
   Kind(String),
+  /// Name of another captured metavar whose text must equal the metavar's text.
+  Equals(String),
+  /// Name of another captured metavar whose text must differ from the metavar's text.
+  NotEquals(String),
+  /// Name of another captured metavar whose text must be a prefix of the metavar's text.
+  StartsWith(String),
+  /// Bounds of the metavar's numeric value. Captures that are not number literals never match,
+  /// nor do integers with a leading zero like `0755`, whose value differs between languages.
+  Number(SerializableNumberRange),
+  /// Bounds of how many lines the metavar's node spans, both the start and end line included.
+  Lines(SerializableNumberRange),
+  /// Bounds of the byte length of the metavar's text.
+  Bytes(SerializableNumberRange),
+  /// Bounds of the character count of the metavar's text.
+  Chars(SerializableNumberRange),
+}
+
+/// Compare a number with the bounds. Unset bounds are not checked.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SerializableNumberRange {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub gt: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub gte: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub lt: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub lte: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub eq: Option<f64>,
+}
+
+impl From<SerializableNumberRange> for NumberRange {
+  fn from(range: SerializableNumberRange) -> Self {
+    let SerializableNumberRange {
+      gt,
+      gte,
+      lt,
+      lte,
+      eq,
+    } = range;
+    Self {
+      gt,
+      gte,
+      lt,
+      lte,
+      eq,
+    }
+  }
+}
+
+/// Bounds of how many nodes a meta variable captures, e.g. `{ min: 2 }` for `$$$ARGS`.
+/// Separators like commas are not counted and a single capture counts as one.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SerializableCount {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub min: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max: Option<usize>,
+}
+
+/// A rule that every or some of the nodes captured by a multi meta variable like `$$$ARGS`
+/// must match, e.g. `every: { kind: identifier }`. Separators like commas are not checked.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub enum SerializableQuantifiedRule {
+  /// Every captured node matches the rule. It holds if no node is captured.
+  Every(SerializableRule),
+  /// At least one captured node matches the rule.
+  Some(SerializableRule),
+}
+
+/// A constraint on a captured meta variable. Besides the meta variable matchers above,
+/// any rule like `has` or `inside` can be used and is matched against the captured node.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum SerializableConstraint {
+  MetaVar(SerializableMetaVarMatcher),
+  Count(SerializableCount),
+  Quantified(SerializableQuantifiedRule),
+  Rule(SerializableRule),
+}
+
+/// A rule constraint on a captured meta variable.
+pub enum RuleConstraint<L: Language> {
+  /// The single captured node matches the rule.
+  Node(Rule<L>),
+  /// Every named node captured by a multi meta variable matches the rule.
+  Every(Rule<L>),
+  /// At least one named node captured by a multi meta variable matches the rule.
+  Some(Rule<L>),
+}
+
+impl<L: Language> RuleConstraint<L> {
+  /// Uncaptured meta variables are skipped. A single capture is checked like a list of one node.
+  fn matches(&self, var: &str, env: &MetaVarEnv<L>) -> bool {
+    let (rule, every) = match self {
+      Self::Node(rule) => {
+        return env
+          .get_match(var)
+          .map_or(true, |node| rule.match_node(node.clone()).is_some())
+      }
+      Self::Every(rule) => (rule, true),
+      Self::Some(rule) => (rule, false),
+    };
+    let nodes = match (env.get_match(var), env.get_labels(var)) {
+      (Some(node), _) => vec![node.clone()],
+      (None, Some(nodes)) => nodes.iter().filter(|n| n.is_named()).cloned().collect(),
+      (None, None) => return true,
+    };
+    let mut matched = nodes
+      .into_iter()
+      .map(|node| rule.match_node(node).is_some());
+    if every {
+      matched.all(|m| m)
+    } else {
+      matched.any(|m| m)
+    }
+  }
 }
 
 #[derive(Debug, Error)]
@@ -32,17 +161,34 @@ pub enum SerializeConstraintsError {
   InvalidKind(#[from] KindMatcherError),
   #[error("Invalid Pattern.")]
   PatternError(#[from] PatternError),
+  #[error("Invalid Rule.")]
+  RuleError(#[from] RuleSerializeError),
+  #[error("Invalid count. `min` or `max` is required and `min` cannot exceed `max`.")]
+  InvalidCount,
 }
 
 pub fn try_from_serializable<L: Language>(
   meta_var: SerializableMetaVarMatcher,
   lang: L,
 ) -> Result<MetaVarMatcher<L>, SerializeConstraintsError> {
+  use MetaVarRelation as R;
   use SerializableMetaVarMatcher as S;
+  // allow referencing other meta var with or without the leading meta char
+  let relation = |relation, var: String| {
+    let var = var.strip_prefix(lang.meta_var_char()).unwrap_or(&var);
+    MetaVarMatcher::Relation(relation, var.to_string())
+  };
   Ok(match meta_var {
     S::Regex(s) => MetaVarMatcher::Regex(RegexMatcher::try_new(&s)?),
     S::Kind(p) => MetaVarMatcher::Kind(KindMatcher::try_new(&p, lang)?),
     S::Pattern(p) => MetaVarMatcher::Pattern(Pattern::try_new(&p, lang)?),
+    S::Number(n) => MetaVarMatcher::Number(NumberMatcher::new(n.into())),
+    S::Lines(n) => MetaVarMatcher::Span(SpanUnit::Lines, n.into()),
+    S::Bytes(n) => MetaVarMatcher::Span(SpanUnit::Bytes, n.into()),
+    S::Chars(n) => MetaVarMatcher::Span(SpanUnit::Chars, n.into()),
+    S::Equals(v) => relation(R::Equals, v),
+    S::NotEquals(v) => relation(R::NotEquals, v),
+    S::StartsWith(v) => relation(R::StartsWith, v),
   })
 }
 
@@ -57,9 +203,61 @@ pub fn try_deserialize_matchers<L: Language>(
   Ok(map)
 }
 
+/// Meta variable matchers and rules of constraints, keyed by meta variable.
+/// Rules are kept apart since they cannot be stored as a core [`MetaVarMatcher`].
+pub type Constraints<L> = (MetaVarMatchers<L>, HashMap<String, RuleConstraint<L>>);
+
+/// Deserialize constraints in a rule config. Rules in constraints can reference utils in `env`.
+pub fn try_deserialize_constraints<L: Language>(
+  constraints: HashMap<String, SerializableConstraint>,
+  env: &DeserializeEnv<L>,
+) -> Result<Constraints<L>, SerializeConstraintsError> {
+  let mut matchers = MetaVarMatchers::new();
+  let mut rules = HashMap::new();
+  for (key, constraint) in constraints {
+    match constraint {
+      SerializableConstraint::MetaVar(SerializableMetaVarMatcher::Regex(r)) if env.word => {
+        matchers.insert(key, MetaVarMatcher::Regex(RegexMatcher::try_new_word(&r)?));
+      }
+      SerializableConstraint::MetaVar(m) => {
+        let matcher = match try_from_serializable(m, env.lang.clone())? {
+          MetaVarMatcher::Pattern(p) => MetaVarMatcher::Pattern(p.ignore_case(env.ignore_case)),
+          matcher => matcher,
+        };
+        matchers.insert(key, matcher);
+      }
+      SerializableConstraint::Count(SerializableCount { min, max }) => {
+        let valid = match (min, max) {
+          (None, None) => false,
+          (Some(min), Some(max)) => min <= max,
+          _ => true,
+        };
+        if !valid {
+          return Err(SerializeConstraintsError::InvalidCount);
+        }
+        matchers.insert(key, MetaVarMatcher::Count { min, max });
+      }
+      SerializableConstraint::Quantified(SerializableQuantifiedRule::Every(r)) => {
+        rules.insert(key, RuleConstraint::Every(deserialize_rule(r, env)?));
+      }
+      SerializableConstraint::Quantified(SerializableQuantifiedRule::Some(r)) => {
+        rules.insert(key, RuleConstraint::Some(deserialize_rule(r, env)?));
+      }
+      SerializableConstraint::Rule(r) => {
+        rules.insert(key, RuleConstraint::Node(deserialize_rule(r, env)?));
+      }
+    }
+  }
+  Ok((matchers, rules))
+}
+
 pub struct RuleWithConstraint<L: Language> {
   rule: Rule<L>,
   matchers: MetaVarMatchers<L>,
+  /// rules matched against captured nodes
+  rule_constraints: HashMap<String, RuleConstraint<L>>,
+  transforms: HashMap<String, Transformation>,
+  wrap: Option<Wrap>,
   kinds: Option<BitSet>,
   // this is required to hold util rule reference
   _utils: RuleRegistration<L>,
@@ -81,10 +279,36 @@ impl<L: Language> RuleWithConstraint<L> {
     Self { matchers, ..self }
   }
 
+  #[inline]
+  pub fn with_rule_constraints(self, rule_constraints: HashMap<String, RuleConstraint<L>>) -> Self {
+    Self {
+      rule_constraints,
+      ..self
+    }
+  }
+
+  #[inline]
+  pub fn with_transforms(self, transforms: HashMap<String, Transformation>) -> Self {
+    Self { transforms, ..self }
+  }
+
+  #[inline]
+  pub fn with_wrap(self, wrap: Option<Wrap>) -> Self {
+    Self { wrap, ..self }
+  }
+
   #[inline]
   pub fn with_utils(self, _utils: RuleRegistration<L>) -> Self {
     Self { _utils, ..self }
   }
+
+  /// Captured nodes must match their rule constraints. Uncaptured meta variables are skipped.
+  fn match_rules(&self, env: &MetaVarEnv<L>) -> bool {
+    self
+      .rule_constraints
+      .iter()
+      .all(|(var, constraint)| constraint.matches(var, env))
+  }
 }
 impl<L: Language> Deref for RuleWithConstraint<L> {
   type Target = Rule<L>;
@@ -99,6 +323,9 @@ impl<L: Language> Default for RuleWithConstraint<L> {
     Self {
       rule: Rule::default(),
       matchers: MetaVarMatchers::default(),
+      rule_constraints: HashMap::new(),
+      transforms: HashMap::new(),
+      wrap: None,
       kinds: None,
       _utils: RuleRegistration::default(),
     }
@@ -116,17 +343,38 @@ impl<L: Language> Matcher<L> for RuleWithConstraint<L> {
         return None;
       }
     }
+    let lang = node.lang().clone();
     let ret = self.rule.match_node_with_env(node, env);
-    if ret.is_some() && env.match_constraints(&self.matchers) {
-      ret
-    } else {
-      None
+    if ret.is_none() || !env.match_constraints(&self.matchers) || !self.match_rules(env) {
+      return None;
+    }
+    // transforms only read captured nodes so they do not depend on each other
+    let transformed: Vec<_> = self
+      .transforms
+      .iter()
+      .map(|(var, trans)| (var.clone(), trans.compute(env, &lang)))
+      .collect();
+    for (var, text) in transformed {
+      env.insert_transformation(var, text);
     }
+    if let (Some(wrap), Some(node)) = (&self.wrap, &ret) {
+      let wrapped = wrap.compute(node, env);
+      env.insert_transformation(WRAPPED_VAR.to_string(), wrapped);
+    }
+    ret
   }
 
   fn potential_kinds(&self) -> Option<BitSet> {
     self.rule.potential_kinds()
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    if self.matchers.is_empty() && self.rule_constraints.is_empty() {
+      return self.rule.explain(node);
+    }
+    let children = vec![self.rule.explain(node.clone())];
+    MatchTrace::from_match("constraints", self.match_node(node)).with_children(children)
+  }
 }
 
 #[cfg(test)]
@@ -213,4 +461,215 @@ mod test {
     };
     assert_eq!(error.to_string(), "Kind `IMPOSSIBLE_KIND` is invalid.");
   }
+
+  /// Rule of the pattern with constraints in YAML, e.g. `X: { regex: a }`.
+  fn make_constrained_rule(pattern: &str, constraints: &str) -> RuleWithConstraint<TypeScript> {
+    let constraints = from_str(constraints).expect("must parse");
+    let env = DeserializeEnv::new(TypeScript::Tsx);
+    let (matchers, rules) = try_deserialize_constraints(constraints, &env).expect("should parse");
+    RuleWithConstraint::new(Rule::Pattern(Pattern::new(pattern, TypeScript::Tsx)))
+      .with_matchers(matchers)
+      .with_rule_constraints(rules)
+  }
+
+  fn constrained_matches(pattern: &str, constraints: &str, src: &str) -> bool {
+    let rule = make_constrained_rule(pattern, constraints);
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let found = grep.root().find(&rule).is_some();
+    found
+  }
+
+  fn make_self_assign_rule(constraint: &str) -> RuleWithConstraint<TypeScript> {
+    make_constrained_rule("$X = $Y", &format!("X: {{ {constraint} }}"))
+  }
+
+  #[test]
+  fn test_serializable_equals() {
+    let rule = make_self_assign_rule("equals: Y");
+    let grep = TypeScript::Tsx.ast_grep("a = a");
+    assert!(grep.root().find(&rule).is_some());
+    let grep = TypeScript::Tsx.ast_grep("a.b = a.b");
+    assert!(grep.root().find(&rule).is_some());
+    let grep = TypeScript::Tsx.ast_grep("a = b");
+    assert!(grep.root().find(&rule).is_none());
+    // leading meta char is optional
+    let rule = make_self_assign_rule("equals: $Y");
+    let grep = TypeScript::Tsx.ast_grep("a = a");
+    assert!(grep.root().find(&rule).is_some());
+  }
+
+  #[test]
+  fn test_serializable_not_equals() {
+    let rule = make_self_assign_rule("notEquals: Y");
+    let grep = TypeScript::Tsx.ast_grep("a = a");
+    assert!(grep.root().find(&rule).is_none());
+    let grep = TypeScript::Tsx.ast_grep("a = b");
+    assert!(grep.root().find(&rule).is_some());
+  }
+
+  #[test]
+  fn test_serializable_starts_with() {
+    let rule = make_self_assign_rule("startsWith: Y");
+    let grep = TypeScript::Tsx.ast_grep("prefixed = prefix");
+    assert!(grep.root().find(&rule).is_some());
+    let grep = TypeScript::Tsx.ast_grep("prefix = prefixed");
+    assert!(grep.root().find(&rule).is_none());
+  }
+
+  fn make_constrained_call_rule(constraint: &str) -> RuleWithConstraint<TypeScript> {
+    make_constrained_rule("foo($FN)", &format!("FN: {constraint}"))
+  }
+
+  #[test]
+  fn test_rule_constraint() {
+    let rule = make_constrained_call_rule("{ has: { kind: return_statement, stopBy: end } }");
+    let grep = TypeScript::Tsx.ast_grep("foo(function () { if (a) { return 1 } })");
+    assert!(grep.root().find(&rule).is_some());
+    let grep = TypeScript::Tsx.ast_grep("foo(function () { bar() })");
+    assert!(grep.root().find(&rule).is_none());
+    // meta variable matchers are still supported
+    let rule = make_constrained_call_rule("{ regex: '^a' }");
+    let grep = TypeScript::Tsx.ast_grep("foo(abc)");
+    assert!(grep.root().find(&rule).is_some());
+  }
+
+  #[test]
+  fn test_invalid_rule_constraint() {
+    let constraints = from_str("FN: { inside: { kind: not_a_kind } }").expect("must parse");
+    let env = DeserializeEnv::new(TypeScript::Tsx);
+    let ret = try_deserialize_constraints(constraints, &env);
+    assert!(matches!(ret, Err(SerializeConstraintsError::RuleError(_))));
+  }
+
+  fn number_matches(range: &str, src: &str) -> bool {
+    constrained_matches("foo($N)", &format!("N: {{ number: {range} }}"), src)
+  }
+
+  fn args_match(constraint: &str, src: &str) -> bool {
+    constrained_matches("foo($$$ARGS)", &format!("ARGS: {constraint}"), src)
+  }
+
+  #[test]
+  fn test_count_min() {
+    assert!(args_match("{ min: 2 }", "foo(a, b)"));
+    assert!(args_match("{ min: 2 }", "foo(a, b, c)"));
+    assert!(!args_match("{ min: 2 }", "foo(a)"));
+    assert!(!args_match("{ min: 1 }", "foo()"));
+  }
+
+  #[test]
+  fn test_count_max() {
+    assert!(args_match("{ max: 2 }", "foo()"));
+    assert!(args_match("{ max: 2 }", "foo(a, b)"));
+    assert!(!args_match("{ max: 2 }", "foo(a, b, c)"));
+  }
+
+  #[test]
+  fn test_count_exact() {
+    assert!(args_match("{ min: 2, max: 2 }", "foo(a, b)"));
+    assert!(!args_match("{ min: 2, max: 2 }", "foo(a)"));
+    assert!(!args_match("{ min: 2, max: 2 }", "foo(a, b, c)"));
+  }
+
+  #[test]
+  fn test_every() {
+    let every = "{ every: { kind: identifier } }";
+    assert!(args_match(every, "foo(a, b, c)"));
+    assert!(!args_match(every, "foo(a, 1, c)"));
+    assert!(!args_match(every, "foo(a.b)"));
+    // separators are not checked and an empty capture passes
+    assert!(args_match(every, "foo()"));
+  }
+
+  #[test]
+  fn test_some() {
+    let some = "{ some: { kind: number } }";
+    assert!(args_match(some, "foo(a, 1, c)"));
+    assert!(args_match(some, "foo(1)"));
+    assert!(!args_match(some, "foo(a, b)"));
+    assert!(!args_match(some, "foo()"));
+    assert!(from_str::<SerializableConstraint>("{ some: { kind: number }, every: {} }").is_err());
+  }
+
+  #[test]
+  fn test_invalid_count() {
+    let env = DeserializeEnv::new(TypeScript::Tsx);
+    for count in ["{ min: 3, max: 2 }", "{}"] {
+      let constraints = from_str(&format!("ARGS: {count}")).expect("must parse");
+      let ret = try_deserialize_constraints(constraints, &env);
+      assert!(matches!(ret, Err(SerializeConstraintsError::InvalidCount)));
+    }
+  }
+
+  #[test]
+  fn test_number_integer() {
+    assert!(number_matches("{ gt: 1000 }", "foo(1001)"));
+    assert!(!number_matches("{ gt: 1000 }", "foo(1000)"));
+    assert!(number_matches("{ gte: 1000 }", "foo(1_000)"));
+    assert!(number_matches("{ eq: 0 }", "foo(0)"));
+    assert!(!number_matches("{ eq: 0 }", "foo(10)"));
+    assert!(number_matches("{ lt: 0 }", "foo(-1)"));
+  }
+
+  #[test]
+  fn test_number_float() {
+    assert!(number_matches("{ gt: 0, lt: 1 }", "foo(0.5)"));
+    assert!(!number_matches("{ gt: 0, lt: 1 }", "foo(1.5)"));
+    assert!(number_matches("{ lte: 1.5 }", "foo(1.5)"));
+    assert!(number_matches("{ eq: 1000 }", "foo(1e3)"));
+  }
+
+  #[test]
+  fn test_number_hex() {
+    assert!(number_matches("{ eq: 255 }", "foo(0xff)"));
+    assert!(number_matches("{ gt: 255 }", "foo(0x100)"));
+    assert!(number_matches("{ lt: 8 }", "foo(0b101)"));
+    assert!(!number_matches("{ lt: 8 }", "foo(0o10)"));
+  }
+
+  #[test]
+  fn test_number_non_numeric() {
+    assert!(!number_matches("{}", "foo(a)"));
+    assert!(!number_matches("{ gt: 0 }", "foo('1')"));
+    assert!(!number_matches("{ gt: 0 }", "foo(1 + 1)"));
+    assert!(number_matches("{}", "foo(1)"));
+    assert!(from_str::<SerializableMetaVarMatcher>("number: { greater: 1 }").is_err());
+  }
+
+  fn span_matches(span: &str, src: &str) -> bool {
+    constrained_matches("const $F = () => $BODY", &format!("BODY: {span}"), src)
+  }
+
+  #[test]
+  fn test_span_lines() {
+    let long = format!("const long = () => {{\n{}}}", "  step()\n".repeat(60));
+    assert!(span_matches("{ lines: { gt: 50 } }", &long));
+    assert!(!span_matches(
+      "{ lines: { gt: 50 } }",
+      "const short = () => {\n  step()\n}"
+    ));
+    assert!(span_matches(
+      "{ lines: { eq: 3 } }",
+      "const short = () => {\n  step()\n}"
+    ));
+    assert!(span_matches(
+      "{ lines: { eq: 1 } }",
+      "const short = () => {}"
+    ));
+  }
+
+  #[test]
+  fn test_span_bytes_chars() {
+    let src = "const f = () => { '日本' }";
+    assert!(span_matches("{ bytes: { eq: 12 } }", src));
+    assert!(span_matches("{ chars: { eq: 8 } }", src));
+    assert!(!span_matches("{ chars: { gt: 8 } }", src));
+  }
+
+  #[test]
+  fn test_relation_to_missing_var() {
+    let rule = make_self_assign_rule("notEquals: Z");
+    let grep = TypeScript::Tsx.ast_grep("a = b");
+    assert!(grep.root().find(&rule).is_none());
+  }
 }
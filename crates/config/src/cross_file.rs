@@ -0,0 +1,307 @@
+use serde::{Deserialize, Serialize};
+
+use crate::constraints::RuleWithConstraint;
+use crate::deserialize_env::DeserializeEnv;
+use crate::rule::{deserialize_rule, RuleSerializeError, SerializableRule};
+use crate::RuleConfig;
+use ast_grep_core::language::Language;
+use ast_grep_core::meta_var::{MetaVariable, MetaVariableID};
+use ast_grep_core::{AstGrep, Node, NodeMatch};
+use thiserror::Error;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// Only report a match if the symbol it captures is not imported from its file by any scanned file.
+/// Imports of every file are indexed before the rule's matches are reported.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableCrossFile {
+  /// Rule matching imports. Its capture of the `unimported` meta variable is the imported symbol.
+  pub imports: SerializableRule,
+  /// The meta variable capturing the symbol in both the rule and `imports`, e.g. `$NAME`.
+  pub unimported: String,
+  /// The meta variable capturing the module specifier in `imports`, e.g. `'./lib'`.
+  pub module: String,
+}
+
+#[derive(Debug, Error)]
+pub enum CrossFileError {
+  #[error("`{0}` is not a single meta variable.")]
+  InvalidSymbol(String),
+  #[error("imports rule is invalid.")]
+  Imports(#[from] RuleSerializeError),
+}
+
+pub struct CrossFile<L: Language> {
+  imports: RuleWithConstraint<L>,
+  symbol: MetaVariableID,
+  module: MetaVariableID,
+}
+
+impl<L: Language> CrossFile<L> {
+  pub fn try_new(
+    cross_file: SerializableCrossFile,
+    env: &DeserializeEnv<L>,
+  ) -> Result<Self, CrossFileError> {
+    let meta_var = |var: String| match env.lang.extract_meta_var(&var) {
+      Some(MetaVariable::Named(n, _)) => Ok(n),
+      _ => Err(CrossFileError::InvalidSymbol(var)),
+    };
+    let symbol = meta_var(cross_file.unimported)?;
+    let module = meta_var(cross_file.module)?;
+    let rule = deserialize_rule(cross_file.imports, env)?;
+    let imports = RuleWithConstraint::new(rule).with_utils(env.registration.clone());
+    Ok(Self {
+      imports,
+      symbol,
+      module,
+    })
+  }
+
+  /// Modules and symbols imported under the root of the file at `path`.
+  /// Imports of packages or other specifiers that cannot name a scanned file are skipped.
+  pub fn imported_symbols(&self, path: &Path, root: Node<L>) -> Vec<(PathBuf, String)> {
+    root
+      .find_all(&self.imports)
+      .filter_map(|nm| {
+        let env = nm.get_env();
+        let specifier = env.get_match(&self.module)?.text();
+        let module = resolve_module(path, &specifier)?;
+        Some((module, env.get_match(&self.symbol)?.text().to_string()))
+      })
+      .collect()
+  }
+
+  /// Text of the symbol captured by the match.
+  pub fn symbol_of(&self, nm: &NodeMatch<L>) -> Option<String> {
+    let node = nm.get_env().get_match(&self.symbol)?;
+    Some(node.text().to_string())
+  }
+}
+
+/// Resolve a relative specifier like `'./lib'` against the directory of the importing file.
+/// Other specifiers, e.g. packages or path aliases, are not resolved.
+fn resolve_module(importer: &Path, specifier: &str) -> Option<PathBuf> {
+  let specifier = specifier.trim_matches(|c| matches!(c, '\'' | '"' | '`'));
+  let is_relative = specifier == "."
+    || specifier == ".."
+    || specifier.starts_with("./")
+    || specifier.starts_with("../");
+  if !is_relative {
+    return None;
+  }
+  let dir = importer.parent().unwrap_or_else(|| Path::new(""));
+  Some(normalize(&dir.join(specifier)))
+}
+
+/// Remove `.` and `..` components without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+  let mut ret = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => (),
+      Component::ParentDir
+        if matches!(ret.components().next_back(), Some(Component::Normal(_))) =>
+      {
+        ret.pop();
+      }
+      c => ret.push(c),
+    }
+  }
+  ret
+}
+
+/// Modules an import may name to refer to the file: the path with or without its extension,
+/// and the directory for an `index` file.
+fn module_names(path: &Path) -> Vec<PathBuf> {
+  let path = normalize(path);
+  let mut names = vec![path.with_extension("")];
+  if path.file_stem().map_or(false, |stem| stem == "index") {
+    names.extend(path.parent().map(Path::to_path_buf));
+  }
+  names.push(path);
+  names
+}
+
+// (rule id, module, symbol)
+type Import = (String, PathBuf, String);
+
+/// Symbols imported by the scanned files with the module they are imported from.
+/// Imports are recorded by rule id since each rule defines what an import is.
+/// Indexing a file again replaces its previous imports, e.g. when it changes in watch mode.
+#[derive(Default)]
+pub struct SymbolIndex {
+  by_file: HashMap<PathBuf, HashSet<Import>>,
+  /// number of files with the import
+  imports: HashMap<Import, usize>,
+}
+
+impl SymbolIndex {
+  /// Record the imports in the file at `path` for each cross-file rule.
+  pub fn index_file<L: Language>(
+    &mut self,
+    rules: &[&RuleConfig<L>],
+    path: &Path,
+    root: &AstGrep<L>,
+  ) {
+    let mut found = HashSet::new();
+    for rule in rules {
+      for (module, symbol) in rule.imported_symbols(path, root.root()) {
+        found.insert((rule.id.clone(), module, symbol));
+      }
+    }
+    self.set_imports(path.to_path_buf(), found);
+  }
+
+  /// Add the imports indexed in another index, e.g. one built for a single file.
+  /// Imports of files in both indexes are replaced.
+  pub fn merge(&mut self, other: SymbolIndex) {
+    for (path, found) in other.by_file {
+      self.set_imports(path, found);
+    }
+  }
+
+  fn set_imports(&mut self, path: PathBuf, found: HashSet<Import>) {
+    for import in self.by_file.remove(&path).into_iter().flatten() {
+      if let Some(count) = self.imports.get_mut(&import) {
+        *count -= 1;
+        if *count == 0 {
+          self.imports.remove(&import);
+        }
+      }
+    }
+    for import in &found {
+      *self.imports.entry(import.clone()).or_default() += 1;
+    }
+    self.by_file.insert(path, found);
+  }
+
+  /// Forget the imports of a file, e.g. when it is deleted in watch mode.
+  pub fn remove_file(&mut self, path: &Path) {
+    self.set_imports(path.to_path_buf(), HashSet::new());
+    self.by_file.remove(path);
+  }
+
+  /// Whether the file at `importer` imports any symbol from the file at `path`.
+  /// Findings of cross-file rules in `path` depend on the imports of `importer`.
+  pub fn imports_from(&self, importer: &Path, path: &Path) -> bool {
+    let names = module_names(path);
+    self.by_file.get(importer).map_or(false, |found| {
+      found.iter().any(|(_, module, _)| names.contains(module))
+    })
+  }
+
+  /// Whether any scanned file imports the symbol from the file at `path` by the imports of the rule.
+  pub fn is_imported(&self, rule_id: &str, path: &Path, symbol: &str) -> bool {
+    module_names(path).into_iter().any(|module| {
+      let import = (rule_id.to_string(), module, symbol.to_string());
+      self.imports.contains_key(&import)
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test::TypeScript;
+  use crate::{from_yaml_string, GlobalRules, RuleConfigError};
+  use ast_grep_core::language::Language;
+
+  const RULE: &str = "
+id: unused-export
+message: test
+severity: warning
+language: Tsx
+rule:
+  pattern: export function $NAME() {}
+crossFile:
+  imports:
+    kind: import_specifier
+    has: { kind: identifier, pattern: $NAME }
+    inside:
+      kind: import_statement
+      has: { field: source, pattern: $SOURCE }
+      stopBy: end
+  unimported: $NAME
+  module: $SOURCE
+";
+
+  #[test]
+  fn test_symbol_index() {
+    let rules = from_yaml_string(RULE, &GlobalRules::default()).expect("should parse");
+    let rules: Vec<_> = rules.iter().collect();
+    let mut index = SymbolIndex::default();
+    let grep = TypeScript::Tsx.ast_grep("import { a, b as c } from './a'\nimport { d } from 'd'");
+    index.index_file(&rules, Path::new("src/main.ts"), &grep);
+    let is_imported =
+      |path: &str, symbol| index.is_imported("unused-export", Path::new(path), symbol);
+    assert!(is_imported("src/a.ts", "a"));
+    assert!(is_imported("./src/a.tsx", "b"));
+    assert!(!is_imported("src/a.ts", "c"));
+    // the same symbol exported by another module
+    assert!(!is_imported("lib/a.ts", "a"));
+    // packages are not scanned files
+    assert!(!is_imported("d.ts", "d"));
+    assert!(!index.is_imported("other-rule", Path::new("src/a.ts"), "a"));
+    let grep = TypeScript::Tsx.ast_grep("export function a() {}\nexport function e() {}");
+    let reported: Vec<_> = grep
+      .root()
+      .find_all(&rules[0].matcher)
+      .filter(|nm| rules[0].passes_index(nm, Path::new("src/a.ts"), Some(&index)))
+      .map(|nm| nm.text().to_string())
+      .collect();
+    assert_eq!(reported, ["export function e() {}"]);
+    // without an index nothing is known to be unused
+    let nm = grep.root().find(&rules[0].matcher).expect("should match");
+    assert!(!rules[0].passes_index(&nm, Path::new("src/a.ts"), None));
+  }
+
+  #[test]
+  fn test_reindex_file() {
+    let rules = from_yaml_string(RULE, &GlobalRules::default()).expect("should parse");
+    let rules: Vec<_> = rules.iter().collect();
+    let mut index = SymbolIndex::default();
+    let path = Path::new("main.ts");
+    index.index_file(
+      &rules,
+      path,
+      &TypeScript::Tsx.ast_grep("import { a } from './lib'"),
+    );
+    index.index_file(
+      &rules,
+      Path::new("b.ts"),
+      &TypeScript::Tsx.ast_grep("import { a } from './lib'"),
+    );
+    index.index_file(
+      &rules,
+      path,
+      &TypeScript::Tsx.ast_grep("import { b } from './lib/index'"),
+    );
+    assert!(index.is_imported("unused-export", Path::new("lib.ts"), "a"));
+    assert!(index.is_imported("unused-export", Path::new("lib/index.ts"), "b"));
+    // a file without imports replaces the imports it had before
+    let mut changed = SymbolIndex::default();
+    changed.index_file(&rules, Path::new("b.ts"), &TypeScript::Tsx.ast_grep(""));
+    index.merge(changed);
+    assert!(!index.is_imported("unused-export", Path::new("lib.ts"), "a"));
+    // a deleted file no longer imports anything
+    assert!(index.imports_from(path, Path::new("lib/index.ts")));
+    assert!(!index.imports_from(path, Path::new("lib.ts")));
+    index.remove_file(path);
+    assert!(!index.imports_from(path, Path::new("lib/index.ts")));
+    assert!(!index.is_imported("unused-export", Path::new("lib/index.ts"), "b"));
+  }
+
+  #[test]
+  fn test_invalid_symbol() {
+    let rule = RULE.replace("unimported: $NAME", "unimported: NAME");
+    let ret = from_yaml_string::<TypeScript>(&rule, &GlobalRules::default());
+    let Err(RuleConfigError::InvalidRule(_, error)) = ret else {
+      panic!("should fail");
+    };
+    assert!(matches!(
+      *error,
+      RuleConfigError::CrossFile(CrossFileError::InvalidSymbol(_))
+    ));
+  }
+}
@@ -9,6 +9,10 @@ use std::collections::HashMap;
 pub struct DeserializeEnv<L: Language> {
   pub(crate) registration: RuleRegistration<L>,
   pub(crate) lang: L,
+  /// compare identifiers in patterns case-insensitively
+  pub(crate) ignore_case: bool,
+  /// only match regexes at word boundaries, like `grep -w`
+  pub(crate) word: bool,
 }
 
 impl<L: Language> DeserializeEnv<L> {
@@ -16,9 +20,22 @@ impl<L: Language> DeserializeEnv<L> {
     Self {
       registration: Default::default(),
       lang,
+      ignore_case: false,
+      word: false,
     }
   }
 
+  pub fn with_ignore_case(self, ignore_case: bool) -> Self {
+    Self {
+      ignore_case,
+      ..self
+    }
+  }
+
+  pub fn with_word(self, word: bool) -> Self {
+    Self { word, ..self }
+  }
+
   pub fn register_local_utils(
     self,
     utils: &HashMap<String, SerializableRule>,
@@ -46,7 +63,7 @@ impl<L: Language> DeserializeEnv<L> {
   pub fn with_globals(self, globals: &GlobalRules<L>) -> Self {
     Self {
       registration: RuleRegistration::from_globals(globals),
-      lang: self.lang,
+      ..self
     }
   }
 }
@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+
+use ast_grep_core::language::Language;
+use ast_grep_core::meta_var::{MetaVarEnv, MetaVariable, MetaVariableID};
+use ast_grep_core::{Edit, Matcher, NodeMatch, Pattern, PatternError};
+use thiserror::Error;
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Anchor to insert the template as a line at the top of the file.
+const FILE_START: &str = "fileStart";
+/// Start of a conditional section, e.g. `{{#EXTRA}}, $EXTRA{{/EXTRA}}`.
+const SECTION_START: &str = "{{#";
+
+/// A template replacing the matched code, or a list of edits applied together for each match.
+/// `SerializableRuleConfig::fix` was an `Option<String>` before fix lists were supported,
+/// a plain template is now `SerializableFix::Str` and can be converted from a `String`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum SerializableFix {
+  Str(String),
+  List(Vec<SerializableFixEdit>),
+}
+
+impl From<String> for SerializableFix {
+  fn from(template: String) -> Self {
+    Self::Str(template)
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableFixEdit {
+  /// The code to replace: a captured meta variable like `$CALLEE`, or `fileStart` to insert
+  /// the template as a line at the top of the file. The matched code is replaced if omitted.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub target: Option<String>,
+  /// Template of the new code. It can reference meta variables captured by the rule.
+  pub template: String,
+}
+
+#[derive(Debug, Error)]
+pub enum FixError {
+  #[error("`{0}` is not a captured meta variable or `fileStart`.")]
+  InvalidTarget(String),
+  #[error("fix template is invalid.")]
+  Template(#[from] PatternError),
+  #[error("Conditional section `{0}` is not closed by `{{{{/{0}}}}}` or is nested.")]
+  Section(String),
+}
+
+/// Whether the fix template has conditional sections of the meta variables in `vars`.
+/// Other text like `{{#each xs}}` is part of the template. See [`FixTemplate`].
+pub fn has_sections(template: &str, vars: &HashSet<String>) -> bool {
+  next_section(template, vars).is_some()
+}
+
+/// The position of the next section start in the template and its meta variable.
+fn next_section<'t>(template: &'t str, vars: &HashSet<String>) -> Option<(usize, &'t str)> {
+  template.match_indices(SECTION_START).find_map(|(i, _)| {
+    let rest = &template[i + SECTION_START.len()..];
+    let var = rest.split("}}").next()?;
+    (rest.len() > var.len() && vars.contains(var)).then_some((i, var))
+  })
+}
+
+enum Segment {
+  Text(String),
+  /// The body is only emitted if the meta variable is captured.
+  Section(String, String),
+}
+
+fn parse_sections(mut template: &str, vars: &HashSet<String>) -> Result<Vec<Segment>, FixError> {
+  let mut segments = vec![];
+  while let Some((i, var)) = next_section(template, vars) {
+    segments.push(Segment::Text(template[..i].to_string()));
+    let var = var.to_string();
+    let unclosed = || FixError::Section(var.clone());
+    template = &template[i + SECTION_START.len() + var.len() + 2..];
+    let end = format!("{{{{/{var}}}}}");
+    let j = template.find(&end).ok_or_else(unclosed)?;
+    let body = &template[..j];
+    if has_sections(body, vars) {
+      return Err(unclosed());
+    }
+    segments.push(Segment::Section(var, body.to_string()));
+    template = &template[j + end.len()..];
+  }
+  segments.push(Segment::Text(template.to_string()));
+  Ok(segments)
+}
+
+fn render_sections(segments: &[Segment], emit: impl Fn(&str) -> bool) -> String {
+  let mut ret = String::new();
+  for segment in segments {
+    match segment {
+      Segment::Text(text) => ret.push_str(text),
+      Segment::Section(var, body) if emit(var) => ret.push_str(body),
+      Segment::Section(..) => (),
+    }
+  }
+  ret
+}
+
+/// A fix template, optionally with conditional sections like `{{#EXTRA}}, $EXTRA{{/EXTRA}}`
+/// whose body is only emitted if the meta variable, e.g. an optional `$?EXTRA`, is captured.
+/// Only meta variables that the rule can capture start a section.
+enum FixTemplate<L: Language> {
+  Fixed(Pattern<L>),
+  Conditional(Vec<Segment>, L),
+}
+
+impl<L: Language> FixTemplate<L> {
+  fn try_new(template: &str, lang: &L, vars: &HashSet<String>) -> Result<Self, FixError> {
+    if !has_sections(template, vars) {
+      return Ok(Self::Fixed(Pattern::try_new(template, lang.clone())?));
+    }
+    let segments = parse_sections(template, vars)?;
+    // report an invalid template at rule parsing, not when a match is fixed
+    Pattern::try_new(&render_sections(&segments, |_| true), lang.clone())?;
+    Ok(Self::Conditional(segments, lang.clone()))
+  }
+
+  /// The template with the sections of captured meta variables, or `None` if it is invalid.
+  fn pattern(&self, env: &MetaVarEnv<L>) -> Option<Cow<'_, Pattern<L>>> {
+    let (segments, lang) = match self {
+      Self::Fixed(pattern) => return Some(Cow::Borrowed(pattern)),
+      Self::Conditional(segments, lang) => (segments, lang),
+    };
+    let captured = |var: &str| {
+      env.get_match(var).is_some()
+        || env.get_transformed(var).is_some()
+        || !env.get_multiple_matches(var).is_empty()
+    };
+    let template = render_sections(segments, captured);
+    Pattern::try_new(&template, lang.clone())
+      .ok()
+      .map(Cow::Owned)
+  }
+}
+
+enum FixTarget {
+  Match,
+  MetaVar(MetaVariableID),
+  FileStart,
+}
+
+struct FixEdit<L: Language> {
+  target: FixTarget,
+  template: FixTemplate<L>,
+}
+
+/// Edits of a fix list, planned together for each match.
+pub struct FixList<L: Language>(Vec<FixEdit<L>>);
+
+impl<L: Language> FixList<L> {
+  /// `vars` are the meta variables the rule can capture, see [`has_sections`].
+  pub fn try_new(
+    edits: Vec<SerializableFixEdit>,
+    lang: &L,
+    vars: &HashSet<String>,
+  ) -> Result<Self, FixError> {
+    let edits = edits
+      .into_iter()
+      .map(|edit| {
+        let target = match edit.target {
+          None => FixTarget::Match,
+          Some(t) if t == FILE_START => FixTarget::FileStart,
+          Some(t) => match lang.extract_meta_var(&t) {
+            Some(MetaVariable::Named(n, _)) => FixTarget::MetaVar(n),
+            _ => return Err(FixError::InvalidTarget(t)),
+          },
+        };
+        let template = FixTemplate::try_new(&edit.template, lang, vars)?;
+        Ok(FixEdit { target, template })
+      })
+      .collect::<Result<_, FixError>>()?;
+    Ok(Self(edits))
+  }
+
+  /// All edits for the match sorted by position. The fix is applied in full or not at all,
+  /// so `None` is returned if a target is not captured or two edits overlap.
+  pub fn plan(&self, nm: &NodeMatch<L>, matcher: &impl Matcher<L>) -> Option<Vec<Edit>> {
+    let mut edits = vec![];
+    for edit in &self.0 {
+      let template = edit.template.pattern(nm.get_env())?;
+      // the text can reference `$$MATCH` besides the captured meta variables
+      let text = || nm.replace_by(&*template).inserted_text;
+      let edit = match &edit.target {
+        FixTarget::Match => nm.replace(matcher, &*template)?,
+        FixTarget::MetaVar(var) => {
+          let range = nm.get_env().get_match(var)?.range();
+          Edit {
+            position: range.start,
+            deleted_length: range.len(),
+            inserted_text: text(),
+          }
+        }
+        FixTarget::FileStart => Edit {
+          position: 0,
+          deleted_length: 0,
+          inserted_text: format!("{}\n", text()),
+        },
+      };
+      edits.push(edit);
+    }
+    // insertions go before a replacement at the same position
+    edits.sort_by_key(|e| (e.position, e.deleted_length));
+    let overlapped = edits
+      .windows(2)
+      .any(|w| w[0].position + w[0].deleted_length > w[1].position);
+    (!overlapped).then_some(edits)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test::TypeScript;
+  use crate::{from_str, from_yaml_string, GlobalRules};
+
+  fn plan(fix: &str, src: &str) -> Option<String> {
+    let edits = from_str(fix).expect("should parse");
+    let vars = HashSet::from(["A".to_string()]);
+    let fixes = FixList::try_new(edits, &TypeScript::Tsx, &vars).expect("should work");
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let pattern = Pattern::new("console.log($A)", TypeScript::Tsx);
+    let nm = grep.root().find(&pattern).expect("should match");
+    let mut fixed = src.to_string();
+    for edit in fixes.plan(&nm, &pattern)?.into_iter().rev() {
+      let range = edit.position..edit.position + edit.deleted_length;
+      fixed.replace_range(range, &edit.inserted_text);
+    }
+    Some(fixed)
+  }
+
+  #[test]
+  fn test_plan_edits() {
+    let fix = "
+- { target: fileStart, template: \"import { log } from './log'\" }
+- { template: log($A) }";
+    let src = "let a = 1\nconsole.log(a)";
+    let expected = "import { log } from './log'\nlet a = 1\nlog(a)";
+    assert_eq!(plan(fix, src).as_deref(), Some(expected));
+    let fix = "[{ target: $A, template: String($A) }]";
+    assert_eq!(
+      plan(fix, src).as_deref(),
+      Some("let a = 1\nconsole.log(String(a))")
+    );
+  }
+
+  #[test]
+  fn test_overlapping_edits() {
+    let fix = "[{ template: log($A) }, { target: $A, template: b }]";
+    assert_eq!(plan(fix, "console.log(a)"), None);
+    let fix = "[{ target: $B, template: b }]";
+    assert_eq!(plan(fix, "console.log(a)"), None);
+  }
+
+  #[test]
+  fn test_invalid_target() {
+    let edits = from_str("[{ target: top, template: a }]").expect("should parse");
+    let ret = FixList::try_new(edits, &TypeScript::Tsx, &HashSet::new());
+    assert!(matches!(ret, Err(FixError::InvalidTarget(_))));
+  }
+
+  #[test]
+  fn test_conditional_section() {
+    let yaml = r"
+id: foo-to-bar
+message: test
+severity: hint
+language: Tsx
+rule: { pattern: 'foo($A, $?EXTRA)' }
+fix: bar($A{{#EXTRA}}, $EXTRA{{/EXTRA}})
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let rule = &rules[0];
+    assert!(rule.has_fix());
+    for (src, expected) in [("foo(a, b)", "bar(a, b)"), ("foo(a)", "bar(a)")] {
+      let grep = TypeScript::Tsx.ast_grep(src);
+      let nm = grep.root().find(&rule.matcher).expect("should match");
+      let edits = rule.fix_edits(&nm).expect("should fix");
+      assert_eq!(edits[0].inserted_text, expected);
+    }
+  }
+
+  #[test]
+  fn test_invalid_section() {
+    let vars = HashSet::from(["A".to_string(), "B".to_string()]);
+    for template in ["bar({{#A}}$A)", "bar({{#A}}{{#B}}$B{{/B}}{{/A}})"] {
+      let ret = FixTemplate::try_new(template, &TypeScript::Tsx, &vars);
+      assert!(matches!(ret, Err(FixError::Section(_))), "{template}");
+    }
+    // not a section without the closing braces or a captured meta variable
+    assert!(!has_sections("bar({{#A", &vars));
+    assert!(!has_sections("bar({{#C}}{{/C}})", &vars));
+  }
+
+  #[test]
+  fn test_handlebars_template() {
+    let yaml = r#"
+id: render
+message: test
+severity: hint
+language: Tsx
+rule: { pattern: 'show($A)' }
+fix: render($A, "{{#each xs}}{{/each}}{{#B}}")
+"#;
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let rule = &rules[0];
+    let grep = TypeScript::Tsx.ast_grep("show(a)");
+    let nm = grep.root().find(&rule.matcher).expect("should match");
+    let edits = rule.fix_edits(&nm).expect("should fix");
+    assert_eq!(
+      edits[0].inserted_text,
+      r#"render(a, "{{#each xs}}{{/each}}{{#B}}")"#
+    );
+  }
+}
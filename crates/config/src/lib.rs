@@ -1,50 +1,226 @@
+mod combined;
 mod constraints;
+mod cross_file;
 mod deserialize_env;
+mod fix;
 mod maybe;
 mod referent_rule;
 mod relational_rule;
+mod reporter;
 mod rule;
 mod rule_collection;
 mod rule_config;
+mod string_content;
+mod text_rule;
+mod transform;
+mod wrap;
 
-use serde::Deserialize;
-use serde_yaml::{with::singleton_map_recursive::deserialize, Deserializer, Error as YamlError};
+use serde::{Deserialize, Serialize};
+use serde_yaml::with::singleton_map_recursive::{deserialize, serialize};
+use serde_yaml::{Deserializer, Error as YamlError, Serializer, Value as YamlValue};
 
 use ast_grep_core::language::Language;
 
+use std::collections::HashMap;
+
+pub use combined::CombinedScan;
+pub use cross_file::SymbolIndex;
 pub use deserialize_env::DeserializeEnv;
 pub use referent_rule::GlobalRules;
+pub use reporter::Reporter;
 pub use rule::{deserialize_rule, Rule, RuleSerializeError, SerializableRule};
 pub use rule_collection::RuleCollection;
 pub use rule_config::{
-  try_deserialize_matchers, RuleConfig, RuleConfigError, RuleWithConstraint,
-  SerializableMetaVarMatcher, SerializableRuleConfig, Severity,
+  try_deserialize_matchers, CrossFileError, Escalate, FixError, RuleConfig, RuleConfigError,
+  RuleConstraint, RuleWithConstraint, SerializableConstraint, SerializableCount,
+  SerializableCrossFile, SerializableFix, SerializableFixEdit, SerializableMetaVarMatcher,
+  SerializableNumberRange, SerializableQuantifiedRule, SerializableRuleConfig,
+  SerializableRuleCore, SerializableTransformation, SerializableWrap, Severity, TransformError,
+  WrapError,
 };
+pub use text_rule::{SerializableTextRule, TextMatch, TextRule, TextRuleError};
 
 pub fn from_str<'de, T: Deserialize<'de>>(s: &'de str) -> Result<T, YamlError> {
   let deserializer = Deserializer::from_str(s);
   deserialize(deserializer)
 }
 
+pub fn to_string<T: Serialize>(value: &T) -> Result<String, YamlError> {
+  let mut buffer = vec![];
+  let mut serializer = Serializer::new(&mut buffer);
+  serialize(value, &mut serializer)?;
+  Ok(String::from_utf8(buffer).expect("yaml should be valid utf8"))
+}
+
+/// Parse rules from a YAML string. The string can contain multiple `---` separated documents,
+/// and each document can be either one rule or a list of rules.
+/// Errors are reported with the index of the invalid rule in the string.
 pub fn from_yaml_string<'a, L: Language + Deserialize<'a>>(
   yamls: &'a str,
   registration: &GlobalRules<L>,
 ) -> Result<Vec<RuleConfig<L>>, RuleConfigError> {
+  from_yaml_string_with_severity(yamls, registration, None)
+}
+
+/// Like `from_yaml_string`, but rules without `severity` use the default severity if given.
+pub fn from_yaml_string_with_severity<'a, L: Language + Deserialize<'a>>(
+  yamls: &'a str,
+  registration: &GlobalRules<L>,
+  default_severity: Option<Severity>,
+) -> Result<Vec<RuleConfig<L>>, RuleConfigError> {
+  let values = parse_yaml_values(yamls, default_severity, &ExtendBases::default())?;
   let mut ret = vec![];
+  for (index, value) in values.into_iter().enumerate() {
+    let rule = parse_rule_value(value, registration);
+    ret.push(rule.map_err(|e| RuleConfigError::InvalidRule(index, Box::new(e)))?);
+  }
+  Ok(ret)
+}
+
+/// Like `from_yaml_string_with_severity`, but rules with `language: all` are returned apart
+/// as [`TextRule`]s, which match the raw text of files in any language.
+/// `extends` can also name a rule in `bases`, e.g. one from another rule file.
+pub fn from_yaml_string_with_text_rules<'a, L: Language + Deserialize<'a>>(
+  yamls: &'a str,
+  registration: &GlobalRules<L>,
+  default_severity: Option<Severity>,
+  bases: &ExtendBases,
+) -> Result<(Vec<RuleConfig<L>>, Vec<TextRule>), RuleConfigError> {
+  let values = parse_yaml_values(yamls, default_severity, bases)?;
+  let mut rules = vec![];
+  let mut text_rules = vec![];
+  for (index, value) in values.into_iter().enumerate() {
+    let invalid = |e| RuleConfigError::InvalidRule(index, Box::new(e));
+    if value.get("language").and_then(YamlValue::as_str) == Some("all") {
+      text_rules.push(parse_text_rule_value(value).map_err(invalid)?);
+    } else {
+      rules.push(parse_rule_value(value, registration).map_err(invalid)?);
+    }
+  }
+  Ok((rules, text_rules))
+}
+
+/// Rules that `extends` can name besides those in the same YAML string,
+/// e.g. all rules in the rule directories. Rules in the same string take precedence.
+#[derive(Default)]
+pub struct ExtendBases(HashMap<String, YamlValue>);
+
+impl ExtendBases {
+  /// Add the rules of a YAML string in the format of `from_yaml_string`.
+  /// The first rule added with an id is kept.
+  pub fn add_yaml(&mut self, yamls: &str) -> Result<(), RuleConfigError> {
+    for value in split_yaml_values(yamls)? {
+      if let Some(id) = value.get("id").and_then(YamlValue::as_str) {
+        self.0.entry(id.to_string()).or_insert(value);
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Split the YAML string into one value per rule.
+fn split_yaml_values(yamls: &str) -> Result<Vec<YamlValue>, RuleConfigError> {
+  let mut values = vec![];
   for yaml in Deserializer::from_str(yamls) {
-    let config = RuleConfig::deserialize(yaml, registration)?;
-    ret.push(config);
+    match YamlValue::deserialize(yaml)? {
+      YamlValue::Sequence(seq) => values.extend(seq),
+      value => values.push(value),
+    }
+  }
+  Ok(values)
+}
+
+/// Split the YAML string into one value per rule, with `extends` and the default severity resolved.
+fn parse_yaml_values(
+  yamls: &str,
+  default_severity: Option<Severity>,
+  bases: &ExtendBases,
+) -> Result<Vec<YamlValue>, RuleConfigError> {
+  let values = split_yaml_values(yamls)?;
+  let ids: HashMap<_, _> = values
+    .iter()
+    .filter_map(|v| Some((v.get("id")?.as_str()?, v)))
+    .collect();
+  let find = |id: &str| ids.get(id).copied().or_else(|| bases.0.get(id));
+  let mut ret = vec![];
+  for (index, value) in values.iter().enumerate() {
+    let invalid = |e| RuleConfigError::InvalidRule(index, Box::new(e));
+    let mut value = resolve_extends(value, &find, &mut vec![]).map_err(invalid)?;
+    if let (Some(severity), YamlValue::Mapping(map)) = (&default_severity, &mut value) {
+      if !map.contains_key("severity") {
+        map.insert("severity".into(), serde_yaml::to_value(severity)?);
+      }
+    }
+    ret.push(value);
   }
   Ok(ret)
 }
+
+/// A rule with `extends: base-id` starts from the base rule found by `find`,
+/// and each field the rule specifies overrides the inherited one.
+/// `extending` has the ids of the rules being resolved, to detect cycles.
+fn resolve_extends<'v>(
+  value: &YamlValue,
+  find: &impl Fn(&str) -> Option<&'v YamlValue>,
+  extending: &mut Vec<String>,
+) -> Result<YamlValue, RuleConfigError> {
+  let (YamlValue::Mapping(map), Some(base)) = (value, value.get("extends")) else {
+    return Ok(value.clone());
+  };
+  let base_id = base.as_str().unwrap_or_default();
+  let Some(base_value) = find(base_id) else {
+    return Err(RuleConfigError::UnknownBase(base_id.to_string()));
+  };
+  let id = value
+    .get("id")
+    .and_then(YamlValue::as_str)
+    .unwrap_or_default();
+  extending.push(id.to_string());
+  if extending.iter().any(|id| id == base_id) {
+    return Err(RuleConfigError::CyclicExtends(base_id.to_string()));
+  }
+  let mut merged = match resolve_extends(base_value, find, extending)? {
+    YamlValue::Mapping(base) => base,
+    _ => unreachable!("rule with an id must be a mapping"),
+  };
+  extending.pop();
+  for (key, value) in map {
+    if key != "extends" {
+      merged.insert(key.clone(), value.clone());
+    }
+  }
+  Ok(YamlValue::Mapping(merged))
+}
+
+/// A rule with `language: all` checks the raw text of files, so its rule can only be a `regex`.
+fn parse_text_rule_value(value: YamlValue) -> Result<TextRule, RuleConfigError> {
+  let is_regex = match value.get("rule") {
+    Some(YamlValue::Mapping(rule)) => rule.len() == 1 && rule.contains_key("regex"),
+    _ => false,
+  };
+  if !is_regex {
+    return Err(RuleConfigError::AllLanguages);
+  }
+  let inner: SerializableTextRule = deserialize(value)?;
+  Ok(TextRule::try_new(inner)?)
+}
+
+fn parse_rule_value<'a, L: Language + Deserialize<'a>>(
+  value: YamlValue,
+  registration: &GlobalRules<L>,
+) -> Result<RuleConfig<L>, RuleConfigError> {
+  let inner: SerializableRuleConfig<L> = deserialize(value)?;
+  RuleConfig::try_from(inner, registration)
+}
+
 #[cfg(test)]
 mod test {
 
   use super::*;
-  use ast_grep_core::language::TSLanguage;
+  use ast_grep_core::language::{Precedence, StringEscape, TSLanguage};
   use std::path::Path;
 
-  #[derive(Clone, Deserialize, PartialEq, Eq)]
+  #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
   pub enum TypeScript {
     Tsx,
   }
@@ -55,6 +231,23 @@ mod test {
     fn from_path<P: AsRef<Path>>(_path: P) -> Option<Self> {
       Some(TypeScript::Tsx)
     }
+    fn string_escape(&self) -> StringEscape {
+      StringEscape::JavaScript
+    }
+    // a subset of the real table in ast-grep-language, enough for testing wrap
+    fn precedence(&self, kind: &str, operator: Option<&str>) -> Option<Precedence> {
+      let precedence = match (kind, operator) {
+        ("ternary_expression", _) => Precedence::right(3),
+        ("binary_expression", Some("||")) => Precedence::left(4),
+        ("binary_expression", Some("&&")) => Precedence::left(5),
+        ("binary_expression", Some("+" | "-")) => Precedence::left(12),
+        ("binary_expression", Some("**")) => Precedence::right(14),
+        ("unary_expression" | "await_expression", _) => Precedence::right(15),
+        ("call_expression" | "member_expression", _) => Precedence::left(17),
+        _ => return None,
+      };
+      Some(precedence)
+    }
   }
 
   fn test_rule_match(yaml: &str, source: &str) {
@@ -190,4 +383,246 @@ constraints:
     test_rule_match(yaml, "function test() { console.log(2) }");
     test_rule_unmatch(yaml, "function tt() { console.log(2) }");
   }
+
+  #[test]
+  fn test_deserialize_relational_constraints() {
+    let yaml = r"
+id: test
+message: test rule
+severity: info
+language: Tsx
+rule:
+  pattern: foo($FN)
+constraints:
+  FN:
+    kind: arrow_function
+    has:
+      kind: return_statement
+      stopBy: end
+";
+    test_rule_match(yaml, "foo(() => { if (a) { return 1 } })");
+    test_rule_unmatch(yaml, "foo(() => { bar() })");
+    test_rule_unmatch(yaml, "foo(function () { return 1 })");
+  }
+
+  #[test]
+  fn test_multiple_rules() {
+    let yaml = r"
+id: a
+message: test
+severity: info
+language: Tsx
+rule: { pattern: a }
+---
+- id: b
+  message: test
+  severity: info
+  language: Tsx
+  rule: { pattern: b }
+- id: c
+  message: test
+  severity: info
+  language: Tsx
+  rule: { pattern: c }
+";
+    let globals = GlobalRules::default();
+    let configs = from_yaml_string::<TypeScript>(yaml, &globals).expect("rules should parse");
+    let ids: Vec<_> = configs.iter().map(|c| c.id.as_str()).collect();
+    assert_eq!(ids, ["a", "b", "c"]);
+    for (config, src) in configs.iter().zip(["a", "b", "c"]) {
+      let grep = config.language.ast_grep(src);
+      assert!(grep.root().find(&config.matcher).is_some());
+    }
+  }
+
+  #[test]
+  fn test_multiple_rules_error_index() {
+    let yaml = r"
+- id: a
+  message: test
+  severity: info
+  language: Tsx
+  rule: { pattern: a }
+- id: b
+  message: test
+  severity: info
+  language: Tsx
+  rule: { kind: not_a_kind }
+";
+    let globals = GlobalRules::default();
+    let error = match from_yaml_string::<TypeScript>(yaml, &globals) {
+      Err(e) => e,
+      Ok(_) => panic!("rule should fail"),
+    };
+    assert!(matches!(error, RuleConfigError::InvalidRule(1, _)));
+  }
+
+  #[test]
+  fn test_default_severity() {
+    let yaml = r"
+- { id: a, message: test, language: Tsx, rule: { pattern: a } }
+- { id: b, message: test, severity: error, language: Tsx, rule: { pattern: b } }
+";
+    let globals = GlobalRules::default();
+    assert!(from_yaml_string::<TypeScript>(yaml, &globals).is_err());
+    let configs =
+      from_yaml_string_with_severity::<TypeScript>(yaml, &globals, Some(Severity::Hint))
+        .expect("rules should parse");
+    assert!(matches!(configs[0].severity, Severity::Hint));
+    assert!(matches!(configs[1].severity, Severity::Error));
+  }
+
+  #[test]
+  fn test_rule_metadata() {
+    let yaml = r"
+id: test
+message: test
+severity: info
+language: Tsx
+rule: { pattern: a }
+metadata:
+  owner: team-a
+  tickets: [A-1, A-2]
+  priority: 2
+";
+    let globals = GlobalRules::default();
+    let rule = from_yaml_string::<TypeScript>(yaml, &globals)
+      .expect("should parse")
+      .pop()
+      .unwrap();
+    let metadata = rule.metadata.as_ref().expect("should have metadata");
+    assert_eq!(metadata["owner"], "team-a");
+    assert_eq!(metadata["tickets"][1], "A-2");
+    assert_eq!(metadata["priority"], 2);
+    let invalid = yaml.replace("  owner: team-a", "  [owner]: team-a");
+    assert!(from_yaml_string::<TypeScript>(&invalid, &globals).is_err());
+    let invalid = format!("{}metadata: 123", &yaml[..yaml.find("metadata").unwrap()]);
+    assert!(from_yaml_string::<TypeScript>(&invalid, &globals).is_err());
+  }
+
+  #[test]
+  fn test_all_languages() {
+    let yaml = r"
+- { id: a, message: test, severity: info, language: Tsx, rule: { pattern: a } }
+- { id: fixme, message: test, language: all, rule: { regex: FIXME } }
+";
+    let globals = GlobalRules::default();
+    let bases = ExtendBases::default();
+    let (rules, text_rules) = from_yaml_string_with_text_rules::<TypeScript>(
+      yaml,
+      &globals,
+      Some(Severity::Warning),
+      &bases,
+    )
+    .expect("rules should parse");
+    assert_eq!(rules.len(), 1);
+    assert_eq!(text_rules.len(), 1);
+    assert!(matches!(text_rules[0].severity, Severity::Warning));
+    let src = "let a = 1 // FIXME\nlet b = '/FIXME/' + c";
+    let found: Vec<_> = text_rules[0]
+      .find_all(src)
+      .map(|m| (m.start_pos(), m.text()))
+      .collect();
+    assert_eq!(found, [((0, 13), "FIXME"), ((1, 10), "FIXME")]);
+    assert!(from_yaml_string::<TypeScript>(yaml, &globals).is_err());
+  }
+
+  #[test]
+  fn test_all_languages_only_regex() {
+    let yaml = r"
+- { id: a, message: test, severity: info, language: Tsx, rule: { pattern: a } }
+- { id: b, message: test, severity: info, language: all, rule: { pattern: a } }
+";
+    let globals = GlobalRules::default();
+    let bases = ExtendBases::default();
+    let ret = from_yaml_string_with_text_rules::<TypeScript>(yaml, &globals, None, &bases);
+    assert!(matches!(
+      ret,
+      Err(RuleConfigError::InvalidRule(1, e)) if matches!(*e, RuleConfigError::AllLanguages)
+    ));
+    let yaml = "{ id: b, message: test, severity: info, language: all, rule: { regex: '(' } }";
+    let ret = from_yaml_string_with_text_rules::<TypeScript>(yaml, &globals, None, &bases);
+    assert!(matches!(
+      ret,
+      Err(RuleConfigError::InvalidRule(0, e)) if matches!(*e, RuleConfigError::TextRule(_))
+    ));
+  }
+
+  #[test]
+  fn test_extends() {
+    let yaml = r"
+id: no-log
+message: no log
+severity: warning
+language: Tsx
+rule: { pattern: console.log($A) }
+constraints: { A: { regex: ^a } }
+fix: log($A)
+---
+id: no-log-debug
+extends: no-log
+severity: info
+fix: debug($A)
+";
+    let globals = GlobalRules::default();
+    let configs = from_yaml_string::<TypeScript>(yaml, &globals).expect("rules should parse");
+    let child = &configs[1];
+    assert_eq!(child.id, "no-log-debug");
+    assert_eq!(child.message, "no log");
+    assert!(matches!(child.severity, Severity::Info));
+    assert!(matches!(&child.fix, Some(SerializableFix::Str(fix)) if fix == "debug($A)"));
+    let grep = TypeScript::Tsx.ast_grep("console.log(a); console.log(b)");
+    let found: Vec<_> = grep
+      .root()
+      .find_all(&child.matcher)
+      .map(|n| n.text().to_string())
+      .collect();
+    assert_eq!(found, ["console.log(a)"]);
+  }
+
+  #[test]
+  fn test_cyclic_extends() {
+    let yaml = r"
+- { id: a, extends: b, message: test, severity: info, language: Tsx, rule: { pattern: a } }
+- { id: b, extends: a }
+";
+    let globals = GlobalRules::default();
+    let ret = from_yaml_string::<TypeScript>(yaml, &globals);
+    assert!(matches!(
+      ret,
+      Err(RuleConfigError::InvalidRule(0, e)) if matches!(*e, RuleConfigError::CyclicExtends(_))
+    ));
+    let yaml = "{ id: a, extends: c }";
+    let ret = from_yaml_string::<TypeScript>(yaml, &globals);
+    assert!(matches!(
+      ret,
+      Err(RuleConfigError::InvalidRule(0, e)) if matches!(*e, RuleConfigError::UnknownBase(_))
+    ));
+  }
+
+  #[test]
+  fn test_extends_bases() {
+    let base = "{ id: no-log, message: no log, severity: warning, language: Tsx, rule: { pattern: console.log($A) } }";
+    let child = "{ id: no-log-info, extends: no-log, severity: info }";
+    let globals = GlobalRules::default();
+    let mut bases = ExtendBases::default();
+    bases.add_yaml(base).unwrap();
+    bases.add_yaml(child).unwrap();
+    let (rules, _) = from_yaml_string_with_text_rules::<TypeScript>(child, &globals, None, &bases)
+      .expect("should find base in other string");
+    assert_eq!(rules[0].message, "no log");
+    assert!(matches!(rules[0].severity, Severity::Info));
+    // a cycle through two strings
+    let a =
+      "{ id: a, extends: b, message: test, severity: info, language: Tsx, rule: { pattern: a } }";
+    let b = "{ id: b, extends: a }";
+    let mut bases = ExtendBases::default();
+    bases.add_yaml(a).unwrap();
+    bases.add_yaml(b).unwrap();
+    let ret = from_yaml_string_with_text_rules::<TypeScript>(b, &globals, None, &bases);
+    assert!(matches!(
+      ret,
+      Err(RuleConfigError::InvalidRule(0, e)) if matches!(*e, RuleConfigError::CyclicExtends(_))
+    ));
+  }
 }
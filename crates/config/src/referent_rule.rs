@@ -1,6 +1,7 @@
 use crate::{Rule, RuleWithConstraint};
 
 use ast_grep_core::language::Language;
+use ast_grep_core::matcher::MatchTrace;
 use ast_grep_core::meta_var::MetaVarEnv;
 use ast_grep_core::{Matcher, Node};
 
@@ -185,6 +186,15 @@ impl<L: Language> Matcher<L> for ReferentRule<L> {
       })
       .flatten()
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let label = format!("matches: {}", self.rule_id);
+    let inner = self
+      .eval_local(|r| r.explain(node.clone()))
+      .or_else(|| self.eval_global(|r| r.explain(node.clone())));
+    let trace = MatchTrace::from_match(label, self.match_node(node));
+    trace.with_children(inner.into_iter().collect())
+  }
 }
 
 #[cfg(test)]
@@ -0,0 +1,204 @@
+use crate::deserialize_env::DeserializeEnv;
+use crate::rule::{deserialize_rule, Rule, RuleSerializeError, SerializableRule};
+use ast_grep_core::language::Language;
+use ast_grep_core::matcher::{KindMatcher, MatchTrace};
+use ast_grep_core::meta_var::MetaVarEnv;
+use ast_grep_core::{Matcher, Node};
+
+use bit_set::BitSet;
+use serde::{Deserialize, Serialize};
+
+/// Match a JSX element by its tag name and attributes.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SerializableJsx {
+  /// Tag name as written, e.g. `img` or `Foo.Bar`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tag: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub has_attribute: Option<SerializableAttribute>,
+  /// Elements with spread attributes are never missing an attribute,
+  /// since the spread may provide it.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub missing_attribute: Option<String>,
+}
+
+impl SerializableJsx {
+  /// Apply `f` to the attribute value rule if present.
+  pub(crate) fn map_rules(&mut self, f: impl FnOnce(SerializableRule) -> SerializableRule) {
+    if let Some(SerializableAttribute::WithValue { value, .. }) = &mut self.has_attribute {
+      **value = f(std::mem::take(&mut **value));
+    }
+  }
+}
+
+/// An attribute name, or a name with a rule for the attribute value.
+/// Values in braces like `{1}` are matched without the braces.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum SerializableAttribute {
+  Name(String),
+  WithValue {
+    name: String,
+    value: Box<SerializableRule>,
+  },
+}
+
+pub struct Jsx<L: Language> {
+  elements: [KindMatcher<L>; 2],
+  tag: Option<String>,
+  has_attribute: Option<(String, Option<Rule<L>>)>,
+  missing_attribute: Option<String>,
+}
+
+impl<L: Language> Jsx<L> {
+  pub fn try_new(
+    jsx: SerializableJsx,
+    env: &DeserializeEnv<L>,
+  ) -> Result<Self, RuleSerializeError> {
+    let elements = [
+      KindMatcher::try_new("jsx_element", env.lang.clone())?,
+      KindMatcher::try_new("jsx_self_closing_element", env.lang.clone())?,
+    ];
+    let has_attribute = match jsx.has_attribute {
+      None => None,
+      Some(SerializableAttribute::Name(name)) => Some((name, None)),
+      Some(SerializableAttribute::WithValue { name, value }) => {
+        Some((name, Some(deserialize_rule(*value, env)?)))
+      }
+    };
+    Ok(Self {
+      elements,
+      tag: jsx.tag,
+      has_attribute,
+      missing_attribute: jsx.missing_attribute,
+    })
+  }
+}
+
+/// The node holding the tag name and attributes of a JSX element.
+fn opening_element<'tree, L: Language>(node: &Node<'tree, L>) -> Option<Node<'tree, L>> {
+  match &*node.kind() {
+    "jsx_self_closing_element" => Some(node.clone()),
+    "jsx_element" => node.field("open_tag"),
+    _ => None,
+  }
+}
+
+fn attribute_name<L: Language>(attr: &Node<L>) -> Option<String> {
+  if attr.kind() != "jsx_attribute" {
+    return None;
+  }
+  Some(attr.child(0)?.text().to_string())
+}
+
+/// The value of an attribute without braces. None for attributes like `<input disabled />`.
+fn attribute_value<'tree, L: Language>(attr: &Node<'tree, L>) -> Option<Node<'tree, L>> {
+  let value = attr.children().filter(Node::is_named).nth(1)?;
+  if value.kind() == "jsx_expression" {
+    value.children().find(Node::is_named)
+  } else {
+    Some(value)
+  }
+}
+
+impl<L: Language> Matcher<L> for Jsx<L> {
+  fn match_node_with_env<'tree>(
+    &self,
+    node: Node<'tree, L>,
+    env: &mut MetaVarEnv<'tree, L>,
+  ) -> Option<Node<'tree, L>> {
+    let opening = opening_element(&node)?;
+    if let Some(tag) = &self.tag {
+      if opening.field("name")?.text() != tag.as_str() {
+        return None;
+      }
+    }
+    let attributes: Vec<_> = opening.field_children("attribute").collect();
+    if let Some(missing) = &self.missing_attribute {
+      let has_spread = attributes.iter().any(|a| a.kind() == "jsx_expression");
+      let found = attributes
+        .iter()
+        .any(|a| attribute_name(a).as_ref() == Some(missing));
+      if has_spread || found {
+        return None;
+      }
+    }
+    if let Some((name, value)) = &self.has_attribute {
+      let mut named = attributes
+        .iter()
+        .filter(|a| attribute_name(a).as_ref() == Some(name));
+      let found = match value {
+        None => named.next().is_some(),
+        Some(rule) => named.any(|a| {
+          attribute_value(a)
+            .and_then(|v| rule.match_node_with_env(v, env))
+            .is_some()
+        }),
+      };
+      if !found {
+        return None;
+      }
+    }
+    Some(node)
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    let mut kinds = BitSet::new();
+    for element in &self.elements {
+      kinds.union_with(&element.potential_kinds()?);
+    }
+    Some(kinds)
+  }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    MatchTrace::from_match("jsx", self.match_node(node))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test::TypeScript as TS;
+  use crate::{from_str, DeserializeEnv};
+
+  fn find_all(rule: &str, src: &str) -> Vec<String> {
+    let jsx: SerializableJsx = from_str(rule).expect("cannot parse rule");
+    let env = DeserializeEnv::new(TS::Tsx);
+    let jsx = Jsx::try_new(jsx, &env).expect("should deserialize");
+    let grep = TS::Tsx.ast_grep(src);
+    let found = grep.root().find_all(jsx).map(|n| n.text().to_string());
+    found.collect()
+  }
+
+  #[test]
+  fn test_missing_attribute() {
+    let rule = "{ tag: img, missingAttribute: alt }";
+    let src = r#"<div><img src="a.png" /><img src="b.png" alt="b" /><video src="c" /></div>"#;
+    assert_eq!(find_all(rule, src), [r#"<img src="a.png" />"#]);
+    let src = r#"<><img {...props} /><img></img></>"#;
+    assert_eq!(find_all(rule, src), ["<img></img>"]);
+  }
+
+  #[test]
+  fn test_has_attribute() {
+    let rule = "{ hasAttribute: onClick }";
+    let src = "<><a onClick={f}>x</a><b /><Foo.Bar onClick /></>";
+    assert_eq!(
+      find_all(rule, src),
+      ["<a onClick={f}>x</a>", "<Foo.Bar onClick />"]
+    );
+    let rule = "{ tag: Foo.Bar, hasAttribute: onClick }";
+    assert_eq!(find_all(rule, src), ["<Foo.Bar onClick />"]);
+  }
+
+  #[test]
+  fn test_attribute_value() {
+    let rule = "{ hasAttribute: { name: tabIndex, value: { regex: '[1-9]' } } }";
+    let src = r#"<><a tabIndex={1} /><b tabIndex={0} /><c tabIndex="2" /><d tabIndex /></>"#;
+    assert_eq!(
+      find_all(rule, src),
+      ["<a tabIndex={1} />", r#"<c tabIndex="2" />"#]
+    );
+  }
+}
@@ -1,12 +1,16 @@
+mod jsx;
 mod stop_by;
 
 use crate::deserialize_env::DeserializeEnv;
 use crate::rule::{deserialize_rule, Rule, RuleSerializeError, SerializableRule};
 use ast_grep_core::language::Language;
+use ast_grep_core::matcher::MatchTrace;
 use ast_grep_core::meta_var::MetaVarEnv;
 use ast_grep_core::{Matcher, Node};
 use stop_by::{SerializableStopBy, StopBy};
 
+pub use jsx::{Jsx, SerializableJsx};
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -19,6 +23,31 @@ pub struct Relation {
   pub field: Option<String>,
 }
 
+impl Relation {
+  /// Apply `f` to every sub-rule in the relation, including the `stopBy` rule if present.
+  pub(crate) fn map_rules(&mut self, mut f: impl FnMut(SerializableRule) -> SerializableRule) {
+    self.rule = f(std::mem::take(&mut self.rule));
+    if let SerializableStopBy::Rule(stop) = &mut self.stop_by {
+      *stop = f(std::mem::take(stop));
+    }
+  }
+}
+
+/// Trace a relational rule. The inner rule is explained against the related node if found.
+fn explain_relation<L: Language, M: Matcher<L>>(
+  label: &str,
+  relation: &M,
+  inner: &Rule<L>,
+  node: Node<L>,
+) -> MatchTrace {
+  let found = relation.match_node(node);
+  let children = found
+    .as_ref()
+    .map(|nm| vec![inner.explain(nm.get_node().clone())])
+    .unwrap_or_default();
+  MatchTrace::from_match(label, found).with_children(children)
+}
+
 pub struct Inside<L: Language> {
   outer: Rule<L>,
   field: Option<String>,
@@ -59,6 +88,10 @@ impl<L: Language> Matcher<L> for Inside<L> {
       self.stop_by.find(ancestors, finder)
     }
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    explain_relation("inside", self, &self.outer, node)
+  }
 }
 
 // NOTE: Has is different from other relational rules
@@ -126,6 +159,10 @@ impl<L: Language> Matcher<L> for Has<L> {
       }
     }
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    explain_relation("has", self, &self.inner, node)
+  }
 }
 
 pub struct Precedes<L: Language> {
@@ -153,6 +190,10 @@ impl<L: Language> Matcher<L> for Precedes<L> {
     let finder = |n| self.later.match_node_with_env(n, env);
     self.stop_by.find(next_all, finder)
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    explain_relation("precedes", self, &self.later, node)
+  }
 }
 
 pub struct Follows<L: Language> {
@@ -180,6 +221,223 @@ impl<L: Language> Matcher<L> for Follows<L> {
     let finder = |n| self.former.match_node_with_env(n, env);
     self.stop_by.find(prev_all, finder)
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    explain_relation("follows", self, &self.former, node)
+  }
+}
+
+/// Sub-rules that must each match a distinct child, in any order.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AnyOrderRelation {
+  pub rules: Vec<SerializableRule>,
+  /// `neighbor` checks direct children. `end` or a rule searches descendants like `has`.
+  #[serde(default)]
+  pub stop_by: SerializableStopBy,
+}
+
+impl AnyOrderRelation {
+  /// Apply `f` to every sub-rule, including the `stopBy` rule if present.
+  pub(crate) fn map_rules(&mut self, mut f: impl FnMut(SerializableRule) -> SerializableRule) {
+    self.rules = std::mem::take(&mut self.rules)
+      .into_iter()
+      .map(&mut f)
+      .collect();
+    if let SerializableStopBy::Rule(stop) = &mut self.stop_by {
+      *stop = f(std::mem::take(stop));
+    }
+  }
+}
+
+/// Match a node whose children can be assigned to sub-rules one to one.
+/// Unlike several `has` rules, two sub-rules can never be satisfied by the same child.
+pub struct AnyOrder<L: Language> {
+  rules: Vec<Rule<L>>,
+  stop_by: StopBy<L>,
+}
+
+impl<L: Language> AnyOrder<L> {
+  pub fn try_new(
+    relation: AnyOrderRelation,
+    env: &DeserializeEnv<L>,
+  ) -> Result<Self, RuleSerializeError> {
+    let mut rules = Vec::with_capacity(relation.rules.len());
+    for rule in relation.rules {
+      rules.push(deserialize_rule(rule, env)?);
+    }
+    Ok(Self {
+      rules,
+      stop_by: StopBy::try_from(relation.stop_by, env)?,
+    })
+  }
+
+  fn candidates<'tree>(&self, node: Node<'tree, L>) -> Vec<Node<'tree, L>> {
+    match &self.stop_by {
+      StopBy::Neighbor => node.children().collect(),
+      StopBy::End => node.dfs().skip(1).collect(),
+      StopBy::Rule(stop) => {
+        let mut ret = vec![];
+        let mut stack: Vec<_> = node.children().collect();
+        stack.reverse();
+        while let Some(n) = stack.pop() {
+          ret.push(n.clone());
+          // like `has`, descendants of a stop node are not searched
+          if !n.matches(stop) {
+            let len = stack.len();
+            stack.extend(n.children());
+            stack[len..].reverse();
+          }
+        }
+        ret
+      }
+    }
+  }
+
+  /// Find for each sub-rule a distinct candidate via bipartite matching.
+  /// Sub-rules are tested against candidates independently of each other's captures.
+  fn assign<'tree>(
+    &self,
+    candidates: &[Node<'tree, L>],
+    env: &MetaVarEnv<'tree, L>,
+  ) -> Option<Vec<usize>> {
+    let accepts: Vec<Vec<usize>> = self
+      .rules
+      .iter()
+      .map(|rule| {
+        let matches = |n: &Node<'tree, L>| {
+          let mut env = env.clone();
+          rule.match_node_with_env(n.clone(), &mut env).is_some()
+        };
+        (0..candidates.len())
+          .filter(|&i| matches(&candidates[i]))
+          .collect()
+      })
+      .collect();
+    let mut owner = vec![None; candidates.len()];
+    for rule in 0..self.rules.len() {
+      let mut visited = vec![false; candidates.len()];
+      if !augment(rule, &accepts, &mut owner, &mut visited) {
+        return None;
+      }
+    }
+    let mut assigned = vec![0; self.rules.len()];
+    for (candidate, rule) in owner.into_iter().enumerate() {
+      if let Some(rule) = rule {
+        assigned[rule] = candidate;
+      }
+    }
+    Some(assigned)
+  }
+}
+
+/// Find an augmenting path from `rule` in the bipartite graph, reassigning candidates on the way.
+fn augment(
+  rule: usize,
+  accepts: &[Vec<usize>],
+  owner: &mut [Option<usize>],
+  visited: &mut [bool],
+) -> bool {
+  for &candidate in &accepts[rule] {
+    if visited[candidate] {
+      continue;
+    }
+    visited[candidate] = true;
+    let free = match owner[candidate] {
+      None => true,
+      Some(other) => augment(other, accepts, owner, visited),
+    };
+    if free {
+      owner[candidate] = Some(rule);
+      return true;
+    }
+  }
+  false
+}
+
+impl<L: Language> Matcher<L> for AnyOrder<L> {
+  fn match_node_with_env<'tree>(
+    &self,
+    node: Node<'tree, L>,
+    env: &mut MetaVarEnv<'tree, L>,
+  ) -> Option<Node<'tree, L>> {
+    let candidates = self.candidates(node.clone());
+    let assigned = self.assign(&candidates, env)?;
+    // captures are merged afterwards so conflicting meta variables fail the match
+    for (rule, candidate) in self.rules.iter().zip(assigned) {
+      let matched = rule.match_node_with_env(candidates[candidate].clone(), env)?;
+      env.add_label("secondary", matched);
+    }
+    Some(node)
+  }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let candidates = self.candidates(node.clone());
+    let Some(assigned) = self.assign(&candidates, &MetaVarEnv::new()) else {
+      return MatchTrace::new("anyOrder", false);
+    };
+    let children = self
+      .rules
+      .iter()
+      .zip(assigned)
+      .map(|(rule, candidate)| rule.explain(candidates[candidate].clone()))
+      .collect();
+    MatchTrace::from_match("anyOrder", self.match_node(node)).with_children(children)
+  }
+}
+
+/// Node kinds of decorators and attributes, e.g. `@deprecated` in TS/Python or `#[test]` in Rust.
+const DECORATOR_KINDS: &[&str] = &["decorator", "attribute_item"];
+
+fn is_decorator<L: Language>(node: &Node<L>) -> bool {
+  DECORATOR_KINDS.contains(&&*node.kind())
+}
+
+/// Decorators attached to the node. They are children of TS classes, preceding siblings of
+/// TS methods and Rust items, and children of the wrapping `decorated_definition` in Python.
+fn decorators<'tree, L: Language>(node: &Node<'tree, L>) -> Vec<Node<'tree, L>> {
+  let mut ret: Vec<_> = node.children().filter(is_decorator).collect();
+  let mut prev = node.prev_named_sibling();
+  while let Some(p) = prev.filter(is_decorator) {
+    prev = p.prev_named_sibling();
+    ret.push(p);
+  }
+  if let Some(parent) = node.parent().filter(|p| p.kind() == "decorated_definition") {
+    ret.extend(parent.children().filter(is_decorator));
+  }
+  ret
+}
+
+/// Match a node with a decorator or attribute satisfying the inner rule.
+pub struct HasDecorator<L: Language> {
+  decorator: Rule<L>,
+}
+
+impl<L: Language> HasDecorator<L> {
+  pub fn try_new(
+    rule: SerializableRule,
+    env: &DeserializeEnv<L>,
+  ) -> Result<Self, RuleSerializeError> {
+    Ok(Self {
+      decorator: deserialize_rule(rule, env)?,
+    })
+  }
+}
+
+impl<L: Language> Matcher<L> for HasDecorator<L> {
+  fn match_node_with_env<'tree>(
+    &self,
+    node: Node<'tree, L>,
+    env: &mut MetaVarEnv<'tree, L>,
+  ) -> Option<Node<'tree, L>> {
+    decorators(&node)
+      .into_iter()
+      .find_map(|d| self.decorator.match_node_with_env(d, env))
+  }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    explain_relation("hasDecorator", self, &self.decorator, node)
+  }
 }
 
 #[cfg(test)]
@@ -571,4 +829,81 @@ mod test {
     test_found(&["for (;a = 1;) {}"], &rule);
     test_not_found(&["for (;; a = 1) {}", "for (;;) { a = 1}"], &rule);
   }
+
+  fn any_order(rules: Vec<Rule<TS>>, stop_by: StopBy<TS>) -> Rule<TS> {
+    Rule::AnyOrder(Box::new(AnyOrder { rules, stop_by }))
+  }
+
+  fn pairs(pairs: &[&str]) -> Vec<Rule<TS>> {
+    let pair = |p| Pattern::contextual(&format!("a = {{ {p} }}"), "pair", TS::Tsx);
+    pairs
+      .iter()
+      .map(|p| Rule::Pattern(pair(p).expect("should parse")))
+      .collect()
+  }
+
+  #[test]
+  fn test_any_order() {
+    let rule = o::All::new(vec![
+      Rule::Kind(KindMatcher::new("object", TS::Tsx)),
+      any_order(pairs(&["id: $ID", "name: $NAME"]), StopBy::Neighbor),
+    ]);
+    test_found(
+      &[
+        "a = { id: 1, name: 'a' }",
+        "a = { name: 'a', id: 1 }",
+        "a = { name: 'a', other: 2, id: 1 }",
+      ],
+      &rule,
+    );
+    test_not_found(
+      &[
+        "a = { id: 1 }",
+        "a = { name: 'a' }",
+        "a = { id: 1, nested: { name: 'a' } }",
+      ],
+      &rule,
+    );
+  }
+
+  #[test]
+  fn test_any_order_distinct_nodes() {
+    // both sub-rules accept `id: 1`, but they must be satisfied by different pairs
+    let rule = o::All::new(vec![
+      Rule::Kind(KindMatcher::new("object", TS::Tsx)),
+      any_order(pairs(&["id: $A", "$KEY: 1"]), StopBy::Neighbor),
+    ]);
+    test_not_found(&["a = { id: 1 }"], &rule);
+    test_found(&["a = { id: 1, b: 1 }", "a = { b: 1, id: 2 }"], &rule);
+    test_not_found(&["a = { id: 1, b: 2 }"], &rule);
+  }
+
+  #[test]
+  fn test_any_order_descendants() {
+    let rule = make_rule(
+      "function test() { $$$ }",
+      any_order(
+        vec![
+          Rule::Pattern(Pattern::new("var a = 1", TS::Tsx)),
+          Rule::Pattern(Pattern::new("var b = 2", TS::Tsx)),
+        ],
+        StopBy::End,
+      ),
+    );
+    test_found(
+      &["function test() { if (c) { var b = 2 } var a = 1 }"],
+      &rule,
+    );
+    test_not_found(&["function test() { var a = 1 }"], &rule);
+  }
+
+  #[test]
+  fn test_any_order_env() {
+    let rule = o::All::new(vec![
+      Rule::Kind(KindMatcher::new("object", TS::Tsx)),
+      any_order(pairs(&["id: $A", "name: $A"]), StopBy::Neighbor),
+    ]);
+    test_found(&["a = { name: x, id: x }"], &rule);
+    test_not_found(&["a = { name: x, id: y }"], &rule);
+  }
 }
@@ -5,11 +5,10 @@ use ast_grep_core::language::Language;
 use ast_grep_core::Node;
 
 use serde::de::{self, Deserializer, MapAccess, Visitor};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::fmt;
 
-#[derive(Serialize, Clone, Default)]
-#[serde(rename_all = "camelCase")]
+#[derive(Clone, Default)]
 pub enum SerializableStopBy {
   #[default]
   Neighbor,
@@ -55,6 +54,20 @@ impl<'de> Deserialize<'de> for SerializableStopBy {
   }
 }
 
+// serialize in the same shape as deserialization accepts
+impl Serialize for SerializableStopBy {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self {
+      SerializableStopBy::Neighbor => serializer.serialize_str("neighbor"),
+      SerializableStopBy::End => serializer.serialize_str("end"),
+      SerializableStopBy::Rule(rule) => rule.serialize(serializer),
+    }
+  }
+}
+
 pub enum StopBy<L: Language> {
   Neighbor,
   End,
@@ -0,0 +1,27 @@
+use crate::RuleConfig;
+
+use ast_grep_core::language::Language;
+use ast_grep_core::NodeMatch;
+
+use std::path::Path;
+
+/// Receive scan results, e.g. to store them in a database or send them over a channel.
+/// See `RuleCollection::report` for the order in which the methods are called.
+pub trait Reporter<L: Language> {
+  type Error;
+  fn on_start(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+  fn on_match(
+    &mut self,
+    file: &Path,
+    rule: &RuleConfig<L>,
+    matched: &NodeMatch<L>,
+  ) -> Result<(), Self::Error>;
+  fn on_file_done(&mut self, _file: &Path) -> Result<(), Self::Error> {
+    Ok(())
+  }
+  fn on_finish(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+}
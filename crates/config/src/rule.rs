@@ -1,16 +1,23 @@
 use crate::deserialize_env::DeserializeEnv;
 use crate::maybe::Maybe;
 use crate::referent_rule::{ReferentRule, ReferentRuleError};
-use crate::relational_rule::{Follows, Has, Inside, Precedes, Relation};
+use crate::relational_rule::{
+  AnyOrder, AnyOrderRelation, Follows, Has, HasDecorator, Inside, Jsx, Precedes, Relation,
+  SerializableJsx,
+};
+use crate::string_content::StringContent;
 
 use ast_grep_core::language::Language;
-use ast_grep_core::matcher::{KindMatcher, KindMatcherError, RegexMatcher, RegexMatcherError};
+use ast_grep_core::matcher::{
+  KindMatcher, KindMatcherError, MatchTrace, OperatorMatcher, RegexMatcher, RegexMatcherError,
+};
 use ast_grep_core::meta_var::MetaVarEnv;
 use ast_grep_core::ops as o;
 use ast_grep_core::{Matcher, Node, Pattern, PatternError};
 
 use bit_set::BitSet;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use thiserror::Error;
 
 /// We have three kinds of rules in ast-grep.
@@ -30,6 +37,14 @@ pub struct SerializableRule {
   pub kind: Maybe<String>,
   #[serde(default, skip_serializing_if = "Maybe::is_absent")]
   pub regex: Maybe<String>,
+  #[serde(default, skip_serializing_if = "Maybe::is_absent")]
+  pub operator: Maybe<String>,
+  #[serde(
+    default,
+    rename = "stringContent",
+    skip_serializing_if = "Maybe::is_absent"
+  )]
+  pub string_content: Maybe<String>,
   // relational
   #[serde(default, skip_serializing_if = "Maybe::is_absent")]
   pub inside: Maybe<Box<Relation>>,
@@ -39,6 +54,16 @@ pub struct SerializableRule {
   pub precedes: Maybe<Box<Relation>>,
   #[serde(default, skip_serializing_if = "Maybe::is_absent")]
   pub follows: Maybe<Box<Relation>>,
+  #[serde(default, rename = "anyOrder", skip_serializing_if = "Maybe::is_absent")]
+  pub any_order: Maybe<Box<AnyOrderRelation>>,
+  #[serde(
+    default,
+    rename = "hasDecorator",
+    skip_serializing_if = "Maybe::is_absent"
+  )]
+  pub has_decorator: Maybe<Box<SerializableRule>>,
+  #[serde(default, skip_serializing_if = "Maybe::is_absent")]
+  pub jsx: Maybe<Box<SerializableJsx>>,
   // composite
   #[serde(default, skip_serializing_if = "Maybe::is_absent")]
   pub all: Maybe<Vec<SerializableRule>>,
@@ -63,12 +88,17 @@ impl SerializableRule {
         pattern: self.pattern.into(),
         kind: self.kind.into(),
         regex: self.regex.into(),
+        operator: self.operator.into(),
+        string_content: self.string_content.into(),
       },
       relational: RelationalRule {
         inside: self.inside.into(),
         has: self.has.into(),
         precedes: self.precedes.into(),
         follows: self.follows.into(),
+        any_order: self.any_order.into(),
+        has_decorator: self.has_decorator.into(),
+        jsx: self.jsx.into(),
       },
       composite: CompositeRule {
         all: self.all.into(),
@@ -78,6 +108,87 @@ impl SerializableRule {
       },
     }
   }
+
+  /// Replace `matches` references to the given utility rules with the utility rule itself.
+  /// Unknown references (e.g. global utils) and cyclic references are kept as is.
+  pub fn inline_utils(self, utils: &HashMap<String, SerializableRule>) -> Self {
+    inline_utils_impl(self, utils, &mut vec![])
+  }
+
+  fn is_empty(&self) -> bool {
+    self.pattern.is_absent()
+      && self.kind.is_absent()
+      && self.regex.is_absent()
+      && self.operator.is_absent()
+      && self.string_content.is_absent()
+      && self.inside.is_absent()
+      && self.has.is_absent()
+      && self.precedes.is_absent()
+      && self.follows.is_absent()
+      && self.any_order.is_absent()
+      && self.has_decorator.is_absent()
+      && self.jsx.is_absent()
+      && self.all.is_absent()
+      && self.any.is_absent()
+      && self.not.is_absent()
+      && self.matches.is_absent()
+  }
+}
+
+fn inline_utils_impl(
+  mut rule: SerializableRule,
+  utils: &HashMap<String, SerializableRule>,
+  visiting: &mut Vec<String>,
+) -> SerializableRule {
+  let relations = [
+    &mut rule.inside,
+    &mut rule.has,
+    &mut rule.precedes,
+    &mut rule.follows,
+  ];
+  for relation in relations.into_iter() {
+    if let Maybe::Present(relation) = relation {
+      relation.map_rules(|r| inline_utils_impl(r, utils, visiting));
+    }
+  }
+  if let Maybe::Present(any_order) = &mut rule.any_order {
+    any_order.map_rules(|r| inline_utils_impl(r, utils, visiting));
+  }
+  for rules in [&mut rule.all, &mut rule.any] {
+    if let Maybe::Present(rules) = rules {
+      *rules = std::mem::take(rules)
+        .into_iter()
+        .map(|r| inline_utils_impl(r, utils, visiting))
+        .collect();
+    }
+  }
+  if let Maybe::Present(decorator) = &mut rule.has_decorator {
+    **decorator = inline_utils_impl(std::mem::take(&mut **decorator), utils, visiting);
+  }
+  if let Maybe::Present(jsx) = &mut rule.jsx {
+    jsx.map_rules(|r| inline_utils_impl(r, utils, visiting));
+  }
+  if let Maybe::Present(not) = &mut rule.not {
+    **not = inline_utils_impl(std::mem::take(&mut **not), utils, visiting);
+  }
+  let Maybe::Present(id) = &rule.matches else {
+    return rule;
+  };
+  let Some(util) = utils.get(id).filter(|_| !visiting.contains(id)) else {
+    return rule;
+  };
+  visiting.push(id.clone());
+  let util = inline_utils_impl(util.clone(), utils, visiting);
+  visiting.pop();
+  rule.matches = Maybe::Absent;
+  if rule.is_empty() {
+    return util;
+  }
+  match &mut rule.all {
+    Maybe::Present(all) => all.push(util),
+    Maybe::Absent => rule.all = Maybe::Present(vec![util]),
+  }
+  rule
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -85,6 +196,8 @@ pub struct AtomicRule {
   pub pattern: Option<PatternStyle>,
   pub kind: Option<String>,
   pub regex: Option<String>,
+  pub operator: Option<String>,
+  pub string_content: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -100,6 +213,9 @@ pub struct RelationalRule {
   pub has: Option<Box<Relation>>,
   pub precedes: Option<Box<Relation>>,
   pub follows: Option<Box<Relation>>,
+  pub any_order: Option<Box<AnyOrderRelation>>,
+  pub has_decorator: Option<Box<SerializableRule>>,
+  pub jsx: Option<Box<SerializableJsx>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -115,11 +231,16 @@ pub enum Rule<L: Language> {
   Pattern(Pattern<L>),
   Kind(KindMatcher<L>),
   Regex(RegexMatcher<L>),
+  Operator(OperatorMatcher<L>),
+  StringContent(StringContent<L>),
   // relational
   Inside(Box<Inside<L>>),
   Has(Box<Has<L>>),
   Precedes(Box<Precedes<L>>),
   Follows(Box<Follows<L>>),
+  AnyOrder(Box<AnyOrder<L>>),
+  HasDecorator(Box<HasDecorator<L>>),
+  Jsx(Box<Jsx<L>>),
   // composite
   All(o::All<L, Rule<L>>),
   Any(o::Any<L, Rule<L>>),
@@ -129,11 +250,17 @@ pub enum Rule<L: Language> {
 impl<L: Language> Rule<L> {
   pub fn is_atomic(&self) -> bool {
     use Rule::*;
-    matches!(self, Pattern(_) | Kind(_) | Regex(_))
+    matches!(
+      self,
+      Pattern(_) | Kind(_) | Regex(_) | Operator(_) | StringContent(_)
+    )
   }
   pub fn is_relational(&self) -> bool {
     use Rule::*;
-    matches!(self, Inside(_) | Has(_) | Precedes(_) | Follows(_))
+    matches!(
+      self,
+      Inside(_) | Has(_) | Precedes(_) | Follows(_) | AnyOrder(_) | HasDecorator(_) | Jsx(_)
+    )
   }
 
   pub fn is_composite(&self) -> bool {
@@ -141,6 +268,23 @@ impl<L: Language> Rule<L> {
     matches!(self, All(_) | Any(_) | Not(_) | Matches(_))
   }
 
+  /// A positive rule selects candidate nodes on its own. `not` only filters candidates
+  /// so it must be combined with a positive term, e.g. `kind` or `inside`.
+  /// `all` needs one positive member while `any` needs every member to be positive.
+  /// An empty `all` matches every node so it is positive.
+  /// `matches` is assumed positive since the referenced rule may not be registered yet.
+  pub fn is_positive(&self) -> bool {
+    match self {
+      Rule::All(all) => {
+        let rules = all.inner();
+        rules.is_empty() || rules.iter().any(Rule::is_positive)
+      }
+      Rule::Any(any) => any.inner().iter().all(Rule::is_positive),
+      Rule::Not(_) => false,
+      _ => true,
+    }
+  }
+
   pub(crate) fn check_cyclic(&self, id: &str) -> bool {
     match self {
       Rule::All(all) => all.inner().iter().any(|r| r.check_cyclic(id)),
@@ -167,11 +311,16 @@ impl<L: Language> Matcher<L> for Rule<L> {
       Pattern(pattern) => pattern.match_node_with_env(node, env),
       Kind(kind) => kind.match_node_with_env(node, env),
       Regex(regex) => regex.match_node_with_env(node, env),
+      Operator(op) => op.match_node_with_env(node, env),
+      StringContent(content) => content.match_node_with_env(node, env),
       // relational
       Inside(parent) => match_and_add_label(&**parent, node, env),
       Has(child) => match_and_add_label(&**child, node, env),
       Precedes(latter) => match_and_add_label(&**latter, node, env),
       Follows(former) => match_and_add_label(&**former, node, env),
+      AnyOrder(set) => set.match_node_with_env(node, env),
+      HasDecorator(decorator) => match_and_add_label(&**decorator, node, env),
+      Jsx(jsx) => jsx.match_node_with_env(node, env),
       // composite
       All(all) => all.match_node_with_env(node, env),
       Any(any) => any.match_node_with_env(node, env),
@@ -187,11 +336,16 @@ impl<L: Language> Matcher<L> for Rule<L> {
       Pattern(pattern) => pattern.potential_kinds(),
       Kind(kind) => kind.potential_kinds(),
       Regex(regex) => regex.potential_kinds(),
+      Operator(op) => op.potential_kinds(),
+      StringContent(content) => content.potential_kinds(),
       // relational
       Inside(parent) => parent.potential_kinds(),
       Has(child) => child.potential_kinds(),
       Precedes(latter) => latter.potential_kinds(),
       Follows(former) => former.potential_kinds(),
+      AnyOrder(set) => set.potential_kinds(),
+      HasDecorator(decorator) => decorator.potential_kinds(),
+      Jsx(jsx) => jsx.potential_kinds(),
       // composite
       All(all) => all.potential_kinds(),
       Any(any) => any.potential_kinds(),
@@ -199,6 +353,31 @@ impl<L: Language> Matcher<L> for Rule<L> {
       Matches(rule) => rule.potential_kinds(),
     }
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    use Rule::*;
+    match self {
+      // atomic
+      Pattern(pattern) => pattern.explain(node),
+      Kind(kind) => kind.explain(node),
+      Regex(regex) => regex.explain(node),
+      Operator(op) => op.explain(node),
+      StringContent(content) => content.explain(node),
+      // relational
+      Inside(parent) => parent.explain(node),
+      Has(child) => child.explain(node),
+      Precedes(latter) => latter.explain(node),
+      Follows(former) => former.explain(node),
+      AnyOrder(set) => set.explain(node),
+      HasDecorator(decorator) => decorator.explain(node),
+      Jsx(jsx) => jsx.explain(node),
+      // composite
+      All(all) => all.explain(node),
+      Any(any) => any.explain(node),
+      Not(not) => not.explain(node),
+      Matches(rule) => rule.explain(node),
+    }
+  }
 }
 
 /// Rule matches nothing by default.
@@ -304,6 +483,16 @@ fn deserialize_relational_rule<L: Language>(
   if let Some(follows) = relational.follows {
     rules.push(R::Follows(Box::new(Follows::try_new(*follows, env)?)));
   }
+  if let Some(any_order) = relational.any_order {
+    rules.push(R::AnyOrder(Box::new(AnyOrder::try_new(*any_order, env)?)));
+  }
+  if let Some(decorator) = relational.has_decorator {
+    let decorator = HasDecorator::try_new(*decorator, env)?;
+    rules.push(R::HasDecorator(Box::new(decorator)));
+  }
+  if let Some(jsx) = relational.jsx {
+    rules.push(R::Jsx(Box::new(Jsx::try_new(*jsx, env)?)));
+  }
   Ok(())
 }
 
@@ -314,18 +503,31 @@ fn deserialze_atomic_rule<L: Language>(
 ) -> Result<(), RuleSerializeError> {
   use Rule as R;
   if let Some(pattern) = atomic.pattern {
-    rules.push(match pattern {
-      PatternStyle::Str(pat) => R::Pattern(Pattern::try_new(&pat, env.lang.clone())?),
+    let pattern = match pattern {
+      PatternStyle::Str(pat) => Pattern::try_new(&pat, env.lang.clone())?,
       PatternStyle::Contextual { context, selector } => {
-        R::Pattern(Pattern::contextual(&context, &selector, env.lang.clone())?)
+        Pattern::contextual(&context, &selector, env.lang.clone())?
       }
-    });
+    };
+    rules.push(R::Pattern(pattern.ignore_case(env.ignore_case)));
   }
   if let Some(kind) = atomic.kind {
     rules.push(R::Kind(KindMatcher::try_new(&kind, env.lang.clone())?));
   }
   if let Some(regex) = atomic.regex {
-    rules.push(R::Regex(RegexMatcher::try_new(&regex)?));
+    let regex = if env.word {
+      RegexMatcher::try_new_word(&regex)?
+    } else {
+      RegexMatcher::try_new(&regex)?
+    };
+    rules.push(R::Regex(regex));
+  }
+  if let Some(operator) = atomic.operator {
+    rules.push(R::Operator(OperatorMatcher::new(&operator)));
+  }
+  if let Some(content) = atomic.string_content {
+    let content = StringContent::try_new(&content, env.lang.clone())?;
+    rules.push(R::StringContent(content));
   }
   Ok(())
 }
@@ -334,8 +536,15 @@ fn deserialze_atomic_rule<L: Language>(
 mod test {
   use super::*;
   use crate::from_str;
+  use crate::test::TypeScript;
   use PatternStyle::*;
 
+  fn make_rule(src: &str) -> Rule<TypeScript> {
+    let rule: SerializableRule = from_str(src).expect("cannot parse rule");
+    let env = DeserializeEnv::new(TypeScript::Tsx);
+    deserialize_rule(rule, &env).expect("should deserialize")
+  }
+
   #[test]
   fn test_pattern() {
     let src = r"
@@ -408,4 +617,157 @@ inside:
     assert!(inside.rule.pattern.is_present());
     assert!(inside.rule.inside.unwrap().rule.pattern.is_present());
   }
+
+  #[test]
+  fn test_explain_failing_inside() {
+    let src = r"
+pattern: console.log($A)
+inside:
+  kind: function_declaration
+  stopBy: end
+";
+    let rule = make_rule(src);
+    let grep = TypeScript::Tsx.ast_grep("const f = () => { console.log(123) }");
+    let node = grep.root().find("console.log($A)").expect("should find");
+    let trace = rule.explain(node.get_node().clone());
+    assert!(!trace.matched);
+    assert_eq!(trace.label, "all");
+    assert!(trace.env.is_empty());
+    let pattern = &trace.children[0];
+    assert!(pattern.matched);
+    assert_eq!(pattern.label, "pattern: console.log($A)");
+    assert_eq!(pattern.env, [("A".to_string(), "123".to_string())]);
+    let inside = &trace.children[1];
+    assert!(!inside.matched);
+    assert_eq!(inside.label, "inside");
+    assert!(inside.children.is_empty());
+
+    let grep = TypeScript::Tsx.ast_grep("function f() { console.log(123) }");
+    let node = grep.root().find("console.log($A)").expect("should find");
+    let trace = rule.explain(node.get_node().clone());
+    assert!(trace.matched);
+    let inside = &trace.children[1];
+    assert!(inside.matched);
+    assert_eq!(inside.children[0].label, "kind: function_declaration");
+  }
+
+  #[test]
+  fn test_any_order_rule() {
+    let src = r"
+kind: object
+anyOrder:
+  rules:
+    - { kind: pair, has: { regex: ^id$ } }
+    - { kind: pair, has: { regex: ^name$ } }
+";
+    let rule = make_rule(src);
+    let matched = |src| TypeScript::Tsx.ast_grep(src).root().find(&rule).is_some();
+    assert!(matched("a = { id: 1, name: 'a' }"));
+    assert!(matched("a = { name: 'a', id: 1 }"));
+    assert!(!matched("a = { id: 1, id: 2 }"));
+    assert!(!matched("a = { name: 'a' }"));
+    let grep = TypeScript::Tsx.ast_grep("a = { name: 'a', b: 2, id: 1 }");
+    let node = grep.root().find(&rule).expect("should find");
+    let trace = rule.explain(node.get_node().clone());
+    let any_order = &trace.children[1];
+    assert_eq!(any_order.label, "anyOrder");
+    assert!(any_order.matched);
+    assert_eq!(any_order.children.len(), 2);
+  }
+
+  #[test]
+  fn test_not_has() {
+    let src = r"
+kind: function_declaration
+not:
+  has:
+    stopBy: end
+    all: [{ pattern: $X }, { kind: await_expression }]
+";
+    let rule = make_rule(src);
+    let src = "
+async function a() { await b() }
+function c() { d() }
+async function e() { if (f) { return await g } }
+async function h() { return () => i }";
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let found: Vec<_> = grep.root().find_all(&rule).collect();
+    let names: Vec<_> = found
+      .iter()
+      .map(|n| n.child_by_field_name("name").unwrap().text().to_string())
+      .collect();
+    assert_eq!(names, ["c", "h"]);
+    // bindings tried inside `not` are discarded
+    assert!(found.iter().all(|n| n.get_env().get_match("X").is_none()));
+  }
+
+  #[test]
+  fn test_has_decorator() {
+    let src = r#"
+kind: method_definition
+hasDecorator:
+  pattern: "@deprecated($MSG)"
+"#;
+    let rule = make_rule(src);
+    let src = "class A {\n  @log\n  @deprecated('old')\n  foo() {}\n  bar() {}\n}";
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let found: Vec<_> = grep.root().find_all(&rule).collect();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].text(), "foo() {}");
+    let msg = found[0].get_env().get_match("MSG").expect("should bind");
+    assert_eq!(msg.text(), "'old'");
+    let grep = TypeScript::Tsx.ast_grep("class A { @log foo() {} bar() {} }");
+    assert!(grep.root().find(&rule).is_none());
+  }
+
+  #[test]
+  fn test_jsx_rule() {
+    let src = "jsx: { tag: img, missingAttribute: alt }";
+    let rule = make_rule(src);
+    assert!(rule.is_relational());
+    let grep = TypeScript::Tsx.ast_grep(r#"<div><img src="a" /><img src="b" alt="" /></div>"#);
+    let found: Vec<_> = grep.root().find_all(&rule).collect();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].text(), r#"<img src="a" />"#);
+  }
+
+  #[test]
+  fn test_operator_rule() {
+    let find_all = |src: &str| {
+      let rule = make_rule(src);
+      let grep = TypeScript::Tsx.ast_grep("if (a & b && c == d) { e === f }");
+      let found: Vec<_> = grep
+        .root()
+        .find_all(&rule)
+        .map(|n| n.text().to_string())
+        .collect();
+      found
+    };
+    assert_eq!(
+      find_all("{ kind: binary_expression, operator: '&' }"),
+      ["a & b"]
+    );
+    assert_eq!(
+      find_all("{ kind: binary_expression, operator: '&&' }"),
+      ["a & b && c == d"]
+    );
+    assert_eq!(
+      find_all("{ kind: binary_expression, operator: '==' }"),
+      ["c == d"]
+    );
+    assert_eq!(
+      find_all("{ kind: binary_expression, operator: '===' }"),
+      ["e === f"]
+    );
+  }
+
+  #[test]
+  fn test_class_decorator() {
+    let src = "kind: class_declaration\nhasDecorator: { pattern: '@sealed' }";
+    let rule = make_rule(src);
+    let grep = TypeScript::Tsx.ast_grep("@sealed class A {}\nclass B {}");
+    let found: Vec<_> = grep.root().find_all(&rule).collect();
+    assert_eq!(found.len(), 1);
+    assert!(found[0].text().contains("class A"));
+  }
 }
@@ -1,6 +1,10 @@
-use crate::RuleConfig;
+use crate::{
+  CombinedScan, Reporter, RuleConfig, SerializableRule, Severity, SymbolIndex, TextRule,
+};
 use ast_grep_core::language::Language;
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use ast_grep_core::{AstGrep, NodeMatch};
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use std::collections::HashMap;
 use std::path::Path;
 
 /// RuleBucket stores rules of the same language id.
@@ -24,16 +28,44 @@ impl<L: Language> RuleBucket<L> {
 
 struct ContingentRule<L: Language> {
   rule: RuleConfig<L>,
-  files_globs: Option<GlobSet>,
-  ignore_globs: Option<GlobSet>,
+  files_globs: Option<GlobList>,
+  ignore_globs: Option<GlobList>,
 }
 
-fn build_glob_set(paths: &Vec<String>) -> Result<GlobSet, globset::Error> {
-  let mut builder = GlobSetBuilder::new();
-  for path in paths {
-    builder.add(Glob::new(path)?);
+/// Ordered globs where a leading `!` negates the glob, e.g. `["src/**", "!src/generated/**"]`.
+/// The last glob matching a path wins, so a later negated glob excludes the path
+/// and a later plain glob includes it again. A path matching no glob is excluded.
+/// Brace expansion like `*.{ts,tsx}` is supported in every glob.
+struct GlobList {
+  set: GlobSet,
+  negated: Vec<bool>,
+}
+
+impl GlobList {
+  fn try_new(globs: &Vec<String>) -> Result<Self, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    let mut negated = vec![];
+    for glob in globs {
+      let (glob, negate) = match glob.strip_prefix('!') {
+        Some(g) => (g, true),
+        None => (glob.as_str(), false),
+      };
+      builder.add(Glob::new(glob)?);
+      negated.push(negate);
+    }
+    Ok(Self {
+      set: builder.build()?,
+      negated,
+    })
+  }
+
+  fn is_match<P: AsRef<Path>>(&self, path: P) -> bool {
+    // matched indices are in ascending order
+    match self.set.matches(path).last() {
+      Some(&idx) => !self.negated[idx],
+      None => false,
+    }
   }
-  builder.build()
 }
 
 impl<L> TryFrom<RuleConfig<L>> for ContingentRule<L>
@@ -42,8 +74,8 @@ where
 {
   type Error = globset::Error;
   fn try_from(rule: RuleConfig<L>) -> Result<Self, Self::Error> {
-    let files_globs = rule.files.as_ref().map(build_glob_set).transpose()?;
-    let ignore_globs = rule.ignores.as_ref().map(build_glob_set).transpose()?;
+    let files_globs = rule.files.as_ref().map(GlobList::try_new).transpose()?;
+    let ignore_globs = rule.ignores.as_ref().map(GlobList::try_new).transpose()?;
     Ok(Self {
       rule,
       files_globs,
@@ -75,28 +107,105 @@ pub struct RuleCollection<L: Language + Eq> {
   tenured: Vec<RuleBucket<L>>,
   /// contingent rules will run against a file if it matches file/ignore glob.
   contingent: Vec<ContingentRule<L>>,
+  /// file globs mapped to languages, tried in order before the file extension.
+  lang_globs: Vec<(GlobMatcher, L)>,
+  /// rules with `language: all`, run against the text of every file.
+  text_rules: Vec<TextRule>,
+  /// ids of rules with severity `off`, which are not added to the collection.
+  disabled: Vec<String>,
+  /// global utils by id with their own utils inlined, to inline them in printed rules.
+  global_utils: HashMap<String, SerializableRule>,
 }
 
 impl<L: Language + Eq> RuleCollection<L> {
   pub fn try_new(configs: Vec<RuleConfig<L>>) -> Result<Self, globset::Error> {
-    let mut tenured = vec![];
-    let mut contingent = vec![];
+    let mut ret = Self {
+      tenured: vec![],
+      contingent: vec![],
+      lang_globs: vec![],
+      text_rules: vec![],
+      disabled: vec![],
+      global_utils: HashMap::new(),
+    };
+    ret.extend(configs)?;
+    Ok(ret)
+  }
+
+  /// Add more rules to the collection. Rules with severity `off` are skipped.
+  pub fn extend(&mut self, configs: Vec<RuleConfig<L>>) -> Result<(), globset::Error> {
     for config in configs {
-      if config.files.is_none() && config.ignores.is_none() {
-        Self::add_tenured_rule(&mut tenured, config);
+      if matches!(config.severity, Severity::Off) {
+        self.disabled.push(config.id.clone());
+      } else if config.files.is_none() && config.ignores.is_none() {
+        Self::add_tenured_rule(&mut self.tenured, config);
       } else {
-        contingent.push(ContingentRule::try_from(config)?);
+        self.contingent.push(ContingentRule::try_from(config)?);
       }
     }
-    Ok(Self {
-      tenured,
-      contingent,
-    })
+    Ok(())
+  }
+
+  /// Add rules matching the raw text of files in any language. Rules with severity `off` are skipped.
+  pub fn add_text_rules(&mut self, rules: Vec<TextRule>) {
+    for rule in rules {
+      if matches!(rule.severity, Severity::Off) {
+        self.disabled.push(rule.id.clone());
+      } else {
+        self.text_rules.push(rule);
+      }
+    }
+  }
+
+  /// Whether a rule with the id was skipped because its severity is `off`.
+  pub fn is_disabled(&self, id: &str) -> bool {
+    self.disabled.iter().any(|d| d == id)
+  }
+
+  pub fn text_rules(&self) -> &[TextRule] {
+    &self.text_rules
+  }
+
+  /// Keep the serialized global utils that the rules were parsed with, see [`Self::global_utils`].
+  pub fn set_global_utils(&mut self, utils: HashMap<String, SerializableRule>) {
+    self.global_utils = utils;
+  }
+
+  /// Global utils to inline with `RuleConfig::with_utils_inlined`.
+  pub fn global_utils(&self) -> &HashMap<String, SerializableRule> {
+    &self.global_utils
+  }
+
+  /// Map file globs to languages, e.g. `*.mts` to TypeScript. The globs take precedence
+  /// over file extensions and are tried in order, so an earlier glob wins if several match.
+  pub fn set_lang_globs(&mut self, globs: Vec<(L, Vec<String>)>) -> Result<(), globset::Error> {
+    let mut lang_globs = vec![];
+    for (lang, globs) in globs {
+      for glob in globs {
+        lang_globs.push((Glob::new(&glob)?.compile_matcher(), lang.clone()));
+      }
+    }
+    self.lang_globs = lang_globs;
+    Ok(())
+  }
+
+  /// Guess the language of a file by the language globs, then by its extension.
+  /// A glob matches either the whole path or the file name, e.g. `Dockerfile*`.
+  pub fn get_lang<P: AsRef<Path>>(&self, path: P) -> Option<L> {
+    let path = path.as_ref();
+    let name = path.file_name()?;
+    let by_glob = self
+      .lang_globs
+      .iter()
+      .find(|(glob, _)| glob.is_match(path) || glob.is_match(name));
+    match by_glob {
+      Some((_, lang)) => Some(lang.clone()),
+      None => L::from_path(path),
+    }
   }
 
   pub fn for_path<P: AsRef<Path>>(&self, path: P) -> Vec<&RuleConfig<L>> {
     let mut all_rules = vec![];
-    let Some(lang) = L::from_path(path.as_ref()) else {
+    let Some(lang) = self.get_lang(path.as_ref()) else {
       return vec![];
     };
     for rule in &self.tenured {
@@ -126,6 +235,105 @@ impl<L: Language + Eq> RuleCollection<L> {
     None
   }
 
+  /// Run all rules applicable to `path` against its parsed `root`.
+  /// Matches are grouped by rule id and kept in document order. Rules without any match are omitted.
+  /// Cross-file rules report nothing since a single file cannot tell what other files import.
+  pub fn scan_file<'r, P: AsRef<Path>>(
+    &self,
+    path: P,
+    root: &'r AstGrep<L>,
+  ) -> HashMap<String, Vec<NodeMatch<'r, L>>> {
+    let mut results = HashMap::new();
+    for (rule, matches) in self.scan_combined(path.as_ref(), root, None) {
+      if !matches.is_empty() {
+        results.insert(rule.id.clone(), matches);
+      }
+    }
+    results
+  }
+
+  /// Scan each file with the rules applicable to its path and send the matches to the reporter.
+  /// Files in an unknown language are skipped. Matches of a file are sent rule by rule,
+  /// followed by `on_file_done`. `on_start` and `on_finish` are called once.
+  /// Files with cross-file rules are reported after all files are parsed and their imports indexed.
+  pub fn report<P, R>(
+    &self,
+    files: impl IntoIterator<Item = (P, String)>,
+    reporter: &mut R,
+  ) -> Result<(), R::Error>
+  where
+    P: AsRef<Path>,
+    R: Reporter<L>,
+  {
+    reporter.on_start()?;
+    let mut index = SymbolIndex::default();
+    let mut deferred = vec![];
+    for (path, src) in files {
+      let Some(lang) = self.get_lang(path.as_ref()) else {
+        continue;
+      };
+      let root = lang.ast_grep(src);
+      let rules = self.for_path(path.as_ref());
+      let cross_file: Vec<_> = rules.into_iter().filter(|r| r.is_cross_file()).collect();
+      if cross_file.is_empty() {
+        self.report_file(path.as_ref(), &root, None, reporter)?;
+      } else {
+        index.index_file(&cross_file, path.as_ref(), &root);
+        deferred.push((path, root));
+      }
+    }
+    for (path, root) in deferred {
+      self.report_file(path.as_ref(), &root, Some(&index), reporter)?;
+    }
+    reporter.on_finish()
+  }
+
+  fn report_file<R: Reporter<L>>(
+    &self,
+    path: &Path,
+    root: &AstGrep<L>,
+    index: Option<&SymbolIndex>,
+    reporter: &mut R,
+  ) -> Result<(), R::Error> {
+    for (rule, matches) in self.scan_combined(path, root, index) {
+      for matched in &matches {
+        reporter.on_match(path, rule, matched)?;
+      }
+    }
+    reporter.on_file_done(path)
+  }
+
+  /// Matches of the rules for the file in a single traversal, in the order of `for_path`.
+  /// See [`CombinedScan`] for how cross-file matches are filtered by the index.
+  fn scan_combined<'t>(
+    &self,
+    path: &Path,
+    root: &'t AstGrep<L>,
+    index: Option<&SymbolIndex>,
+  ) -> Vec<(&RuleConfig<L>, Vec<NodeMatch<'t, L>>)> {
+    let rules = self.for_path(path);
+    let mut combined = CombinedScan::new(rules.clone());
+    if let Some(index) = index {
+      combined = combined.with_index(path, index);
+    }
+    let mut matched: Vec<_> = rules.iter().map(|_| vec![]).collect();
+    for (idx, nm) in combined.scan(root) {
+      matched[idx].push(nm);
+    }
+    rules
+      .into_iter()
+      .zip(matched)
+      .map(|(rule, matches)| (rule, rule.filter_first_per_scope(matches)))
+      .collect()
+  }
+
+  /// Iterate over all rules in the collection, tenured rules first.
+  pub fn iter(&self) -> impl Iterator<Item = &RuleConfig<L>> {
+    let tenured = self.tenured.iter().flat_map(|bucket| bucket.rules.iter());
+    let contingent = self.contingent.iter().map(|cont| &cont.rule);
+    tenured.chain(contingent)
+  }
+
   fn add_tenured_rule(tenured: &mut Vec<RuleBucket<L>>, rule: RuleConfig<L>) {
     let lang = rule.language.clone();
     for bucket in tenured.iter_mut() {
@@ -148,13 +356,17 @@ mod test {
   use crate::test::TypeScript;
 
   fn make_rule(files: &str) -> RuleCollection<TypeScript> {
+    make_rule_with_severity(files, "info")
+  }
+
+  fn make_rule_with_severity(files: &str, severity: &str) -> RuleCollection<TypeScript> {
     let globals = GlobalRules::default();
     let rule_config = from_yaml_string(
       &format!(
         r"
 id: test
 message: test rule
-severity: info
+severity: {severity}
 language: Tsx
 rule:
   all: []
@@ -218,4 +430,229 @@ ignores:
     assert_match_path(&collection, "./src/some_folder/test.py");
     assert_ignore_path(&collection, "./src/excluded/app.py");
   }
+
+  #[test]
+  fn test_brace_expansion() {
+    let collection = make_rule("files: ['src/**/*.{ts,tsx}']");
+    assert_match_path(&collection, "src/app.ts");
+    assert_match_path(&collection, "src/components/button.tsx");
+    assert_ignore_path(&collection, "src/app.js");
+    assert_ignore_path(&collection, "test/app.ts");
+  }
+
+  #[test]
+  fn test_negated_glob() {
+    let src = r#"
+files:
+  - "src/**/*.{ts,tsx}"
+  - "!src/generated/**"
+  - "src/generated/api.ts"
+"#;
+    let collection = make_rule(src);
+    assert_match_path(&collection, "src/app.ts");
+    assert_ignore_path(&collection, "src/generated/schema.ts");
+    // the last matching glob wins
+    assert_match_path(&collection, "src/generated/api.ts");
+    let src = r#"
+ignores:
+  - "**/generated/**"
+  - "!src/generated/**"
+"#;
+    let collection = make_rule(src);
+    assert_ignore_path(&collection, "lib/generated/schema.ts");
+    assert_match_path(&collection, "src/generated/schema.ts");
+    assert_match_path(&collection, "src/app.ts");
+  }
+
+  #[test]
+  fn test_invalid_glob() {
+    let globals = GlobalRules::default();
+    let yaml = "id: test\nmessage: test\nseverity: info\nlanguage: Tsx\nrule: {all: []}\nfiles: ['!src/{a,b']";
+    let rule = from_yaml_string::<TypeScript>(yaml, &globals).unwrap();
+    assert!(RuleCollection::try_new(rule).is_err());
+  }
+
+  #[test]
+  fn test_lang_globs() {
+    let mut collection = make_rule("");
+    let globs = vec![(TypeScript::Tsx, vec!["Dockerfile*".to_string()])];
+    collection.set_lang_globs(globs).expect("should parse");
+    assert!(collection.get_lang("docker/Dockerfile.dev").is_some());
+    assert_match_path(&collection, "docker/Dockerfile.dev");
+    let invalid = vec![(TypeScript::Tsx, vec!["a{".to_string()])];
+    assert!(collection.set_lang_globs(invalid).is_err());
+  }
+
+  #[test]
+  fn test_off_rule() {
+    let collection = make_rule_with_severity("", "off");
+    assert_ignore_path(&collection, "./src/app.ts");
+    assert!(collection.get_rule("test").is_none());
+    assert!(collection.is_disabled("test"));
+    assert_eq!(collection.iter().count(), 0);
+    let collection = make_rule_with_severity("files: [\"**/*.ts\"]", "off");
+    assert_ignore_path(&collection, "./src/app.ts");
+  }
+
+  #[test]
+  fn test_off_rule_still_validated() {
+    let globals = GlobalRules::default();
+    let invalid = r"
+id: test
+message: test rule
+severity: off
+language: Tsx
+rule:
+  kind: not_a_real_kind
+";
+    assert!(from_yaml_string::<TypeScript>(invalid, &globals).is_err());
+  }
+
+  #[test]
+  fn test_scan_file() {
+    let yaml = r"
+id: no-log
+message: test
+severity: info
+language: Tsx
+rule:
+  pattern: console.log($A)
+---
+id: no-debugger
+message: test
+severity: info
+language: Tsx
+rule:
+  kind: debugger_statement
+---
+id: no-alert
+message: test
+severity: info
+language: Tsx
+rule:
+  pattern: alert($A)
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let collection = RuleCollection::try_new(rules).expect("should build");
+    let grep = TypeScript::Tsx.ast_grep("console.log(1); debugger; console.log(2)");
+    let results = collection.scan_file("test.tsx", &grep);
+    assert_eq!(results.len(), 2);
+    let logs: Vec<_> = results["no-log"].iter().map(|m| m.text()).collect();
+    assert_eq!(logs, ["console.log(1)", "console.log(2)"]);
+    assert_eq!(results["no-debugger"].len(), 1);
+    assert!(!results.contains_key("no-alert"));
+  }
+
+  #[derive(Default)]
+  struct VecReporter {
+    found: Vec<(String, String, String)>,
+    events: Vec<String>,
+  }
+
+  impl Reporter<TypeScript> for VecReporter {
+    type Error = std::convert::Infallible;
+    fn on_start(&mut self) -> Result<(), Self::Error> {
+      self.events.push("start".into());
+      Ok(())
+    }
+    fn on_match(
+      &mut self,
+      file: &Path,
+      rule: &RuleConfig<TypeScript>,
+      matched: &NodeMatch<TypeScript>,
+    ) -> Result<(), Self::Error> {
+      let file = file.display().to_string();
+      self
+        .found
+        .push((file, rule.id.clone(), matched.text().to_string()));
+      Ok(())
+    }
+    fn on_file_done(&mut self, file: &Path) -> Result<(), Self::Error> {
+      self.events.push(file.display().to_string());
+      Ok(())
+    }
+    fn on_finish(&mut self) -> Result<(), Self::Error> {
+      self.events.push("finish".into());
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_custom_reporter() {
+    let yaml = "
+id: no-log
+message: test
+severity: info
+language: Tsx
+rule:
+  pattern: console.log($A)
+ignores: ['b.ts']
+---
+id: no-debugger
+message: test
+severity: info
+language: Tsx
+rule:
+  kind: debugger_statement
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let collection = RuleCollection::try_new(rules).expect("should build");
+    let files = [
+      ("a.ts", "console.log(1); debugger".to_string()),
+      ("b.ts", "console.log(2); debugger".to_string()),
+    ];
+    let mut reporter = VecReporter::default();
+    let Ok(()) = collection.report(files, &mut reporter);
+    let found: Vec<_> = reporter
+      .found
+      .iter()
+      .map(|(f, id, text)| (f.as_str(), id.as_str(), text.as_str()))
+      .collect();
+    assert_eq!(
+      found,
+      [
+        ("a.ts", "no-debugger", "debugger"),
+        ("a.ts", "no-log", "console.log(1)"),
+        ("b.ts", "no-debugger", "debugger"),
+      ]
+    );
+    assert_eq!(reporter.events, ["start", "a.ts", "b.ts", "finish"]);
+  }
+
+  #[test]
+  fn test_report_cross_file() {
+    let yaml = "
+id: unused-export
+message: test
+severity: info
+language: Tsx
+rule:
+  pattern: export function $NAME() {}
+crossFile:
+  imports:
+    kind: import_specifier
+    has: { kind: identifier, pattern: $NAME }
+    inside:
+      kind: import_statement
+      has: { field: source, pattern: $SOURCE }
+      stopBy: end
+  unimported: $NAME
+  module: $SOURCE
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let collection = RuleCollection::try_new(rules).expect("should build");
+    let lib = "export function a() {}\nexport function b() {}";
+    let files = [
+      ("lib.ts", lib.to_string()),
+      ("main.ts", "import { a } from './lib'".to_string()),
+      ("other.ts", "import { b } from './other-lib'".to_string()),
+    ];
+    let mut reporter = VecReporter::default();
+    let Ok(()) = collection.report(files, &mut reporter);
+    let found: Vec<_> = reporter.found.iter().map(|f| f.2.as_str()).collect();
+    assert_eq!(found, ["export function b() {}"]);
+    // a single file cannot tell whether its exports are imported
+    let grep = TypeScript::Tsx.ast_grep(lib);
+    assert!(collection.scan_file("lib.ts", &grep).is_empty());
+  }
 }
@@ -1,32 +1,61 @@
+use crate::cross_file::{CrossFile, SymbolIndex};
 use crate::deserialize_env::DeserializeEnv;
+use crate::fix::{has_sections, FixList};
 use crate::referent_rule::GlobalRules;
 use crate::rule::{deserialize_rule, RuleSerializeError, SerializableRule};
+use crate::text_rule::TextRuleError;
+use crate::transform::{try_deserialize_transforms, Transformation};
+use crate::wrap::{Wrap, WRAPPED_VAR};
 
 pub use crate::constraints::{
-  try_deserialize_matchers, try_from_serializable as deserialize_meta_var, RuleWithConstraint,
-  SerializableMetaVarMatcher, SerializeConstraintsError,
+  try_deserialize_constraints, try_deserialize_matchers,
+  try_from_serializable as deserialize_meta_var, Constraints, RuleConstraint, RuleWithConstraint,
+  SerializableConstraint, SerializableCount, SerializableMetaVarMatcher, SerializableNumberRange,
+  SerializableQuantifiedRule, SerializeConstraintsError,
 };
+pub use crate::cross_file::{CrossFileError, SerializableCrossFile};
+pub use crate::fix::{FixError, SerializableFix, SerializableFixEdit};
+pub use crate::transform::{SerializableTransformation, TransformError};
+pub use crate::wrap::{SerializableWrap, WrapError};
 use ast_grep_core::language::Language;
-use ast_grep_core::meta_var::MetaVarMatchers;
-use ast_grep_core::replace_meta_var_in_string;
-use ast_grep_core::NodeMatch;
+use ast_grep_core::matcher::{KindMatcher, KindMatcherError};
+use ast_grep_core::meta_var::{MetaVarMatchers, MetaVariable};
+use ast_grep_core::replace_meta_var_in_message;
+use ast_grep_core::{Edit, Node, NodeMatch};
 use ast_grep_core::{Pattern, PatternError};
 use serde::{Deserialize, Serialize};
-use serde_yaml::{with::singleton_map_recursive::deserialize, Deserializer, Error as YamlError};
+use serde_yaml::with::singleton_map_recursive::deserialize;
+use serde_yaml::{Deserializer, Error as YamlError, Value as YamlValue};
 use thiserror::Error;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Severities are ordered from `off` to `error`, so `off` is below any severity to report.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
 #[serde(rename_all = "camelCase")]
 pub enum Severity {
+  /// Turn off the rule. It is still parsed and validated but never run in scanning.
+  #[cfg_attr(feature = "clap", value(skip))]
+  Off,
   Hint,
   Info,
   Warning,
   Error,
 }
 
+/// Raise the severity of a rule that matches too often in one scan.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Escalate {
+  /// The severity escalates if the rule has more matches than this.
+  pub threshold: usize,
+  /// The severity after escalation.
+  pub to: Severity,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SerializableRuleCore<L: Language> {
   /// Unique, descriptive identifier, e.g., no-unused-variable
@@ -36,14 +65,29 @@ pub struct SerializableRuleCore<L: Language> {
   /// Pattern rules to find matching AST nodes
   pub rule: SerializableRule,
   /// Addtional meta variables pattern to filter matching
-  pub constraints: Option<HashMap<String, SerializableMetaVarMatcher>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub constraints: Option<HashMap<String, SerializableConstraint>>,
+  /// New meta variables computed from captured ones, usable in `fix` and `message`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub transform: Option<HashMap<String, SerializableTransformation>>,
   /// Utility rules that can be used in `matches`
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub utils: Option<HashMap<String, SerializableRule>>,
+  /// Match identifiers in the patterns of this rule case-insensitively, including the patterns
+  /// in `constraints`. Strings still match exactly.
+  #[serde(default, rename = "ignoreCase", skip_serializing_if = "is_false")]
+  pub ignore_case: bool,
+  /// Match the regexes of this rule only as whole words, like `grep -w`. `foo` does not match `foobar`.
+  #[serde(default, skip_serializing_if = "is_false")]
+  pub word: bool,
 }
 
 impl<L: Language> SerializableRuleCore<L> {
   fn get_deserialize_env(&self, globals: &GlobalRules<L>) -> RResult<DeserializeEnv<L>> {
-    let env = DeserializeEnv::new(self.language.clone()).with_globals(globals);
+    let env = DeserializeEnv::new(self.language.clone())
+      .with_globals(globals)
+      .with_ignore_case(self.ignore_case)
+      .with_word(self.word);
     if let Some(utils) = &self.utils {
       let env = env.register_local_utils(utils)?;
       Ok(env)
@@ -52,24 +96,50 @@ impl<L: Language> SerializableRuleCore<L> {
     }
   }
 
-  fn get_meta_var_matchers(&self) -> RResult<MetaVarMatchers<L>> {
+  fn get_constraints(&self, env: &DeserializeEnv<L>) -> RResult<Constraints<L>> {
     Ok(if let Some(constraints) = self.constraints.clone() {
-      try_deserialize_matchers(constraints, self.language.clone())?
+      try_deserialize_constraints(constraints, env)?
     } else {
-      MetaVarMatchers::default()
+      (MetaVarMatchers::default(), HashMap::new())
+    })
+  }
+
+  fn get_transforms(&self) -> RResult<HashMap<String, Transformation>> {
+    Ok(if let Some(transform) = self.transform.clone() {
+      try_deserialize_transforms(transform, &self.language)?
+    } else {
+      HashMap::new()
     })
   }
 
   pub fn get_matcher(&self, globals: &GlobalRules<L>) -> RResult<RuleWithConstraint<L>> {
     let env = self.get_deserialize_env(globals)?;
     let rule = deserialize_rule(self.rule.clone(), &env)?;
-    let matchers = self.get_meta_var_matchers()?;
+    // a top level `not` needs a positive term to define the candidates it filters
+    if !rule.is_positive() {
+      return Err(RuleSerializeError::MissPositiveMatcher.into());
+    }
+    let (matchers, rule_constraints) = self.get_constraints(&env)?;
+    let transforms = self.get_transforms()?;
     Ok(
       RuleWithConstraint::new(rule)
         .with_matchers(matchers)
+        .with_rule_constraints(rule_constraints)
+        .with_transforms(transforms)
         .with_utils(env.registration),
     )
   }
+
+  /// The rule with its local utils and the given global utils inlined.
+  /// A local util shadows a global one with the same id, and cyclic references are kept as `matches`.
+  pub fn rule_with_utils_inlined(
+    &self,
+    globals: &HashMap<String, SerializableRule>,
+  ) -> SerializableRule {
+    let mut utils = globals.clone();
+    utils.extend(self.utils.clone().unwrap_or_default());
+    self.rule.clone().inline_utils(&utils)
+  }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -80,34 +150,182 @@ pub struct SerializableRuleConfig<L: Language> {
   /// but specific enough to be understood without additional context.
   pub message: String,
   /// Additional notes to elaborate the message and provide potential fix to the issue.
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub note: Option<String>,
   /// One of: Info, Warning, or Error
   pub severity: Severity,
   /// A pattern to auto fix the issue. It can reference metavariables appeared in rule.
-  pub fix: Option<String>,
-  /// Glob patterns to specify that the rule only applies to matching files
+  /// A list of edits with their own targets, e.g. adding an import, is applied together.
+  /// A section like `{{#EXTRA}}, $EXTRA{{/EXTRA}}` is only emitted if `$EXTRA` is captured.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub fix: Option<SerializableFix>,
+  /// Surround the matched code with `before` and `after` as the fix.
+  /// Parentheses are added when the operator precedence requires. It cannot be used with `fix`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub wrap: Option<SerializableWrap>,
+  /// Glob patterns to specify that the rule only applies to matching files.
+  /// A glob starting with `!` excludes files, and the last matching glob takes precedence.
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub files: Option<Vec<String>>,
-  /// Glob patterns that exclude rules from applying to files
+  /// Glob patterns that exclude rules from applying to files.
+  /// A glob starting with `!` includes files again, and the last matching glob takes precedence.
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub ignores: Option<Vec<String>>,
   /// Documentation link to this rule
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub url: Option<String>,
-  /// Extra information for the rule
-  pub metadata: Option<HashMap<String, String>>,
+  /// Extra information for the rule, e.g. owner or ticket links.
+  /// It is a free-form map that does not affect matching and is passed through to JSON output.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub metadata: Option<HashMap<String, YamlValue>>,
+  /// Only report the first match in each file.
+  #[serde(default, rename = "firstPerFile", skip_serializing_if = "is_false")]
+  pub first_per_file: bool,
+  /// Only report the first match inside each node of the given kind, e.g. `function_declaration`.
+  /// Matches outside any such node share the file as their scope.
+  #[serde(rename = "firstPerScope", skip_serializing_if = "Option::is_none")]
+  pub first_per_scope: Option<String>,
+  /// Escalate the severity if the rule has more than `threshold` matches across the scan.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub escalate: Option<Escalate>,
+  /// Only report matches whose symbol is not imported by any scanned file.
+  #[serde(rename = "crossFile", skip_serializing_if = "Option::is_none")]
+  pub cross_file: Option<SerializableCrossFile>,
+  /// Milliseconds the rule may spend matching in one file.
+  /// Beyond it the rule is skipped for the rest of the file and its matches there are dropped.
+  /// The budget is checked between nodes, so it does not interrupt a single slow match,
+  /// e.g. an `inside` rule with `stopBy: end` walking up a deep tree.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub timeout: Option<u64>,
+}
+
+fn is_false(b: &bool) -> bool {
+  !b
 }
 
 type RResult<T> = std::result::Result<T, RuleConfigError>;
 
 impl<L: Language> SerializableRuleConfig<L> {
+  /// Names of the meta variables written in the rule and its local utils, and those defined
+  /// by `transform`. Only these can be tested by conditional sections of the fix.
+  fn captured_vars(&self) -> HashSet<String> {
+    let meta_char = self.language.meta_var_char();
+    let text = serde_yaml::to_string(&(&self.rule, &self.utils)).unwrap_or_default();
+    let mut vars: HashSet<_> = text
+      .split(meta_char)
+      .skip(1)
+      .filter_map(|s| {
+        let name: String = s
+          .trim_start_matches('?')
+          .chars()
+          .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+          .collect();
+        let var = format!("{meta_char}{name}");
+        match self.language.extract_meta_var(&var) {
+          Some(MetaVariable::Named(name, _)) => Some(name),
+          _ => None,
+        }
+      })
+      .collect();
+    vars.extend(self.transform.iter().flat_map(|t| t.keys().cloned()));
+    vars
+  }
+
   fn get_fixer(&self) -> RResult<Option<Pattern<L>>> {
     if let Some(fix) = &self.fix {
-      Ok(Some(Pattern::try_new(fix, self.language.clone())?))
+      if self.wrap.is_some() {
+        return Err(RuleConfigError::FixConflict);
+      }
+      match fix {
+        // a template with conditional sections is planned as a fix list of one edit
+        SerializableFix::Str(fix) if has_sections(fix, &self.captured_vars()) => Ok(None),
+        SerializableFix::Str(fix) => Ok(Some(Pattern::try_new(fix, self.language.clone())?)),
+        SerializableFix::List(_) => Ok(None),
+      }
+    } else if self.wrap.is_some() {
+      // the wrapped text is computed during matching and stored in a reserved meta variable
+      let fix = format!("{}{WRAPPED_VAR}", self.language.meta_var_char());
+      Ok(Some(Pattern::try_new(&fix, self.language.clone())?))
     } else {
       Ok(None)
     }
   }
 
+  fn get_fix_list(&self) -> RResult<Option<FixList<L>>> {
+    let vars = self.captured_vars();
+    Ok(match self.fix.clone() {
+      Some(SerializableFix::List(edits)) => Some(FixList::try_new(edits, &self.language, &vars)?),
+      Some(SerializableFix::Str(template)) if has_sections(&template, &vars) => {
+        let edit = SerializableFixEdit {
+          target: None,
+          template,
+        };
+        Some(FixList::try_new(vec![edit], &self.language, &vars)?)
+      }
+      _ => None,
+    })
+  }
+
+  fn get_wrap(&self) -> RResult<Option<Wrap>> {
+    Ok(match self.wrap.clone() {
+      Some(wrap) => Some(Wrap::try_new(wrap, &self.language)?),
+      None => None,
+    })
+  }
+
+  fn get_first_per(&self) -> RResult<Option<FirstPer<L>>> {
+    match (&self.first_per_scope, self.first_per_file) {
+      (Some(_), true) => Err(RuleConfigError::FirstPerConflict),
+      (Some(kind), false) => {
+        let kind = KindMatcher::try_new(kind, self.language.clone())
+          .map_err(RuleConfigError::FirstPerScope)?;
+        Ok(Some(FirstPer::Scope(kind)))
+      }
+      (None, true) => Ok(Some(FirstPer::File)),
+      (None, false) => Ok(None),
+    }
+  }
+
+  fn get_cross_file(&self, globals: &GlobalRules<L>) -> RResult<Option<CrossFile<L>>> {
+    let Some(cross_file) = self.cross_file.clone() else {
+      return Ok(None);
+    };
+    let env = self.get_deserialize_env(globals)?;
+    Ok(Some(CrossFile::try_new(cross_file, &env)?))
+  }
+
+  /// The effective severity given the number of matches across the scan.
+  pub fn severity_for_count(&self, count: usize) -> &Severity {
+    match &self.escalate {
+      Some(escalate) if count > escalate.threshold => &escalate.to,
+      _ => &self.severity,
+    }
+  }
+
+  /// Whether the time spent matching in one file exceeds the `timeout` of the rule.
+  pub fn timed_out(&self, elapsed: Duration) -> bool {
+    self
+      .timeout
+      .map_or(false, |ms| elapsed > Duration::from_millis(ms))
+  }
+
   fn get_message(&self, node: &NodeMatch<L>) -> String {
-    replace_meta_var_in_string(&self.message, node.get_env(), node.lang())
+    replace_meta_var_in_message(&self.message, node.get_env(), node.lang())
+  }
+
+  fn get_note(&self, node: &NodeMatch<L>) -> Option<String> {
+    let note = self.note.as_ref()?;
+    let env = node.get_env();
+    Some(replace_meta_var_in_message(note, env, node.lang()))
+  }
+
+  /// Returns a copy of the config whose rule has all local utils and the given global utils
+  /// inlined, e.g. the rules of `utilDirs`. References to other utils are kept as `matches`.
+  pub fn with_utils_inlined(&self, globals: &HashMap<String, SerializableRule>) -> Self {
+    let mut ret = self.clone();
+    ret.core.rule = self.rule_with_utils_inlined(globals);
+    ret.core.utils = None;
+    ret
   }
 }
 
@@ -134,12 +352,45 @@ pub enum RuleConfigError {
   Fixer(#[from] PatternError),
   #[error("constraints is not configured correctly.")]
   Constraints(#[from] SerializeConstraintsError),
+  #[error("transform is not configured correctly.")]
+  Transform(#[from] TransformError),
+  #[error("wrap is not configured correctly.")]
+  Wrap(#[from] WrapError),
+  #[error("fix list is not configured correctly.")]
+  FixList(#[from] FixError),
+  #[error("crossFile is not configured correctly.")]
+  CrossFile(#[from] CrossFileError),
+  #[error("fix and wrap cannot be used together.")]
+  FixConflict,
+  #[error("firstPerScope is not a valid kind.")]
+  FirstPerScope(#[source] KindMatcherError),
+  #[error("firstPerFile and firstPerScope cannot be used together.")]
+  FirstPerConflict,
+  #[error("Rule for all languages can only be a regex.")]
+  AllLanguages,
+  #[error("Rule for all languages is invalid.")]
+  TextRule(#[from] TextRuleError),
+  #[error("Base rule `{0}` is not found.")]
+  UnknownBase(String),
+  #[error("Rule extends itself through `{0}`.")]
+  CyclicExtends(String),
+  #[error("Rule #{0} is invalid.")]
+  InvalidRule(usize, #[source] Box<RuleConfigError>),
+}
+
+/// The scope in which only the first match of a rule is reported.
+enum FirstPer<L: Language> {
+  File,
+  Scope(KindMatcher<L>),
 }
 
 pub struct RuleConfig<L: Language> {
   inner: SerializableRuleConfig<L>,
   pub matcher: RuleWithConstraint<L>,
   pub fixer: Option<Pattern<L>>,
+  fix_list: Option<FixList<L>>,
+  first_per: Option<FirstPer<L>>,
+  cross_file: Option<CrossFile<L>>,
 }
 
 impl<L: Language> RuleConfig<L> {
@@ -155,12 +406,18 @@ impl<L: Language> RuleConfig<L> {
     inner: SerializableRuleConfig<L>,
     globals: &GlobalRules<L>,
   ) -> Result<Self, RuleConfigError> {
-    let matcher = inner.get_matcher(globals)?;
+    let matcher = inner.get_matcher(globals)?.with_wrap(inner.get_wrap()?);
     let fixer = inner.get_fixer()?;
+    let fix_list = inner.get_fix_list()?;
+    let first_per = inner.get_first_per()?;
+    let cross_file = inner.get_cross_file(globals)?;
     Ok(Self {
       inner,
       matcher,
       fixer,
+      fix_list,
+      first_per,
+      cross_file,
     })
   }
 
@@ -178,6 +435,83 @@ impl<L: Language> RuleConfig<L> {
   pub fn get_message(&self, node: &NodeMatch<L>) -> String {
     self.inner.get_message(node)
   }
+
+  /// The note with meta variables replaced by the captured text of the match.
+  pub fn get_note(&self, node: &NodeMatch<L>) -> Option<String> {
+    self.inner.get_note(node)
+  }
+
+  pub fn has_fix(&self) -> bool {
+    self.fixer.is_some() || self.fix_list.is_some()
+  }
+
+  /// Edits of the fix for the match sorted by position, or `None` if the rule has no fix
+  /// or the edits of a fix list cannot be applied together.
+  pub fn fix_edits(&self, nm: &NodeMatch<L>) -> Option<Vec<Edit>> {
+    if let Some(fix_list) = &self.fix_list {
+      return fix_list.plan(nm, &self.matcher);
+    }
+    let fixer = self.fixer.as_ref()?;
+    Some(vec![nm.replace(&self.matcher, fixer)?])
+  }
+
+  /// Whether the rule needs a [`SymbolIndex`] of all scanned files to run.
+  pub fn is_cross_file(&self) -> bool {
+    self.cross_file.is_some()
+  }
+
+  /// Modules and symbols imported under the root by the `crossFile` imports, empty for other rules.
+  pub fn imported_symbols(&self, path: &Path, root: Node<L>) -> Vec<(PathBuf, String)> {
+    match &self.cross_file {
+      Some(cross_file) => cross_file.imported_symbols(path, root),
+      None => vec![],
+    }
+  }
+
+  /// Whether the match in the file at `path` is reported given the imports of all scanned files.
+  /// A match of a cross-file rule is dropped if its symbol is imported from the file,
+  /// and also without an index since a single file cannot tell whether the symbol is used.
+  pub fn passes_index(&self, nm: &NodeMatch<L>, path: &Path, index: Option<&SymbolIndex>) -> bool {
+    let Some(cross_file) = &self.cross_file else {
+      return true;
+    };
+    let Some(index) = index else {
+      return false;
+    };
+    match cross_file.symbol_of(nm) {
+      Some(symbol) => !index.is_imported(&self.id, path, &symbol),
+      None => true,
+    }
+  }
+
+  /// Id of the scope node in which only the first match is reported,
+  /// or `None` if the rule reports every match.
+  pub fn first_match_scope(&self, node: &Node<L>) -> Option<usize> {
+    let scope = match self.first_per.as_ref()? {
+      FirstPer::File => None,
+      FirstPer::Scope(kind) => node.ancestors().find(|n| n.matches(kind)),
+    };
+    let scope = scope
+      .or_else(|| node.ancestors().last())
+      .unwrap_or_else(|| node.clone());
+    Some(scope.node_id())
+  }
+
+  /// Keep only the first match in each scope if `firstPerFile` or `firstPerScope` is set.
+  /// Matches must be in document order.
+  pub fn filter_first_per_scope<'t>(
+    &self,
+    matches: impl IntoIterator<Item = NodeMatch<'t, L>>,
+  ) -> Vec<NodeMatch<'t, L>> {
+    let mut seen = HashSet::new();
+    matches
+      .into_iter()
+      .filter(|nm| match self.first_match_scope(nm) {
+        Some(scope) => seen.insert(scope),
+        None => true,
+      })
+      .collect()
+  }
 }
 impl<L: Language> Deref for RuleConfig<L> {
   type Target = SerializableRuleConfig<L>;
@@ -189,8 +523,8 @@ impl<L: Language> Deref for RuleConfig<L> {
 #[cfg(test)]
 mod test {
   use super::*;
-  use crate::from_str;
   use crate::test::TypeScript;
+  use crate::{from_str, from_yaml_string};
 
   fn ts_rule_config(rule: SerializableRule) -> SerializableRuleConfig<TypeScript> {
     let core = SerializableRuleCore {
@@ -198,7 +532,10 @@ mod test {
       language: TypeScript::Tsx,
       rule,
       constraints: None,
+      transform: None,
       utils: None,
+      ignore_case: false,
+      word: false,
     };
     SerializableRuleConfig {
       core,
@@ -206,10 +543,16 @@ mod test {
       note: None,
       severity: Severity::Hint,
       fix: None,
+      wrap: None,
       files: None,
       ignores: None,
       url: None,
       metadata: None,
+      first_per_file: false,
+      first_per_scope: None,
+      escalate: None,
+      cross_file: None,
+      timeout: None,
     }
   }
 
@@ -226,6 +569,17 @@ mod test {
       .find(config.get_matcher(&globals).unwrap())
       .expect("should find match");
     assert_eq!(config.get_message(&node_match), "Found TestClass");
+    assert_eq!(config.get_note(&node_match), None);
+    config.message = "Avoid `$A` in $FILE".into();
+    config.note = Some("Rename $A.".into());
+    assert_eq!(
+      config.get_message(&node_match),
+      "Avoid `TestClass` in $FILE"
+    );
+    assert_eq!(
+      config.get_note(&node_match).as_deref(),
+      Some("Rename TestClass.")
+    );
   }
 
   #[test]
@@ -322,4 +676,300 @@ test-rule:
     let grep = TypeScript::Tsx.ast_grep("some()");
     assert!(grep.root().find(&matcher).is_none());
   }
+
+  #[test]
+  fn test_inline_utils() {
+    let config = get_matches_config().with_utils_inlined(&HashMap::new());
+    assert!(config.utils.is_none());
+    let yaml = serde_yaml::to_string(&config.rule).expect("should serialize");
+    assert_eq!(yaml, "pattern: some($A)\n");
+    let globals = GlobalRules::default();
+    let matcher = config.get_matcher(&globals).unwrap();
+    let grep = TypeScript::Tsx.ast_grep("some(123)");
+    assert!(grep.root().find(&matcher).is_some());
+  }
+
+  #[test]
+  fn test_inline_nested_utils() {
+    let rule = from_str(
+      "
+kind: call_expression
+has: { matches: a }
+",
+    )
+    .unwrap();
+    let utils = from_str(
+      "
+a: { any: [{ matches: b }, { matches: global }] }
+b: { pattern: b, not: { matches: a } }
+",
+    )
+    .unwrap();
+    let mut config = ts_rule_config(rule);
+    config.utils = Some(utils);
+    let inlined = config.with_utils_inlined(&HashMap::new());
+    let yaml = serde_yaml::to_string(&inlined.rule).expect("should serialize");
+    // cyclic and unknown references are kept as is
+    assert!(yaml.contains("pattern: b"));
+    assert!(yaml.contains("matches: a"));
+    assert!(yaml.contains("matches: global"));
+    assert!(!yaml.contains("matches: b"));
+    let globals = from_str("global: { kind: number }").unwrap();
+    let inlined = config.with_utils_inlined(&globals);
+    let yaml = serde_yaml::to_string(&inlined.rule).expect("should serialize");
+    assert!(yaml.contains("kind: number"));
+    assert!(!yaml.contains("matches: global"));
+  }
+
+  #[test]
+  fn test_transform_fix() {
+    let globals = GlobalRules::default();
+    let rule = from_str("pattern: log($$$ARGS)").expect("should parse");
+    let mut config = ts_rule_config(rule);
+    config.transform = Some(
+      from_str(
+        "WRAPPED: { splice: { source: $$$ARGS, template: String($ARGS), separator: ', ' } }",
+      )
+      .expect("should parse"),
+    );
+    config.message = "wrap $WRAPPED".into();
+    config.fix = Some(SerializableFix::Str("log($WRAPPED)".into()));
+    let config = RuleConfig::try_from(config, &globals).expect("should work");
+    let grep = TypeScript::Tsx.ast_grep("log(a, b.c, 1)");
+    let nm = grep.root().find(&config.matcher).expect("should match");
+    let fixer = config.fixer.as_ref().expect("should have fixer");
+    let fixed = nm.replace_by(fixer).inserted_text;
+    assert_eq!(fixed, "log(String(a), String(b.c), String(1))");
+    assert_eq!(
+      config.get_message(&nm),
+      "wrap String(a), String(b.c), String(1)"
+    );
+  }
+
+  #[test]
+  fn test_invalid_transform() {
+    let globals = GlobalRules::default();
+    let rule = from_str("pattern: log($$$ARGS)").expect("should parse");
+    let mut config = ts_rule_config(rule);
+    config.transform =
+      Some(from_str("A: { join: { source: ARGS, separator: ',' } }").expect("should parse"));
+    let ret = RuleConfig::try_from(config, &globals);
+    assert!(matches!(ret, Err(RuleConfigError::Transform(_))));
+  }
+
+  fn wrap_fix(wrap: &str, src: &str) -> String {
+    let globals = GlobalRules::default();
+    let rule = from_str("pattern: log($EXPR)").expect("should parse");
+    let mut config = ts_rule_config(rule);
+    config.wrap = Some(from_str(wrap).expect("should parse"));
+    let config = RuleConfig::try_from(config, &globals).expect("should work");
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let nm = grep.root().find(&config.matcher).expect("should match");
+    let fixer = config.fixer.as_ref().expect("should have fixer");
+    nm.replace_by(fixer).inserted_text
+  }
+
+  #[test]
+  fn test_wrap_fix() {
+    assert_eq!(wrap_fix("before: '-'", "log(a + b)"), "-log(a + b)");
+    assert_eq!(
+      wrap_fix("before: 'await '", "log(a + b)"),
+      "await log(a + b)"
+    );
+    let wrap = "before: 'logger.debug('\nafter: ', $EXPR)'";
+    assert_eq!(wrap_fix(wrap, "log(a)"), "logger.debug(log(a), a)");
+  }
+
+  #[test]
+  fn test_fix_conflict() {
+    let globals = GlobalRules::default();
+    let rule = from_str("pattern: log($EXPR)").expect("should parse");
+    let mut config = ts_rule_config(rule);
+    config.fix = Some(SerializableFix::Str("$EXPR".into()));
+    config.wrap = Some(from_str("before: 'await '").expect("should parse"));
+    let ret = RuleConfig::try_from(config, &globals);
+    assert!(matches!(ret, Err(RuleConfigError::FixConflict)));
+  }
+
+  #[test]
+  fn test_top_level_not() {
+    let globals = GlobalRules::default();
+    let rule = from_str(
+      "
+kind: export_statement
+not:
+  follows:
+    kind: comment
+    stopBy: neighbor",
+    )
+    .expect("should parse");
+    let config = RuleConfig::try_from(ts_rule_config(rule), &globals).expect("should work");
+    let src = "
+/** documented */
+export function a() {}
+export function b() {}
+// documented
+export const c = 1
+function d() {}
+export class E {}";
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let undocumented: Vec<_> = grep
+      .root()
+      .find_all(&config.matcher)
+      .map(|n| n.text().to_string())
+      .collect();
+    assert_eq!(
+      undocumented,
+      ["export function b() {}", "export class E {}"]
+    );
+  }
+
+  #[test]
+  fn test_not_without_candidate() {
+    let globals = GlobalRules::default();
+    let rules = [
+      "not: { kind: comment }",
+      "all: [{ not: { kind: comment } }]",
+    ];
+    for rule in rules {
+      let rule = from_str(rule).expect("should parse");
+      let ret = RuleConfig::try_from(ts_rule_config(rule), &globals);
+      assert!(matches!(
+        ret,
+        Err(RuleConfigError::Rule(
+          RuleSerializeError::MissPositiveMatcher
+        ))
+      ));
+    }
+    let rule = from_str("any: [{ kind: comment }, { not: { kind: comment } }]").unwrap();
+    assert!(RuleConfig::try_from(ts_rule_config(rule), &globals).is_err());
+  }
+
+  fn first_matches(first_per: &str, src: &str) -> Vec<String> {
+    let globals = GlobalRules::default();
+    let yaml = format!(
+      "id: test\nmessage: test\nseverity: hint\nlanguage: Tsx\n{first_per}\nrule:\n  pattern: console.log($A)"
+    );
+    let config = from_str(&yaml).expect("should parse");
+    let config = RuleConfig::try_from(config, &globals).expect("should work");
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let matches = grep.root().find_all(&config.matcher);
+    config
+      .filter_first_per_scope(matches)
+      .into_iter()
+      .map(|nm| nm.get_env().get_match("A").unwrap().text().to_string())
+      .collect()
+  }
+
+  #[test]
+  fn test_first_per_file() {
+    let src = "console.log(1)\nfunction a() { console.log(2) }\nconsole.log(3)";
+    assert_eq!(first_matches("firstPerFile: true", src), ["1"]);
+    assert_eq!(first_matches("firstPerFile: false", src), ["1", "2", "3"]);
+  }
+
+  #[test]
+  fn test_first_per_scope() {
+    let src = "
+function a() { console.log(1); console.log(2) }
+function b() { console.log(3); console.log(4) }
+console.log(5)
+console.log(6)";
+    let first = first_matches("firstPerScope: function_declaration", src);
+    assert_eq!(first, ["1", "3", "5"]);
+  }
+
+  #[test]
+  fn test_invalid_first_per() {
+    let globals = GlobalRules::default();
+    let rule = from_str("pattern: log($EXPR)").expect("should parse");
+    let mut config = ts_rule_config(rule);
+    config.first_per_scope = Some("not_a_kind".into());
+    let ret = RuleConfig::try_from(config.clone(), &globals);
+    assert!(matches!(ret, Err(RuleConfigError::FirstPerScope(_))));
+    config.first_per_scope = Some("function_declaration".into());
+    config.first_per_file = true;
+    let ret = RuleConfig::try_from(config, &globals);
+    assert!(matches!(ret, Err(RuleConfigError::FirstPerConflict)));
+  }
+
+  #[test]
+  fn test_ignore_case() {
+    let yaml = "
+id: no-get-url
+message: test
+severity: hint
+language: Tsx
+ignoreCase: true
+rule: { pattern: getUrl($A) }
+---
+id: no-fetch
+message: test
+severity: hint
+language: Tsx
+rule: { pattern: fetchData($A) }
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let grep = TypeScript::Tsx.ast_grep("getURL(a); FETCHDATA(b); fetchData(c)");
+    let count = |rule: &RuleConfig<TypeScript>| grep.root().find_all(&rule.matcher).count();
+    assert_eq!(count(&rules[0]), 1);
+    // other rules still match exactly
+    assert_eq!(count(&rules[1]), 1);
+    let yaml = yaml.replace("ignoreCase: true", "ignoreCase: false");
+    let rules = from_yaml_string(&yaml, &GlobalRules::default()).expect("should parse");
+    assert_eq!(count(&rules[0]), 0);
+  }
+
+  #[test]
+  fn test_ignore_case_constraints() {
+    let yaml = "
+id: no-get-url
+message: test
+severity: hint
+language: Tsx
+ignoreCase: true
+rule: { pattern: $F($A) }
+constraints: { F: { pattern: getUrl } }
+";
+    let grep = TypeScript::Tsx.ast_grep("getURL(a); fetch(b)");
+    let count = |yaml: &str| {
+      let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+      grep.root().find_all(&rules[0].matcher).count()
+    };
+    assert_eq!(count(yaml), 1);
+    let yaml = yaml.replace("ignoreCase: true", "ignoreCase: false");
+    assert_eq!(count(&yaml), 0);
+  }
+
+  #[test]
+  fn test_word() {
+    let yaml = "
+id: no-foo
+message: test
+severity: hint
+language: Tsx
+word: true
+rule: { kind: identifier, regex: foo }
+---
+id: no-foo-call
+message: test
+severity: hint
+language: Tsx
+word: true
+rule: { pattern: $F($A) }
+constraints: { F: { regex: foo } }
+";
+    let rules = from_yaml_string(yaml, &GlobalRules::default()).expect("should parse");
+    let grep = TypeScript::Tsx.ast_grep("foobar(a); foo_bar(b); foo(c); a.foo(d)");
+    let texts = |rule: &RuleConfig<TypeScript>| {
+      let matches = grep.root().find_all(&rule.matcher);
+      matches.map(|m| m.text().to_string()).collect::<Vec<_>>()
+    };
+    assert_eq!(texts(&rules[0]), ["foo"]);
+    assert_eq!(texts(&rules[1]), ["foo(c)", "a.foo(d)"]);
+    let yaml = yaml.replace("word: true", "word: false");
+    let rules = from_yaml_string(&yaml, &GlobalRules::default()).expect("should parse");
+    assert_eq!(texts(&rules[0]), ["foobar", "foo_bar", "foo"]);
+    assert_eq!(texts(&rules[1]).len(), 4);
+  }
 }
@@ -0,0 +1,355 @@
+use ast_grep_core::language::{Language, StringEscape};
+use ast_grep_core::matcher::{MatchTrace, RegexMatcherError};
+use ast_grep_core::meta_var::MetaVarEnv;
+use ast_grep_core::{Matcher, Node};
+
+use bit_set::BitSet;
+use regex::Regex;
+
+use std::iter::Peekable;
+use std::marker::PhantomData;
+use std::str::Chars;
+
+/// A string literal split into its delimiters and body, e.g. `r#"a"#` or `'''a'''`.
+/// Prefixes like `r`, `b` or `f` are kept as part of the opening delimiter.
+pub struct StringLiteral<'a> {
+  open: &'a str,
+  body: &'a str,
+  close: &'a str,
+  quote: char,
+  /// raw strings like `r"\d"` have no escape sequences
+  raw: bool,
+  /// triple quoted and template strings can contain quotes and newlines
+  multiline: bool,
+  escape: StringEscape,
+}
+
+impl<'a> StringLiteral<'a> {
+  pub fn parse(text: &'a str, escape: StringEscape) -> Option<Self> {
+    let start = text.find(['"', '\'', '`'])?;
+    let prefix = &text[..start];
+    if !prefix.chars().all(|c| c.is_ascii_alphabetic() || c == '#') {
+      return None;
+    }
+    let quote = text[start..].chars().next()?;
+    let triple: String = std::iter::repeat(quote).take(3).collect();
+    let rest = &text[start..];
+    let quote_len = if rest.len() >= 6 && rest.starts_with(&triple) {
+      3
+    } else {
+      1
+    };
+    let hashes = prefix.chars().filter(|&c| c == '#').count();
+    let open_len = start + quote_len;
+    let close_len = quote_len + hashes;
+    if text.len() < open_len + close_len {
+      return None;
+    }
+    let close = &text[text.len() - close_len..];
+    if !close.starts_with(quote)
+      || close.trim_start_matches(quote) != &prefix[prefix.len() - hashes..]
+    {
+      return None;
+    }
+    Some(Self {
+      open: &text[..open_len],
+      body: &text[open_len..text.len() - close_len],
+      close,
+      quote,
+      raw: prefix.contains(['r', 'R']),
+      multiline: quote_len == 3 || quote == '`',
+      escape,
+    })
+  }
+
+  /// The string value with escape sequences resolved by the language's rules.
+  /// Invalid escape sequences are kept as written.
+  pub fn content(&self) -> String {
+    use StringEscape as E;
+    if self.raw {
+      return self.body.to_string();
+    }
+    let mut ret = String::new();
+    let mut chars = self.body.chars().peekable();
+    while let Some(c) = chars.next() {
+      if c != '\\' {
+        ret.push(c);
+        continue;
+      }
+      let Some(next) = chars.next() else {
+        ret.push(c);
+        break;
+      };
+      match next {
+        'n' => ret.push('\n'),
+        't' => ret.push('\t'),
+        'r' => ret.push('\r'),
+        '0' => ret.push('\0'),
+        '\n' if self.escape == E::Rust => while chars.next_if(|c| c.is_whitespace()).is_some() {},
+        '\n' => (),
+        'x' | 'u' | 'U' => match self.unescape_code(next, &mut chars) {
+          Some(ch) => ret.push(ch),
+          None => {
+            ret.push(c);
+            ret.push(next);
+          }
+        },
+        '\\' | '\'' | '"' => ret.push(next),
+        // JavaScript drops the backslash of any other character, e.g. `\$` in templates
+        _ if self.escape == E::JavaScript => ret.push(next),
+        _ => {
+          ret.push(c);
+          ret.push(next);
+        }
+      }
+    }
+    ret
+  }
+
+  /// The literal with the same delimiters and a new content, escaped as needed.
+  /// Raw strings cannot escape anything so the content is inserted verbatim.
+  /// In template strings `${` is kept as is, so it starts a substitution.
+  pub fn with_content(&self, content: &str) -> String {
+    let mut ret = self.open.to_string();
+    if self.raw {
+      ret.push_str(content);
+      ret.push_str(self.close);
+      return ret;
+    }
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+      match c {
+        '\\' => ret.push_str("\\\\"),
+        '\n' if !self.multiline => ret.push_str("\\n"),
+        '\r' if !self.multiline => ret.push_str("\\r"),
+        '\t' => ret.push_str("\\t"),
+        '\0' => ret.push_str("\\0"),
+        c if c == self.quote && self.needs_escape(chars.peek()) => {
+          ret.push('\\');
+          ret.push(c);
+        }
+        c => ret.push(c),
+      }
+    }
+    ret.push_str(self.close);
+    ret
+  }
+
+  /// Whether the quote character must be escaped given the character after it.
+  /// Quotes in triple quoted strings are escaped only if they could end the string.
+  fn needs_escape(&self, next: Option<&char>) -> bool {
+    if self.quote == '`' || !self.multiline {
+      return true;
+    }
+    next.map_or(true, |&n| n == self.quote)
+  }
+
+  /// Resolve `\xHH`, `\uHHHH`, `\UHHHHHHHH` or `\u{H..}` after the backslash and the letter.
+  /// Forms the language does not have are left unresolved.
+  fn unescape_code(&self, letter: char, chars: &mut Peekable<Chars>) -> Option<char> {
+    use StringEscape as E;
+    let mut lookahead = chars.clone();
+    let digits: String = if letter == 'u' && lookahead.peek() == Some(&'{') {
+      if !matches!(self.escape, E::JavaScript | E::Rust) {
+        return None;
+      }
+      lookahead.next();
+      lookahead.by_ref().take_while(|&c| c != '}').collect()
+    } else {
+      let len = match (letter, self.escape) {
+        ('x', _) => 2,
+        ('u', E::Rust) | ('U', E::JavaScript | E::Rust) => return None,
+        ('u', _) => 4,
+        _ => 8,
+      };
+      let digits: String = lookahead.by_ref().take(len).collect();
+      if digits.len() != len {
+        return None;
+      }
+      digits
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+      return None;
+    }
+    let ch = char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?;
+    *chars = lookahead;
+    Some(ch)
+  }
+}
+
+/// Kinds of string literal nodes in the supported grammars.
+const STRING_KINDS: &[&str] = &[
+  "string",
+  "template_string",
+  "string_literal",
+  "raw_string_literal",
+  "interpreted_string_literal",
+  "verbatim_string_literal",
+  "line_string_literal",
+  "multi_line_string_literal",
+  "string_value",
+];
+
+/// Match a string literal whose content, with escape sequences resolved, matches the regex.
+#[derive(Clone)]
+pub struct StringContent<L: Language> {
+  regex: Regex,
+  /// string literal kinds that exist in the language
+  kinds: BitSet,
+  escape: StringEscape,
+  lang: PhantomData<L>,
+}
+
+impl<L: Language> StringContent<L> {
+  pub fn try_new(regex: &str, lang: L) -> Result<Self, RegexMatcherError> {
+    let kinds = STRING_KINDS
+      .iter()
+      .filter_map(|kind| lang.kind_id_for(kind))
+      .map(usize::from)
+      .collect();
+    Ok(Self {
+      regex: Regex::new(regex)?,
+      kinds,
+      escape: lang.string_escape(),
+      lang: PhantomData,
+    })
+  }
+}
+
+impl<L: Language> Matcher<L> for StringContent<L> {
+  fn match_node_with_env<'tree>(
+    &self,
+    node: Node<'tree, L>,
+    _env: &mut MetaVarEnv<'tree, L>,
+  ) -> Option<Node<'tree, L>> {
+    if !self.kinds.contains(node.kind_id().into()) {
+      return None;
+    }
+    let text = node.text();
+    let literal = StringLiteral::parse(&text, self.escape)?;
+    self.regex.is_match(&literal.content()).then_some(node)
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    Some(self.kinds.clone())
+  }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let label = format!("stringContent: {}", self.regex.as_str());
+    MatchTrace::from_match(label, self.match_node(node))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::test::TypeScript;
+  use StringEscape as E;
+
+  fn content(text: &str, escape: StringEscape) -> String {
+    let literal = StringLiteral::parse(text, escape);
+    literal.expect("should parse").content()
+  }
+
+  fn rewrite(text: &str, escape: StringEscape, new_content: &str) -> String {
+    let literal = StringLiteral::parse(text, escape);
+    literal.expect("should parse").with_content(new_content)
+  }
+
+  #[test]
+  fn test_unescape() {
+    assert_eq!(content(r#""a\tb\"c\"""#, E::C), "a\tb\"c\"");
+    assert_eq!(content(r"'it\'s'", E::C), "it's");
+    assert_eq!(content(r#""\d+\\""#, E::C), r"\d+\");
+    assert_eq!(content(r#"r"\d+""#, E::Rust), r"\d+");
+    assert_eq!(content(r##"r#"a "b" c"#"##, E::Rust), r#"a "b" c"#);
+    assert_eq!(content(r#"'''a'b'''"#, E::Python), "a'b");
+    assert_eq!(content("``", E::JavaScript), "");
+    assert!(StringLiteral::parse("abc", E::C).is_none());
+    assert!(StringLiteral::parse("a + \"b\"", E::C).is_none());
+  }
+
+  #[test]
+  fn test_unescape_js() {
+    assert_eq!(
+      content(r#""\x2FapiA\u0041\u{1F600}""#, E::JavaScript),
+      "/apiAA😀"
+    );
+    // a backslash before another character is dropped
+    assert_eq!(content(r#""C:\dir""#, E::JavaScript), "C:dir");
+    assert_eq!(content(r"`a \${b}`", E::JavaScript), "a ${b}");
+  }
+
+  #[test]
+  fn test_unescape_python() {
+    assert_eq!(content(r#""\x41\u0042\U0001F600""#, E::Python), "AB😀");
+    // unknown escapes keep the backslash
+    assert_eq!(content(r#""C:\dir""#, E::Python), r"C:\dir");
+    assert_eq!(content(r#""\u{41}""#, E::Python), r"\u{41}");
+  }
+
+  #[test]
+  fn test_unescape_rust() {
+    assert_eq!(content(r#""\x41\u{1F600}""#, E::Rust), "A😀");
+    // `\u` needs braces in Rust
+    assert_eq!(content(r#""\u0041""#, E::Rust), r"\u0041");
+    assert_eq!(content("\"a \\\n    b\"", E::Rust), "a b");
+    assert_eq!(content("\"a \\\n    b\"", E::Python), "a     b");
+  }
+
+  #[test]
+  fn test_escape_roundtrip() {
+    for (text, escape) in [
+      (r#""a\tb\"c\"""#, E::JavaScript),
+      (r"'it\'s'", E::JavaScript),
+      (r#""\\d+\\""#, E::JavaScript),
+      ("`a ${b}`", E::JavaScript),
+      (r#"r"\d+""#, E::Rust),
+      (r#"b"bytes\n""#, E::Rust),
+      (r#""\\d+\\""#, E::Python),
+      (r#"'''a'b'''"#, E::Python),
+    ] {
+      let literal = StringLiteral::parse(text, escape).expect("should parse");
+      assert_eq!(literal.with_content(&literal.content()), text);
+    }
+  }
+
+  #[test]
+  fn test_escape_new_content() {
+    assert_eq!(
+      rewrite(r#""a""#, E::JavaScript, "say \"hi\"\n\\"),
+      r#""say \"hi\"\n\\""#
+    );
+    assert_eq!(
+      rewrite("'a'", E::JavaScript, "it's \"ok\""),
+      r#"'it\'s "ok"'"#
+    );
+  }
+
+  #[test]
+  fn test_escape_backslash() {
+    // a backslash is always escaped, so every language reads it back as written
+    for escape in [E::C, E::JavaScript, E::Python, E::Rust] {
+      let rewritten = rewrite(r#""a""#, escape, r"C:\dir");
+      assert_eq!(rewritten, r#""C:\\dir""#);
+      assert_eq!(content(&rewritten, escape), r"C:\dir");
+    }
+    assert_eq!(rewrite(r#"r"a""#, E::Rust, r"C:\dir"), r#"r"C:\dir""#);
+  }
+
+  #[test]
+  fn test_string_content_matcher() {
+    let matcher = StringContent::try_new("^/api/", TypeScript::Tsx).expect("should compile");
+    let kinds = matcher.potential_kinds().expect("should have string kinds");
+    let string = TypeScript::Tsx.kind_id_for("string").expect("should exist");
+    assert!(kinds.contains(string.into()));
+    let grep =
+      TypeScript::Tsx.ast_grep(r#"a("/api/x"); b('\x2Fapi/y'); c("/web/api/"); d(`/api/${z}`)"#);
+    let found: Vec<_> = grep
+      .root()
+      .find_all(&matcher)
+      .map(|n| n.text().to_string())
+      .collect();
+    assert_eq!(found, [r#""/api/x""#, r"'\x2Fapi/y'", "`/api/${z}`"]);
+  }
+}
@@ -0,0 +1,166 @@
+use crate::Severity;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::ops::{Deref, Range};
+
+/// The only language name of a text rule.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum AllLanguages {
+  All,
+}
+
+/// A text rule only has a `regex`, since structural rules need a parser for the language.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SerializableTextPattern {
+  pub regex: String,
+}
+
+/// A rule with `language: all`. It checks the raw text of every scanned file,
+/// including files in languages without a parser, e.g. for leftover `FIXME`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SerializableTextRule {
+  pub id: String,
+  pub language: AllLanguages,
+  pub rule: SerializableTextPattern,
+  /// Main message highlighting why this rule fired.
+  pub message: String,
+  /// Additional notes to elaborate the message and provide potential fix to the issue.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub note: Option<String>,
+  /// One of: Info, Warning, or Error
+  pub severity: Severity,
+  /// Documentation link to this rule
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub url: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum TextRuleError {
+  #[error("regex is invalid.")]
+  Regex(#[from] regex::Error),
+}
+
+pub struct TextRule {
+  inner: SerializableTextRule,
+  regex: Regex,
+}
+
+impl TextRule {
+  pub fn try_new(inner: SerializableTextRule) -> Result<Self, TextRuleError> {
+    let regex = Regex::new(&inner.rule.regex)?;
+    Ok(Self { inner, regex })
+  }
+
+  /// Non-overlapping matches of the regex in the text, in order.
+  pub fn find_all<'t>(&'t self, text: &'t str) -> impl Iterator<Item = TextMatch<'t>> + 't {
+    self
+      .regex
+      .find_iter(text)
+      .map(move |m| TextMatch::new(text, m.range()))
+  }
+}
+
+impl Deref for TextRule {
+  type Target = SerializableTextRule;
+  fn deref(&self) -> &Self::Target {
+    &self.inner
+  }
+}
+
+/// A match of a text rule. Positions are zero-based lines and byte columns like those of nodes.
+#[derive(Clone)]
+pub struct TextMatch<'t> {
+  source: &'t str,
+  range: Range<usize>,
+}
+
+impl<'t> TextMatch<'t> {
+  pub fn new(source: &'t str, range: Range<usize>) -> Self {
+    Self { source, range }
+  }
+
+  /// The whole text the match is found in.
+  pub fn source(&self) -> &'t str {
+    self.source
+  }
+
+  pub fn text(&self) -> &'t str {
+    &self.source[self.range.clone()]
+  }
+
+  pub fn range(&self) -> Range<usize> {
+    self.range.clone()
+  }
+
+  pub fn start_pos(&self) -> (usize, usize) {
+    self.pos(self.range.start)
+  }
+
+  pub fn end_pos(&self) -> (usize, usize) {
+    self.pos(self.range.end)
+  }
+
+  fn pos(&self, offset: usize) -> (usize, usize) {
+    let before = &self.source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count(), offset - line_start)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::from_str;
+
+  fn text_rule(regex: &str) -> TextRule {
+    let yaml = format!(
+      "{{ id: fixme, language: all, message: test, severity: info, rule: {{ regex: '{regex}' }} }}"
+    );
+    TextRule::try_new(from_str(&yaml).expect("should parse")).expect("should build")
+  }
+
+  #[test]
+  fn test_find_all() {
+    let rule = text_rule("FIXME");
+    let src = "let a = 1 // FIXME\n\n  # FIXME: é FIXME";
+    let found: Vec<_> = rule
+      .find_all(src)
+      .map(|m| (m.start_pos(), m.end_pos(), m.text()))
+      .collect();
+    assert_eq!(
+      found,
+      [
+        ((0, 13), (0, 18), "FIXME"),
+        ((2, 4), (2, 9), "FIXME"),
+        ((2, 14), (2, 19), "FIXME"),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_multiline_match() {
+    let rule = text_rule("a\\nb");
+    let found: Vec<_> = rule.find_all("xa\nby").collect();
+    assert_eq!(found[0].range(), 1..4);
+    assert_eq!(found[0].start_pos(), (0, 1));
+    assert_eq!(found[0].end_pos(), (1, 1));
+  }
+
+  #[test]
+  fn test_only_regex() {
+    let yaml = "{ id: fixme, language: all, message: test, severity: info, rule: { pattern: a } }";
+    assert!(from_str::<SerializableTextRule>(yaml).is_err());
+    let yaml = "{ id: fixme, language: all, message: test, severity: info, rule: { regex: '(' } }";
+    let rule = from_str(yaml).expect("should parse");
+    assert!(matches!(
+      TextRule::try_new(rule),
+      Err(TextRuleError::Regex(_))
+    ));
+  }
+}
@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+
+use ast_grep_core::language::Language;
+use ast_grep_core::meta_var::{MetaVarEnv, MetaVariable, MetaVariableID};
+use ast_grep_core::replace_meta_var_in_string;
+use regex::Regex;
+use thiserror::Error;
+
+use crate::string_content::StringLiteral;
+
+use std::collections::HashMap;
+
+/// Compute a new meta variable from captured ones. The result can be used in `fix` and `message`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum SerializableTransformation {
+  /// Rewrite each element of a captured list with a template and join the results.
+  Splice(Splice),
+  /// Join the text of each element of a captured list.
+  Join(Join),
+  /// Replace regex matches in the content of a captured string literal.
+  /// The result is the literal with the same quotes and the new content escaped.
+  ReplaceString(ReplaceString),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Splice {
+  /// The captured meta variable to transform, e.g. `$$$ARGS`.
+  pub source: String,
+  /// Template applied to each element. The source name, e.g. `$ARGS`, refers to the element.
+  pub template: String,
+  /// Text inserted between transformed elements.
+  pub separator: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Join {
+  /// The captured meta variable to join, e.g. `$$$ARGS`.
+  pub source: String,
+  /// Text inserted between elements.
+  pub separator: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReplaceString {
+  /// The captured string literal, e.g. `$STR`.
+  pub source: String,
+  /// Regex to find in the string content with escape sequences resolved.
+  pub replace: String,
+  /// Replacement text. Regex groups can be referenced like `$1` or `${name}`.
+  pub by: String,
+}
+
+#[derive(Debug, Error)]
+pub enum TransformError {
+  #[error("`{0}` is not a captured meta variable.")]
+  MalformedSource(String),
+  #[error("Cannot parse the regex in replaceString.")]
+  Regex(#[from] regex::Error),
+}
+
+enum Transform {
+  /// splice with a template, or join without one
+  Splice {
+    template: Option<String>,
+    separator: String,
+  },
+  ReplaceString {
+    regex: Regex,
+    by: String,
+  },
+}
+
+pub struct Transformation {
+  source: MetaVariableID,
+  transform: Transform,
+}
+
+impl Transformation {
+  pub fn try_new<L: Language>(
+    trans: SerializableTransformation,
+    lang: &L,
+  ) -> Result<Self, TransformError> {
+    let (source, transform) = match trans {
+      SerializableTransformation::Splice(s) => {
+        let template = Some(s.template);
+        let transform = Transform::Splice {
+          template,
+          separator: s.separator,
+        };
+        (s.source, transform)
+      }
+      SerializableTransformation::Join(j) => {
+        let transform = Transform::Splice {
+          template: None,
+          separator: j.separator,
+        };
+        (j.source, transform)
+      }
+      SerializableTransformation::ReplaceString(r) => {
+        let regex = Regex::new(&r.replace)?;
+        (r.source, Transform::ReplaceString { regex, by: r.by })
+      }
+    };
+    let source = match lang.extract_meta_var(&source) {
+      Some(MetaVariable::Named(n, _))
+      | Some(MetaVariable::NamedEllipsis(n))
+      | Some(MetaVariable::Optional(n)) => n,
+      _ => return Err(TransformError::MalformedSource(source)),
+    };
+    Ok(Self { source, transform })
+  }
+
+  pub fn compute<L: Language>(&self, env: &MetaVarEnv<L>, lang: &L) -> String {
+    match &self.transform {
+      Transform::Splice {
+        template,
+        separator,
+      } => self.splice(template.as_deref(), separator, env, lang),
+      Transform::ReplaceString { regex, by } => self.replace_string(regex, by, env, lang),
+    }
+  }
+
+  /// Transform every named element of the source. Punctuation like `,` in the capture is skipped.
+  /// A single capture is treated as a list of one element.
+  fn splice<L: Language>(
+    &self,
+    template: Option<&str>,
+    separator: &str,
+    env: &MetaVarEnv<L>,
+    lang: &L,
+  ) -> String {
+    let mut nodes = env.get_multiple_matches(&self.source);
+    if let Some(node) = env.get_match(&self.source) {
+      nodes.push(node.clone());
+    }
+    let texts: Vec<_> = nodes
+      .iter()
+      .filter(|n| n.is_named())
+      .map(|n| {
+        let Some(template) = template else {
+          return n.text().to_string();
+        };
+        let mut env = env.clone();
+        env.insert_transformation(self.source.clone(), n.text().to_string());
+        replace_meta_var_in_string(template, &env, lang)
+      })
+      .collect();
+    texts.join(separator)
+  }
+
+  /// The source is returned unchanged if it is not a string literal.
+  fn replace_string<L: Language>(
+    &self,
+    regex: &Regex,
+    by: &str,
+    env: &MetaVarEnv<L>,
+    lang: &L,
+  ) -> String {
+    let Some(node) = env.get_match(&self.source) else {
+      return String::new();
+    };
+    let text = node.text();
+    let Some(literal) = StringLiteral::parse(&text, lang.string_escape()) else {
+      return text.to_string();
+    };
+    let content = literal.content();
+    literal.with_content(&regex.replace_all(&content, by))
+  }
+}
+
+pub fn try_deserialize_transforms<L: Language>(
+  transforms: HashMap<String, SerializableTransformation>,
+  lang: &L,
+) -> Result<HashMap<MetaVariableID, Transformation>, TransformError> {
+  let mut ret = HashMap::new();
+  for (key, trans) in transforms {
+    let key = key.strip_prefix(lang.meta_var_char()).unwrap_or(&key);
+    ret.insert(key.to_string(), Transformation::try_new(trans, lang)?);
+  }
+  Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::from_str;
+  use crate::test::TypeScript;
+  use ast_grep_core::Pattern;
+
+  fn transform(trans: &str, src: &str) -> String {
+    let trans = from_str(trans).expect("should parse");
+    let trans = Transformation::try_new(trans, &TypeScript::Tsx).expect("should work");
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let pattern = Pattern::new("foo($$$ARGS)", TypeScript::Tsx);
+    let nm = grep.root().find(pattern).expect("should match");
+    trans.compute(nm.get_env(), &TypeScript::Tsx)
+  }
+
+  #[test]
+  fn test_splice() {
+    let trans = "splice: {source: $$$ARGS, template: String($ARGS), separator: ', '}";
+    assert_eq!(
+      transform(trans, "foo(a, b + 1, c)"),
+      "String(a), String(b + 1), String(c)"
+    );
+    assert_eq!(transform(trans, "foo(a)"), "String(a)");
+    assert_eq!(transform(trans, "foo()"), "");
+  }
+
+  #[test]
+  fn test_join() {
+    let trans = "join: {source: $$$ARGS, separator: ' + '}";
+    assert_eq!(transform(trans, "foo(a, b,c)"), "a + b + c");
+  }
+
+  #[test]
+  fn test_malformed_source() {
+    let trans = from_str("join: {source: ARGS, separator: ','}").expect("should parse");
+    let ret = Transformation::try_new(trans, &TypeScript::Tsx);
+    assert!(matches!(ret, Err(TransformError::MalformedSource(_))));
+  }
+
+  fn replace_string(src: &str) -> String {
+    let trans = r#"replaceString: {source: $STR, replace: 'v(\d)', by: 'v2 "beta$1"'}"#;
+    let trans = from_str(trans).expect("should parse");
+    let trans = Transformation::try_new(trans, &TypeScript::Tsx).expect("should work");
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let pattern = Pattern::new("foo($STR)", TypeScript::Tsx);
+    let nm = grep.root().find(pattern).expect("should match");
+    trans.compute(nm.get_env(), &TypeScript::Tsx)
+  }
+
+  #[test]
+  fn test_replace_string() {
+    assert_eq!(
+      replace_string(r#"foo("/api/v1/users\t\"x\"")"#),
+      r#""/api/v2 \"beta1\"/users\t\"x\"""#
+    );
+    assert_eq!(replace_string(r"foo('it\'s v1')"), r#"'it\'s v2 "beta1"'"#);
+    assert_eq!(replace_string("foo(v1)"), "v1");
+  }
+
+  #[test]
+  fn test_invalid_replace_regex() {
+    let trans = "replaceString: {source: $STR, replace: '(', by: ''}";
+    let trans = from_str(trans).expect("should parse");
+    let ret = Transformation::try_new(trans, &TypeScript::Tsx);
+    assert!(matches!(ret, Err(TransformError::Regex(_))));
+  }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+use ast_grep_core::language::{Language, Precedence};
+use ast_grep_core::meta_var::MetaVarEnv;
+use ast_grep_core::{replace_meta_var_in_string, Node};
+use thiserror::Error;
+
+/// Meta variable holding the wrapped text. It is used as the fixer of rules with `wrap`.
+pub const WRAPPED_VAR: &str = "SG_WRAPPED";
+/// Stand-in for the matched code when parsing `before` and `after` together.
+const PLACEHOLDER: &str = "x";
+
+/// Surround the matched code with text, adding parentheses when operator precedence requires.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableWrap {
+  /// Text inserted before the matched code. It can reference meta variables.
+  #[serde(default)]
+  pub before: String,
+  /// Text inserted after the matched code. It can reference meta variables.
+  #[serde(default)]
+  pub after: String,
+}
+
+#[derive(Debug, Error)]
+pub enum WrapError {
+  #[error("`before` and `after` must keep the matched code as a separate expression.")]
+  MergedPlaceholder,
+}
+
+pub struct Wrap {
+  before: String,
+  after: String,
+  /// precedence of the expression enclosing the matched code
+  outer: Option<Precedence>,
+  /// whether the matched code is the leftmost operand of the enclosing expression
+  leftmost: bool,
+}
+
+impl Wrap {
+  pub fn try_new<L: Language>(wrap: SerializableWrap, lang: &L) -> Result<Self, WrapError> {
+    let before = lang.pre_process_pattern(&wrap.before);
+    let after = lang.pre_process_pattern(&wrap.after);
+    let src = format!("{before}{PLACEHOLDER}{after}");
+    let start = before.len();
+    let grep = lang.ast_grep(src);
+    let root = grep.root();
+    let placeholder = root
+      .named_descendant_for_range(start..start + PLACEHOLDER.len())
+      .filter(|n| n.range() == (start..start + PLACEHOLDER.len()))
+      .ok_or(WrapError::MergedPlaceholder)?;
+    let (outer, leftmost) = match placeholder.parent() {
+      Some(p) => (p.precedence(), p.range().start == start),
+      None => (None, false),
+    };
+    Ok(Self {
+      before: wrap.before,
+      after: wrap.after,
+      outer,
+      leftmost,
+    })
+  }
+
+  fn needs_parens(&self, inner: Option<Precedence>) -> bool {
+    let (Some(outer), Some(inner)) = (self.outer, inner) else {
+      return false;
+    };
+    if inner.level != outer.level {
+      return inner.level < outer.level;
+    }
+    // the operand on the side opposite to associativity needs parentheses, e.g. `a - (b - c)`
+    self.leftmost == outer.right_assoc
+  }
+
+  pub fn compute<L: Language>(&self, node: &Node<L>, env: &MetaVarEnv<L>) -> String {
+    let lang = node.lang();
+    let before = replace_meta_var_in_string(&self.before, env, lang);
+    let after = replace_meta_var_in_string(&self.after, env, lang);
+    let text = node.text();
+    if self.needs_parens(node.precedence()) {
+      format!("{before}({text}){after}")
+    } else {
+      format!("{before}{text}{after}")
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::from_str;
+  use crate::test::TypeScript;
+  use ast_grep_core::Pattern;
+
+  fn wrap(wrap: &str, src: &str) -> String {
+    let wrap = from_str(wrap).expect("should parse");
+    let wrap = Wrap::try_new(wrap, &TypeScript::Tsx).expect("should work");
+    let grep = TypeScript::Tsx.ast_grep(src);
+    let pattern = Pattern::new("foo($EXPR)", TypeScript::Tsx);
+    let nm = grep.root().find(pattern).expect("should match");
+    let expr = nm.get_env().get_match("EXPR").expect("should capture");
+    wrap.compute(expr, nm.get_env())
+  }
+
+  #[test]
+  fn test_wrap_parens() {
+    let neg = "before: '-'";
+    assert_eq!(wrap(neg, "foo(a + b)"), "-(a + b)");
+    assert_eq!(wrap(neg, "foo(a)"), "-a");
+    assert_eq!(wrap(neg, "foo(a.b())"), "-a.b()");
+    assert_eq!(wrap("after: '.foo()'", "foo(a.b())"), "a.b().foo()");
+    assert_eq!(wrap("after: '.foo()'", "foo(a || b)"), "(a || b).foo()");
+    assert_eq!(wrap("before: 'await '", "foo(a && b)"), "await (a && b)");
+  }
+
+  #[test]
+  fn test_wrap_no_parens() {
+    let log = "before: 'logger.debug('\nafter: ')'";
+    assert_eq!(wrap(log, "foo(a)"), "logger.debug(a)");
+    assert_eq!(wrap(log, "foo(a ? b : c)"), "logger.debug(a ? b : c)");
+    let call = "before: '$EXPR.foo('\nafter: ')'";
+    assert_eq!(wrap(call, "foo(a + b)"), "a + b.foo(a + b)");
+    assert_eq!(wrap("before: 'await '", "foo(a.b())"), "await a.b()");
+  }
+
+  #[test]
+  fn test_wrap_associativity() {
+    assert_eq!(wrap("before: '1 - '", "foo(a - b)"), "1 - (a - b)");
+    assert_eq!(wrap("after: ' - 1'", "foo(a - b)"), "a - b - 1");
+    assert_eq!(wrap("before: '2 ** '", "foo(a ** b)"), "2 ** a ** b");
+    assert_eq!(wrap("after: ' ** 2'", "foo(a ** b)"), "(a ** b) ** 2");
+  }
+
+  #[test]
+  fn test_merged_placeholder() {
+    let wrap = from_str("before: 'foo'").expect("should parse");
+    let ret = Wrap::try_new(wrap, &TypeScript::Tsx);
+    assert!(matches!(ret, Err(WrapError::MergedPlaceholder)));
+  }
+}
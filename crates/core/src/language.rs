@@ -1,4 +1,5 @@
 use crate::meta_var::{extract_meta_var, MetaVariable};
+use crate::node::KindId;
 use crate::AstGrep;
 use std::borrow::Cow;
 use std::path::Path;
@@ -22,6 +23,15 @@ pub trait Language: Clone {
 
   /// tree sitter language to parse the source
   fn get_ts_language(&self) -> TSLanguage;
+  /// Return the numeric id of a named node kind, or None if the kind does not exist.
+  /// Comparing ids is cheaper than comparing `Node::kind` strings.
+  fn kind_id_for(&self, kind: &str) -> Option<KindId> {
+    let id = self
+      .get_ts_language()
+      .id_for_node_kind(kind, /*named*/ true);
+    // tree-sitter returns 0 (builtin end symbol) for unknown kinds
+    (id != 0).then_some(id)
+  }
   /// ignore trivial tokens in language matching
   fn skippable_kind_ids(&self) -> &'static [u16] {
     &[]
@@ -35,6 +45,8 @@ pub trait Language: Clone {
 
   /// Configure meta variable special character
   /// By default $ is the metavar char, but in PHP it can be #
+  /// Override this for languages where $ is part of the source syntax, e.g. PHP or shell.
+  /// The char is used for recognizing captures in patterns and in fix templates.
   #[inline]
   fn meta_var_char(&self) -> char {
     '$'
@@ -53,6 +65,70 @@ pub trait Language: Clone {
   fn extract_meta_var(&self, source: &str) -> Option<MetaVariable> {
     extract_meta_var(source, self.expando_char())
   }
+
+  /// Precedence of an expression node, used to add parentheses when wrapping code.
+  /// `operator` is the text of the node's `operator` field, if any.
+  /// None means the node never needs parentheses, e.g. identifiers or calls' arguments.
+  fn precedence(&self, _kind: &str, _operator: Option<&str>) -> Option<Precedence> {
+    None
+  }
+
+  /// Delimiter starting a comment that runs to the end of line, e.g. `//`.
+  /// None if the language has no line comment.
+  fn line_comment(&self) -> Option<&'static str> {
+    None
+  }
+
+  /// Opening and closing delimiters of a block comment, e.g. `("/*", "*/")`.
+  /// None if the language has no block comment.
+  fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+    None
+  }
+
+  /// Escape sequences of the language's string literals.
+  fn string_escape(&self) -> StringEscape {
+    StringEscape::C
+  }
+}
+
+/// Escape sequence rules of string literals. All of them resolve `\n`, `\t`, `\r`, `\0`,
+/// `\xHH`, escaped quotes and escaped backslashes. They differ in unicode escapes and in
+/// a backslash before a character without escape meaning, like `\d`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringEscape {
+  /// `\uHHHH` and `\UHHHHHHHH`. `\d` is invalid, e.g. C, Java or Go.
+  C,
+  /// `\uHHHH` and `\u{H..}`. `\d` is read as `d`.
+  JavaScript,
+  /// `\uHHHH` and `\UHHHHHHHH`. `\d` is read as a backslash and `d`.
+  Python,
+  /// `\u{H..}` only. `\d` is invalid, and a backslash before a newline also skips
+  /// the indentation of the next line.
+  Rust,
+}
+
+/// How tightly an expression binds. Higher levels bind tighter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Precedence {
+  pub level: u8,
+  /// operators like `**` or assignment group from right to left
+  pub right_assoc: bool,
+}
+
+impl Precedence {
+  pub const fn left(level: u8) -> Self {
+    Self {
+      level,
+      right_assoc: false,
+    }
+  }
+
+  pub const fn right(level: u8) -> Self {
+    Self {
+      level,
+      right_assoc: true,
+    }
+  }
 }
 
 impl Language for TSLanguage {
@@ -78,3 +154,53 @@ mod test {
 
 #[cfg(test)]
 pub use test::*;
+
+#[cfg(test)]
+mod test_meta_var_char {
+  use super::*;
+  use crate::Pattern;
+
+  // $ is a valid identifier char in JavaScript, like the variable sigil in PHP
+  #[derive(Clone)]
+  struct MuTsx;
+  impl Language for MuTsx {
+    fn get_ts_language(&self) -> TSLanguage {
+      tree_sitter_typescript::language_tsx().into()
+    }
+    fn meta_var_char(&self) -> char {
+      'µ'
+    }
+  }
+
+  fn find_all(pattern: &str, source: &str) -> Vec<String> {
+    let grep = MuTsx.ast_grep(source);
+    let pattern = Pattern::new(pattern, MuTsx);
+    grep
+      .root()
+      .find_all(pattern)
+      .map(|n| n.text().to_string())
+      .collect()
+  }
+
+  #[test]
+  fn test_custom_meta_var_char() {
+    let source = "$a = 1; $b = 2; $a = 3;";
+    assert_eq!(find_all("$a = µVAL", source), ["$a = 1", "$a = 3"]);
+    assert_eq!(find_all("µVAR = 2", source), ["$b = 2"]);
+    assert_eq!(find_all("$VAR = 2", source), Vec::<String>::new());
+  }
+
+  #[test]
+  fn test_custom_meta_var_ellipsis() {
+    let source = "echo($a, $b, $c)";
+    assert_eq!(find_all("echo(µµµARGS)", source), [source]);
+    assert_eq!(find_all("echo($a, µµµ)", source), [source]);
+  }
+
+  #[test]
+  fn test_custom_meta_var_replace() {
+    let mut grep = MuTsx.ast_grep("$a = 1; $b = 2;");
+    grep.replace("$b = µVAL", "$c = µVAL").unwrap();
+    assert_eq!(grep.generate(), "$a = 1; $c = 2;");
+  }
+}
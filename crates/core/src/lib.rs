@@ -15,13 +15,16 @@ mod ts_parser;
 
 pub use language::Language;
 pub use matcher::{Matcher, NodeMatch, Pattern, PatternError};
-pub use node::Node;
-pub use replacer::replace_meta_var_in_string;
+pub use node::{JsonOptions, KindId, Node};
+pub use replacer::{replace_meta_var_in_message, replace_meta_var_in_string};
+pub use ts_parser::Edit;
 
 use crate::replacer::Replacer;
 use node::Root;
 use source::Content;
-use ts_parser::{Edit, TSParseError};
+use std::collections::HashMap;
+use std::path::Path;
+use ts_parser::TSParseError;
 
 #[derive(Clone)]
 pub struct AstGrep<L: Language> {
@@ -42,6 +45,16 @@ impl<L: Language> AstGrep<L> {
     })
   }
 
+  /// Parse the source in the language detected from the path, e.g. TSX for `a.tsx`.
+  pub fn new_with_language_detection<P: AsRef<Path>>(
+    src: &str,
+    path: P,
+  ) -> Result<Self, TSParseError> {
+    Ok(Self {
+      inner: Root::new_with_language_detection(src, path)?,
+    })
+  }
+
   pub fn source(&self) -> &str {
     &self.inner.source
   }
@@ -68,10 +81,26 @@ impl<L: Language> AstGrep<L> {
     }
   }
 
+  /// Compute edits for all non-overlapping matches without changing the source.
+  /// Edits are sorted by position. When matches nest, only the outermost one is replaced.
+  pub fn replace_all<M: Matcher<L>, R: Replacer<L>>(&self, matcher: M, replacer: R) -> Vec<Edit> {
+    self.root().replace_all(matcher, replacer)
+  }
+
   pub fn lang(&self) -> &L {
     &self.inner.lang
   }
 
+  /// Count the nodes of each kind in the tree, e.g. for code metrics.
+  pub fn kind_histogram(&self) -> HashMap<String, usize> {
+    self.inner.kind_histogram()
+  }
+
+  /// The whole tree dumped in tree-sitter's S-expression, useful for writing rules.
+  pub fn to_sexp(&self) -> String {
+    self.root().to_sexp().to_string()
+  }
+
   pub fn generate(self) -> String {
     self.inner.source.to_string()
   }
@@ -101,6 +130,30 @@ mod test {
     assert_eq!(source, "console.log('it works!')");
   }
 
+  #[test]
+  fn test_replace_all_edits() {
+    let ast_grep = Tsx.ast_grep("var a = 1; var b = 2;");
+    let edits = ast_grep.replace_all("var $A = $B", "let $A = $B");
+    assert_eq!(edits.len(), 2);
+    assert_eq!(edits[0].range(), 0..9);
+    assert_eq!(edits[0].inserted_text, "let a = 1");
+    assert_eq!(edits[1].range(), 11..20);
+    assert_eq!(edits[1].inserted_text, "let b = 2");
+    // source is not changed
+    assert_eq!(ast_grep.source(), "var a = 1; var b = 2;");
+  }
+
+  #[test]
+  fn test_replace_all_overlapping() {
+    let ast_grep = Tsx.ast_grep("Some(Some(1)); Some(2)");
+    let edits = ast_grep.replace_all("Some($A)", "Ok($A)");
+    assert_eq!(edits.len(), 2);
+    assert_eq!(edits[0].range(), 0..13);
+    assert_eq!(edits[0].inserted_text, "Ok(Some(1))");
+    assert_eq!(edits[1].range(), 15..22);
+    assert_eq!(edits[1].inserted_text, "Ok(2)");
+  }
+
   #[test]
   fn test_replace_unnamed_node() {
     // ++ and -- is unnamed node in tree-sitter javascript
@@ -122,4 +175,19 @@ mod test {
     let source = ast_grep.generate();
     assert_eq!(source, "let a = 1; /*haha*/");
   }
+
+  #[test]
+  fn test_replace_whole_match() {
+    let ast_grep = Tsx.ast_grep("var a = 1; foo(b)");
+    let edits = ast_grep.replace_all("foo($A)", "wrap($$MATCH)");
+    assert_eq!(edits[0].inserted_text, "wrap(foo(b))");
+    // a capture named MATCH is not shadowed
+    let edits = ast_grep.replace_all("foo($MATCH)", "$MATCH + $$MATCH");
+    assert_eq!(edits[0].inserted_text, "b + foo(b)");
+    // the trailing semicolon is not part of the match
+    let ast_grep = Tsx.ast_grep("var a = 1;");
+    let edits = ast_grep.replace_all("var $A = $B", "if (x) { $$MATCH }");
+    assert_eq!(edits[0].inserted_text, "if (x) { var a = 1 }");
+    assert_eq!(edits[0].range(), 0..9);
+  }
 }
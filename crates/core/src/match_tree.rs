@@ -2,6 +2,8 @@ use crate::meta_var::{MetaVarEnv, MetaVariable};
 use crate::Language;
 use crate::Node;
 
+use unicode_normalization::UnicodeNormalization;
+
 fn match_leaf_meta_var<'goal, 'tree, L: Language>(
   goal: &Node<'goal, L>,
   candidate: Node<'tree, L>,
@@ -10,6 +12,14 @@ fn match_leaf_meta_var<'goal, 'tree, L: Language>(
   let extracted = extract_var_from_node(goal)?;
   use MetaVariable as MV;
   match extracted {
+    // the absent case is tried by the parent, see `match_optional_nodes`
+    MV::Optional(name) => {
+      if !candidate.is_named() {
+        return None;
+      }
+      env.insert(name, candidate.clone())?;
+      Some(candidate)
+    }
     MV::Named(name, named) => {
       if named && !candidate.is_named() {
         None
@@ -45,6 +55,69 @@ fn try_get_ellipsis_mode(node: &Node<impl Language>) -> Result<Option<String>, (
   }
 }
 
+fn is_optional<L: Language>(goal: &Node<L>) -> bool {
+  matches!(extract_var_from_node(goal), Some(MetaVariable::Optional(_)))
+}
+
+/// The number of goals left out if an optional meta variable like `$?A` starts at the goal
+/// and is absent. One separator next to the variable is left out with it, e.g. the comma
+/// in `foo(a, $?B)` or `foo($?A, b)`. A wrapper of the variable is optional instead of it.
+fn optional_len<L: Language>(goal: &Node<L>) -> Option<usize> {
+  if is_optional(goal) {
+    if goal.parent().map_or(false, |p| p.range() == goal.range()) {
+      return None;
+    }
+    let prev = goal.prev().filter(|p| !p.is_named() && p.prev().is_some());
+    let next = goal.next().filter(|n| !n.is_named() && n.next().is_some());
+    return Some(if prev.is_none() && next.is_some() {
+      2
+    } else {
+      1
+    });
+  }
+  // a separator before the variable, but not an opening bracket
+  let next = goal.next()?;
+  (!goal.is_named() && goal.prev().is_some() && is_optional(&next)).then_some(2)
+}
+
+/// Returns the byte ranges of source text not covered by any child, relative to node start.
+/// Such text is only present in nodes like template strings, where static content is not parsed.
+fn unparsed_ranges<L: Language>(node: &Node<L>) -> Vec<std::ops::Range<usize>> {
+  let start = node.range().start;
+  let mut pos = start;
+  let mut ranges = vec![];
+  for child in node.children() {
+    let range = child.range();
+    if range.start > pos {
+      ranges.push(pos - start..range.start - start);
+    }
+    pos = pos.max(range.end);
+  }
+  if node.range().end > pos {
+    ranges.push(pos - start..node.range().end - start);
+  }
+  ranges
+}
+
+/// Static text between children must be the same. Blank text is insignificant like whitespace.
+pub(crate) fn match_unparsed_text<L: Language>(goal: &Node<L>, candidate: &Node<L>) -> bool {
+  // ellipsis can consume any candidate children so gaps do not line up
+  if goal.children().any(|n| try_get_ellipsis_mode(&n).is_ok()) {
+    return true;
+  }
+  let goal_text = goal.text();
+  let cand_text = candidate.text();
+  let goal_gaps = unparsed_ranges(goal)
+    .into_iter()
+    .map(|r| &goal_text[r])
+    .filter(|s| !s.trim().is_empty());
+  let cand_gaps = unparsed_ranges(candidate)
+    .into_iter()
+    .map(|r| &cand_text[r])
+    .filter(|s| !s.trim().is_empty());
+  goal_gaps.eq(cand_gaps)
+}
+
 fn update_ellipsis_env<'t, L: Language>(
   optional_name: &Option<String>,
   mut matched: Vec<Node<'t, L>>,
@@ -60,8 +133,52 @@ fn update_ellipsis_env<'t, L: Language>(
   }
 }
 
-pub fn match_end_non_recursive<L: Language>(goal: &Node<L>, candidate: Node<L>) -> Option<usize> {
+/// How the text of identifier leaves is compared besides exact equality.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextCompare {
+  /// compare in Unicode NFC form so that composed and decomposed characters are equal
+  pub normalize: bool,
+  /// compare in lowercase so that `getURL` equals `getUrl`
+  pub ignore_case: bool,
+}
+
+impl TextCompare {
+  fn fold(&self, text: &str) -> String {
+    let text: String = if self.normalize {
+      text.nfc().collect()
+    } else {
+      text.to_string()
+    };
+    if self.ignore_case {
+      text.to_lowercase()
+    } else {
+      text
+    }
+  }
+}
+
+/// Leaf nodes match if their text is equal. Identifiers are also compared as `compare` folds them.
+fn match_leaf_text<L: Language>(goal: &Node<L>, candidate: &Node<L>, compare: TextCompare) -> bool {
+  let goal_text = goal.text();
+  let cand_text = candidate.text();
+  if goal_text == cand_text {
+    return true;
+  }
+  if compare == TextCompare::default() || !goal.kind().contains("identifier") {
+    return false;
+  }
+  compare.fold(&goal_text) == compare.fold(&cand_text)
+}
+
+pub fn match_end_non_recursive<L: Language>(
+  goal: &Node<L>,
+  candidate: Node<L>,
+  compare: TextCompare,
+) -> Option<usize> {
   let is_leaf = goal.is_leaf();
+  if is_leaf && is_optional(goal) && !candidate.is_named() {
+    return None;
+  }
   if is_leaf && extract_var_from_node(goal).is_some() {
     return Some(candidate.range().end);
   }
@@ -72,26 +189,35 @@ pub fn match_end_non_recursive<L: Language>(goal: &Node<L>, candidate: Node<L>)
     if extract_var_from_node(goal).is_some() {
       return None;
     }
-    return if goal.text() == candidate.text() {
+    return if match_leaf_text(goal, &candidate, compare) {
       Some(candidate.range().end)
     } else {
       None
     };
   }
+  if !match_unparsed_text(goal, &candidate) {
+    return None;
+  }
   let goal_children = goal.children();
   let cand_children = candidate.children();
-  match_multi_nodes_end_non_recursive(goal_children, cand_children)
+  match_multi_nodes_end_non_recursive(goal_children, cand_children, compare)
 }
 
 pub fn match_multi_nodes_end_non_recursive<'g, 'c, L: Language + 'g + 'c>(
   goals: impl Iterator<Item = Node<'g, L>>,
   candidates: impl Iterator<Item = Node<'c, L>>,
+  compare: TextCompare,
 ) -> Option<usize> {
   let mut goal_children = goals.peekable();
   let mut cand_children = candidates.peekable();
   let mut end = cand_children.peek()?.range().end;
   loop {
     let curr_node = goal_children.peek().unwrap();
+    if let Some(len) = optional_len(curr_node) {
+      let goals: Vec<_> = goal_children.collect();
+      let cands: Vec<_> = cand_children.collect();
+      return match_optional_nodes_end(&goals, &cands, len, end, compare);
+    }
     if try_get_ellipsis_mode(curr_node).is_ok() {
       goal_children.next();
       // goal has all matched
@@ -113,6 +239,7 @@ pub fn match_multi_nodes_end_non_recursive<'g, 'c, L: Language + 'g + 'c>(
         if match_end_non_recursive(
           goal_children.peek().unwrap(),
           cand_children.peek().unwrap().clone(),
+          compare,
         )
         .is_some()
         {
@@ -125,6 +252,7 @@ pub fn match_multi_nodes_end_non_recursive<'g, 'c, L: Language + 'g + 'c>(
     end = match_end_non_recursive(
       goal_children.peek().unwrap(),
       cand_children.peek().unwrap().clone(),
+      compare,
     )?;
     goal_children.next();
     if goal_children.peek().is_none() {
@@ -136,10 +264,36 @@ pub fn match_multi_nodes_end_non_recursive<'g, 'c, L: Language + 'g + 'c>(
   }
 }
 
+/// Like `match_optional_nodes` but returns the end of the match.
+fn match_optional_nodes_end<'g, 'c, L: Language + 'g + 'c>(
+  goals: &[Node<'g, L>],
+  candidates: &[Node<'c, L>],
+  len: usize,
+  end: usize,
+  compare: TextCompare,
+) -> Option<usize> {
+  let rest = &goals[len..];
+  let match_rest = |candidates: &[Node<'c, L>], end| {
+    if rest.is_empty() {
+      return Some(end);
+    }
+    match_multi_nodes_end_non_recursive(rest.iter().cloned(), candidates.iter().cloned(), compare)
+  };
+  let present = || {
+    let mut end = end;
+    for (goal, cand) in goals[..len].iter().zip(candidates.get(..len)?) {
+      end = match_end_non_recursive(goal, cand.clone(), compare)?;
+    }
+    match_rest(&candidates[len..], end)
+  };
+  present().or_else(|| match_rest(candidates, end))
+}
+
 pub fn match_node_non_recursive<'goal, 'tree, L: Language>(
   goal: &Node<'goal, L>,
   candidate: Node<'tree, L>,
   env: &mut MetaVarEnv<'tree, L>,
+  compare: TextCompare,
 ) -> Option<Node<'tree, L>> {
   let is_leaf = goal.is_leaf();
   if is_leaf {
@@ -154,15 +308,18 @@ pub fn match_node_non_recursive<'goal, 'tree, L: Language>(
     if extract_var_from_node(goal).is_some() {
       return None;
     }
-    return if goal.text() == candidate.text() {
+    return if match_leaf_text(goal, &candidate, compare) {
       Some(candidate)
     } else {
       None
     };
   }
+  if !match_unparsed_text(goal, &candidate) {
+    return None;
+  }
   let goal_children = goal.children();
   let cand_children = candidate.children();
-  if match_nodes_non_recursive(goal_children, cand_children, env).is_some() {
+  if match_nodes_non_recursive(goal_children, cand_children, env, compare).is_some() {
     Some(candidate)
   } else {
     None
@@ -173,12 +330,18 @@ pub fn match_nodes_non_recursive<'goal, 'tree, L: Language + 'tree + 'goal>(
   goals: impl Iterator<Item = Node<'goal, L>>,
   candidates: impl Iterator<Item = Node<'tree, L>>,
   env: &mut MetaVarEnv<'tree, L>,
+  compare: TextCompare,
 ) -> Option<()> {
   let mut goal_children = goals.peekable();
   let mut cand_children = candidates.peekable();
   cand_children.peek()?;
   loop {
     let curr_node = goal_children.peek().unwrap();
+    if let Some(len) = optional_len(curr_node) {
+      let goals: Vec<_> = goal_children.collect();
+      let cands: Vec<_> = cand_children.collect();
+      return match_optional_nodes(&goals, &cands, len, env, compare);
+    }
     if let Ok(optional_name) = try_get_ellipsis_mode(curr_node) {
       let mut matched = vec![];
       goal_children.next();
@@ -220,6 +383,7 @@ pub fn match_nodes_non_recursive<'goal, 'tree, L: Language + 'tree + 'goal>(
           goal_children.peek().unwrap(),
           cand_children.peek().unwrap().clone(),
           env,
+          compare,
         )
         .is_some()
         {
@@ -241,6 +405,7 @@ pub fn match_nodes_non_recursive<'goal, 'tree, L: Language + 'tree + 'goal>(
       goal_children.peek().unwrap(),
       cand_children.peek().unwrap().clone(),
       env,
+      compare,
     )?;
     goal_children.next();
     if goal_children.peek().is_none() {
@@ -252,6 +417,42 @@ pub fn match_nodes_non_recursive<'goal, 'tree, L: Language + 'tree + 'goal>(
   }
 }
 
+/// Match the goals starting with an optional meta variable and its separator, see `optional_len`.
+/// The variable is tried present first so it binds a node whenever the rest still matches.
+/// The goals before it are matched only once, and no pattern is built per combination.
+fn match_optional_nodes<'goal, 'tree, L: Language + 'tree + 'goal>(
+  goals: &[Node<'goal, L>],
+  candidates: &[Node<'tree, L>],
+  len: usize,
+  env: &mut MetaVarEnv<'tree, L>,
+  compare: TextCompare,
+) -> Option<()> {
+  let rest = &goals[len..];
+  let match_rest = |candidates: &[Node<'tree, L>], env: &mut MetaVarEnv<'tree, L>| {
+    if rest.is_empty() {
+      return Some(());
+    }
+    match_nodes_non_recursive(
+      rest.iter().cloned(),
+      candidates.iter().cloned(),
+      env,
+      compare,
+    )
+  };
+  let present = |env: &mut MetaVarEnv<'tree, L>| {
+    for (goal, cand) in goals[..len].iter().zip(candidates.get(..len)?) {
+      match_node_non_recursive(goal, cand.clone(), env, compare)?;
+    }
+    match_rest(&candidates[len..], env)
+  };
+  let mut trial = env.clone();
+  if present(&mut trial).is_some() {
+    *env = trial;
+    return Some(());
+  }
+  match_rest(candidates, env)
+}
+
 pub fn does_node_match_exactly<L: Language>(goal: &Node<L>, candidate: Node<L>) -> bool {
   if goal.kind_id() != candidate.kind_id() {
     return false;
@@ -261,7 +462,7 @@ pub fn does_node_match_exactly<L: Language>(goal: &Node<L>, candidate: Node<L>)
   }
   let goal_children = goal.children();
   let cand_children = candidate.children();
-  if goal_children.len() != cand_children.len() {
+  if goal_children.len() != cand_children.len() || !match_unparsed_text(goal, &candidate) {
     return false;
   }
   goal_children
@@ -290,7 +491,7 @@ mod test {
     node: Node<'tree, Tsx>,
     env: &mut MetaVarEnv<'tree, Tsx>,
   ) -> Option<Node<'tree, Tsx>> {
-    match_node_non_recursive(goal, node.clone(), env).or_else(|| {
+    match_node_non_recursive(goal, node.clone(), env, TextCompare::default()).or_else(|| {
       node
         .children()
         .find_map(|sub| find_node_recursive(goal, sub, env))
@@ -447,4 +648,14 @@ mod test {
     test_non_match("'a'", "'b'");
     test_non_match("'abcdefg'", "'gggggg'");
   }
+
+  #[test]
+  fn test_template_string() {
+    let env = test_match("`Hello ${ $NAME }`", "`Hello ${user.name}`");
+    assert_eq!(env["NAME"], "user.name");
+    test_match("`a${$A}b${$B}c`", "`a${1}b${2}c`");
+    test_non_match("`Hello ${ $NAME }`", "`Bye ${ name }`");
+    test_non_match("`Hello ${ $NAME }`", "`Hello ${ name }!`");
+    test_non_match("`a${$A}b${$B}c`", "`a${1}c${2}b`");
+  }
 }
@@ -1,10 +1,15 @@
 mod kind;
+mod literal;
 mod node_match;
+mod number;
+mod operator;
 mod pattern;
 #[cfg(feature = "regex")]
 mod text;
+mod trace;
 
 use crate::meta_var::MetaVarEnv;
+use crate::node::KindId;
 use crate::traversal::Pre;
 use crate::Language;
 use crate::Node;
@@ -12,10 +17,14 @@ use crate::Node;
 use bit_set::BitSet;
 
 pub use kind::{KindMatcher, KindMatcherError};
+pub use literal::LiteralSetMatcher;
 pub use node_match::NodeMatch;
+pub use number::{parse_number, NumberMatcher, NumberRange};
+pub use operator::OperatorMatcher;
 pub use pattern::{Pattern, PatternError};
 #[cfg(feature = "regex")]
 pub use text::{RegexMatcher, RegexMatcherError};
+pub use trace::MatchTrace;
 
 /**
  * N.B. At least one positive term is required for matching
@@ -49,13 +58,38 @@ pub trait Matcher<L: Language> {
     Some(NodeMatch::new(node, env))
   }
 
+  /// Like `match_node`, but reuse the caller's env to save allocations when trying many nodes.
+  /// The env is cleared before matching and moved into the returned match.
+  fn match_node_in<'tree>(
+    &self,
+    node: Node<'tree, L>,
+    env: &mut MetaVarEnv<'tree, L>,
+  ) -> Option<NodeMatch<'tree, L>> {
+    env.clear();
+    let node = self.match_node_with_env(node, env)?;
+    Some(NodeMatch::new(node, std::mem::take(env)))
+  }
+
   fn find_node<'tree>(&self, node: Node<'tree, L>) -> Option<NodeMatch<'tree, L>> {
-    for n in node.dfs() {
-      if let Some(ret) = self.match_node(n.clone()) {
-        return Some(ret);
-      }
+    // anonymous nodes cannot match if every potential kind is named
+    let named_only = self.potential_kinds().map_or(false, |kinds| {
+      let lang = node.lang().get_ts_language();
+      kinds.iter().all(|id| lang.node_kind_is_named(id as KindId))
+    });
+    let mut env = MetaVarEnv::new();
+    if named_only {
+      node
+        .dfs_named()
+        .find_map(|n| self.match_node_in(n, &mut env))
+    } else {
+      node.dfs().find_map(|n| self.match_node_in(n, &mut env))
     }
-    None
+  }
+
+  /// Explain why the node is matched or not, for debugging rules.
+  /// Composite matchers override this to record the outcome of each sub-matcher.
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    MatchTrace::from_match("matcher", self.match_node(node))
   }
 }
 
@@ -73,6 +107,11 @@ impl<L: Language> Matcher<L> for str {
     let pattern = Pattern::new(self, node.lang().clone());
     pattern.get_match_len(node)
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let pattern = Pattern::new(self, node.lang().clone());
+    pattern.explain(node)
+  }
 }
 
 impl<L, T> Matcher<L> for &T
@@ -103,6 +142,10 @@ where
   fn get_match_len(&self, node: Node<L>) -> Option<usize> {
     (**self).get_match_len(node)
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    (**self).explain(node)
+  }
 }
 
 impl<L: Language> Matcher<L> for Box<dyn Matcher<L>> {
@@ -130,6 +173,10 @@ impl<L: Language> Matcher<L> for Box<dyn Matcher<L>> {
   fn get_match_len(&self, node: Node<L>) -> Option<usize> {
     (**self).get_match_len(node)
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    (**self).explain(node)
+  }
 }
 
 pub struct FindAllNodes<'tree, L: Language, M: Matcher<L>> {
@@ -137,6 +184,8 @@ pub struct FindAllNodes<'tree, L: Language, M: Matcher<L>> {
   // e.g. for pattern Some($A) with replacement $A, Some(Some(1)) will cause panic
   dfs: Pre<'tree, L>,
   matcher: M,
+  /// reused by candidates that fail to match
+  env: MetaVarEnv<'tree, L>,
 }
 
 impl<'tree, L: Language, M: Matcher<L>> FindAllNodes<'tree, L, M> {
@@ -144,6 +193,7 @@ impl<'tree, L: Language, M: Matcher<L>> FindAllNodes<'tree, L, M> {
     Self {
       dfs: node.dfs(),
       matcher,
+      env: MetaVarEnv::new(),
     }
   }
 }
@@ -152,7 +202,7 @@ impl<'tree, L: Language, M: Matcher<L>> Iterator for FindAllNodes<'tree, L, M> {
   type Item = NodeMatch<'tree, L>;
   fn next(&mut self) -> Option<Self::Item> {
     for cand in self.dfs.by_ref() {
-      if let Some(matched) = self.matcher.match_node(cand) {
+      if let Some(matched) = self.matcher.match_node_in(cand, &mut self.env) {
         return Some(matched);
       }
     }
@@ -209,4 +259,38 @@ mod test {
     let cand = cand.root();
     assert!(boxed.find_node(cand).is_some());
   }
+
+  #[test]
+  fn test_match_node_in() {
+    let src = "foo(a, 2); foo(b, 2); foo(c, 1); foo(d, 2); foo(e, 1)";
+    let root = pattern_node(&src.repeat(20));
+    let pattern = Pattern::new("foo($A, 1)", Tsx);
+    let node = root.root();
+    let fresh: Vec<_> = node.dfs().filter_map(|n| pattern.match_node(n)).collect();
+    let reused: Vec<_> = node.find_all(&pattern).collect();
+    let texts = |matches: Vec<NodeMatch<Tsx>>| -> Vec<(String, String)> {
+      let var = |m: &NodeMatch<Tsx>| m.get_env().get_match("A").unwrap().text().to_string();
+      matches
+        .iter()
+        .map(|m| (m.text().to_string(), var(m)))
+        .collect()
+    };
+    assert_eq!(fresh.len(), 40);
+    assert_eq!(texts(fresh), texts(reused));
+    let first = node.find(&pattern).expect("should find");
+    assert_eq!(first.get_env().get_match("A").unwrap().text(), "c");
+  }
+
+  #[test]
+  fn test_reused_env_is_reset() {
+    // foo(a, b) binds $A before failing, which must not leak into the next candidate
+    let root = pattern_node("foo(a, b); foo(c, c); foo(a, a)");
+    let pattern = Pattern::new("foo($A, $A)", Tsx);
+    let node = root.root();
+    let matches: Vec<_> = node
+      .find_all(&pattern)
+      .map(|m| m.text().to_string())
+      .collect();
+    assert_eq!(matches, ["foo(c, c)", "foo(a, a)"]);
+  }
 }
@@ -1,5 +1,6 @@
-use super::Matcher;
+use super::{MatchTrace, Matcher};
 
+use crate::language::TSLanguage;
 use crate::meta_var::MetaVarEnv;
 use crate::node::KindId;
 use crate::Language;
@@ -18,8 +19,17 @@ const TS_BUILTIN_SYM_ERROR: KindId = 65535;
 
 #[derive(Debug, Error)]
 pub enum KindMatcherError {
-  #[error("Kind `{0}` is invalid.")]
-  InvalidKindName(String),
+  /// The invalid kind name and similar valid kind names as suggestions.
+  #[error("Kind `{0}` is invalid.{}", did_you_mean(.1))]
+  InvalidKindName(String, Vec<String>),
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+  if suggestions.is_empty() {
+    return String::new();
+  }
+  let names: Vec<_> = suggestions.iter().map(|s| format!("`{s}`")).collect();
+  format!(" Did you mean: {}?", names.join(", "))
 }
 
 #[derive(Clone)]
@@ -39,9 +49,14 @@ impl<L: Language> KindMatcher<L> {
   }
 
   pub fn try_new(node_kind: &str, lang: L) -> Result<Self, KindMatcherError> {
+    let ts_lang = lang.get_ts_language();
     let s = Self::new(node_kind, lang);
     if s.is_invalid() {
-      Err(KindMatcherError::InvalidKindName(node_kind.into()))
+      let suggestions = suggest_kinds(&ts_lang, node_kind);
+      Err(KindMatcherError::InvalidKindName(
+        node_kind.into(),
+        suggestions,
+      ))
     } else {
       Ok(s)
     }
@@ -68,6 +83,44 @@ impl<L: Language> KindMatcher<L> {
   }
 }
 
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Find named kinds whose Levenshtein distance to the invalid kind is less than half of their length.
+/// Kinds starting with the invalid name are ranked first since abbreviation is a common mistake.
+fn suggest_kinds(lang: &TSLanguage, invalid: &str) -> Vec<String> {
+  let mut candidates: Vec<_> = (0..lang.node_kind_count())
+    .filter(|&id| lang.node_kind_is_named(id) && lang.node_kind_is_visible(id))
+    .filter_map(|id| lang.node_kind_for_id(id))
+    .map(|kind| {
+      let is_prefix = kind.starts_with(invalid);
+      (!is_prefix, levenshtein(invalid, &kind), kind.to_string())
+    })
+    .filter(|(_, dist, kind)| dist * 2 < invalid.len().max(kind.len()))
+    .collect();
+  candidates.sort();
+  candidates.dedup();
+  candidates
+    .into_iter()
+    .take(MAX_SUGGESTIONS)
+    .map(|(_, _, kind)| kind)
+    .collect()
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut prev: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut curr = vec![i + 1];
+    for (j, cb) in b.iter().enumerate() {
+      let cost = usize::from(ca != *cb);
+      let val = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+      curr.push(val);
+    }
+    prev = curr;
+  }
+  prev[b.len()]
+}
+
 impl<L: Language> Matcher<L> for KindMatcher<L> {
   fn match_node_with_env<'tree>(
     &self,
@@ -86,6 +139,12 @@ impl<L: Language> Matcher<L> for KindMatcher<L> {
     set.insert(self.kind.into());
     Some(set)
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let ts_lang = node.lang().get_ts_language();
+    let kind = ts_lang.node_kind_for_id(self.kind).unwrap_or_default();
+    MatchTrace::from_match(format!("kind: {kind}"), self.match_node(node))
+  }
 }
 
 #[cfg(test)]
@@ -135,4 +194,26 @@ mod test {
     // should has exactly one potential kind
     assert_eq!(potential_kinds.len(), 1);
   }
+
+  #[test]
+  fn test_levenshtein() {
+    assert_eq!(levenshtein("kitten", "sitting"), 3);
+    assert_eq!(levenshtein("", "abc"), 3);
+    assert_eq!(levenshtein("abc", "abc"), 0);
+  }
+
+  #[test]
+  fn test_kind_suggestion() {
+    let error = match KindMatcher::try_new("function_decl", Tsx) {
+      Err(e) => e,
+      Ok(_) => panic!("kind should be invalid"),
+    };
+    let KindMatcherError::InvalidKindName(_, suggestions) = &error;
+    assert_eq!(suggestions[0], "function_declaration");
+    assert!(error
+      .to_string()
+      .contains("Did you mean: `function_declaration`"));
+    let error = KindMatcher::try_new("IMPOSSIBLE_KIND", Tsx).err().unwrap();
+    assert_eq!(error.to_string(), "Kind `IMPOSSIBLE_KIND` is invalid.");
+  }
 }
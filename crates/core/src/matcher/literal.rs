@@ -0,0 +1,83 @@
+use super::Matcher;
+
+use crate::meta_var::MetaVarEnv;
+use crate::Language;
+use crate::Node;
+
+use bit_set::BitSet;
+
+use std::collections::HashSet;
+use std::marker::PhantomData;
+
+/// Match identifier-like leaf nodes whose text is in the set, e.g. for a denylist of names.
+/// A set lookup per node is much cheaper than trying one pattern per word.
+#[derive(Clone)]
+pub struct LiteralSetMatcher<L: Language> {
+  literals: HashSet<String>,
+  kinds: BitSet,
+  lang: PhantomData<L>,
+}
+
+impl<L: Language> LiteralSetMatcher<L> {
+  /// Only named kinds with `identifier` in their name, e.g. `property_identifier`, are matched.
+  pub fn new(literals: HashSet<String>, lang: L) -> Self {
+    let ts_lang = lang.get_ts_language();
+    let kinds = (0..ts_lang.node_kind_count())
+      .filter(|&id| ts_lang.node_kind_is_named(id))
+      .filter(|&id| {
+        ts_lang
+          .node_kind_for_id(id)
+          .map_or(false, |kind| kind.contains("identifier"))
+      })
+      .map(usize::from)
+      .collect();
+    Self {
+      literals,
+      kinds,
+      lang: PhantomData,
+    }
+  }
+}
+
+impl<L: Language> Matcher<L> for LiteralSetMatcher<L> {
+  fn match_node_with_env<'tree>(
+    &self,
+    node: Node<'tree, L>,
+    _env: &mut MetaVarEnv<'tree, L>,
+  ) -> Option<Node<'tree, L>> {
+    if !node.is_leaf() || !self.kinds.contains(node.kind_id().into()) {
+      return None;
+    }
+    self.literals.contains(&*node.text()).then_some(node)
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    Some(self.kinds.clone())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+  use crate::Root;
+
+  #[test]
+  fn test_literal_set_matcher() {
+    let banned: HashSet<_> = (0..100).map(|i| format!("banned{i}")).collect();
+    let matcher = LiteralSetMatcher::new(banned, Tsx);
+    let src = "banned1(a.banned42, banned100); let ok = 'banned7'; type T = banned99";
+    let root = Root::new(src, Tsx);
+    let found: Vec<_> = root
+      .root()
+      .find_all(&matcher)
+      .map(|n| n.text().to_string())
+      .collect();
+    assert_eq!(found, ["banned1", "banned42", "banned99"]);
+    let kinds = matcher.potential_kinds().expect("should have kinds");
+    let ts_lang = Tsx.get_ts_language();
+    assert!(kinds.contains(ts_lang.id_for_node_kind("identifier", true).into()));
+    let string_kind = ts_lang.id_for_node_kind("string_fragment", true);
+    assert!(!kinds.contains(string_kind.into()));
+  }
+}
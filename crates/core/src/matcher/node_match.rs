@@ -28,18 +28,24 @@ impl<'tree, L: Language> NodeMatch<'tree, L> {
   }
 
   pub fn replace_by<R: Replacer<L>>(&self, replacer: R) -> Edit {
-    let lang = self.lang().clone();
-    let env = self.get_env();
     let range = self.range();
     let position = range.start;
     let deleted_length = range.len();
-    let inserted_text = replacer.generate_replacement(env, lang);
+    let inserted_text = self.generate_replacement(&replacer, deleted_length);
     Edit {
       position,
       deleted_length,
       inserted_text,
     }
   }
+
+  /// Generate the replacement with `$$MATCH` bound to the first `len` bytes of the matched text.
+  pub(crate) fn generate_replacement<R: Replacer<L>>(&self, replacer: &R, len: usize) -> String {
+    let lang = self.lang().clone();
+    let mut env = self.get_env().clone();
+    env.insert_whole_match(self.text()[..len].to_string());
+    replacer.generate_replacement(&env, lang)
+  }
   /// # Safety
   /// should only called for readopting nodes
   pub(crate) unsafe fn get_mut_node(&mut self) -> &mut Node<'tree, L> {
@@ -120,5 +126,7 @@ mod test {
     assert_eq!(fixed.position, 0);
     assert_eq!(fixed.deleted_length, 9);
     assert_eq!(fixed.inserted_text, "var b = a");
+    let fixed = find.replace_by("wrap(() => { $$MATCH })");
+    assert_eq!(fixed.inserted_text, "wrap(() => { var a = 1 })");
   }
 }
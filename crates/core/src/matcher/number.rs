@@ -0,0 +1,186 @@
+use super::{MatchTrace, Matcher};
+use crate::meta_var::MetaVarEnv;
+use crate::Language;
+use crate::Node;
+
+use bit_set::BitSet;
+
+use std::marker::PhantomData;
+
+/// Bounds to compare a number with. Unset bounds are not checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NumberRange {
+  pub gt: Option<f64>,
+  pub gte: Option<f64>,
+  pub lt: Option<f64>,
+  pub lte: Option<f64>,
+  pub eq: Option<f64>,
+}
+
+impl NumberRange {
+  pub fn contains(&self, n: f64) -> bool {
+    self.gt.map_or(true, |b| n > b)
+      && self.gte.map_or(true, |b| n >= b)
+      && self.lt.map_or(true, |b| n < b)
+      && self.lte.map_or(true, |b| n <= b)
+      && self.eq.map_or(true, |b| n == b)
+  }
+}
+
+/// Match a numeric literal by its value. Nodes whose text is not a number never match.
+#[derive(Clone)]
+pub struct NumberMatcher<L: Language> {
+  range: NumberRange,
+  lang: PhantomData<L>,
+}
+
+impl<L: Language> NumberMatcher<L> {
+  pub fn new(range: NumberRange) -> Self {
+    Self {
+      range,
+      lang: PhantomData,
+    }
+  }
+}
+
+/// Parse the text of a numeric literal. Signs, `_` separators, `0x`/`0o`/`0b` prefixes
+/// and type suffixes like `10u32`, `1.5f` or `10L` are accepted.
+/// Integers with a leading zero like `0755` are rejected: they are legacy octal in C and
+/// JavaScript but decimal in Rust, so their value depends on the language.
+pub fn parse_number(text: &str) -> Option<f64> {
+  let text = text.trim().replace('_', "");
+  let (negative, text) = match text.as_bytes().first()? {
+    b'-' => (true, text[1..].trim_start()),
+    b'+' => (false, text[1..].trim_start()),
+    _ => (false, &text[..]),
+  };
+  let radix = match text.get(..2).map(str::to_ascii_lowercase).as_deref() {
+    Some("0x") => 16,
+    Some("0o") => 8,
+    Some("0b") => 2,
+    _ => 10,
+  };
+  let n = if radix == 10 {
+    let end = text
+      .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-')))
+      .unwrap_or(text.len());
+    let (digits, suffix) = text.split_at(end);
+    if !is_type_suffix(suffix) {
+      return None;
+    }
+    let int_len = digits
+      .find(|c: char| !c.is_ascii_digit())
+      .unwrap_or(digits.len());
+    if int_len > 1 && digits.starts_with('0') {
+      return None;
+    }
+    digits.parse::<f64>().ok()?
+  } else {
+    let digits = &text[2..];
+    let end = digits
+      .find(|c: char| !c.is_digit(radix))
+      .unwrap_or(digits.len());
+    let (digits, suffix) = digits.split_at(end);
+    if !is_type_suffix(suffix) {
+      return None;
+    }
+    u128::from_str_radix(digits, radix).ok()? as f64
+  };
+  Some(if negative { -n } else { n })
+}
+
+/// Type suffixes start with a letter, e.g. `u32` in Rust, `L` in Java or `n` for JS BigInt.
+fn is_type_suffix(suffix: &str) -> bool {
+  let mut chars = suffix.chars();
+  match chars.next() {
+    None => true,
+    Some(c) => c.is_ascii_alphabetic() && chars.all(|c| c.is_ascii_alphanumeric()),
+  }
+}
+
+impl<L: Language> Matcher<L> for NumberMatcher<L> {
+  fn match_node_with_env<'tree>(
+    &self,
+    node: Node<'tree, L>,
+    _env: &mut MetaVarEnv<'tree, L>,
+  ) -> Option<Node<'tree, L>> {
+    let n = parse_number(&node.text())?;
+    self.range.contains(n).then_some(node)
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    None
+  }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    MatchTrace::from_match("number", self.match_node(node))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_number() {
+    assert_eq!(parse_number("1000"), Some(1000.0));
+    assert_eq!(parse_number("1_000_000"), Some(1e6));
+    assert_eq!(parse_number("-42"), Some(-42.0));
+    assert_eq!(parse_number("- 42"), Some(-42.0));
+    assert_eq!(parse_number("2.5"), Some(2.5));
+    assert_eq!(parse_number(".5"), Some(0.5));
+    assert_eq!(parse_number("1e3"), Some(1000.0));
+    assert_eq!(parse_number("0xFF"), Some(255.0));
+    assert_eq!(parse_number("0XfF"), Some(255.0));
+    assert_eq!(parse_number("0o17"), Some(15.0));
+    assert_eq!(parse_number("0b1010"), Some(10.0));
+    assert_eq!(parse_number("10u32"), Some(10.0));
+    assert_eq!(parse_number("0xFFu8"), Some(255.0));
+    assert_eq!(parse_number("1.5f64"), Some(1.5));
+    assert_eq!(parse_number("10L"), Some(10.0));
+    assert_eq!(parse_number("10n"), Some(10.0));
+  }
+
+  #[test]
+  fn test_parse_non_number() {
+    assert_eq!(parse_number(""), None);
+    assert_eq!(parse_number("abc"), None);
+    assert_eq!(parse_number("a + 1"), None);
+    assert_eq!(parse_number("1 + 1"), None);
+    assert_eq!(parse_number("0x"), None);
+    assert_eq!(parse_number("0b102"), None);
+    assert_eq!(parse_number("'1'"), None);
+    // legacy octal in C and JavaScript, decimal in Rust
+    assert_eq!(parse_number("0755"), None);
+    assert_eq!(parse_number("-08"), None);
+    assert_eq!(parse_number("00.5"), None);
+  }
+
+  #[test]
+  fn test_parse_leading_zero() {
+    assert_eq!(parse_number("0"), Some(0.0));
+    assert_eq!(parse_number("0.5"), Some(0.5));
+    assert_eq!(parse_number("0e3"), Some(0.0));
+    assert_eq!(parse_number("0u8"), Some(0.0));
+    assert_eq!(parse_number("0o755"), Some(493.0));
+  }
+
+  #[test]
+  fn test_number_range() {
+    let range = NumberRange {
+      gt: Some(0.0),
+      lte: Some(10.0),
+      ..Default::default()
+    };
+    assert!(range.contains(10.0));
+    assert!(range.contains(0.5));
+    assert!(!range.contains(0.0));
+    assert!(!range.contains(10.5));
+    let range = NumberRange {
+      eq: Some(0.0),
+      ..Default::default()
+    };
+    assert!(range.contains(-0.0));
+    assert!(!range.contains(1.0));
+  }
+}
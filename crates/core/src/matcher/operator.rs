@@ -0,0 +1,74 @@
+use super::{MatchTrace, Matcher};
+use crate::meta_var::MetaVarEnv;
+use crate::Language;
+use crate::Node;
+
+use bit_set::BitSet;
+
+use std::marker::PhantomData;
+
+/// Match an expression by its operator token, e.g. `&&` in `a && b`.
+/// The `operator` field is checked first. Nodes without the field match
+/// if any anonymous child has exactly the operator text.
+#[derive(Clone)]
+pub struct OperatorMatcher<L: Language> {
+  operator: String,
+  lang: PhantomData<L>,
+}
+
+impl<L: Language> OperatorMatcher<L> {
+  pub fn new(operator: &str) -> Self {
+    Self {
+      operator: operator.to_string(),
+      lang: PhantomData,
+    }
+  }
+}
+
+impl<L: Language> Matcher<L> for OperatorMatcher<L> {
+  fn match_node_with_env<'tree>(
+    &self,
+    node: Node<'tree, L>,
+    _env: &mut MetaVarEnv<'tree, L>,
+  ) -> Option<Node<'tree, L>> {
+    let matched = match node.field("operator") {
+      Some(op) => op.text() == self.operator.as_str(),
+      None => node
+        .children()
+        .any(|c| !c.is_named() && c.text() == self.operator.as_str()),
+    };
+    matched.then_some(node)
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    None
+  }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    MatchTrace::from_match("operator", self.match_node(node))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+  use crate::Root;
+
+  fn find_all(operator: &str, src: &str) -> Vec<String> {
+    let matcher = OperatorMatcher::<Tsx>::new(operator);
+    let root = Root::new(src, Tsx);
+    let found = root.root().find_all(&matcher).map(|n| n.text().to_string());
+    found.collect()
+  }
+
+  #[test]
+  fn test_operator_matcher() {
+    let src = "a & b; c && d; e === f; g == h; !i";
+    assert_eq!(find_all("&", src), ["a & b"]);
+    assert_eq!(find_all("&&", src), ["c && d"]);
+    assert_eq!(find_all("==", src), ["g == h"]);
+    assert_eq!(find_all("===", src), ["e === f"]);
+    assert_eq!(find_all("!", src), ["!i"]);
+  }
+}
@@ -1,12 +1,18 @@
 use crate::language::Language;
-use crate::match_tree::{extract_var_from_node, match_end_non_recursive, match_node_non_recursive};
-use crate::matcher::{KindMatcher, KindMatcherError, Matcher};
+use crate::match_tree::{
+  extract_var_from_node, match_end_non_recursive, match_node_non_recursive, match_unparsed_text,
+  TextCompare,
+};
+use crate::matcher::{KindMatcher, KindMatcherError, MatchTrace, Matcher};
+use crate::meta_var::{encode_optional_meta_var, MetaVarEnv, MetaVariable, MetaVariableID};
 use crate::ts_parser::TSParseError;
-use crate::{meta_var::MetaVarEnv, Node, Root};
+use crate::{Node, Root};
 
 use bit_set::BitSet;
 use thiserror::Error;
 
+use std::collections::{HashMap, HashSet};
+
 /// Pattern style specify how we find the ast node to match, assuming pattern text's root is `Program`
 /// the effective AST node to match is either
 #[derive(Clone)]
@@ -22,6 +28,10 @@ enum PatternStyle<L: Language> {
 pub struct Pattern<L: Language> {
   pub(crate) root: Root<L>,
   style: PatternStyle<L>,
+  // precomputed potential_kinds to reject candidates before structural matching
+  kinds: Option<BitSet>,
+  // how identifiers are compared besides exact equality
+  compare: TextCompare,
 }
 
 #[derive(Debug, Error)]
@@ -36,6 +46,8 @@ pub enum PatternError {
   InvalidKind(#[from] KindMatcherError),
   #[error("Fails to create Contextual pattern: selector `{selector}` matches no node in the context `{context}`.")]
   NoSelectorInContext { context: String, selector: String },
+  #[error("Pattern has a syntax error near `{0}`.")]
+  SyntaxError(String),
 }
 
 #[inline]
@@ -47,29 +59,74 @@ fn is_single_node(n: &tree_sitter::Node) -> bool {
   }
 }
 
+fn check_single<L: Language>(root: &Root<L>, src: &str) -> Result<(), PatternError> {
+  let goal = root.root();
+  if goal.inner.child_count() == 0 {
+    return Err(PatternError::NoContent(src.into()));
+  }
+  if !is_single_node(&goal.inner) {
+    return Err(PatternError::MultipleNode(src.into()));
+  }
+  Ok(())
+}
+
 impl<L: Language> Pattern<L> {
   pub fn try_new(src: &str, lang: L) -> Result<Self, PatternError> {
-    let processed = lang.pre_process_pattern(src);
-    let root = Root::try_new(&processed, lang)?;
-    let goal = root.root();
-    if goal.inner.child_count() == 0 {
-      return Err(PatternError::NoContent(src.into()));
-    }
-    if !is_single_node(&goal.inner) {
-      return Err(PatternError::MultipleNode(src.into()));
+    let root = Self::parse(src, &lang)?;
+    check_single(&root, src)?;
+    Ok(Self::with_kinds(root, PatternStyle::Single))
+  }
+
+  /// Like [`Pattern::try_new`] but reports every issue instead of the first one.
+  /// Syntax errors do not prevent building the pattern so the pattern may be returned
+  /// along with diagnostics. None is returned if the pattern cannot be built.
+  pub fn new_with_diagnostics(src: &str, lang: L) -> (Option<Self>, Vec<PatternError>) {
+    let root = match Self::parse(src, &lang) {
+      Ok(root) => root,
+      Err(e) => return (None, vec![e]),
+    };
+    let mut errors = vec![];
+    let checked = check_single(&root, src);
+    let syntax_errors = root.root().dfs().filter(|n| n.inner.is_error());
+    // report only the outermost error node
+    let syntax_errors = syntax_errors.filter(|n| !n.ancestors().any(|a| a.inner.is_error()));
+    errors.extend(syntax_errors.map(|n| PatternError::SyntaxError(n.text().into())));
+    match checked {
+      Ok(()) => (Some(Self::with_kinds(root, PatternStyle::Single)), errors),
+      Err(e) => {
+        errors.insert(0, e);
+        (None, errors)
+      }
     }
-    Ok(Self {
-      root,
-      style: PatternStyle::Single,
-    })
+  }
+
+  fn parse(src: &str, lang: &L) -> Result<Root<L>, PatternError> {
+    let encoded = encode_optional_meta_var(src, lang.meta_var_char());
+    let processed = lang.pre_process_pattern(&encoded);
+    Ok(Root::try_new(&processed, lang.clone())?)
   }
 
   pub fn new(src: &str, lang: L) -> Self {
     Self::try_new(src, lang).unwrap()
   }
 
+  /// Compare identifiers in Unicode NFC form, so `café` matches whether `é` is one
+  /// code point or `e` followed by a combining accent. Off by default to match exact text.
+  pub fn normalize_identifiers(mut self, normalize: bool) -> Self {
+    self.compare.normalize = normalize;
+    self
+  }
+
+  /// Compare identifiers case-insensitively, so `getUrl` matches `getURL`.
+  /// Other text like strings and keywords still matches exactly.
+  pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+    self.compare.ignore_case = ignore_case;
+    self
+  }
+
   pub fn contextual(context: &str, selector: &str, lang: L) -> Result<Self, PatternError> {
-    let processed = lang.pre_process_pattern(context);
+    let encoded = encode_optional_meta_var(context, lang.meta_var_char());
+    let processed = lang.pre_process_pattern(&encoded);
     let root = Root::try_new(&processed, lang.clone())?;
     let goal = root.root();
     let kind_matcher = KindMatcher::try_new(selector, lang)?;
@@ -79,10 +136,35 @@ impl<L: Language> Pattern<L> {
         selector: selector.into(),
       });
     }
-    Ok(Self {
+    let style = PatternStyle::Selector(kind_matcher);
+    Ok(Self::with_kinds(root, style))
+  }
+
+  fn with_kinds(root: Root<L>, style: PatternStyle<L>) -> Self {
+    let mut pattern = Self {
       root,
-      style: PatternStyle::Selector(kind_matcher),
-    })
+      style,
+      kinds: None,
+      compare: TextCompare::default(),
+    };
+    pattern.kinds = pattern.compute_kinds();
+    pattern
+  }
+
+  fn compute_kinds(&self) -> Option<BitSet> {
+    let kind = match &self.style {
+      PatternStyle::Selector(kind) => return kind.potential_kinds(),
+      PatternStyle::Single => {
+        let matcher = self.single_matcher();
+        if matcher.is_leaf() && extract_var_from_node(&matcher).is_some() {
+          return None;
+        }
+        matcher.kind_id()
+      }
+    };
+    let mut kinds = BitSet::new();
+    kinds.insert(kind.into());
+    Some(kinds)
   }
 
   fn single_matcher(&self) -> Node<L> {
@@ -107,6 +189,78 @@ impl<L: Language> Pattern<L> {
       .map(Node::from)
       .expect("contextual match should succeed")
   }
+
+  fn matcher(&self) -> Node<'_, L> {
+    match &self.style {
+      PatternStyle::Single => self.single_matcher(),
+      PatternStyle::Selector(kind) => self.kind_matcher(kind),
+    }
+  }
+
+  /// Whether the patterns have the same tree with meta variables renamed consistently,
+  /// so they match the same code. `$A + $B` equals `$X + $Y` but not `$A + $A`.
+  pub fn structural_eq(&self, other: &Self) -> bool {
+    if self.compare != other.compare {
+      return false;
+    }
+    let mut renaming = Renaming::default();
+    node_structural_eq(&self.matcher(), &other.matcher(), &mut renaming)
+  }
+}
+
+/// A one-to-one mapping from meta variables of a pattern to those of another.
+#[derive(Default)]
+struct Renaming {
+  mapped: HashMap<MetaVariableID, MetaVariableID>,
+  targets: HashSet<MetaVariableID>,
+}
+
+impl Renaming {
+  fn rename(&mut self, from: MetaVariableID, to: MetaVariableID) -> bool {
+    if let Some(mapped) = self.mapped.get(&from) {
+      return *mapped == to;
+    }
+    if !self.targets.insert(to.clone()) {
+      return false;
+    }
+    self.mapped.insert(from, to);
+    true
+  }
+}
+
+fn meta_var_structural_eq(a: MetaVariable, b: MetaVariable, renaming: &mut Renaming) -> bool {
+  use MetaVariable as MV;
+  match (a, b) {
+    (MV::Named(a, a_named), MV::Named(b, b_named)) => a_named == b_named && renaming.rename(a, b),
+    (MV::NamedEllipsis(a), MV::NamedEllipsis(b)) | (MV::Optional(a), MV::Optional(b)) => {
+      renaming.rename(a, b)
+    }
+    (MV::Anonymous(a_named), MV::Anonymous(b_named)) => a_named == b_named,
+    (MV::Ellipsis, MV::Ellipsis) => true,
+    _ => false,
+  }
+}
+
+fn node_structural_eq<L: Language>(a: &Node<L>, b: &Node<L>, renaming: &mut Renaming) -> bool {
+  // a meta variable matches by itself regardless of the kind it is parsed as
+  let meta_var = |n: &Node<L>| n.is_leaf().then(|| extract_var_from_node(n)).flatten();
+  match (meta_var(a), meta_var(b)) {
+    (Some(a), Some(b)) => return meta_var_structural_eq(a, b, renaming),
+    (None, None) => (),
+    _ => return false,
+  }
+  if a.kind_id() != b.kind_id() || a.is_leaf() != b.is_leaf() {
+    return false;
+  }
+  if a.is_leaf() {
+    return a.text() == b.text();
+  }
+  if !match_unparsed_text(a, b) || a.children().count() != b.children().count() {
+    return false;
+  }
+  a.children()
+    .zip(b.children())
+    .all(|(a, b)| node_structural_eq(&a, &b, renaming))
 }
 
 impl<L: Language> Matcher<L> for Pattern<L> {
@@ -115,42 +269,37 @@ impl<L: Language> Matcher<L> for Pattern<L> {
     node: Node<'tree, L>,
     env: &mut MetaVarEnv<'tree, L>,
   ) -> Option<Node<'tree, L>> {
+    if let Some(kinds) = &self.kinds {
+      if !kinds.contains(node.kind_id().into()) {
+        return None;
+      }
+    }
     match &self.style {
       PatternStyle::Single => {
         let matcher = self.single_matcher();
-        match_node_non_recursive(&matcher, node, env)
+        match_node_non_recursive(&matcher, node, env, self.compare)
       }
       PatternStyle::Selector(kind) => {
         let matcher = self.kind_matcher(kind);
-        match_node_non_recursive(&matcher, node, env)
+        match_node_non_recursive(&matcher, node, env, self.compare)
       }
     }
   }
 
-  fn potential_kinds(&self) -> Option<bit_set::BitSet> {
-    let kind = match &self.style {
-      PatternStyle::Selector(kind) => return kind.potential_kinds(),
-      PatternStyle::Single => {
-        let matcher = self.single_matcher();
-        if matcher.is_leaf() && extract_var_from_node(&matcher).is_some() {
-          return None;
-        }
-        matcher.kind_id()
-      }
-    };
-    let mut kinds = BitSet::new();
-    kinds.insert(kind.into());
-    Some(kinds)
+  fn potential_kinds(&self) -> Option<BitSet> {
+    self.kinds.clone()
   }
 
   fn get_match_len(&self, node: Node<L>) -> Option<usize> {
     let start = node.range().start;
-    let end = match &self.style {
-      PatternStyle::Single => match_end_non_recursive(&self.single_matcher(), node)?,
-      PatternStyle::Selector(kind) => match_end_non_recursive(&self.kind_matcher(kind), node)?,
-    };
+    let end = match_end_non_recursive(&self.matcher(), node, self.compare)?;
     Some(end - start)
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let label = format!("pattern: {}", self.root.source.trim());
+    MatchTrace::from_match(label, self.match_node(node))
+  }
 }
 
 impl<L: Language> std::fmt::Debug for Pattern<L> {
@@ -335,6 +484,166 @@ mod test {
     assert_eq!(env["B"], "1+2+3");
   }
 
+  #[test]
+  fn test_template_string_meta_var() {
+    let env = match_env("`Hello ${ $NAME }`", "let a = `Hello ${name}`");
+    assert_eq!(env["NAME"], "name");
+    test_non_match("`Hello ${ $NAME }`", "let a = `Bye ${name}`");
+  }
+
+  // count how many times the matcher inspects a meta variable
+  #[derive(Clone)]
+  struct CountingTsx(std::rc::Rc<std::cell::Cell<usize>>);
+  impl Language for CountingTsx {
+    fn get_ts_language(&self) -> crate::language::TSLanguage {
+      Tsx.get_ts_language()
+    }
+    fn extract_meta_var(&self, source: &str) -> Option<crate::meta_var::MetaVariable> {
+      self.0.set(self.0.get() + 1);
+      Tsx.extract_meta_var(source)
+    }
+  }
+
+  #[test]
+  fn test_reject_kind_before_matching() {
+    let lang = CountingTsx(Default::default());
+    let pattern = Pattern::new("foo", lang.clone());
+    let root = Root::new("foo; 123", lang.clone());
+    let stmts: Vec<_> = root.root().children().collect();
+    let number = stmts[1].child(0).unwrap();
+    lang.0.set(0);
+    assert!(pattern.match_node(number).is_none());
+    assert_eq!(
+      lang.0.get(),
+      0,
+      "should reject without inspecting the pattern"
+    );
+    let ident = stmts[0].child(0).unwrap();
+    assert!(pattern.match_node(ident).is_some());
+    assert!(lang.0.get() > 0);
+  }
+
+  #[test]
+  fn test_meta_var_pattern_has_no_kind_check() {
+    let pattern = Pattern::new("$A", Tsx);
+    assert!(pattern.potential_kinds().is_none());
+    let root = pattern_node("foo; 123");
+    let stmts: Vec<_> = root.root().children().collect();
+    assert!(pattern.match_node(stmts[0].clone()).is_some());
+    assert!(pattern.match_node(stmts[1].child(0).unwrap()).is_some());
+  }
+
+  #[test]
+  fn test_optional_meta_var() {
+    test_match("foo($A, $?B)", "foo(a, b)");
+    test_match("foo($A, $?B)", "foo(a)");
+    test_match("foo($?A, b)", "foo(b)");
+    test_match("foo($?A)", "foo()");
+    test_non_match("foo($A, $?B)", "foo(a, b, c)");
+    test_non_match("foo($A, $?B)", "foo()");
+    let env = match_env("foo($A, $?B)", "foo(a, b)");
+    assert_eq!(env["B"], "b");
+    let env = match_env("foo($A, $?B)", "foo(a)");
+    assert_eq!(env["A"], "a");
+    assert!(!env.contains_key("B"));
+  }
+
+  #[test]
+  fn test_multiple_optional_meta_var() {
+    test_match("foo($?A, $?B)", "foo()");
+    test_match("foo($?A, $?B)", "foo(a)");
+    test_match("foo($?A, $?B)", "foo(a, b)");
+    test_non_match("foo($?A, $?B)", "foo(a, b, c)");
+    // optional nodes are matched one by one instead of trying every combination
+    let pattern = "foo($?A, $?B, $?C, $?D, $?E, $?F, $?G, $?H, $?I, $?J, $?K, $?L)";
+    test_match(pattern, "foo()");
+    test_match(pattern, "foo(a, b, c)");
+    test_non_match(pattern, "foo(a b)");
+    let env = match_env(pattern, "foo(a, b)");
+    assert_eq!(env["A"], "a");
+    assert_eq!(env["B"], "b");
+    assert!(!env.contains_key("C"));
+  }
+
+  #[test]
+  fn test_pattern_diagnostics() {
+    let (pattern, errors) = Pattern::new_with_diagnostics("foo(a b); bar()", Tsx);
+    assert!(pattern.is_none());
+    let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+      errors,
+      [
+        "Multiple AST nodes are detected. Please check the pattern source `foo(a b); bar()`.",
+        "Pattern has a syntax error near `b`.",
+      ]
+    );
+    // syntax errors are recoverable
+    let (pattern, errors) = Pattern::new_with_diagnostics("foo(a b)", Tsx);
+    assert!(pattern.is_some());
+    assert!(matches!(&errors[..], [PatternError::SyntaxError(_)]));
+    let (pattern, errors) = Pattern::new_with_diagnostics("foo(a, b)", Tsx);
+    assert!(pattern.is_some());
+    assert!(errors.is_empty());
+  }
+
+  #[test]
+  fn test_normalize_identifiers() {
+    let nfc = "caf\u{e9}";
+    let nfd = "cafe\u{301}";
+    for (pat, src) in [(nfc, nfd), (nfd, nfc)] {
+      let pattern = Pattern::new(&format!("{pat}($A)"), Tsx);
+      let root = Root::new(&format!("{src}(1)"), Tsx);
+      assert!(root.root().find(&pattern).is_none());
+      let pattern = pattern.normalize_identifiers(true);
+      let found = root.root().find(&pattern).expect("should match");
+      let len = pattern.get_match_len(found.get_node().clone());
+      assert_eq!(len, Some(src.len() + 3));
+    }
+    // optional meta variables do not change how identifiers are compared
+    let pattern = Pattern::new(&format!("{nfc}($?A)"), Tsx).normalize_identifiers(true);
+    let root = Root::new(&format!("{nfd}()"), Tsx);
+    assert!(root.root().find(&pattern).is_some());
+    // strings are not identifiers
+    let pattern = Pattern::new(&format!("'{nfc}'"), Tsx).normalize_identifiers(true);
+    let root = Root::new(&format!("'{nfd}'"), Tsx);
+    assert!(root.root().find(&pattern).is_none());
+  }
+
+  #[test]
+  fn test_ignore_case() {
+    let pattern = Pattern::new("getUrl($A)", Tsx);
+    let root = Root::new("getURL(1); GETURL(2); geturl('X')", Tsx);
+    assert_eq!(root.root().find_all(&pattern).count(), 0);
+    let pattern = pattern.ignore_case(true);
+    assert_eq!(root.root().find_all(&pattern).count(), 3);
+    // strings are not identifiers
+    let pattern = Pattern::new("foo('a')", Tsx).ignore_case(true);
+    let root = Root::new("FOO('A')", Tsx);
+    assert!(root.root().find(&pattern).is_none());
+    let root = Root::new("FOO('a')", Tsx);
+    assert!(root.root().find(&pattern).is_some());
+  }
+
+  #[test]
+  fn test_structural_eq() {
+    let eq = |a: &str, b: &str| Pattern::new(a, Tsx).structural_eq(&Pattern::new(b, Tsx));
+    assert!(eq("$A + $B", "$X + $Y"));
+    assert!(eq("$A + $B", "$B + $A"));
+    assert!(eq("$A + $A", "$X + $X"));
+    assert!(!eq("$A + $A", "$X + $Y"));
+    assert!(!eq("$A + $B", "$X + $X"));
+    assert!(!eq("$A + $B", "$A - $B"));
+    assert!(!eq("a + $B", "b + $B"));
+    assert!(!eq("$A + $B", "$A + b"));
+    assert!(eq("foo($$$ARGS, $A)", "foo($$$REST, $B)"));
+    assert!(!eq("foo($$$)", "foo($$$ARGS)"));
+    assert!(!eq("foo($A)", "foo($_)"));
+    assert!(eq("`a${$A}`", "`a${$B}`"));
+    assert!(!eq("`a${$A}`", "`b${$A}`"));
+    let pattern = Pattern::new("foo($A)", Tsx);
+    assert!(!pattern.structural_eq(&pattern.clone().normalize_identifiers(true)));
+  }
+
   #[test]
   #[ignore]
   fn test_pattern_size() {
@@ -1,4 +1,4 @@
-use super::Matcher;
+use super::{MatchTrace, Matcher};
 use crate::meta_var::MetaVarEnv;
 use crate::Language;
 use crate::Node;
@@ -8,6 +8,7 @@ use regex::{Error as RegexError, Regex};
 use thiserror::Error;
 
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 #[derive(Debug, Error)]
 pub enum RegexMatcherError {
@@ -15,19 +16,26 @@ pub enum RegexMatcherError {
   Regex(#[from] RegexError),
 }
 
+/// Match node by its text. The regex is compiled once in construction
+/// and shared by all clones, e.g. across threads in parallel scanning.
 #[derive(Clone)]
 pub struct RegexMatcher<L: Language> {
-  regex: Regex,
+  regex: Arc<Regex>,
   lang: PhantomData<L>,
 }
 
 impl<L: Language> RegexMatcher<L> {
   pub fn try_new(text: &str) -> Result<Self, RegexMatcherError> {
     Ok(RegexMatcher {
-      regex: Regex::new(text)?,
+      regex: Arc::new(Regex::new(text)?),
       lang: PhantomData,
     })
   }
+
+  /// Like `try_new` but the regex only matches whole words, like `grep -w`.
+  pub fn try_new_word(text: &str) -> Result<Self, RegexMatcherError> {
+    Self::try_new(&format!(r"\b(?:{text})\b"))
+  }
 }
 
 impl<L: Language> Matcher<L> for RegexMatcher<L> {
@@ -42,4 +50,64 @@ impl<L: Language> Matcher<L> for RegexMatcher<L> {
   fn potential_kinds(&self) -> Option<BitSet> {
     None
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let label = format!("regex: {}", self.regex.as_str());
+    MatchTrace::from_match(label, self.match_node(node))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+  use crate::Root;
+
+  fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+  #[test]
+  fn test_regex_matcher() {
+    let matcher = RegexMatcher::<Tsx>::try_new("^a+$").expect("should compile");
+    let root = Root::new("aaa; b", Tsx);
+    assert_eq!(root.root().find_all(&matcher).count(), 1);
+    assert!(RegexMatcher::<Tsx>::try_new("*").is_err());
+  }
+
+  #[test]
+  fn test_word_regex_matcher() {
+    let is_match = |matcher: &RegexMatcher<Tsx>, src: &str| {
+      let root = Root::new(src, Tsx);
+      matcher.match_node(root.root()).is_some()
+    };
+    let matcher = RegexMatcher::<Tsx>::try_new_word("foo").expect("should compile");
+    assert!(is_match(&matcher, "foo"));
+    assert!(is_match(&matcher, "a.foo()"));
+    assert!(!is_match(&matcher, "foobar"));
+    assert!(!is_match(&matcher, "barfoo"));
+    let matcher = RegexMatcher::<Tsx>::try_new_word("foo|bar").expect("should compile");
+    assert!(is_match(&matcher, "bar"));
+    assert!(!is_match(&matcher, "foobar"));
+  }
+
+  #[test]
+  fn test_regex_compiled_once() {
+    let matcher = RegexMatcher::<Tsx>::try_new("a").expect("should compile");
+    assert_send_sync(&matcher);
+    let cloned = matcher.clone();
+    // clones share the compiled regex instead of compiling a new one
+    assert!(Arc::ptr_eq(&matcher.regex, &cloned.regex));
+    let root = Root::new("a; b; a", Tsx);
+    for node in root.root().dfs() {
+      let mut env = MetaVarEnv::new();
+      cloned.match_node_with_env(node, &mut env);
+    }
+    assert_eq!(Arc::strong_count(&matcher.regex), 2);
+    std::thread::scope(|s| {
+      s.spawn(|| {
+        let root = Root::new("a", Tsx);
+        assert!(root.root().find(&cloned).is_some());
+      });
+    });
+    assert!(Arc::ptr_eq(&matcher.regex, &cloned.regex));
+  }
 }
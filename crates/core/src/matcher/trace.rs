@@ -0,0 +1,72 @@
+use super::NodeMatch;
+use crate::meta_var::MetaVarEnv;
+use crate::Language;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A tree recording which matchers succeeded or failed against one node.
+/// It is produced by [`Matcher::explain`](super::Matcher::explain) to debug complex rules.
+#[derive(Clone, Debug)]
+pub struct MatchTrace {
+  /// human readable description of the matcher, e.g. `pattern: console.log($A)`
+  pub label: String,
+  pub matched: bool,
+  /// meta variables captured by the matcher, sorted by name
+  pub env: Vec<(String, String)>,
+  /// traces of sub-matchers, each evaluated against the node independently
+  pub children: Vec<MatchTrace>,
+}
+
+impl MatchTrace {
+  pub fn new<S: Into<String>>(label: S, matched: bool) -> Self {
+    Self {
+      label: label.into(),
+      matched,
+      env: vec![],
+      children: vec![],
+    }
+  }
+
+  /// Create a trace from the result of [`Matcher::match_node`](super::Matcher::match_node).
+  pub fn from_match<S: Into<String>, L: Language>(label: S, ret: Option<NodeMatch<L>>) -> Self {
+    let Some(nm) = ret else {
+      return Self::new(label, false);
+    };
+    let env: MetaVarEnv<L> = nm.get_env().clone();
+    let mut env: Vec<_> = HashMap::from(env).into_iter().collect();
+    env.sort();
+    Self {
+      env,
+      ..Self::new(label, true)
+    }
+  }
+
+  pub fn with_children(mut self, children: Vec<MatchTrace>) -> Self {
+    self.children = children;
+    self
+  }
+
+  fn fmt_depth(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+    let mark = if self.matched { '✔' } else { '✘' };
+    writeln!(f, "{:indent$}{mark} {}", "", self.label, indent = depth * 2)?;
+    for child in &self.children {
+      child.fmt_depth(f, depth + 1)?;
+    }
+    Ok(())
+  }
+}
+
+/// Print the trace as an indented tree, followed by the captured meta variables.
+impl fmt::Display for MatchTrace {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.fmt_depth(f, 0)?;
+    if !self.env.is_empty() {
+      writeln!(f, "captured:")?;
+    }
+    for (name, text) in &self.env {
+      writeln!(f, "  ${name} = {text}")?;
+    }
+    Ok(())
+  }
+}
@@ -1,17 +1,36 @@
 use crate::match_tree::does_node_match_exactly;
-use crate::matcher::{KindMatcher, Pattern, RegexMatcher};
+use crate::matcher::{KindMatcher, NumberMatcher, NumberRange, Pattern, RegexMatcher};
 use crate::Language;
 use crate::Node;
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 pub type MetaVariableID = String;
 
+/// Key of the whole match text bound for `$$MATCH` in fixes.
+/// It is not a valid meta variable name so it never shadows a capture or transform.
+const WHOLE_MATCH: &str = "$$MATCH";
+
 /// a dictionary that stores metavariable instantiation
 /// const a = 123 matched with const a = $A will produce env: $A => 123
 #[derive(Clone)]
 pub struct MetaVarEnv<'tree, L: Language> {
   single_matched: HashMap<MetaVariableID, Node<'tree, L>>,
   multi_matched: HashMap<MetaVariableID, Vec<Node<'tree, L>>>,
+  /// text computed from other captures, e.g. by transforms in rule config
+  transformed_var: HashMap<MetaVariableID, String>,
+  /// bindings to undo on rollback, recorded only between checkpoint and rollback
+  undo: Vec<Undo<'tree, L>>,
+  checkpoints: usize,
+}
+
+/// How to revert one binding made after a checkpoint.
+#[derive(Clone)]
+enum Undo<'tree, L: Language> {
+  /// remove a meta variable bound for the first time
+  Single(MetaVariableID),
+  /// restore the nodes of an ellipsis or a label, or remove it if absent before
+  Multi(MetaVariableID, Option<Vec<Node<'tree, L>>>),
 }
 
 impl<'tree, L: Language> MetaVarEnv<'tree, L> {
@@ -19,6 +38,50 @@ impl<'tree, L: Language> MetaVarEnv<'tree, L> {
     Self {
       single_matched: HashMap::new(),
       multi_matched: HashMap::new(),
+      transformed_var: HashMap::new(),
+      undo: Vec::new(),
+      checkpoints: 0,
+    }
+  }
+
+  /// Remove all bindings but keep the allocated memory for reuse.
+  pub fn clear(&mut self) {
+    self.single_matched.clear();
+    self.multi_matched.clear();
+    self.transformed_var.clear();
+    self.undo.clear();
+    self.checkpoints = 0;
+  }
+
+  /// Start recording meta variable and label bindings so that `rollback` can undo them,
+  /// e.g. to discard what a trial match binds. Checkpoints can be nested.
+  pub fn checkpoint(&mut self) -> usize {
+    self.checkpoints += 1;
+    self.undo.len()
+  }
+
+  /// Undo the bindings made since the checkpoint. Nothing is allocated if none were made.
+  pub fn rollback(&mut self, checkpoint: usize) {
+    while self.undo.len() > checkpoint {
+      match self.undo.pop().expect("must exist") {
+        Undo::Single(id) => {
+          self.single_matched.remove(&id);
+        }
+        Undo::Multi(id, Some(nodes)) => {
+          self.multi_matched.insert(id, nodes);
+        }
+        Undo::Multi(id, None) => {
+          self.multi_matched.remove(&id);
+        }
+      }
+    }
+    self.checkpoints -= 1;
+  }
+
+  fn record_multi(&mut self, id: &str) {
+    if self.checkpoints > 0 {
+      let nodes = self.multi_matched.get(id).cloned();
+      self.undo.push(Undo::Multi(id.to_string(), nodes));
     }
   }
 
@@ -26,6 +89,9 @@ impl<'tree, L: Language> MetaVarEnv<'tree, L> {
     if !self.match_variable(&id, ret.clone()) {
       return None;
     }
+    if self.checkpoints > 0 && !self.single_matched.contains_key(&id) {
+      self.undo.push(Undo::Single(id.clone()));
+    }
     self.single_matched.insert(id, ret);
     Some(self)
   }
@@ -35,13 +101,16 @@ impl<'tree, L: Language> MetaVarEnv<'tree, L> {
     id: MetaVariableID,
     ret: Vec<Node<'tree, L>>,
   ) -> Option<&mut Self> {
+    self.record_multi(&id);
     self.multi_matched.insert(id, ret);
     Some(self)
   }
 
   pub fn get(&self, var: &MetaVariable) -> Option<MatchResult<'_, 'tree, L>> {
     match var {
-      MetaVariable::Named(n, _) => self.single_matched.get(n).map(MatchResult::Single),
+      MetaVariable::Named(n, _) | MetaVariable::Optional(n) => {
+        self.single_matched.get(n).map(MatchResult::Single)
+      }
       MetaVariable::NamedEllipsis(n) => self.multi_matched.get(n).map(MatchResult::Multi),
       _ => None,
     }
@@ -55,7 +124,25 @@ impl<'tree, L: Language> MetaVarEnv<'tree, L> {
     self.multi_matched.get(var).cloned().unwrap_or_default()
   }
 
+  /// Bind a meta variable to computed text. It takes precedence over captured nodes in replacement.
+  pub fn insert_transformation(&mut self, id: MetaVariableID, text: String) {
+    self.transformed_var.insert(id, text);
+  }
+
+  pub fn get_transformed(&self, var: &str) -> Option<&String> {
+    self.transformed_var.get(var)
+  }
+
+  pub(crate) fn insert_whole_match(&mut self, text: String) {
+    self.transformed_var.insert(WHOLE_MATCH.into(), text);
+  }
+
+  pub(crate) fn get_whole_match(&self) -> Option<&String> {
+    self.transformed_var.get(WHOLE_MATCH)
+  }
+
   pub fn add_label(&mut self, label: &str, node: Node<'tree, L>) {
+    self.record_multi(label);
     self
       .multi_matched
       .entry(label.into())
@@ -70,7 +157,14 @@ impl<'tree, L: Language> MetaVarEnv<'tree, L> {
   pub fn match_constraints(&self, var_matchers: &MetaVarMatchers<L>) -> bool {
     for (var_id, candidate) in &self.single_matched {
       if let Some(m) = var_matchers.0.get(var_id) {
-        if !m.matches(candidate.clone()) {
+        if !m.matches(candidate.clone(), self) {
+          return false;
+        }
+      }
+    }
+    for (var_id, candidates) in &self.multi_matched {
+      if let Some(m) = var_matchers.0.get(var_id) {
+        if !m.matches_multiple(candidates) {
           return false;
         }
       }
@@ -117,6 +211,9 @@ impl<'tree, L: Language> From<MetaVarEnv<'tree, L>> for HashMap<String, String>
       let s = s.join(", ");
       ret.insert(id, format!("[{s}]"));
     }
+    for (id, text) in env.transformed_var {
+      ret.insert(id, text);
+    }
     ret
   }
 }
@@ -138,6 +235,8 @@ pub enum MetaVariable {
   Ellipsis,
   /// $$$A for captured ellipsis
   NamedEllipsis(MetaVariableID),
+  /// $?A for optional meta var matching zero or one node
+  Optional(MetaVariableID),
 }
 
 #[derive(Clone)]
@@ -151,6 +250,10 @@ impl<L: Language> MetaVarMatchers<L> {
   pub fn insert(&mut self, var_id: MetaVariableID, matcher: MetaVarMatcher<L>) {
     self.0.insert(var_id, matcher);
   }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
 }
 
 impl<L: Language> Default for MetaVarMatchers<L> {
@@ -168,24 +271,115 @@ pub enum MetaVarMatcher<L: Language> {
   Pattern(Pattern<L>),
   /// A kind_id to filter matched metavar based on its ts-node kind
   Kind(KindMatcher<L>),
+  /// A range to filter matched metavar based on its numeric value
+  Number(NumberMatcher<L>),
+  /// A relation between matched metavar's text and another captured metavar's text
+  Relation(MetaVarRelation, MetaVariableID),
+  /// Bounds of how many nodes a metavar captures, e.g. arguments captured by `$$$ARGS`
+  Count {
+    min: Option<usize>,
+    max: Option<usize>,
+  },
+  /// A range to filter matched metavar based on its size, e.g. the lines a function spans
+  Span(SpanUnit, NumberRange),
+}
+
+/// The unit in which the size of a matched metavar is measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanUnit {
+  /// Lines from the start to the end of the node, both inclusive.
+  /// A trailing line break does not start another line.
+  Lines,
+  Bytes,
+  Chars,
+}
+
+impl SpanUnit {
+  fn measure<L: Language>(&self, node: &Node<L>) -> usize {
+    match self {
+      Self::Lines => {
+        let (start, _) = node.start_pos();
+        let (end, end_column) = node.end_pos();
+        // a node ending right after a line break does not reach into the next line
+        let end = if end_column == 0 && end > start {
+          end - 1
+        } else {
+          end
+        };
+        end - start + 1
+      }
+      Self::Bytes => node.range().len(),
+      Self::Chars => node.text().chars().count(),
+    }
+  }
+}
+
+/// How the text of a matched metavar relates to the text of another captured metavar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetaVarRelation {
+  Equals,
+  NotEquals,
+  StartsWith,
 }
 
 impl<L: Language> MetaVarMatcher<L> {
-  pub fn matches(&self, candidate: Node<L>) -> bool {
+  /// Check the candidate against the matcher. `env` is used to resolve other captured metavars.
+  /// A relation fails if the referenced metavar is not captured in `env`.
+  pub fn matches<'tree>(&self, candidate: Node<'tree, L>, env: &MetaVarEnv<'tree, L>) -> bool {
     use crate::matcher::Matcher;
     use MetaVarMatcher::*;
-    let mut env = MetaVarEnv::new();
+    let mut new_env = MetaVarEnv::new();
     match self {
       #[cfg(feature = "regex")]
-      Regex(r) => r.match_node_with_env(candidate, &mut env).is_some(),
-      Pattern(p) => p.match_node_with_env(candidate, &mut env).is_some(),
-      Kind(k) => k.match_node_with_env(candidate, &mut env).is_some(),
+      Regex(r) => r.match_node_with_env(candidate, &mut new_env).is_some(),
+      Pattern(p) => p.match_node_with_env(candidate, &mut new_env).is_some(),
+      Kind(k) => k.match_node_with_env(candidate, &mut new_env).is_some(),
+      Number(n) => n.match_node_with_env(candidate, &mut new_env).is_some(),
+      Count { min, max } => in_count(1, *min, *max),
+      Span(unit, range) => range.contains(unit.measure(&candidate) as f64),
+      Relation(relation, other) => {
+        let Some(other) = env.get_match(other) else {
+          return false;
+        };
+        let (text, other) = (candidate.text(), other.text());
+        match relation {
+          MetaVarRelation::Equals => text == other,
+          MetaVarRelation::NotEquals => text != other,
+          MetaVarRelation::StartsWith => text.starts_with(&*other),
+        }
+      }
+    }
+  }
+
+  /// Check the nodes captured by a multi metavar like `$$$ARGS`. Only `Count` checks them,
+  /// counting named nodes so that separators like commas are not counted.
+  pub fn matches_multiple(&self, candidates: &[Node<L>]) -> bool {
+    match self {
+      Self::Count { min, max } => {
+        let count = candidates.iter().filter(|n| n.is_named()).count();
+        in_count(count, *min, *max)
+      }
+      _ => true,
     }
   }
 }
 
+fn in_count(count: usize, min: Option<usize>, max: Option<usize>) -> bool {
+  min.map_or(true, |m| count >= m) && max.map_or(true, |m| count <= m)
+}
+
 pub(crate) fn extract_meta_var(src: &str, meta_char: char) -> Option<MetaVariable> {
   use MetaVariable::*;
+  let optional: String = std::iter::repeat(meta_char).take(4).collect();
+  if let Some(trimmed) = src.strip_prefix(&optional) {
+    if trimmed.is_empty() || trimmed.starts_with('_') {
+      return None;
+    }
+    if !trimmed.chars().all(is_valid_meta_var_char) {
+      return None;
+    }
+    return Some(Optional(trimmed.to_owned()));
+  }
   let ellipsis: String = std::iter::repeat(meta_char).take(3).collect();
   if src == ellipsis {
     return Some(Ellipsis);
@@ -230,6 +424,28 @@ pub fn split_first_meta_var(src: &str, meta_char: char) -> (&str, &str) {
   }
 }
 
+/// `$?A` is not valid syntax in most languages, so it is encoded as `$$$$A` before parsing.
+pub(crate) fn encode_optional_meta_var(src: &str, meta_char: char) -> Cow<'_, str> {
+  let optional = format!("{meta_char}?");
+  if !src.contains(&optional) {
+    return Cow::Borrowed(src);
+  }
+  let encoded: String = std::iter::repeat(meta_char).take(4).collect();
+  let mut ret = String::with_capacity(src.len());
+  let mut rest = src;
+  while let Some(i) = rest.find(&optional) {
+    ret.push_str(&rest[..i]);
+    rest = &rest[i + optional.len()..];
+    if rest.starts_with(|c: char| c != '_' && is_valid_meta_var_char(c)) {
+      ret.push_str(&encoded);
+    } else {
+      ret.push_str(&optional);
+    }
+  }
+  ret.push_str(rest);
+  Cow::Owned(ret)
+}
+
 fn is_valid_meta_var_char(c: char) -> bool {
   matches!(c, 'A'..='Z' | '_')
 }
@@ -253,6 +469,58 @@ mod test {
     assert_eq!(extract_var("$_"), Some(Anonymous(true)));
     assert_eq!(extract_var("abc"), None);
     assert_eq!(extract_var("$abc"), None);
+    assert_eq!(extract_var("$$$$ABC"), Some(Optional("ABC".into())));
+    assert_eq!(extract_var("$$$$_"), None);
+  }
+
+  #[test]
+  fn test_encode_optional() {
+    assert_eq!(
+      encode_optional_meta_var("foo($A, $?B)", '$'),
+      "foo($A, $$$$B)"
+    );
+    assert_eq!(
+      encode_optional_meta_var("a ? $?B : c", '$'),
+      "a ? $$$$B : c"
+    );
+    assert_eq!(encode_optional_meta_var("$?_ $?b", '$'), "$?_ $?b");
+  }
+
+  #[test]
+  fn test_rollback() {
+    let root = Tsx.ast_grep("a; b");
+    let a = root.root().child(0).expect("should exist");
+    let b = root.root().child(1).expect("should exist");
+    let mut env = MetaVarEnv::new();
+    env.insert("A".into(), a.clone());
+    env.add_label("secondary", a.clone());
+    let checkpoint = env.checkpoint();
+    env.insert("A".into(), a.clone());
+    env.insert("B".into(), b.clone());
+    env.add_label("secondary", b.clone());
+    env.insert_multi("C".into(), vec![b]);
+    env.rollback(checkpoint);
+    assert_eq!(env.get_match("A").map(|n| n.text()), Some(a.text()));
+    assert!(env.get_match("B").is_none());
+    assert_eq!(env.get_labels("secondary").map(Vec::len), Some(1));
+    assert!(env.get_multiple_matches("C").is_empty());
+    assert!(env.undo.is_empty());
+    // nothing is recorded without a checkpoint
+    env.insert("B".into(), a);
+    assert!(env.undo.is_empty());
+  }
+
+  #[test]
+  fn test_span_lines() {
+    let lines = |src: &str| {
+      let root = Tsx.ast_grep(src);
+      let node = root.root();
+      (node.text().ends_with('\n'), SpanUnit::Lines.measure(&node))
+    };
+    assert_eq!(lines("let a = 1"), (false, 1));
+    assert_eq!(lines("let a = 1\n"), (true, 1));
+    assert_eq!(lines("let a = 1\nlet b = 2\n"), (true, 2));
+    assert_eq!(lines("let a = {\n}"), (false, 2));
   }
 
   fn match_constraints(pattern: &str, node: &str) -> bool {
@@ -279,6 +547,7 @@ mod test {
     assert_eq!(extract("µ_"), Some(Anonymous(true)));
     assert_eq!(extract("abc"), None);
     assert_eq!(extract("µabc"), None);
+    assert_eq!(extract("µµµµABC"), Some(Optional("ABC".into())));
   }
 
   #[test]
@@ -290,4 +559,29 @@ mod test {
   fn test_match_not_constraints() {
     assert!(!match_constraints("a - b", "a + b"));
   }
+
+  fn match_relation(relation: MetaVarRelation, other: &str, src: &str) -> bool {
+    let mut matchers = MetaVarMatchers(HashMap::new());
+    matchers.insert(
+      "A".to_string(),
+      MetaVarMatcher::Relation(relation, other.to_string()),
+    );
+    let root = Tsx.ast_grep(src);
+    let matched = root.root().find("$A = $B").expect("should match");
+    matched.get_env().match_constraints(&matchers)
+  }
+
+  #[test]
+  fn test_match_relation_constraints() {
+    use MetaVarRelation::*;
+    assert!(match_relation(Equals, "B", "a = a"));
+    assert!(!match_relation(Equals, "B", "a = b"));
+    assert!(match_relation(NotEquals, "B", "a = b"));
+    assert!(!match_relation(NotEquals, "B", "a = a"));
+    assert!(match_relation(StartsWith, "B", "abc = ab"));
+    assert!(!match_relation(StartsWith, "B", "ab = abc"));
+    // referenced meta var is not captured
+    assert!(!match_relation(Equals, "C", "a = a"));
+    assert!(!match_relation(NotEquals, "C", "a = b"));
+  }
 }
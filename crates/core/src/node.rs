@@ -1,11 +1,14 @@
-use crate::language::Language;
+use crate::language::{Language, Precedence};
 use crate::matcher::{FindAllNodes, Matcher, NodeMatch};
+use crate::meta_var::MetaVarEnv;
 use crate::replacer::Replacer;
 use crate::source::{Content, Source};
 use crate::traversal::{Pre, Visitor};
 use crate::ts_parser::{parse, perform_edit, Edit, TSParseError};
 
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
 
 /// Represents [`tree_sitter::Tree`] and owns source string
 /// Note: Root is generic against [`Language`](crate::language::Language)
@@ -38,6 +41,16 @@ impl<L: Language> Root<L> {
   pub fn new(src: &str, lang: L) -> Self {
     Self::try_new(src, lang).expect("should parse")
   }
+
+  /// Parse the source in the language detected from the path, e.g. TSX for `a.tsx`.
+  pub fn new_with_language_detection<P: AsRef<Path>>(
+    src: &str,
+    path: P,
+  ) -> Result<Self, TSParseError> {
+    let path = path.as_ref();
+    let lang = L::from_path(path).ok_or_else(|| TSParseError::UnknownLanguage(path.into()))?;
+    Self::try_new(src, lang)
+  }
   // extract non generic implementation to reduce code size
   pub fn do_edit(&mut self, edit: Edit) -> Result<(), TSParseError> {
     let input = self.source.as_mut_vec();
@@ -55,6 +68,21 @@ impl<L: Language> Root<L> {
     }
   }
 
+  /// The kind of the root node, e.g. `program` for JavaScript.
+  pub fn root_kind(&self) -> String {
+    self.root().kind().into_owned()
+  }
+
+  /// Count the nodes of each kind in one pre-order traversal.
+  /// Anonymous nodes like punctuation are counted by their text, e.g. `;`.
+  pub fn kind_histogram(&self) -> HashMap<String, usize> {
+    let mut histogram = HashMap::new();
+    for node in self.root().dfs() {
+      *histogram.entry(node.kind().into_owned()).or_insert(0) += 1;
+    }
+    histogram
+  }
+
   /// Adopt the tree_sitter as the descendant of the root and return the wrapped sg Node.
   /// It assumes `inner` is the under the root and will panic at dev build if wrong node is used.
   pub fn adopt<'r>(&'r self, inner: tree_sitter::Node<'r>) -> Node<'r, L> {
@@ -85,6 +113,29 @@ pub struct Node<'r, L: Language> {
 }
 pub type KindId = u16;
 
+/// Options of [`Node::to_json`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonOptions {
+  /// Include anonymous children like punctuation and keywords, whose kind is their text.
+  pub anonymous: bool,
+}
+
+fn push_json_str(s: &str, out: &mut String) {
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+}
+
 struct NodeWalker<'tree, L: Language> {
   cursor: tree_sitter::TreeCursor<'tree>,
   root: &'tree Root<L>,
@@ -154,6 +205,23 @@ impl<'r, L: Language> Node<'r, L> {
     (pos.row() as usize, pos.column() as usize)
   }
 
+  /// Start and end positions as zero-based rows and columns counted in UTF-16 code units,
+  /// as required by the Language Server Protocol. `start_pos` and `end_pos` count bytes.
+  pub fn utf16_range(&self) -> ((usize, usize), (usize, usize)) {
+    let range = self.range();
+    let (start_row, start_col) = self.start_pos();
+    let (end_row, end_col) = self.end_pos();
+    let start = (start_row, self.utf16_column(range.start, start_col));
+    let end = (end_row, self.utf16_column(range.end, end_col));
+    (start, end)
+  }
+
+  /// Convert the byte column of the byte offset to UTF-16 code units.
+  fn utf16_column(&self, offset: usize, byte_column: usize) -> usize {
+    let line = &self.root.source[offset - byte_column..offset];
+    line.encode_utf16().count()
+  }
+
   pub fn text(&self) -> Cow<'r, str> {
     self
       .inner
@@ -161,11 +229,147 @@ impl<'r, L: Language> Node<'r, L> {
       .expect("invalid source text encoding")
   }
 
+  /// The source of the node with the captures named in `replacements` substituted by the
+  /// given text, e.g. `ARGS` for `$$$ARGS`. Captures outside the node or nested in another
+  /// substituted capture are ignored. Other text is kept verbatim.
+  pub fn clone_subtree_source(
+    &self,
+    env: &MetaVarEnv<'r, L>,
+    replacements: &HashMap<String, String>,
+  ) -> String {
+    let range = self.range();
+    let mut regions: Vec<_> = replacements
+      .iter()
+      .filter_map(|(name, text)| {
+        let multi = env.get_multiple_matches(name);
+        let start = env.get_match(name).or(multi.first())?.range().start;
+        let end = env.get_match(name).or(multi.last())?.range().end;
+        Some((start..end, text))
+      })
+      .filter(|(r, _)| range.start <= r.start && r.end <= range.end)
+      .collect();
+    regions.sort_by_key(|(r, _)| (r.start, std::cmp::Reverse(r.end)));
+    let source = &self.root.source;
+    let mut ret = String::new();
+    let mut start = range.start;
+    for (region, text) in regions {
+      if region.start < start {
+        continue;
+      }
+      ret.push_str(&source[start..region.start]);
+      ret.push_str(text);
+      start = region.end;
+    }
+    ret.push_str(&source[start..range.end]);
+    ret
+  }
+
   /// Node's tree structure dumped in Lisp like S-experssion
   pub fn to_sexp(&self) -> Cow<'_, str> {
     self.inner.to_sexp()
   }
 
+  /// Like [`Node::to_sexp`], but each named node is followed by its byte range,
+  /// e.g. `(program [0, 5] (expression_statement [0, 5] ...))`.
+  pub fn to_sexp_with_range(&self) -> String {
+    let mut ret = String::new();
+    self.write_sexp_with_range(None, &mut ret);
+    ret
+  }
+
+  fn write_sexp_with_range(&self, field: Option<&str>, out: &mut String) {
+    if let Some(field) = field {
+      out.push_str(field);
+      out.push_str(": ");
+    }
+    let range = self.range();
+    out.push_str(&format!(
+      "({} [{}, {}]",
+      self.kind(),
+      range.start,
+      range.end
+    ));
+    let mut cursor = self.inner.walk();
+    if cursor.goto_first_child() {
+      loop {
+        let inner = cursor.node();
+        if inner.is_named() {
+          out.push(' ');
+          let child = Node {
+            inner,
+            root: self.root,
+          };
+          child.write_sexp_with_range(cursor.field_name().as_deref(), out);
+        }
+        if !cursor.goto_next_sibling() {
+          break;
+        }
+      }
+    }
+    out.push(')');
+  }
+
+  /// Node's subtree as JSON, e.g. `{"kind":"identifier","field":"object","range":[0,1],"children":[]}`.
+  /// `field` is only present on children with a field name. Only named children are included by default.
+  pub fn to_json(&self, opts: JsonOptions) -> String {
+    let mut ret = String::new();
+    self.write_json(None, opts, &mut ret);
+    ret
+  }
+
+  fn write_json(&self, field: Option<&str>, opts: JsonOptions, out: &mut String) {
+    out.push_str("{\"kind\":");
+    push_json_str(&self.kind(), out);
+    if let Some(field) = field {
+      out.push_str(",\"field\":");
+      push_json_str(field, out);
+    }
+    let range = self.range();
+    out.push_str(&format!(
+      ",\"range\":[{},{}],\"children\":[",
+      range.start, range.end
+    ));
+    let mut cursor = self.inner.walk();
+    let mut first = true;
+    if cursor.goto_first_child() {
+      loop {
+        let inner = cursor.node();
+        if opts.anonymous || inner.is_named() {
+          if !first {
+            out.push(',');
+          }
+          first = false;
+          let child = Node {
+            inner,
+            root: self.root,
+          };
+          child.write_json(cursor.field_name().as_deref(), opts, out);
+        }
+        if !cursor.goto_next_sibling() {
+          break;
+        }
+      }
+    }
+    out.push_str("]}");
+  }
+
+  /// Precedence of the node as an expression, defined by [`Language::precedence`].
+  pub fn precedence(&self) -> Option<Precedence> {
+    let operator = self.field("operator").map(|n| n.text());
+    self.lang().precedence(&self.kind(), operator.as_deref())
+  }
+
+  /// The smallest named node in the subtree that spans the byte range.
+  pub fn named_descendant_for_range(&self, range: std::ops::Range<usize>) -> Option<Self> {
+    let inner = self
+      .inner
+      .named_descendant_for_byte_range(range.start as u32, range.end as u32)?;
+    Some(Node {
+      inner,
+      root: self.root,
+    })
+  }
+
   #[doc(hidden)]
   pub fn display_context(&self, context_lines: usize) -> DisplayContext<'r> {
     let bytes = self.root.source.as_bytes();
@@ -260,6 +464,11 @@ impl<'r, L: Language> Node<'r, L> {
     Pre::new(self)
   }
 
+  /// Pre-order traversal that skips anonymous nodes like punctuation.
+  pub fn dfs_named(&self) -> impl Iterator<Item = Node<'r, L>> {
+    self.dfs().filter(|n| n.is_named())
+  }
+
   #[must_use]
   pub fn find<M: Matcher<L>>(&self, pat: M) -> Option<NodeMatch<'r, L>> {
     pat.find_node(self.clone())
@@ -281,6 +490,11 @@ impl<'r, L: Language> Node<'r, L> {
     })
   }
 
+  /// Same as [`Node::field`], named after the tree-sitter API.
+  pub fn child_by_field_name(&self, name: &str) -> Option<Self> {
+    self.field(name)
+  }
+
   pub fn field_children(&self, name: &str) -> impl Iterator<Item = Node<'r, L>> {
     let field_id = self
       .root
@@ -366,6 +580,25 @@ impl<'r, L: Language> Node<'r, L> {
     })
   }
 
+  /// The next sibling skipping anonymous nodes like punctuation.
+  #[must_use]
+  pub fn next_named_sibling(&self) -> Option<Self> {
+    let inner = self.inner.next_named_sibling()?;
+    Some(Node {
+      inner,
+      root: self.root,
+    })
+  }
+  /// The previous sibling skipping anonymous nodes like punctuation.
+  #[must_use]
+  pub fn prev_named_sibling(&self) -> Option<Self> {
+    let inner = self.inner.prev_named_sibling()?;
+    Some(Node {
+      inner,
+      root: self.root,
+    })
+  }
+
   // TODO: use cursor to optimize clone.
   // investigate why tree_sitter cursor cannot goto next_sibling
   pub fn prev_all(&self) -> impl Iterator<Item = Node<'r, L>> + '_ {
@@ -386,14 +619,12 @@ impl<'r, L: Language> Node<'r, L> {
     M: Matcher<L>,
     R: Replacer<L>,
   {
-    let lang = self.root.lang.clone();
-    let env = matched.get_env();
     let range = matched.range();
     let position = range.start;
     let deleted_length = matcher
       .get_match_len(matched.get_node().clone())
       .unwrap_or_else(|| range.len());
-    let inserted_text = replacer.generate_replacement(env, lang);
+    let inserted_text = matched.generate_replacement(replacer, deleted_length);
     Edit {
       position,
       deleted_length,
@@ -455,6 +686,7 @@ impl<'r, L: Language> Node<'r, L> {
 #[cfg(test)]
 mod test {
   use crate::language::{Language, Tsx};
+  use std::collections::HashMap;
   #[test]
   fn test_is_leaf() {
     let root = Tsx.ast_grep("let a = 123");
@@ -462,6 +694,107 @@ mod test {
     assert!(!node.is_leaf());
   }
 
+  #[test]
+  fn test_dfs_named() {
+    let root = Tsx.ast_grep("let a = (1 + 2);");
+    let node = root.root();
+    let all = node.dfs().count();
+    let named = node.dfs_named().count();
+    // program, lexical_declaration, variable_declarator, identifier,
+    // parenthesized_expression, binary_expression, number, number
+    assert_eq!(named, 8);
+    // let, =, (, +, ) and ;
+    assert_eq!(all, named + 6);
+    assert!(node.dfs_named().all(|n| n.is_named()));
+    let found = node.find("1 + $A").expect("should find");
+    assert_eq!(found.text(), "1 + 2");
+  }
+
+  #[test]
+  fn test_utf16_range() {
+    let root = Tsx.ast_grep("let s = '😀é';\n/*😀*/ foo(a)");
+    let node = root.root();
+    let string = node.find("'$A'").expect("should find");
+    assert_eq!(string.start_pos(), (0, 8));
+    assert_eq!(string.end_pos(), (0, 16));
+    // the emoji is a surrogate pair of two code units and `é` is one
+    assert_eq!(string.utf16_range(), ((0, 8), (0, 13)));
+    let call = node.find("foo($A)").expect("should find");
+    assert_eq!(call.start_pos(), (1, 9));
+    assert_eq!(call.utf16_range(), ((1, 7), (1, 13)));
+  }
+
+  #[test]
+  fn test_clone_subtree_source() {
+    let root = Tsx.ast_grep("let x = 1; foo(a + 1, b, c)");
+    let nm = root.root().find("foo($A, $$$REST)").expect("should find");
+    let replace = |pairs: &[(&str, &str)]| {
+      let map = pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+      nm.clone_subtree_source(nm.get_env(), &map)
+    };
+    assert_eq!(replace(&[("A", "bar")]), "foo(bar, b, c)");
+    assert_eq!(replace(&[("REST", "d")]), "foo(a + 1, d)");
+    assert_eq!(replace(&[("A", "1"), ("REST", "2")]), "foo(1, 2)");
+    // unknown captures are ignored and the source is unchanged
+    assert_eq!(replace(&[("B", "x")]), "foo(a + 1, b, c)");
+    // captures outside the node are ignored
+    let a = nm.get_env().get_match("A").expect("should capture");
+    let map = HashMap::from([("REST".to_string(), "d".to_string())]);
+    assert_eq!(a.clone_subtree_source(nm.get_env(), &map), "a + 1");
+  }
+
+  #[test]
+  fn test_kind_histogram() {
+    let root = Tsx.ast_grep("let a = (1 + 2); foo(a)");
+    let root = &root.inner;
+    assert_eq!(root.root_kind(), "program");
+    let histogram = root.kind_histogram();
+    assert_eq!(histogram["program"], 1);
+    assert_eq!(histogram["lexical_declaration"], 1);
+    assert_eq!(histogram["identifier"], 3);
+    assert_eq!(histogram["number"], 2);
+    assert_eq!(histogram["call_expression"], 1);
+    assert_eq!(histogram["("], 2);
+    assert_eq!(histogram.values().sum::<usize>(), root.root().dfs().count());
+    assert!(!histogram.contains_key("string"));
+  }
+
+  #[test]
+  fn test_navigate_from_match() {
+    let root = Tsx.ast_grep("let a = 1; console.log(a); foo()");
+    let found = root.root().find("console.log($A)").expect("should find");
+    let stmt = found.parent().expect("should have parent");
+    assert_eq!(stmt.kind(), "expression_statement");
+    let prev = stmt.prev_named_sibling().expect("should have prev");
+    assert_eq!(prev.text(), "let a = 1;");
+    let next = stmt.next_named_sibling().expect("should have next");
+    assert_eq!(next.text(), "foo()");
+    assert!(next.next_named_sibling().is_none());
+    let callee = found
+      .child_by_field_name("function")
+      .expect("should have callee");
+    assert_eq!(callee.text(), "console.log");
+    let args = found.child_by_field_name("arguments").unwrap();
+    // `(` is skipped
+    assert_eq!(
+      args.child(0).unwrap().next_named_sibling().unwrap().text(),
+      "a"
+    );
+    assert!(found.child_by_field_name("body").is_none());
+  }
+
+  #[test]
+  fn test_kind_id_for() {
+    let root = Tsx.ast_grep("let a = 123");
+    for node in root.root().dfs().filter(|n| n.is_named()) {
+      assert_eq!(Tsx.kind_id_for(&node.kind()), Some(node.kind_id()));
+    }
+    assert_eq!(Tsx.kind_id_for("not_a_kind"), None);
+  }
+
   #[test]
   fn test_children() {
     let root = Tsx.ast_grep("let a = 123");
@@ -504,4 +837,42 @@ mod test {
     assert_eq!(edits[0].inserted_text, "Some(1)");
     assert_eq!(edits[1].inserted_text, "2");
   }
+
+  #[test]
+  fn test_sexp_with_range() {
+    let root = Tsx.ast_grep("a.b");
+    assert_eq!(
+      root.to_sexp(),
+      "(program (expression_statement (member_expression object: (identifier) property: (property_identifier))))"
+    );
+    assert_eq!(
+      root.root().to_sexp_with_range(),
+      "(program [0, 3] (expression_statement [0, 3] (member_expression [0, 3] object: (identifier [0, 1]) property: (property_identifier [2, 3]))))"
+    );
+    let node = root
+      .root()
+      .named_descendant_for_range(2..3)
+      .expect("should find");
+    assert_eq!(node.to_sexp_with_range(), "(property_identifier [2, 3])");
+  }
+
+  #[test]
+  fn test_to_json() {
+    let root = Tsx.ast_grep("a.b");
+    let member = root.root().find("$A.$B").expect("should find");
+    assert_eq!(
+      member.to_json(super::JsonOptions::default()),
+      concat!(
+        r#"{"kind":"member_expression","range":[0,3],"children":["#,
+        r#"{"kind":"identifier","field":"object","range":[0,1],"children":[]},"#,
+        r#"{"kind":"property_identifier","field":"property","range":[2,3],"children":[]}]}"#,
+      )
+    );
+    let opts = super::JsonOptions { anonymous: true };
+    let json = member.to_json(opts);
+    assert!(json.contains(r#"{"kind":".","range":[1,2],"children":[]}"#));
+    let root = Tsx.ast_grep(r#""a""#);
+    let json = root.root().to_json(opts);
+    assert!(json.contains(r#"{"kind":"\"","range":[0,1],"children":[]}"#));
+  }
 }
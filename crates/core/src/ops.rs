@@ -1,4 +1,4 @@
-use crate::matcher::{MatchAll, MatchNone, Matcher};
+use crate::matcher::{MatchAll, MatchNone, MatchTrace, Matcher};
 use crate::meta_var::{MetaVarEnv, MetaVarMatcher, MetaVarMatchers};
 use crate::Language;
 use crate::Node;
@@ -35,6 +35,14 @@ where
       _ => set1.xor(set2),
     }
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let children = vec![
+      self.pattern1.explain(node.clone()),
+      self.pattern2.explain(node.clone()),
+    ];
+    MatchTrace::from_match("and", self.match_node(node)).with_children(children)
+  }
 }
 
 // we precompute and cache potential_kinds. So patterns should not be mutated.
@@ -97,6 +105,15 @@ impl<L: Language, P: Matcher<L>> Matcher<L> for All<L, P> {
   fn potential_kinds(&self) -> Option<BitSet> {
     self.kinds.clone()
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let children = self
+      .patterns
+      .iter()
+      .map(|p| p.explain(node.clone()))
+      .collect();
+    MatchTrace::from_match("all", self.match_node(node)).with_children(children)
+  }
 }
 
 // Box<[P]> for immutability and potential_kinds cache correctness
@@ -161,6 +178,15 @@ impl<L: Language, M: Matcher<L>> Matcher<L> for Any<L, M> {
   fn potential_kinds(&self) -> Option<BitSet> {
     self.kinds.clone()
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let children = self
+      .patterns
+      .iter()
+      .map(|p| p.explain(node.clone()))
+      .collect();
+    MatchTrace::from_match("any", self.match_node(node)).with_children(children)
+  }
 }
 
 pub struct Or<L: Language, P1: Matcher<L>, P2: Matcher<L>> {
@@ -198,6 +224,14 @@ where
     set1.union_with(&set2);
     Some(set1)
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let children = vec![
+      self.pattern1.explain(node.clone()),
+      self.pattern2.explain(node.clone()),
+    ];
+    MatchTrace::from_match("or", self.match_node(node)).with_children(children)
+  }
 }
 
 pub struct Not<L: Language, M: Matcher<L>> {
@@ -227,10 +261,16 @@ where
     node: Node<'tree, L>,
     env: &mut MetaVarEnv<'tree, L>,
   ) -> Option<Node<'tree, L>> {
-    self
-      .not
-      .match_node_with_env(node.clone(), env)
-      .xor(Some(node))
+    // bindings from the inner match, even a partial one, never escape `not`
+    let checkpoint = env.checkpoint();
+    let matched = self.not.match_node_with_env(node.clone(), env);
+    env.rollback(checkpoint);
+    matched.xor(Some(node))
+  }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    let children = vec![self.not.explain(node.clone())];
+    MatchTrace::from_match("not", self.match_node(node)).with_children(children)
   }
 }
 
@@ -261,6 +301,14 @@ where
   fn potential_kinds(&self) -> Option<BitSet> {
     self.inner.potential_kinds()
   }
+
+  fn explain(&self, node: Node<L>) -> MatchTrace {
+    if self.meta_vars.is_empty() {
+      return self.inner.explain(node);
+    }
+    let children = vec![self.inner.explain(node.clone())];
+    MatchTrace::from_match("constraints", self.match_node(node)).with_children(children)
+  }
 }
 
 pub struct Predicate<F> {
@@ -433,6 +481,23 @@ mod test {
     test_not_find(&matcher, "let a = 123");
   }
 
+  #[test]
+  fn test_not_discards_env() {
+    // `$A` is bound before the inner matcher fails
+    let matcher = Op::not(Op::every("let a = $A").and("let a = 1"));
+    let root = Root::new("let a = 2", Tsx);
+    let decl = root.root().child(0).expect("should have declaration");
+    let mut env = MetaVarEnv::new();
+    let found = matcher.match_node_with_env(decl.clone(), &mut env);
+    assert_eq!(found.expect("should match").text(), "let a = 2");
+    assert!(env.get_match("A").is_none());
+    // bindings made before `not` are kept
+    env.insert("B".into(), decl.clone());
+    assert!(matcher.match_node_with_env(decl, &mut env).is_some());
+    assert!(env.get_match("A").is_none());
+    assert!(env.get_match("B").is_some());
+  }
+
   #[test]
   fn test_api_and() {
     let matcher = Op::every("let a = $_").and(Op::not("let a = 123"));
@@ -591,4 +656,35 @@ mod test {
     let code = Root::new("b", Tsx);
     assert!(code.root().find(&matcher).is_none());
   }
+
+  #[test]
+  fn test_explain_any() {
+    let matcher = Op::any([
+      Op::all(["foo($A)".t(), "impossible".t()]),
+      Op::all(["foo($B)".t()]),
+    ]);
+    let code = Root::new("foo(123)", Tsx);
+    let node = code.root().child(0).unwrap().child(0).unwrap();
+    let trace = matcher.explain(node);
+    assert!(trace.matched);
+    assert_eq!(trace.label, "any");
+    assert_eq!(trace.env, [("B".to_string(), "123".to_string())]);
+    let failed = &trace.children[0];
+    assert!(!failed.matched);
+    assert!(failed.children[0].matched);
+    assert!(!failed.children[1].matched);
+    assert_eq!(failed.children[1].label, "pattern: impossible");
+    assert!(trace.children[1].matched);
+    let expected = "\
+✔ any
+  ✘ all
+    ✔ pattern: foo($A)
+    ✘ pattern: impossible
+  ✔ all
+    ✔ pattern: foo($B)
+captured:
+  $B = 123
+";
+    assert_eq!(trace.to_string(), expected);
+  }
 }
@@ -1,9 +1,13 @@
 use crate::language::Language;
-use crate::meta_var::{split_first_meta_var, MatchResult, MetaVarEnv};
+use crate::meta_var::{
+  encode_optional_meta_var, split_first_meta_var, MatchResult, MetaVarEnv, MetaVariable,
+};
 use crate::ts_parser::Edit;
 use crate::Pattern;
 use crate::{Node, Root};
 
+use std::ops::Range;
+
 /// Replace meta variable in the replacer string
 pub trait Replacer<L: Language> {
   fn generate_replacement(&self, env: &MetaVarEnv<L>, lang: L) -> String;
@@ -11,7 +15,8 @@ pub trait Replacer<L: Language> {
 
 impl<L: Language> Replacer<L> for str {
   fn generate_replacement(&self, env: &MetaVarEnv<L>, lang: L) -> String {
-    let root = Root::new(self, lang.clone());
+    let encoded = encode_optional_meta_var(self, lang.meta_var_char());
+    let root = Root::new(&encoded, lang.clone());
     let edits = collect_edits(&root, env, lang);
     merge_edits_to_string(edits, &root)
   }
@@ -37,16 +42,24 @@ where
 fn collect_edits<L: Language>(root: &Root<L>, env: &MetaVarEnv<L>, lang: L) -> Vec<Edit> {
   let mut node = root.root();
   let root_id = node.inner.id();
-  let mut edits = vec![];
+  let mut edits: Vec<Edit> = vec![];
 
   // this is a post-order DFS that stops traversal when the node matches
   'outer: loop {
     if let Some(text) = get_meta_var_replacement(&node, env, lang.clone()) {
-      let position = node.inner.start_byte();
-      let length = node.inner.end_byte() - position;
+      let optional = lang.extract_meta_var(&node.text());
+      let mut range = if text.is_empty() && matches!(optional, Some(MetaVariable::Optional(_))) {
+        absent_optional_range(&node)
+      } else {
+        node.range()
+      };
+      // the separator may already be removed with a previous absent node
+      if let Some(last) = edits.last() {
+        range.start = range.start.max(last.position + last.deleted_length);
+      }
       edits.push(Edit {
-        position: position as usize,
-        deleted_length: length as usize,
+        position: range.start,
+        deleted_length: range.len(),
         inserted_text: text,
       });
     } else if let Some(first_child) = node.child(0) {
@@ -84,12 +97,49 @@ fn collect_edits<L: Language>(root: &Root<L>, env: &MetaVarEnv<L>, lang: L) -> V
   edits
 }
 
+/// The text removed for an optional meta variable replaced by nothing: the variable in its
+/// outermost wrapper and one separator next to it, so `bar($A, $?B)` becomes `bar(a)`.
+fn absent_optional_range<L: Language>(node: &Node<L>) -> Range<usize> {
+  let range = node.range();
+  let mut node = node.clone();
+  while let Some(p) = node.parent().filter(|p| p.range() == range) {
+    node = p;
+  }
+  let prev = node.prev().filter(|p| !p.is_named() && p.prev().is_some());
+  let next = node.next().filter(|n| !n.is_named() && n.next().is_some());
+  match (prev, next) {
+    (Some(p), _) => p.range().start..range.end,
+    // keep the space before the next node, e.g. `foo($?A, b)` becomes `foo(b)`
+    (None, Some(n)) => range.start..n.next().expect("separator has next").range().start,
+    (None, None) => range,
+  }
+}
+
 // replace meta_var in template string, e.g. "Hello $NAME" -> "Hello World"
 // TODO: use Cow instead of String
 pub fn replace_meta_var_in_string<L: Language>(
+  template: &str,
+  env: &MetaVarEnv<L>,
+  lang: &L,
+) -> String {
+  interpolate(template, env, lang, false)
+}
+
+/// Like `replace_meta_var_in_string`, but an unbound meta variable is kept as written,
+/// e.g. `$FN`, so that a diagnostic message shows it instead of silently dropping it.
+pub fn replace_meta_var_in_message<L: Language>(
+  template: &str,
+  env: &MetaVarEnv<L>,
+  lang: &L,
+) -> String {
+  interpolate(template, env, lang, true)
+}
+
+fn interpolate<L: Language>(
   mut template: &str,
   env: &MetaVarEnv<L>,
   lang: &L,
+  keep_unbound: bool,
 ) -> String {
   let mv_char = lang.meta_var_char();
   let mut ret = String::new();
@@ -97,8 +147,13 @@ pub fn replace_meta_var_in_string<L: Language>(
     ret.push_str(&template[..i]);
     template = &template[i..];
     let (meta_var, remaining) = split_first_meta_var(template, mv_char);
-    if let Some(n) = env.get_match(meta_var) {
+    if let Some(text) = env.get_transformed(meta_var) {
+      ret.push_str(text);
+    } else if let Some(n) = env.get_match(meta_var) {
       ret.push_str(&n.text());
+    } else if keep_unbound {
+      ret.push(mv_char);
+      ret.push_str(meta_var);
     }
     template = remaining;
   }
@@ -127,7 +182,23 @@ fn get_meta_var_replacement<L: Language>(
     return None;
   }
   let meta_var = lang.extract_meta_var(&node.text())?;
-  let replaced = match env.get(&meta_var)? {
+  // `$$MATCH` is the text of the whole match
+  if matches!(&meta_var, MetaVariable::Named(name, false) if name == "MATCH") {
+    if let Some(text) = env.get_whole_match() {
+      return Some(text.clone());
+    }
+  }
+  if let MetaVariable::Named(name, _) | MetaVariable::Optional(name) = &meta_var {
+    if let Some(text) = env.get_transformed(name) {
+      return Some(text.clone());
+    }
+  }
+  let matched = env.get(&meta_var);
+  // unbound optional meta variable is replaced by empty text
+  if matched.is_none() && matches!(meta_var, MetaVariable::Optional(_)) {
+    return Some(String::new());
+  }
+  let replaced = match matched? {
     MatchResult::Single(replaced) => replaced.text().to_string(),
     MatchResult::Multi(nodes) => {
       if nodes.is_empty() {
@@ -202,6 +273,21 @@ mod test {
     );
   }
 
+  #[test]
+  fn test_optional_meta_var() {
+    test_str_replace(
+      "bar($A).baz($?B)",
+      &[("A", "a"), ("B", "b")],
+      "bar(a).baz(b)",
+    );
+    test_str_replace("bar($A).baz($?B)", &[("A", "a")], "bar(a).baz()");
+    // the separator goes with the absent node
+    test_str_replace("bar($A, $?B)", &[("A", "a")], "bar(a)");
+    test_str_replace("bar($?A, $B)", &[("B", "b")], "bar(b)");
+    test_str_replace("bar($A, $?B, $C)", &[("A", "a"), ("C", "c")], "bar(a, c)");
+    test_str_replace("bar($?A, $?B)", &[], "bar()");
+  }
+
   #[test]
   fn test_multiple_occurrences() {
     test_str_replace("let $A = $A", &[("A", "a")], "let a = a");
@@ -269,6 +355,45 @@ mod test {
     test_template_replace("$B $A", &[("A", "World"), ("B", "Hello")], "Hello World");
   }
 
+  #[test]
+  fn test_transformed_replace() {
+    let grep = Tsx.ast_grep("foo(a)");
+    let mut env = MetaVarEnv::new();
+    env.insert(
+      "A".into(),
+      grep.root().find("a").unwrap().get_node().clone(),
+    );
+    env.insert_transformation("A".into(), "String(a)".into());
+    env.insert_transformation("B".into(), "b".into());
+    assert_eq!(
+      "bar($A, $B)".generate_replacement(&env, Tsx),
+      "bar(String(a), b)"
+    );
+    assert_eq!(
+      replace_meta_var_in_string("$A $B", &env, &Tsx),
+      "String(a) b"
+    );
+  }
+
+  #[test]
+  fn test_replace_meta_var_in_message() {
+    let grep = Tsx.ast_grep("foo(a)");
+    let mut env = MetaVarEnv::new();
+    env.insert(
+      "A".into(),
+      grep.root().find("a").unwrap().get_node().clone(),
+    );
+    let message = "$A costs $5, not $B or $$$ARGS";
+    assert_eq!(
+      replace_meta_var_in_message(message, &env, &Tsx),
+      "a costs $5, not $B or $$$ARGS"
+    );
+    assert_eq!(
+      replace_meta_var_in_string(message, &env, &Tsx),
+      "a costs 5, not  or "
+    );
+  }
+
   #[test]
   fn test_nested_matching_replace() {
     // TODO
@@ -1,5 +1,7 @@
 use thiserror::Error;
 use tree_sitter::{InputEdit, LanguageError, Parser, ParserError, Point};
+
+use std::path::PathBuf;
 pub use tree_sitter::{Language, Tree};
 
 /// Represents tree-sitter related error
@@ -16,6 +18,8 @@ pub enum TSParseError {
   /// * The parser has not yet had a language assigned with [Parser::set_language]
   #[error("general error when tree-sitter fails to parse.")]
   TreeUnavailable,
+  #[error("language cannot be detected from path `{}`.", .0.display())]
+  UnknownLanguage(PathBuf),
 }
 
 pub fn parse(
@@ -33,6 +37,7 @@ pub fn parse(
 }
 
 // https://github.com/tree-sitter/tree-sitter/blob/e4e5ffe517ca2c668689b24cb17c51b8c6db0790/cli/src/parse.rs
+/// A text replacement in source code, described by byte offsets.
 #[derive(Debug)]
 pub struct Edit {
   pub position: usize,
@@ -40,6 +45,13 @@ pub struct Edit {
   pub inserted_text: String,
 }
 
+impl Edit {
+  /// Byte range in the original source that will be replaced by `inserted_text`.
+  pub fn range(&self) -> std::ops::Range<usize> {
+    self.position..self.position + self.deleted_length
+  }
+}
+
 fn position_for_offset(input: &[u8], offset: usize) -> Point {
   debug_assert!(offset <= input.len());
   let (mut row, mut col) = (0, 0);
@@ -9,6 +9,12 @@ impl Language for CSharp {
   fn get_ts_language(&self) -> TSLanguage {
     language_c_sharp()
   }
+  fn line_comment(&self) -> Option<&'static str> {
+    Some("//")
+  }
+  fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+    Some(("/*", "*/"))
+  }
   // https://docs.microsoft.com/en-us/dotnet/csharp/language-reference/language-specification/lexical-structure#643-identifiers
   // all letter number is accepted
   // https://www.compart.com/en/unicode/category/Nl
@@ -8,6 +8,9 @@ impl Language for Css {
   fn get_ts_language(&self) -> TSLanguage {
     language_css()
   }
+  fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+    Some(("/*", "*/"))
+  }
   fn expando_char(&self) -> char {
     '_'
   }
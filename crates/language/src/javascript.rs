@@ -0,0 +1,79 @@
+use ast_grep_core::language::Precedence;
+
+/// Operator precedence shared by JavaScript, TypeScript and Tsx.
+/// Levels follow the MDN operator precedence table, compressed to consecutive numbers.
+pub fn js_precedence(kind: &str, operator: Option<&str>) -> Option<Precedence> {
+  let precedence = match kind {
+    "sequence_expression" => Precedence::left(1),
+    "assignment_expression" | "augmented_assignment_expression" => Precedence::right(2),
+    "arrow_function" | "yield_expression" => Precedence::right(2),
+    "ternary_expression" => Precedence::right(3),
+    "binary_expression" => binary_precedence(operator?)?,
+    "as_expression" | "satisfies_expression" => Precedence::left(10),
+    "unary_expression" | "await_expression" => Precedence::right(15),
+    "update_expression" => Precedence::left(16),
+    "call_expression" | "member_expression" | "subscript_expression" | "new_expression" => {
+      Precedence::left(17)
+    }
+    _ => return None,
+  };
+  Some(precedence)
+}
+
+fn binary_precedence(operator: &str) -> Option<Precedence> {
+  let level = match operator {
+    "??" | "||" => 4,
+    "&&" => 5,
+    "|" => 6,
+    "^" => 7,
+    "&" => 8,
+    "==" | "!=" | "===" | "!==" => 9,
+    "<" | ">" | "<=" | ">=" | "instanceof" | "in" => 10,
+    "<<" | ">>" | ">>>" => 11,
+    "+" | "-" => 12,
+    "*" | "/" | "%" => 13,
+    "**" => return Some(Precedence::right(14)),
+    _ => return None,
+  };
+  Some(Precedence::left(level))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::TypeScript;
+  use ast_grep_core::language::Language;
+
+  fn precedence(src: &str) -> Option<u8> {
+    let grep = TypeScript.ast_grep(src);
+    let stmt = grep.root().child(0)?;
+    let expr = stmt.child(0)?;
+    expr.precedence().map(|p| p.level)
+  }
+
+  #[test]
+  fn test_js_precedence() {
+    assert_eq!(precedence("a, b"), Some(1));
+    assert_eq!(precedence("a = b"), Some(2));
+    assert_eq!(precedence("a ? b : c"), Some(3));
+    assert_eq!(precedence("a || b"), Some(4));
+    assert_eq!(precedence("a + b"), Some(12));
+    assert_eq!(precedence("a * b"), Some(13));
+    assert_eq!(precedence("await a"), Some(15));
+    assert_eq!(precedence("a.b()"), Some(17));
+    assert_eq!(precedence("a"), None);
+  }
+
+  #[test]
+  fn test_right_assoc() {
+    assert_eq!(
+      js_precedence("binary_expression", Some("**")),
+      Some(Precedence::right(14))
+    );
+    assert_eq!(
+      js_precedence("binary_expression", Some("-")),
+      Some(Precedence::left(12))
+    );
+    assert_eq!(js_precedence("binary_expression", None), None);
+  }
+}
@@ -1,5 +1,6 @@
 mod csharp;
 mod css;
+mod javascript;
 mod parsers;
 mod python;
 mod rust;
@@ -9,35 +10,82 @@ use std::path::Path;
 
 pub use csharp::CSharp;
 pub use css::Css;
+use javascript::js_precedence;
 pub use python::Python;
 pub use rust::Rust;
 
 macro_rules! impl_lang {
-  ($lang: ident, $func: ident) => {
+  ($lang: ident, $func: ident $(, $method: ident: $value: expr)*) => {
     #[derive(Clone, Copy)]
     pub struct $lang;
     impl Language for $lang {
       fn get_ts_language(&self) -> TSLanguage {
         parsers::$func().into()
       }
+      $(lang_method!($method, $value);)*
     }
   };
 }
 
-impl_lang!(C, language_c);
-impl_lang!(Dart, language_dart);
-impl_lang!(Go, language_go);
-impl_lang!(Html, language_html);
-impl_lang!(Java, language_java);
-impl_lang!(JavaScript, language_javascript);
-impl_lang!(Kotlin, language_kotlin);
-impl_lang!(Lua, language_lua);
-impl_lang!(Swift, language_swift);
-impl_lang!(Thrift, language_thrift);
-impl_lang!(Tsx, language_tsx);
-impl_lang!(TypeScript, language_typescript);
+/// Trait methods a language in `impl_lang!` can override.
+macro_rules! lang_method {
+  (precedence, $precedence: expr) => {
+    fn precedence(&self, kind: &str, operator: Option<&str>) -> Option<Precedence> {
+      $precedence(kind, operator)
+    }
+  };
+  (line_comment, $start: expr) => {
+    fn line_comment(&self) -> Option<&'static str> {
+      Some($start)
+    }
+  };
+  (block_comment, $delimiters: expr) => {
+    fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+      Some($delimiters)
+    }
+  };
+  (string_escape, $escape: expr) => {
+    fn string_escape(&self) -> StringEscape {
+      $escape
+    }
+  };
+}
+
+impl_lang!(C, language_c, line_comment: "//", block_comment: ("/*", "*/"));
+impl_lang!(Dart, language_dart, line_comment: "//", block_comment: ("/*", "*/"));
+impl_lang!(Go, language_go, line_comment: "//", block_comment: ("/*", "*/"));
+impl_lang!(Html, language_html, block_comment: ("<!--", "-->"));
+impl_lang!(Java, language_java, line_comment: "//", block_comment: ("/*", "*/"));
+impl_lang!(
+  JavaScript,
+  language_javascript,
+  precedence: js_precedence,
+  line_comment: "//",
+  block_comment: ("/*", "*/"),
+  string_escape: StringEscape::JavaScript
+);
+impl_lang!(Kotlin, language_kotlin, line_comment: "//", block_comment: ("/*", "*/"));
+impl_lang!(Lua, language_lua, line_comment: "--", block_comment: ("--[[", "]]"));
+impl_lang!(Swift, language_swift, line_comment: "//", block_comment: ("/*", "*/"));
+impl_lang!(Thrift, language_thrift, line_comment: "//", block_comment: ("/*", "*/"));
+impl_lang!(
+  Tsx,
+  language_tsx,
+  precedence: js_precedence,
+  line_comment: "//",
+  block_comment: ("/*", "*/"),
+  string_escape: StringEscape::JavaScript
+);
+impl_lang!(
+  TypeScript,
+  language_typescript,
+  precedence: js_precedence,
+  line_comment: "//",
+  block_comment: ("/*", "*/"),
+  string_escape: StringEscape::JavaScript
+);
 
-use ast_grep_core::language::TSLanguage;
+use ast_grep_core::language::{Precedence, StringEscape, TSLanguage};
 use ast_grep_core::meta_var::MetaVariable;
 pub use ast_grep_core::Language;
 use serde::{Deserialize, Serialize};
@@ -149,10 +197,15 @@ impl Language for SupportLang {
   impl_lang_method!(meta_var_char, () => char);
   impl_lang_method!(expando_char, () => char);
   impl_lang_method!(extract_meta_var, (source: &str) => Option<MetaVariable>);
+  impl_lang_method!(precedence, (kind: &str, operator: Option<&str>) => Option<Precedence>);
 
   fn pre_process_pattern<'q>(&self, query: &'q str) -> Cow<'q, str> {
     execute_lang_method! { self, pre_process_pattern, query }
   }
+
+  impl_lang_method!(line_comment, () => Option<&'static str>);
+  impl_lang_method!(block_comment, () => Option<(&'static str, &'static str)>);
+  impl_lang_method!(string_escape, () => StringEscape);
 }
 
 /// Guess which programming language a file is written in
@@ -257,6 +310,34 @@ mod test {
     source.generate()
   }
 
+  #[test]
+  fn test_comment_delimiters() {
+    use SupportLang as S;
+    assert_eq!(S::TypeScript.line_comment(), Some("//"));
+    assert_eq!(S::TypeScript.block_comment(), Some(("/*", "*/")));
+    assert_eq!(S::Python.line_comment(), Some("#"));
+    assert_eq!(S::Python.block_comment(), None);
+    assert_eq!(S::Css.line_comment(), None);
+    assert_eq!(S::Css.block_comment(), Some(("/*", "*/")));
+    assert_eq!(S::Html.line_comment(), None);
+    assert_eq!(S::Html.block_comment(), Some(("<!--", "-->")));
+    assert_eq!(S::Lua.line_comment(), Some("--"));
+    assert_eq!(S::Rust.block_comment(), Some(("/*", "*/")));
+    // the language structs agree with SupportLang
+    assert_eq!(Python.line_comment(), Some("#"));
+    assert_eq!(Lua.block_comment(), Some(("--[[", "]]")));
+    assert_eq!(CSharp.line_comment(), Some("//"));
+  }
+
+  #[test]
+  fn test_string_escape() {
+    use SupportLang as S;
+    assert_eq!(S::Tsx.string_escape(), StringEscape::JavaScript);
+    assert_eq!(S::Python.string_escape(), StringEscape::Python);
+    assert_eq!(S::Rust.string_escape(), StringEscape::Rust);
+    assert_eq!(S::Go.string_escape(), StringEscape::C);
+  }
+
   #[test]
   fn test_js_string() {
     test_match_lang("'a'", "'a'", JavaScript);
@@ -270,5 +351,18 @@ mod test {
     assert_eq!(from_extension(path), Some(SupportLang::Rust));
   }
 
+  #[test]
+  fn test_language_detection() {
+    use ast_grep_core::AstGrep;
+    let grep = AstGrep::<SupportLang>::new_with_language_detection("<div />", "src/a.tsx");
+    let grep = grep.expect("should detect");
+    assert_eq!(grep.root().lang(), &SupportLang::Tsx);
+    assert!(grep.root().find("<div />").is_some());
+    let ret = AstGrep::<SupportLang>::new_with_language_detection("a", "a.unknown");
+    let error = ret.err().expect("should fail");
+    let expected = "language cannot be detected from path `a.unknown`.";
+    assert_eq!(error.to_string(), expected);
+  }
+
   // TODO: add test for file_types
 }
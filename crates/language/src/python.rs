@@ -1,5 +1,5 @@
 use crate::parsers::language_python;
-use ast_grep_core::language::{Language, TSLanguage};
+use ast_grep_core::language::{Language, StringEscape, TSLanguage};
 use std::borrow::Cow;
 
 // impl_lang!(Python, language_python);
@@ -9,6 +9,12 @@ impl Language for Python {
   fn get_ts_language(&self) -> TSLanguage {
     language_python()
   }
+  fn line_comment(&self) -> Option<&'static str> {
+    Some("#")
+  }
+  fn string_escape(&self) -> StringEscape {
+    StringEscape::Python
+  }
   // we can use any char in unicode range [:XID_Start:]
   // https://docs.python.org/3/reference/lexical_analysis.html#identifiers
   // see also [PEP 3131](https://peps.python.org/pep-3131/) for further details.
@@ -1,5 +1,5 @@
 use crate::parsers::language_rust;
-use ast_grep_core::language::{Language, TSLanguage};
+use ast_grep_core::language::{Language, StringEscape, TSLanguage};
 use std::borrow::Cow;
 
 // impl_lang!(Rust, language_rust);
@@ -9,6 +9,15 @@ impl Language for Rust {
   fn get_ts_language(&self) -> TSLanguage {
     language_rust()
   }
+  fn line_comment(&self) -> Option<&'static str> {
+    Some("//")
+  }
+  fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+    Some(("/*", "*/"))
+  }
+  fn string_escape(&self) -> StringEscape {
+    StringEscape::Rust
+  }
   // we can use any char in unicode range [:XID_Start:]
   // https://doc.rust-lang.org/reference/identifiers.html
   fn expando_char(&self) -> char {
@@ -4,7 +4,7 @@ use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
 
 use ast_grep_config::Severity;
-use ast_grep_config::{RuleCollection, RuleConfig};
+use ast_grep_config::{CombinedScan, RuleCollection, RuleConfig};
 use ast_grep_core::{language::Language, AstGrep, Node, NodeMatch};
 
 use std::collections::HashMap;
@@ -133,8 +133,7 @@ impl<L: LSPLang> LanguageServer for Backend<L> {
 }
 
 fn convert_node_to_range<L: Language>(node_match: &Node<L>) -> Range {
-  let (start_row, start_col) = node_match.start_pos();
-  let (end_row, end_col) = node_match.end_pos();
+  let ((start_row, start_col), (end_row, end_col)) = node_match.utf16_range();
   Range {
     start: Position {
       line: start_row as u32,
@@ -147,6 +146,39 @@ fn convert_node_to_range<L: Language>(node_match: &Node<L>) -> Range {
   }
 }
 
+/// The position of the byte offset with the column in UTF-16 code units.
+fn convert_offset_to_position(src: &str, offset: usize) -> Position {
+  let line_start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+  Position {
+    line: src[..offset].matches('\n').count() as u32,
+    character: src[line_start..offset].encode_utf16().count() as u32,
+  }
+}
+
+/// Run all rules in one traversal. Diagnostics are grouped by rule in document order.
+/// Cross-file rules report nothing as the server has no index of the imports in the workspace.
+fn get_diagnostics<L: Language>(
+  rules: Vec<&RuleConfig<L>>,
+  root: &AstGrep<L>,
+  uri: &Url,
+) -> Vec<Diagnostic> {
+  let combined = CombinedScan::new(rules);
+  let mut matches: Vec<Vec<_>> = combined.rules.iter().map(|_| vec![]).collect();
+  for (idx, nm) in combined.scan(root) {
+    matches[idx].push(nm);
+  }
+  let mut diagnostics = vec![];
+  for (rule, matches) in combined.rules.iter().zip(matches) {
+    let matches = rule.filter_first_per_scope(matches);
+    diagnostics.extend(
+      matches
+        .into_iter()
+        .map(|m| convert_match_to_diagnostic(m, rule, uri)),
+    );
+  }
+  diagnostics
+}
+
 fn convert_match_to_diagnostic<L: Language>(
   node_match: NodeMatch<L>,
   rule: &RuleConfig<L>,
@@ -161,6 +193,7 @@ fn convert_match_to_diagnostic<L: Language>(
       Severity::Warning => DiagnosticSeverity::WARNING,
       Severity::Info => DiagnosticSeverity::INFORMATION,
       Severity::Hint => DiagnosticSeverity::HINT,
+      Severity::Off => unreachable!("turned-off rule should not have match"),
     }),
     message: rule.get_message(&node_match),
     source: Some(String::from("ast-grep")),
@@ -206,14 +239,8 @@ impl<L: LSPLang> Backend<L> {
     }
   }
   async fn publish_diagnostics(&self, uri: Url, versioned: &VersionedAst<L>) -> Option<()> {
-    let mut diagnostics = vec![];
     let path = uri.to_file_path().ok()?;
-    let rules = self.rules.for_path(&path);
-    for rule in rules {
-      let to_diagnostic = |m| convert_match_to_diagnostic(m, rule, &uri);
-      let matcher = &rule.matcher;
-      diagnostics.extend(versioned.root.root().find_all(matcher).map(to_diagnostic));
-    }
+    let diagnostics = get_diagnostics(self.rules.for_path(&path), &versioned.root, &uri);
     self
       .client
       .publish_diagnostics(uri, diagnostics, Some(versioned.version))
@@ -224,7 +251,7 @@ impl<L: LSPLang> Backend<L> {
     let text_doc = params.text_document;
     let uri = text_doc.uri.as_str().to_owned();
     let text = text_doc.text;
-    let lang = Self::infer_lang_from_uri(&text_doc.uri)?;
+    let lang = self.infer_lang_from_uri(&text_doc.uri)?;
     let root = AstGrep::new(text, lang);
     let versioned = VersionedAst {
       version: text_doc.version,
@@ -238,7 +265,7 @@ impl<L: LSPLang> Backend<L> {
     let text_doc = params.text_document;
     let uri = text_doc.uri.as_str();
     let text = &params.content_changes[0].text;
-    let lang = Self::infer_lang_from_uri(&text_doc.uri)?;
+    let lang = self.infer_lang_from_uri(&text_doc.uri)?;
     let root = AstGrep::new(text, lang);
     let mut versioned = self.map.get_mut(uri)?;
     // skip old version update
@@ -283,17 +310,29 @@ impl<L: LSPLang> Backend<L> {
         if !ranges.contains(&range) {
           continue;
         }
-        let fixer = match &config.fixer {
-          Some(fixer) => fixer,
-          None => continue,
-        };
-        let edit = matched_node.replace_by(fixer);
-        let edit = TextEdit {
-          range,
-          new_text: edit.inserted_text,
+        let edits = if let Some(fixer) = &config.fixer {
+          let edit = matched_node.replace_by(fixer);
+          vec![TextEdit {
+            range,
+            new_text: edit.inserted_text,
+          }]
+        } else if let Some(edits) = config.fix_edits(&matched_node) {
+          let src = versioned.root.source();
+          edits
+            .into_iter()
+            .map(|edit| TextEdit {
+              range: Range {
+                start: convert_offset_to_position(src, edit.position),
+                end: convert_offset_to_position(src, edit.position + edit.deleted_length),
+              },
+              new_text: edit.inserted_text,
+            })
+            .collect()
+        } else {
+          continue;
         };
         let mut changes = HashMap::new();
-        changes.insert(text_doc.uri.clone(), vec![edit]);
+        changes.insert(text_doc.uri.clone(), edits);
         let edit = Some(WorkspaceEdit {
           changes: Some(changes),
           document_changes: None,
@@ -316,8 +355,8 @@ impl<L: LSPLang> Backend<L> {
   }
 
   // TODO: support other urls besides file_scheme
-  fn infer_lang_from_uri(uri: &Url) -> Option<L> {
+  fn infer_lang_from_uri(&self, uri: &Url) -> Option<L> {
     let path = uri.to_file_path().ok()?;
-    L::from_path(path)
+    self.rules.get_lang(path)
   }
 }